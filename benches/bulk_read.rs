@@ -0,0 +1,198 @@
+//! Bulk read throughput of `Device::command`, which returns a new `Vec`,
+//! against `Device::command_pooled`, which reads into reused buffers.
+//! Allocations per command are counted and printed before the timings.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use ptp::{CallOptions, CommandCode, Device, Endpoint, Error, Transport};
+
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+const PACKET_SIZE: usize = 512;
+const HEADER_SIZE: usize = 12;
+const RECEIVE: u16 = 0x9002;
+const SIZES: [usize; 3] = [4 * 1024, 256 * 1024, 4 * 1024 * 1024];
+
+// Answers every command with a data container as long as its first
+// parameter says, then a response, without keeping any record of them
+struct StreamingCamera {
+    state: Mutex<Stream>,
+}
+
+#[derive(Default)]
+struct Stream {
+    // the data and response containers, back to back
+    buf: Vec<u8>,
+    pos: usize,
+    // where the data container ends
+    boundary: usize,
+    zlp: bool,
+}
+
+impl StreamingCamera {
+    fn new() -> StreamingCamera {
+        StreamingCamera {
+            state: Mutex::new(Stream::default()),
+        }
+    }
+}
+
+fn header(buf: &mut Vec<u8>, len: usize, kind: u16, code: u16, tid: u32) {
+    buf.extend_from_slice(&(len as u32).to_le_bytes());
+    buf.extend_from_slice(&kind.to_le_bytes());
+    buf.extend_from_slice(&code.to_le_bytes());
+    buf.extend_from_slice(&tid.to_le_bytes());
+}
+
+impl Transport for StreamingCamera {
+    fn write_bulk(&self, buf: &[u8], _timeout: Duration) -> Result<usize, Error> {
+        let tid = u32::from_le_bytes([buf[8], buf[9], buf[10], buf[11]]);
+        let len = u32::from_le_bytes([buf[12], buf[13], buf[14], buf[15]]) as usize;
+
+        // the stream's storage is reused from one command to the next
+        let mut state = self.state.lock().unwrap();
+        let stream = &mut *state;
+        stream.buf.clear();
+        header(&mut stream.buf, HEADER_SIZE + len, 2, RECEIVE, tid);
+        stream.buf.resize(HEADER_SIZE + len, 0xa5);
+        stream.boundary = stream.buf.len();
+        header(&mut stream.buf, HEADER_SIZE, 3, 0x2001, tid);
+        stream.pos = 0;
+        stream.zlp = false;
+        Ok(buf.len())
+    }
+
+    fn read_bulk(&self, buf: &mut [u8], _timeout: Duration) -> Result<usize, Error> {
+        let mut state = self.state.lock().unwrap();
+        let stream = &mut *state;
+        if stream.zlp {
+            stream.zlp = false;
+            return Ok(0);
+        }
+
+        let end = if stream.pos < stream.boundary {
+            stream.boundary
+        } else {
+            stream.buf.len()
+        };
+        let n = buf.len().min(end - stream.pos);
+        buf[..n].copy_from_slice(&stream.buf[stream.pos..stream.pos + n]);
+        stream.pos += n;
+        stream.zlp = stream.pos == stream.boundary
+            && n == buf.len()
+            && stream.boundary.is_multiple_of(PACKET_SIZE);
+        Ok(n)
+    }
+
+    fn read_interrupt(&self, _buf: &mut [u8], _timeout: Duration) -> Result<usize, Error> {
+        Err(Error::Usb(rusb::Error::Timeout))
+    }
+
+    fn max_packet_size(&self, _endpoint: Endpoint) -> usize {
+        PACKET_SIZE
+    }
+
+    fn clear_halt(&self, _endpoint: Endpoint) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn reset(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+type Read = fn(&Device<StreamingCamera>, usize) -> usize;
+
+fn read_vec(device: &Device<StreamingCamera>, len: usize) -> usize {
+    device
+        .command(CommandCode::Other(RECEIVE), &[len as u32], None, None)
+        .unwrap()
+        .len()
+}
+
+fn read_pooled(device: &Device<StreamingCamera>, len: usize) -> usize {
+    device
+        .command_pooled(
+            CommandCode::Other(RECEIVE),
+            &[len as u32],
+            None,
+            None,
+            &CallOptions::default(),
+        )
+        .unwrap()
+        .len()
+}
+
+// Allocations per command once the device and its pool are warm
+fn allocations(read: Read, len: usize) -> f64 {
+    const RUNS: usize = 100;
+    let device = Device::with_transport(StreamingCamera::new());
+    read(&device, len);
+
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    for _ in 0..RUNS {
+        read(&device, len);
+    }
+    (ALLOCATIONS.load(Ordering::Relaxed) - before) as f64 / RUNS as f64
+}
+
+fn bulk_read(c: &mut Criterion) {
+    let apis: [(&str, Read); 2] = [("vec", read_vec), ("pooled", read_pooled)];
+
+    for &len in &SIZES {
+        for &(name, read) in &apis {
+            println!(
+                "bulk_read/{}/{}: {:.1} allocations per command",
+                name,
+                len,
+                allocations(read, len)
+            );
+        }
+    }
+
+    let mut group = c.benchmark_group("bulk_read");
+    for &len in &SIZES {
+        group.throughput(Throughput::Bytes(len as u64));
+        for &(name, read) in &apis {
+            let device = Device::with_transport(StreamingCamera::new());
+            group.bench_with_input(BenchmarkId::new(name, len), &len, |b, &len| {
+                b.iter(|| read(&device, len))
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bulk_read);
+criterion_main!(benches);
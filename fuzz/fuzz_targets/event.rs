@@ -0,0 +1,12 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 2 {
+        return;
+    }
+
+    let code = u16::from_le_bytes([data[0], data[1]]);
+    let _ = ptp::Event::new(code, &data[2..]);
+});
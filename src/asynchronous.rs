@@ -0,0 +1,527 @@
+use std::collections::VecDeque;
+use std::mem;
+use std::pin::Pin;
+use std::sync::{mpsc, Arc};
+use std::task::{Context, Poll};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use futures_core::Stream;
+use log::warn;
+use tokio::sync::{mpsc as channel, oneshot};
+
+use crate::{
+    CallOptions, CommandCode, Data, Device, DeviceInfo, Error, Event, ObjectFilesystemInfo,
+    ObjectFormatCode, ObjectHandle, ObjectInfo, Session, SessionConfig, StandardResponseCode,
+    StorageId, StorageInfo, StreamInfo, Transport, UsbTransport, VendorExtensionMap,
+};
+
+// How often the event thread checks whether its waiters have gone away
+const EVENT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+const EVENT_BUFFER: usize = 64;
+
+type Job<T> = Box<dyn FnOnce(&mut Worker<'_, T>) + Send>;
+
+// State of the worker thread, which owns the open session
+struct Worker<'d, T: Transport> {
+    device: &'d Device<T>,
+    session: Option<Session<'d, T>>,
+}
+
+impl<'d, T: Transport> Worker<'d, T> {
+    fn session(&self) -> Result<&Session<'d, T>, Error> {
+        self.session
+            .as_ref()
+            .ok_or(Error::Response(StandardResponseCode::SessionNotOpen.into()))
+    }
+}
+
+fn work<T: Transport>(device: Arc<Device<T>>, jobs: mpsc::Receiver<Job<T>>) {
+    let mut worker = Worker {
+        device: &device,
+        session: None,
+    };
+    for job in jobs {
+        job(&mut worker);
+    }
+}
+
+// Runs `f` on the worker thread. A job that has started runs to completion
+// even if the returned future is dropped, so that the device never sees half
+// a transaction; a job whose future was dropped before it started is skipped.
+async fn run<T, R, F>(jobs: &mpsc::Sender<Job<T>>, f: F) -> Result<R, Error>
+where
+    T: Transport,
+    R: Send + 'static,
+    F: FnOnce(&mut Worker<'_, T>) -> Result<R, Error> + Send + 'static,
+{
+    let (tx, rx) = oneshot::channel();
+    let job: Job<T> = Box::new(move |worker| {
+        if !tx.is_closed() {
+            let _ = tx.send(f(worker));
+        }
+    });
+    jobs.send(job).map_err(|_| Error::WorkerStopped)?;
+    rx.await.map_err(|_| Error::WorkerStopped)?
+}
+
+// A caller waiting on the event thread
+enum EventWaiter {
+    // an `AsyncDevice::event` call, answered once
+    Call {
+        reply: oneshot::Sender<Result<Option<Event>, Error>>,
+        deadline: Option<Instant>,
+    },
+    Stream(channel::Sender<Result<Event, Error>>),
+}
+
+// Reads events for the waiters sent to it, and only while there are any.
+// Each event goes to one waiter: the call that has waited longest, or else
+// the streams in turn. The thread exits once the `AsyncDevice` and all of
+// its streams are dropped.
+fn read_events<T: Transport>(device: Arc<Device<T>>, requests: mpsc::Receiver<EventWaiter>) {
+    let mut calls = VecDeque::new();
+    let mut streams = VecDeque::new();
+    loop {
+        let first = if calls.is_empty() && streams.is_empty() {
+            match requests.recv() {
+                Ok(waiter) => Some(waiter),
+                Err(_) => return,
+            }
+        } else {
+            None
+        };
+        for waiter in first.into_iter().chain(requests.try_iter()) {
+            match waiter {
+                EventWaiter::Call { reply, deadline } => calls.push_back((reply, deadline)),
+                EventWaiter::Stream(tx) => streams.push_back(tx),
+            }
+        }
+
+        // answer the calls that timed out and forget the waiters that went
+        // away, which a dropped future or stream is noticed as
+        let now = Instant::now();
+        let mut timeout = EVENT_POLL_INTERVAL;
+        for (reply, deadline) in mem::take(&mut calls) {
+            match deadline {
+                _ if reply.is_closed() => {}
+                Some(deadline) if deadline <= now => {
+                    let _ = reply.send(Ok(None));
+                }
+                _ => {
+                    if let Some(deadline) = deadline {
+                        timeout = timeout.min(deadline - now);
+                    }
+                    calls.push_back((reply, deadline));
+                }
+            }
+        }
+        streams.retain(|tx| !tx.is_closed());
+        if calls.is_empty() && streams.is_empty() {
+            continue;
+        }
+
+        // a zero timeout would wait forever
+        let timeout = timeout.max(Duration::from_millis(1));
+        let mut pending = match device.event(Some(timeout)) {
+            Ok(None) => None,
+            Ok(Some(event)) => Some(Ok(event)),
+            Err(e) => Some(Err(e)),
+        };
+        while let Some(result) = pending.take() {
+            if let Some((reply, _)) = calls.pop_front() {
+                // a caller that went away just now passes it on
+                if let Err(result) = reply.send(result.map(Some)) {
+                    pending = result.transpose();
+                }
+            } else if let Some(tx) = streams.pop_front() {
+                let fatal = matches!(result, Err(Error::Usb(_)));
+                match tx.blocking_send(result) {
+                    Ok(()) if !fatal => streams.push_back(tx),
+                    Ok(()) => {}
+                    Err(channel::error::SendError(result)) => pending = Some(result),
+                }
+            }
+        }
+    }
+}
+
+/// An asynchronous interface to a `Device`.
+///
+/// Transactions are performed one at a time on a dedicated worker thread, so
+/// they never block the executor. Dropping a future cancels its transaction
+/// only if it has not started yet; otherwise the transaction completes and
+/// its result is discarded, which keeps transaction IDs in step with the
+/// device.
+///
+/// The worker runs transactions in the order they were submitted, whatever
+/// their `CallOptions::priority`; a priority only orders them against
+/// transactions made through `device()` from other threads.
+pub struct AsyncDevice<T: Transport + 'static = UsbTransport<rusb::Context>> {
+    device: Arc<Device<T>>,
+    jobs: mpsc::Sender<Job<T>>,
+    events: mpsc::Sender<EventWaiter>,
+}
+
+impl<T: Transport + 'static> AsyncDevice<T> {
+    /// Starts the worker and event threads for `device`. The worker exits
+    /// once the `AsyncDevice` and all of its sessions are dropped, the event
+    /// thread once the `AsyncDevice` and all of its event streams are.
+    pub fn new(device: Device<T>) -> AsyncDevice<T> {
+        let device = Arc::new(device);
+        let (jobs, receiver) = mpsc::channel();
+        let (events, waiters) = mpsc::channel();
+
+        let worker_device = device.clone();
+        thread::Builder::new()
+            .name("ptp-worker".into())
+            .spawn(move || work(worker_device, receiver))
+            .expect("failed to spawn the device worker thread");
+
+        let event_device = device.clone();
+        thread::Builder::new()
+            .name("ptp-events".into())
+            .spawn(move || read_events(event_device, waiters))
+            .expect("failed to spawn the device event thread");
+
+        AsyncDevice {
+            device,
+            jobs,
+            events,
+        }
+    }
+
+    /// Returns the underlying device, for its non-blocking accessors such as
+    /// `device_info` and `supports`.
+    pub fn device(&self) -> &Device<T> {
+        &self.device
+    }
+
+    /// See `Device::command`.
+    pub async fn command(
+        &self,
+        code: CommandCode,
+        params: &[u32],
+        data: Option<&[u8]>,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<u8>, Error> {
+        self.command_with(code, params, data, timeout, &CallOptions::default())
+            .await
+    }
+
+    /// See `Device::transaction`.
+    pub async fn transaction(
+        &self,
+        code: CommandCode,
+        params: &[u32],
+        data: Option<&[u8]>,
+        timeout: Option<Duration>,
+    ) -> Result<(Vec<u8>, Vec<u32>), Error> {
+        self.transaction_with(code, params, data, timeout, &CallOptions::default())
+            .await
+    }
+
+    /// See `Device::command_with`.
+    pub async fn command_with(
+        &self,
+        code: CommandCode,
+        params: &[u32],
+        data: Option<&[u8]>,
+        timeout: Option<Duration>,
+        options: &CallOptions,
+    ) -> Result<Vec<u8>, Error> {
+        self.transaction_with(code, params, data, timeout, options)
+            .await
+            .map(|(data, _)| data)
+    }
+
+    /// See `Device::transaction_with`.
+    pub async fn transaction_with(
+        &self,
+        code: CommandCode,
+        params: &[u32],
+        data: Option<&[u8]>,
+        timeout: Option<Duration>,
+        options: &CallOptions,
+    ) -> Result<(Vec<u8>, Vec<u32>), Error> {
+        let params = params.to_vec();
+        let data = data.map(<[u8]>::to_vec);
+        let options = options.clone();
+        run(&self.jobs, move |worker| {
+            worker
+                .device
+                .transaction_with(code, &params, data.as_deref(), timeout, &options)
+        })
+        .await
+    }
+
+    /// See `Device::get_device_info`.
+    pub async fn get_device_info(&self, timeout: Option<Duration>) -> Result<DeviceInfo, Error> {
+        run(&self.jobs, move |worker| {
+            worker.device.get_device_info(timeout)
+        })
+        .await
+    }
+
+    /// See `Device::event`. Events are read on a thread of their own, so
+    /// waiting for one does not hold up transactions. That thread is shared
+    /// with the other `event` calls and the `events` streams, and each event
+    /// goes to only one of them, the longest waiting call first.
+    pub async fn event(&self, timeout: Option<Duration>) -> Result<Option<Event>, Error> {
+        let (reply, rx) = oneshot::channel();
+        // no deadline waits forever, as a zero timeout does
+        let deadline = timeout
+            .filter(|timeout| !timeout.is_zero())
+            .and_then(|timeout| Instant::now().checked_add(timeout));
+        self.events
+            .send(EventWaiter::Call { reply, deadline })
+            .map_err(|_| Error::WorkerStopped)?;
+        rx.await.map_err(|_| Error::WorkerStopped)?
+    }
+
+    /// Returns a stream of the events the device sends, read on the same
+    /// thread as `event`. The stream ends after a USB error. A stream that
+    /// is not read holds up the other event waiters once its buffer is full.
+    pub fn events(&self) -> EventStream {
+        let (tx, rx) = channel::channel(EVENT_BUFFER);
+        // if the event thread has stopped, the stream ends straight away
+        let _ = self.events.send(EventWaiter::Stream(tx));
+        EventStream { events: rx }
+    }
+
+    /// See `Device::open_session`.
+    pub async fn open_session(&self, config: SessionConfig) -> Result<AsyncSession<T>, Error> {
+        let jobs = self.jobs.clone();
+        run(&self.jobs, move |worker| {
+            let device = worker.device;
+            worker.session = Some(device.open_session(config)?);
+            // if the caller has gone away, dropping this closes the session
+            Ok(AsyncSession {
+                jobs,
+                closed: false,
+            })
+        })
+        .await
+    }
+
+    /// Closes the open session, if any, and releases the device.
+    pub async fn disconnect(self) -> Result<(), Error> {
+        run(&self.jobs, |worker| {
+            if let Some(session) = worker.session.take() {
+                if let Err(e) = session.close() {
+                    warn!("closing the session failed: {}", e);
+                }
+            }
+            worker.device.transport().release()
+        })
+        .await
+    }
+}
+
+/// The events of an `AsyncDevice`, see `AsyncDevice::events`.
+pub struct EventStream {
+    events: channel::Receiver<Result<Event, Error>>,
+}
+
+impl Stream for EventStream {
+    type Item = Result<Event, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.events.poll_recv(cx)
+    }
+}
+
+macro_rules! session_methods {
+    ($(fn $name:ident(&self $(, $arg:ident: $ty:ty)*) -> $ret:ty;)*) => {
+        $(
+            #[doc = concat!("See `Session::", stringify!($name), "`.")]
+            pub async fn $name(&self $(, $arg: $ty)*) -> Result<$ret, Error> {
+                self.run(move |session| session.$name($($arg),*)).await
+            }
+        )*
+    };
+}
+
+/// An open session of an `AsyncDevice`, returned by
+/// `AsyncDevice::open_session`. The session is closed when this is dropped;
+/// use `close` to find out whether closing succeeded.
+pub struct AsyncSession<T: Transport + 'static> {
+    jobs: mpsc::Sender<Job<T>>,
+    closed: bool,
+}
+
+impl<T: Transport + 'static> AsyncSession<T> {
+    async fn run<R, F>(&self, f: F) -> Result<R, Error>
+    where
+        R: Send + 'static,
+        F: FnOnce(&Session<'_, T>) -> Result<R, Error> + Send + 'static,
+    {
+        run(&self.jobs, move |worker| f(worker.session()?)).await
+    }
+
+    /// Closes the session. Once the returned future has been polled, the
+    /// session is closed even if the future is dropped.
+    pub async fn close(mut self) -> Result<(), Error> {
+        // unlike the jobs of `run`, this one runs whether or not anyone is
+        // still waiting for it, since `Drop` no longer closes the session
+        let (tx, rx) = oneshot::channel();
+        let job: Job<T> = Box::new(move |worker| {
+            let result = match worker.session.take() {
+                Some(session) => session.close(),
+                None => Ok(()),
+            };
+            let _ = tx.send(result);
+        });
+        self.jobs.send(job).map_err(|_| Error::WorkerStopped)?;
+        self.closed = true;
+        rx.await.map_err(|_| Error::WorkerStopped)?
+    }
+
+    /// See `Session::command`.
+    pub async fn command(
+        &self,
+        code: CommandCode,
+        params: &[u32],
+        data: Option<&[u8]>,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<u8>, Error> {
+        self.command_with(code, params, data, timeout, &CallOptions::default())
+            .await
+    }
+
+    /// See `Session::transaction`.
+    pub async fn transaction(
+        &self,
+        code: CommandCode,
+        params: &[u32],
+        data: Option<&[u8]>,
+        timeout: Option<Duration>,
+    ) -> Result<(Vec<u8>, Vec<u32>), Error> {
+        self.transaction_with(code, params, data, timeout, &CallOptions::default())
+            .await
+    }
+
+    /// See `Session::command_with`.
+    pub async fn command_with(
+        &self,
+        code: CommandCode,
+        params: &[u32],
+        data: Option<&[u8]>,
+        timeout: Option<Duration>,
+        options: &CallOptions,
+    ) -> Result<Vec<u8>, Error> {
+        self.transaction_with(code, params, data, timeout, options)
+            .await
+            .map(|(data, _)| data)
+    }
+
+    /// See `Session::transaction_with`.
+    pub async fn transaction_with(
+        &self,
+        code: CommandCode,
+        params: &[u32],
+        data: Option<&[u8]>,
+        timeout: Option<Duration>,
+        options: &CallOptions,
+    ) -> Result<(Vec<u8>, Vec<u32>), Error> {
+        let params = params.to_vec();
+        let data = data.map(<[u8]>::to_vec);
+        let options = options.clone();
+        self.run(move |session| {
+            session.transaction_with(code, &params, data.as_deref(), timeout, &options)
+        })
+        .await
+    }
+
+    /// See `Session::send_object`.
+    pub async fn send_object(&self, data: &[u8], timeout: Option<Duration>) -> Result<(), Error> {
+        let data = data.to_vec();
+        self.run(move |session| session.send_object(&data, timeout))
+            .await
+    }
+
+    session_methods! {
+        fn get_object_info(&self, handle: ObjectHandle, timeout: Option<Duration>) -> ObjectInfo;
+        fn send_object_info(
+            &self,
+            storage_id: StorageId,
+            parent: ObjectHandle,
+            info: ObjectInfo,
+            timeout: Option<Duration>
+        ) -> ObjectHandle;
+        fn set_device_prop_value(&self, code: u16, value: Data, timeout: Option<Duration>) -> ();
+        fn get_object(&self, handle: ObjectHandle, timeout: Option<Duration>) -> Vec<u8>;
+        fn get_partial_object(
+            &self,
+            handle: ObjectHandle,
+            offset: u32,
+            len: u32,
+            timeout: Option<Duration>
+        ) -> Vec<u8>;
+        fn get_object_handles(
+            &self,
+            storage_id: StorageId,
+            format: Option<ObjectFormatCode>,
+            parent: Option<ObjectHandle>,
+            timeout: Option<Duration>
+        ) -> Vec<ObjectHandle>;
+        fn get_num_objects(
+            &self,
+            storage_id: Option<StorageId>,
+            format: Option<ObjectFormatCode>,
+            parent: Option<ObjectHandle>,
+            timeout: Option<Duration>
+        ) -> u32;
+        fn get_storage_info(&self, storage_id: StorageId, timeout: Option<Duration>) -> StorageInfo;
+        fn get_storage_ids(&self, timeout: Option<Duration>) -> Vec<StorageId>;
+        fn list_storage(
+            &self,
+            storage_id: StorageId,
+            timeout: Option<Duration>
+        ) -> Vec<(ObjectHandle, ObjectInfo)>;
+        fn start_enum_handles(
+            &self,
+            storage_id: StorageId,
+            format: Option<ObjectFormatCode>,
+            parent: Option<ObjectHandle>,
+            timeout: Option<Duration>
+        ) -> ();
+        fn enum_handles(&self, max: u32, timeout: Option<Duration>) -> Vec<ObjectHandle>;
+        fn stop_enum_handles(&self, timeout: Option<Duration>) -> ();
+        fn get_vendor_extension_maps(&self, timeout: Option<Duration>) -> Vec<VendorExtensionMap>;
+        fn get_vendor_device_info(&self, vendor_ex_id: u32, timeout: Option<Duration>) -> DeviceInfo;
+        fn get_resized_image_object(
+            &self,
+            handle: ObjectHandle,
+            width: u32,
+            height: u32,
+            timeout: Option<Duration>
+        ) -> Vec<u8>;
+        fn get_filesystem_manifest(
+            &self,
+            storage_id: StorageId,
+            format: Option<ObjectFormatCode>,
+            parent: Option<ObjectHandle>,
+            timeout: Option<Duration>
+        ) -> Vec<ObjectFilesystemInfo>;
+        fn get_stream_info(&self, stream_type: u32, timeout: Option<Duration>) -> StreamInfo;
+        fn get_stream(&self, stream_type: u32, timeout: Option<Duration>) -> Vec<u8>;
+    }
+}
+
+impl<T: Transport + 'static> Drop for AsyncSession<T> {
+    fn drop(&mut self) {
+        if self.closed {
+            return;
+        }
+        // the session is closed on the worker thread, after any transactions
+        // that are still queued
+        let _ = self.jobs.send(Box::new(|worker| {
+            if let Some(session) = worker.session.take() {
+                if let Err(e) = session.close() {
+                    warn!("closing the session failed: {}", e);
+                }
+            }
+        }));
+    }
+}
@@ -0,0 +1,240 @@
+use std::fmt;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Buffers kept by a default pool
+pub const DEFAULT_POOL_BUFFERS: usize = 4;
+
+/// Largest buffer a default pool keeps; larger ones, e.g. for whole
+/// downloads, are freed when dropped
+pub const DEFAULT_POOL_RETAINED_SIZE: usize = 16 * 1024 * 1024;
+
+/// How a `BufferPool` has been used
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct PoolStats {
+    /// Buffers allocated or grown, because none of the free ones was large
+    /// enough
+    pub allocations: u64,
+
+    /// Buffers taken from the pool that were large enough as they were
+    pub reuses: u64,
+
+    /// Buffers free in the pool now
+    pub free: usize,
+}
+
+struct PoolInner {
+    free: Mutex<Vec<Vec<u8>>>,
+    max_buffers: usize,
+    max_retained_size: usize,
+    allocations: AtomicU64,
+    reuses: AtomicU64,
+}
+
+/// Buffers that bulk reads land in, reused from one container to the next.
+///
+/// Buffers are zeroed when they are allocated or grown and hold whatever
+/// was last read into them after that, so reusing them costs neither an
+/// allocation nor initialising memory. Clones share the same buffers.
+#[derive(Clone)]
+pub struct BufferPool {
+    inner: Arc<PoolInner>,
+}
+
+impl BufferPool {
+    /// A pool keeping up to `max_buffers` free buffers of at most
+    /// `max_retained_size` bytes each
+    pub fn new(max_buffers: usize, max_retained_size: usize) -> BufferPool {
+        BufferPool {
+            inner: Arc::new(PoolInner {
+                free: Mutex::new(Vec::with_capacity(max_buffers)),
+                max_buffers,
+                max_retained_size,
+                allocations: AtomicU64::new(0),
+                reuses: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    /// Takes the largest free buffer from the pool, growing it to at least
+    /// `len` bytes, or allocates one if there is none. The buffer goes back
+    /// to the pool when dropped.
+    pub fn take(&self, len: usize) -> PooledBuffer {
+        let buf = {
+            let mut free = self.inner.free.lock().unwrap_or_else(|e| e.into_inner());
+            // the largest, as a read only learns how much it needs from the
+            // header of what it reads
+            let largest = free
+                .iter()
+                .enumerate()
+                .max_by_key(|(_, b)| b.len())
+                .map(|(i, _)| i);
+            largest.map(|i| free.swap_remove(i))
+        };
+
+        let mut buf = PooledBuffer {
+            buf: buf.unwrap_or_default(),
+            start: 0,
+            end: 0,
+            pool: Some(self.inner.clone()),
+        };
+        if buf.buf.len() >= len && buf.buf.capacity() > 0 {
+            self.inner.reuses.fetch_add(1, Ordering::Relaxed);
+        }
+        buf.reserve(len);
+        buf
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        PoolStats {
+            allocations: self.inner.allocations.load(Ordering::Relaxed),
+            reuses: self.inner.reuses.load(Ordering::Relaxed),
+            free: self
+                .inner
+                .free
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .len(),
+        }
+    }
+}
+
+impl Default for BufferPool {
+    fn default() -> BufferPool {
+        BufferPool::new(DEFAULT_POOL_BUFFERS, DEFAULT_POOL_RETAINED_SIZE)
+    }
+}
+
+impl fmt::Debug for BufferPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BufferPool")
+            .field("max_buffers", &self.inner.max_buffers)
+            .field("max_retained_size", &self.inner.max_retained_size)
+            .field("stats", &self.stats())
+            .finish()
+    }
+}
+
+/// A payload read into a buffer of a `BufferPool`, which it returns to the
+/// pool when dropped. It derefs to the payload's bytes; `into_vec` keeps
+/// them instead.
+pub struct PooledBuffer {
+    // initialised storage, of which start..end is the payload
+    buf: Vec<u8>,
+    start: usize,
+    end: usize,
+    pool: Option<Arc<PoolInner>>,
+}
+
+impl PooledBuffer {
+    /// An empty buffer that belongs to no pool
+    pub fn empty() -> PooledBuffer {
+        PooledBuffer::from(Vec::new())
+    }
+
+    /// Takes the payload out of the buffer. A payload small enough for the
+    /// pool to keep its buffer is copied, so that the buffer goes back to
+    /// the pool; the storage of a larger one is handed over instead.
+    pub fn into_vec(mut self) -> Vec<u8> {
+        if self.is_retained() {
+            return self.to_vec();
+        }
+        let mut buf = std::mem::take(&mut self.buf);
+        buf.copy_within(self.start..self.end, 0);
+        buf.truncate(self.end - self.start);
+        buf
+    }
+
+    // Whether the pool would keep the storage, if it has room
+    fn is_retained(&self) -> bool {
+        match &self.pool {
+            Some(pool) => !self.buf.is_empty() && self.buf.len() <= pool.max_retained_size,
+            None => false,
+        }
+    }
+
+    // All of the storage, for reading into
+    pub(crate) fn storage_mut(&mut self) -> &mut [u8] {
+        &mut self.buf
+    }
+
+    // Makes the storage at least `len` bytes, zeroing what is added
+    pub(crate) fn reserve(&mut self, len: usize) {
+        if self.buf.len() >= len {
+            return;
+        }
+        if self.buf.capacity() < len {
+            if let Some(pool) = &self.pool {
+                pool.allocations.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.buf.resize(len, 0);
+    }
+
+    // Sets which part of the storage is the payload
+    pub(crate) fn set_payload(&mut self, start: usize, end: usize) {
+        assert!(start <= end && end <= self.buf.len());
+        self.start = start;
+        self.end = end;
+    }
+}
+
+impl From<Vec<u8>> for PooledBuffer {
+    /// Wraps a payload that belongs to no pool
+    fn from(buf: Vec<u8>) -> PooledBuffer {
+        PooledBuffer {
+            start: 0,
+            end: buf.len(),
+            buf,
+            pool: None,
+        }
+    }
+}
+
+impl Default for PooledBuffer {
+    fn default() -> PooledBuffer {
+        PooledBuffer::empty()
+    }
+}
+
+impl Deref for PooledBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buf[self.start..self.end]
+    }
+}
+
+impl AsRef<[u8]> for PooledBuffer {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl fmt::Debug for PooledBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PooledBuffer")
+            .field("len", &self.len())
+            .field("pooled", &self.pool.is_some())
+            .finish()
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        let pool = match &self.pool {
+            Some(pool) if self.is_retained() => pool,
+            _ => return,
+        };
+
+        let mut free = pool.free.lock().unwrap_or_else(|e| e.into_inner());
+        if free.len() < pool.max_buffers {
+            free.push(std::mem::take(&mut self.buf));
+        }
+    }
+}
@@ -1,17 +1,86 @@
-use std::fmt::{self, LowerHex};
-use num_traits::{FromPrimitive, ToPrimitive};
 use num_derive::{FromPrimitive, ToPrimitive};
+use num_traits::{FromPrimitive, ToPrimitive};
+use std::fmt::{self, Display, LowerHex};
+use std::str::FromStr;
 
 #[cfg(feature = "serde")]
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+
+use crate::names::parse_code;
+use crate::{Error, VendorProfile};
 
 #[derive(Debug, Eq, PartialEq, Copy, Clone, Hash)]
-#[cfg_attr(feature = "serde", derive(Serialize))]
 pub enum CommandCode {
     Standard(StandardCommandCode),
     Other(u16),
 }
 
+impl CommandCode {
+    /// Returns the name of a standard operation code
+    pub fn name(&self) -> Option<&'static str> {
+        match self {
+            CommandCode::Standard(code) => Some(code.name()),
+            CommandCode::Other(_) => None,
+        }
+    }
+
+    /// Returns the name of this code, also looking up MTP and vendor-defined
+    /// operations in `vendor`, usually `Device::vendor()`.
+    pub fn name_for(&self, vendor: Option<&VendorProfile>) -> Option<&'static str> {
+        match self {
+            CommandCode::Standard(code) => Some(code.name()),
+            CommandCode::Other(code) => vendor?.operation_name(*code),
+        }
+    }
+
+    /// Returns a short description of this code
+    pub fn description(&self) -> &'static str {
+        match self {
+            CommandCode::Standard(code) => code.description(),
+            CommandCode::Other(_) => "vendor-defined operation",
+        }
+    }
+
+    /// Returns a short description of this code, naming the vendor of the
+    /// MTP and vendor-defined operations `vendor` knows.
+    pub fn description_for(&self, vendor: Option<&VendorProfile>) -> String {
+        match (self, vendor) {
+            (CommandCode::Other(code), Some(vendor)) if vendor.operation_name(*code).is_some() => {
+                format!("{} operation", vendor.name)
+            }
+            _ => self.description().to_owned(),
+        }
+    }
+
+    /// Parses a code given by name or as a number such as `"0x1009"`, also
+    /// accepting the names of MTP and vendor-defined operations in `vendor`.
+    pub fn parse_for(s: &str, vendor: Option<&VendorProfile>) -> Result<CommandCode, Error> {
+        let s = s.trim();
+        StandardCommandCode::from_name(s)
+            .map(CommandCode::Standard)
+            .or_else(|| vendor?.operation_code(s).map(CommandCode::Other))
+            .or_else(|| parse_code(s).and_then(CommandCode::from_u16))
+            .ok_or_else(|| Error::UnknownCode(s.to_owned()))
+    }
+}
+
+impl Display for CommandCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.name() {
+            Some(name) => f.write_str(name),
+            None => write!(f, "0x{:04X}", self.to_u16().unwrap()),
+        }
+    }
+}
+
+impl FromStr for CommandCode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        CommandCode::parse_for(s, None)
+    }
+}
+
 impl LowerHex for CommandCode {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -23,17 +92,17 @@ impl LowerHex for CommandCode {
 
 impl FromPrimitive for CommandCode {
     fn from_i64(n: i64) -> Option<Self> {
-        Some(StandardCommandCode::from_i64(n).map_or_else(
-            || CommandCode::Other(n as u16),
-            |code| CommandCode::Standard(code),
-        ))
+        Some(
+            StandardCommandCode::from_i64(n)
+                .map_or_else(|| CommandCode::Other(n as u16), CommandCode::Standard),
+        )
     }
 
     fn from_u64(n: u64) -> Option<Self> {
-        Some(StandardCommandCode::from_u64(n).map_or_else(
-            || CommandCode::Other(n as u16),
-            |code| CommandCode::Standard(code),
-        ))
+        Some(
+            StandardCommandCode::from_u64(n)
+                .map_or_else(|| CommandCode::Other(n as u16), CommandCode::Standard),
+        )
     }
 }
 
@@ -61,7 +130,7 @@ impl From<StandardCommandCode> for CommandCode {
 
 #[repr(u16)]
 #[derive(FromPrimitive, ToPrimitive, Debug, Copy, Clone, Eq, PartialEq, Hash)]
-#[cfg_attr(feature = "serde", derive(Serialize))]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum StandardCommandCode {
     Undefined = 0x1000,
     GetDeviceInfo = 0x1001,
@@ -92,6 +161,15 @@ pub enum StandardCommandCode {
     CopyObject = 0x101A,
     GetPartialObject = 0x101B,
     InitiateOpenCapture = 0x101C,
+    StartEnumHandles = 0x101D,
+    EnumHandles = 0x101E,
+    StopEnumHandles = 0x101F,
+    GetVendorExtensionMaps = 0x1020,
+    GetVendorDeviceInfo = 0x1021,
+    GetResizedImageObject = 0x1022,
+    GetFilesystemManifest = 0x1023,
+    GetStreamInfo = 0x1024,
+    GetStream = 0x1025,
 }
 
 impl LowerHex for StandardCommandCode {
@@ -100,3 +178,44 @@ impl LowerHex for StandardCommandCode {
         fmt::LowerHex::fmt(&val, f)
     }
 }
+
+code_names!(StandardCommandCode {
+    Undefined => "undefined operation",
+    GetDeviceInfo => "get the DeviceInfo dataset",
+    OpenSession => "open a session",
+    CloseSession => "close the session",
+    GetStorageIDs => "list the available storages",
+    GetStorageInfo => "get the StorageInfo dataset of a storage",
+    GetNumObjects => "count the objects on a storage",
+    GetObjectHandles => "list the objects on a storage",
+    GetObjectInfo => "get the ObjectInfo dataset of an object",
+    GetObject => "download an object",
+    GetThumb => "download the thumbnail of an object",
+    DeleteObject => "delete an object",
+    SendObjectInfo => "announce an object to be uploaded",
+    SendObject => "upload an object",
+    InitiateCapture => "capture a new object",
+    FormatStore => "format a storage",
+    ResetDevice => "reset the device",
+    SelfTest => "run a self test",
+    SetObjectProtection => "change the protection status of an object",
+    PowerDown => "power the device down",
+    GetDevicePropDesc => "describe a device property",
+    GetDevicePropValue => "get the value of a device property",
+    SetDevicePropValue => "set the value of a device property",
+    ResetDevicePropValue => "reset a device property to its default",
+    TerminateOpenCapture => "end an open capture",
+    MoveObject => "move an object",
+    CopyObject => "copy an object",
+    GetPartialObject => "download part of an object",
+    InitiateOpenCapture => "start an open-ended capture",
+    StartEnumHandles => "start enumerating object handles",
+    EnumHandles => "get the next batch of object handles",
+    StopEnumHandles => "stop enumerating object handles",
+    GetVendorExtensionMaps => "get the vendor extension code mappings",
+    GetVendorDeviceInfo => "get the DeviceInfo dataset of another vendor extension",
+    GetResizedImageObject => "download a resized image",
+    GetFilesystemManifest => "get the filesystem manifest of a storage",
+    GetStreamInfo => "describe a stream",
+    GetStream => "read a stream",
+});
@@ -1,13 +1,15 @@
 use std::{
+    convert::TryFrom,
     fmt::{LowerHex, UpperHex},
-    io::{Cursor, Write},
+    io::Cursor,
 };
 
 #[cfg(feature = "serde")]
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
-use num_traits::ToPrimitive;
+use num_derive::{FromPrimitive, ToPrimitive};
+use num_traits::{FromPrimitive, ToPrimitive};
 
 use crate::Error;
 
@@ -44,16 +46,18 @@ pub trait PtpRead: ReadBytesExt {
         Ok(self.read_i64::<LittleEndian>()?)
     }
 
+    /// Reads a 128-bit value as (high, low) 64-bit halves
     fn read_ptp_u128(&mut self) -> Result<(u64, u64), Error> {
-        let hi = self.read_u64::<LittleEndian>()?;
         let lo = self.read_u64::<LittleEndian>()?;
-        Ok((lo, hi))
+        let hi = self.read_u64::<LittleEndian>()?;
+        Ok((hi, lo))
     }
 
+    /// Reads a 128-bit value as (high, low) 64-bit halves
     fn read_ptp_i128(&mut self) -> Result<(u64, u64), Error> {
-        let hi = self.read_u64::<LittleEndian>()?;
         let lo = self.read_u64::<LittleEndian>()?;
-        Ok((lo, hi))
+        let hi = self.read_u64::<LittleEndian>()?;
+        Ok((hi, lo))
     }
 
     #[inline(always)]
@@ -139,6 +143,10 @@ impl<T: AsRef<[u8]>> PtpRead for Cursor<T> {
     }
 }
 
+/// Maximum length of a PTP string in UTF-16 code units, including the
+/// terminating NUL
+pub const PTP_MAX_STR_LEN: usize = 255;
+
 pub trait PtpWrite: WriteBytesExt {
     fn write_ptp_u8(&mut self, val: u8) -> Result<(), Error> {
         Ok(self.write_u8(val)?)
@@ -172,14 +180,16 @@ pub trait PtpWrite: WriteBytesExt {
         Ok(self.write_i64::<LittleEndian>(val)?)
     }
 
+    /// Writes a 128-bit value given as (high, low) 64-bit halves
     fn write_ptp_u128(&mut self, val: (u64, u64)) -> Result<(), Error> {
-        let (lo, hi) = val;
+        let (hi, lo) = val;
         self.write_u64::<LittleEndian>(lo)?;
         Ok(self.write_u64::<LittleEndian>(hi)?)
     }
 
+    /// Writes a 128-bit value given as (high, low) 64-bit halves
     fn write_ptp_i128(&mut self, val: (u64, u64)) -> Result<(), Error> {
-        let (lo, hi) = val;
+        let (hi, lo) = val;
         self.write_u64::<LittleEndian>(lo)?;
         Ok(self.write_u64::<LittleEndian>(hi)?)
     }
@@ -195,23 +205,35 @@ pub trait PtpWrite: WriteBytesExt {
         Ok(())
     }
 
+    /// Writes a PTP string: a u8 count of UTF-16 code units including the
+    /// terminating NUL, followed by the code units. The empty string is a
+    /// single zero byte.
     fn write_ptp_str(&mut self, val: &str) -> Result<(), Error> {
         let utf16: Vec<u16> = val.encode_utf16().collect();
-        self.write_u8((utf16.len() as u8) * 2 + 1)?;
-        if !utf16.is_empty() {
-            for c in utf16 {
-                self.write_u16::<LittleEndian>(c)?;
-            }
-            self.write_all(&[0, 0])?;
+        if utf16.is_empty() {
+            return Ok(self.write_u8(0)?);
         }
-        Ok(())
+
+        let len = utf16.len() + 1;
+        if len > PTP_MAX_STR_LEN {
+            return Err(Error::StringTooLong(len));
+        }
+
+        self.write_u8(len as u8)?;
+        for c in utf16 {
+            self.write_u16::<LittleEndian>(c)?;
+        }
+        Ok(self.write_u16::<LittleEndian>(0)?)
     }
 }
 
 impl<W: WriteBytesExt> PtpWrite for W {}
 
+/// A PTP value. With the `serde` feature it serializes tagged with its
+/// DataType, e.g. `{"type": "UINT16", "value": 32769}`.
 #[derive(Debug, Eq, PartialEq, PartialOrd, Clone)]
-#[cfg_attr(feature = "serde", derive(Serialize))]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type", content = "value"))]
 pub enum Data {
     UNDEF,
     INT8(i8),
@@ -238,147 +260,229 @@ pub enum Data {
 }
 
 impl Data {
-    pub fn encode(&self) -> Vec<u8> {
-        use self::Data::*;
+    pub fn encode(&self) -> Result<Vec<u8>, Error> {
         let mut out = vec![];
+        self.write(&mut out)?;
+        Ok(out)
+    }
+
+    pub fn write<W: PtpWrite>(&self, w: &mut W) -> Result<(), Error> {
+        use self::Data::*;
         match self {
-            // UNDEF => {},
-            &INT8(val) => {
-                out.write_i8(val).ok();
-            }
-            &UINT8(val) => {
-                out.write_u8(val).ok();
-            }
-            &INT16(val) => {
-                out.write_i16::<LittleEndian>(val).ok();
-            }
-            &UINT16(val) => {
-                out.write_u16::<LittleEndian>(val).ok();
-            }
-            &INT32(val) => {
-                out.write_i32::<LittleEndian>(val).ok();
-            }
-            &UINT32(val) => {
-                out.write_u32::<LittleEndian>(val).ok();
-            }
-            &INT64(val) => {
-                out.write_i64::<LittleEndian>(val).ok();
-            }
-            &UINT64(val) => {
-                out.write_u64::<LittleEndian>(val).ok();
-            }
-            &INT128((hi, lo)) => {
-                out.write_u64::<LittleEndian>(lo).ok();
-                out.write_u64::<LittleEndian>(hi).ok();
-            }
-            &UINT128((hi, lo)) => {
-                out.write_u64::<LittleEndian>(lo).ok();
-                out.write_u64::<LittleEndian>(hi).ok();
-            }
-            &AINT8(ref val) => {
-                out.write_u32::<LittleEndian>(val.len() as u32).ok();
-                for item in val {
-                    out.write_i8(*item).ok();
-                }
-            }
-            &AUINT8(ref val) => {
-                out.write_u32::<LittleEndian>(val.len() as u32).ok();
-                for item in val {
-                    out.write_u8(*item).ok();
-                }
-            }
-            &AINT16(ref val) => {
-                out.write_u32::<LittleEndian>(val.len() as u32).ok();
-                for item in val {
-                    out.write_i16::<LittleEndian>(*item).ok();
-                }
-            }
-            &AUINT16(ref val) => {
-                out.write_u32::<LittleEndian>(val.len() as u32).ok();
-                for item in val {
-                    out.write_u16::<LittleEndian>(*item).ok();
-                }
-            }
-            &AINT32(ref val) => {
-                out.write_u32::<LittleEndian>(val.len() as u32).ok();
-                for item in val {
-                    out.write_i32::<LittleEndian>(*item).ok();
-                }
-            }
-            &AUINT32(ref val) => {
-                out.write_u32::<LittleEndian>(val.len() as u32).ok();
-                for item in val {
-                    out.write_u32::<LittleEndian>(*item).ok();
-                }
-            }
-            &AINT64(ref val) => {
-                out.write_u32::<LittleEndian>(val.len() as u32).ok();
-                for item in val {
-                    out.write_i64::<LittleEndian>(*item).ok();
-                }
-            }
-            &AUINT64(ref val) => {
-                out.write_u32::<LittleEndian>(val.len() as u32).ok();
-                for item in val {
-                    out.write_u64::<LittleEndian>(*item).ok();
-                }
-            }
-            &AINT128(ref val) => {
-                out.write_u32::<LittleEndian>(val.len() as u32).ok();
-                for &(hi, lo) in val {
-                    out.write_u64::<LittleEndian>(lo).ok();
-                    out.write_u64::<LittleEndian>(hi).ok();
-                }
-            }
-            &AUINT128(ref val) => {
-                out.write_u32::<LittleEndian>(val.len() as u32).ok();
-                for &(hi, lo) in val {
-                    out.write_u64::<LittleEndian>(lo).ok();
-                    out.write_u64::<LittleEndian>(hi).ok();
-                }
-            }
-            &STR(ref val) => {
-                out.write_u8(((val.len() as u8) * 2) + 1).ok();
-                if val.len() > 0 {
-                    for e in val.encode_utf16() {
-                        out.write_u16::<LittleEndian>(e).ok();
-                    }
-                    out.write_all(b"\0\0").ok();
-                }
-            }
-            _ => {}
+            UNDEF => Ok(()),
+            &INT8(val) => w.write_ptp_i8(val),
+            &UINT8(val) => w.write_ptp_u8(val),
+            &INT16(val) => w.write_ptp_i16(val),
+            &UINT16(val) => w.write_ptp_u16(val),
+            &INT32(val) => w.write_ptp_i32(val),
+            &UINT32(val) => w.write_ptp_u32(val),
+            &INT64(val) => w.write_ptp_i64(val),
+            &UINT64(val) => w.write_ptp_u64(val),
+            &INT128(val) => w.write_ptp_i128(val),
+            &UINT128(val) => w.write_ptp_u128(val),
+            AINT8(val) => w.write_ptp_vec(val, |w, &v| w.write_ptp_i8(v)),
+            AUINT8(val) => w.write_ptp_vec(val, |w, &v| w.write_ptp_u8(v)),
+            AINT16(val) => w.write_ptp_vec(val, |w, &v| w.write_ptp_i16(v)),
+            AUINT16(val) => w.write_ptp_vec(val, |w, &v| w.write_ptp_u16(v)),
+            AINT32(val) => w.write_ptp_vec(val, |w, &v| w.write_ptp_i32(v)),
+            AUINT32(val) => w.write_ptp_vec(val, |w, &v| w.write_ptp_u32(v)),
+            AINT64(val) => w.write_ptp_vec(val, |w, &v| w.write_ptp_i64(v)),
+            AUINT64(val) => w.write_ptp_vec(val, |w, &v| w.write_ptp_u64(v)),
+            AINT128(val) => w.write_ptp_vec(val, |w, &v| w.write_ptp_i128(v)),
+            AUINT128(val) => w.write_ptp_vec(val, |w, &v| w.write_ptp_u128(v)),
+            STR(val) => w.write_ptp_str(val),
         }
-        out
     }
 
-    pub fn read_type<T: PtpRead>(kind: u16, reader: &mut T) -> Result<Data, Error> {
+    /// Reads a value of type `kind`. UNDEF is an error, as reading it as
+    /// nothing would leave the reader in the middle of the actual value.
+    pub fn read_type<T: PtpRead>(kind: DataType, reader: &mut T) -> Result<Data, Error> {
         use self::Data::*;
         Ok(match kind {
-            // 0x0000 => UNDEF,
-            0x0001 => INT8(reader.read_ptp_i8()?),
-            0x0002 => UINT8(reader.read_ptp_u8()?),
-            0x0003 => INT16(reader.read_ptp_i16()?),
-            0x0004 => UINT16(reader.read_ptp_u16()?),
-            0x0005 => INT32(reader.read_ptp_i32()?),
-            0x0006 => UINT32(reader.read_ptp_u32()?),
-            0x0007 => INT64(reader.read_ptp_i64()?),
-            0x0008 => UINT64(reader.read_ptp_u64()?),
-            0x0009 => INT128(reader.read_ptp_i128()?),
-            0x000A => UINT128(reader.read_ptp_u128()?),
-            0x4001 => AINT8(reader.read_ptp_i8_vec()?),
-            0x4002 => AUINT8(reader.read_ptp_u8_vec()?),
-            0x4003 => AINT16(reader.read_ptp_i16_vec()?),
-            0x4004 => AUINT16(reader.read_ptp_u16_vec()?),
-            0x4005 => AINT32(reader.read_ptp_i32_vec()?),
-            0x4006 => AUINT32(reader.read_ptp_u32_vec()?),
-            0x4007 => AINT64(reader.read_ptp_i64_vec()?),
-            0x4008 => AUINT64(reader.read_ptp_u64_vec()?),
-            0x4009 => AINT128(reader.read_ptp_i128_vec()?),
-            0x400A => AUINT128(reader.read_ptp_u128_vec()?),
-            0xFFFF => STR(reader.read_ptp_str()?),
-            _ => UNDEF,
+            DataType::UNDEF => return Err(Error::BadDataType(DataType::UNDEF as u16)),
+            DataType::INT8 => INT8(reader.read_ptp_i8()?),
+            DataType::UINT8 => UINT8(reader.read_ptp_u8()?),
+            DataType::INT16 => INT16(reader.read_ptp_i16()?),
+            DataType::UINT16 => UINT16(reader.read_ptp_u16()?),
+            DataType::INT32 => INT32(reader.read_ptp_i32()?),
+            DataType::UINT32 => UINT32(reader.read_ptp_u32()?),
+            DataType::INT64 => INT64(reader.read_ptp_i64()?),
+            DataType::UINT64 => UINT64(reader.read_ptp_u64()?),
+            DataType::INT128 => INT128(reader.read_ptp_i128()?),
+            DataType::UINT128 => UINT128(reader.read_ptp_u128()?),
+            DataType::AINT8 => AINT8(reader.read_ptp_i8_vec()?),
+            DataType::AUINT8 => AUINT8(reader.read_ptp_u8_vec()?),
+            DataType::AINT16 => AINT16(reader.read_ptp_i16_vec()?),
+            DataType::AUINT16 => AUINT16(reader.read_ptp_u16_vec()?),
+            DataType::AINT32 => AINT32(reader.read_ptp_i32_vec()?),
+            DataType::AUINT32 => AUINT32(reader.read_ptp_u32_vec()?),
+            DataType::AINT64 => AINT64(reader.read_ptp_i64_vec()?),
+            DataType::AUINT64 => AUINT64(reader.read_ptp_u64_vec()?),
+            DataType::AINT128 => AINT128(reader.read_ptp_i128_vec()?),
+            DataType::AUINT128 => AUINT128(reader.read_ptp_u128_vec()?),
+            DataType::STR => STR(reader.read_ptp_str()?),
         })
     }
+
+    pub fn data_type(&self) -> DataType {
+        match self {
+            Data::UNDEF => DataType::UNDEF,
+            Data::INT8(_) => DataType::INT8,
+            Data::UINT8(_) => DataType::UINT8,
+            Data::INT16(_) => DataType::INT16,
+            Data::UINT16(_) => DataType::UINT16,
+            Data::INT32(_) => DataType::INT32,
+            Data::UINT32(_) => DataType::UINT32,
+            Data::INT64(_) => DataType::INT64,
+            Data::UINT64(_) => DataType::UINT64,
+            Data::INT128(_) => DataType::INT128,
+            Data::UINT128(_) => DataType::UINT128,
+            Data::AINT8(_) => DataType::AINT8,
+            Data::AUINT8(_) => DataType::AUINT8,
+            Data::AINT16(_) => DataType::AINT16,
+            Data::AUINT16(_) => DataType::AUINT16,
+            Data::AINT32(_) => DataType::AINT32,
+            Data::AUINT32(_) => DataType::AUINT32,
+            Data::AINT64(_) => DataType::AINT64,
+            Data::AUINT64(_) => DataType::AUINT64,
+            Data::AINT128(_) => DataType::AINT128,
+            Data::AUINT128(_) => DataType::AUINT128,
+            Data::STR(_) => DataType::STR,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Data::STR(v) => Some(v),
+            _ => None,
+        }
+    }
+}
+
+/// The PTP DataType codes, used to describe property values.
+#[repr(u16)]
+#[derive(FromPrimitive, ToPrimitive, Debug, Copy, Clone, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum DataType {
+    UNDEF = 0x0000,
+    INT8 = 0x0001,
+    UINT8 = 0x0002,
+    INT16 = 0x0003,
+    UINT16 = 0x0004,
+    INT32 = 0x0005,
+    UINT32 = 0x0006,
+    INT64 = 0x0007,
+    UINT64 = 0x0008,
+    INT128 = 0x0009,
+    UINT128 = 0x000A,
+    AINT8 = 0x4001,
+    AUINT8 = 0x4002,
+    AINT16 = 0x4003,
+    AUINT16 = 0x4004,
+    AINT32 = 0x4005,
+    AUINT32 = 0x4006,
+    AINT64 = 0x4007,
+    AUINT64 = 0x4008,
+    AINT128 = 0x4009,
+    AUINT128 = 0x400A,
+    STR = 0xFFFF,
+}
+
+impl DataType {
+    const ARRAY_FLAG: u16 = 0x4000;
+
+    pub fn is_array(&self) -> bool {
+        *self != DataType::STR && (*self as u16) & Self::ARRAY_FLAG != 0
+    }
+
+    /// Returns the type of the elements of an array type.
+    pub fn element_type(&self) -> Option<DataType> {
+        if self.is_array() {
+            DataType::from_u16(*self as u16 & !Self::ARRAY_FLAG)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the encoded size in bytes of a value of this type, or of each
+    /// element for array types. Strings have no fixed size.
+    pub fn size(&self) -> Option<usize> {
+        match self.element_type().unwrap_or(*self) {
+            DataType::UNDEF => Some(0),
+            DataType::INT8 | DataType::UINT8 => Some(1),
+            DataType::INT16 | DataType::UINT16 => Some(2),
+            DataType::INT32 | DataType::UINT32 => Some(4),
+            DataType::INT64 | DataType::UINT64 => Some(8),
+            DataType::INT128 | DataType::UINT128 => Some(16),
+            _ => None,
+        }
+    }
+
+    /// Reads a DataType code, failing on codes the specification doesn't
+    /// define, and on UNDEF, since the size of their values is unknown.
+    pub fn read<T: PtpRead>(reader: &mut T) -> Result<DataType, Error> {
+        let code = reader.read_ptp_u16()?;
+        match DataType::from_u16(code) {
+            Some(DataType::UNDEF) | None => Err(Error::BadDataType(code)),
+            Some(kind) => Ok(kind),
+        }
+    }
+}
+
+macro_rules! data_accessors {
+    ($($variant:ident($ty:ty) => $as_fn:ident),* $(,)?) => {
+        impl Data {
+            $(
+                pub fn $as_fn(&self) -> Option<$ty> {
+                    match self {
+                        Data::$variant(v) => Some(*v),
+                        _ => None,
+                    }
+                }
+            )*
+        }
+
+        $(
+            impl TryFrom<Data> for $ty {
+                type Error = Error;
+
+                fn try_from(value: Data) -> Result<Self, Error> {
+                    match value {
+                        Data::$variant(v) => Ok(v),
+                        other => Err(Error::UnexpectedDataType {
+                            expected: DataType::$variant,
+                            actual: other.data_type(),
+                        }),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+data_accessors! {
+    INT8(i8) => as_i8,
+    UINT8(u8) => as_u8,
+    INT16(i16) => as_i16,
+    UINT16(u16) => as_u16,
+    INT32(i32) => as_i32,
+    UINT32(u32) => as_u32,
+    INT64(i64) => as_i64,
+    UINT64(u64) => as_u64,
+}
+
+impl TryFrom<Data> for String {
+    type Error = Error;
+
+    fn try_from(value: Data) -> Result<Self, Error> {
+        match value {
+            Data::STR(v) => Ok(v),
+            other => Err(Error::UnexpectedDataType {
+                expected: DataType::STR,
+                actual: other.data_type(),
+            }),
+        }
+    }
 }
 
 impl ToPrimitive for Data {
@@ -390,7 +494,7 @@ impl ToPrimitive for Data {
             Data::UINT16(v) => Some(*v as i64),
             Data::INT32(v) => Some(*v as i64),
             Data::UINT32(v) => Some(*v as i64),
-            Data::INT64(v) => Some(*v as i64),
+            Data::INT64(v) => Some(*v),
             _ => None,
         }
     }
@@ -400,7 +504,7 @@ impl ToPrimitive for Data {
             Data::UINT8(v) => Some(*v as u64),
             Data::UINT16(v) => Some(*v as u64),
             Data::UINT32(v) => Some(*v as u64),
-            Data::UINT64(v) => Some(*v as u64),
+            Data::UINT64(v) => Some(*v),
             _ => None,
         }
     }
@@ -592,49 +696,49 @@ impl UpperHex for Data {
     }
 }
 
-impl<'a> From<i8> for Data {
+impl From<i8> for Data {
     fn from(value: i8) -> Self {
         Data::INT8(value)
     }
 }
 
-impl<'a> From<u8> for Data {
+impl From<u8> for Data {
     fn from(value: u8) -> Self {
         Data::UINT8(value)
     }
 }
 
-impl<'a> From<i16> for Data {
+impl From<i16> for Data {
     fn from(value: i16) -> Self {
         Data::INT16(value)
     }
 }
 
-impl<'a> From<u16> for Data {
+impl From<u16> for Data {
     fn from(value: u16) -> Self {
         Data::UINT16(value)
     }
 }
 
-impl<'a> From<i32> for Data {
+impl From<i32> for Data {
     fn from(value: i32) -> Self {
         Data::INT32(value)
     }
 }
 
-impl<'a> From<u32> for Data {
+impl From<u32> for Data {
     fn from(value: u32) -> Self {
         Data::UINT32(value)
     }
 }
 
-impl<'a> From<i64> for Data {
+impl From<i64> for Data {
     fn from(value: i64) -> Self {
         Data::INT64(value)
     }
 }
 
-impl<'a> From<u64> for Data {
+impl From<u64> for Data {
     fn from(value: u64) -> Self {
         Data::UINT64(value)
     }
@@ -646,7 +750,7 @@ impl<'a> From<&'a str> for Data {
     }
 }
 
-impl<'a> From<String> for Data {
+impl From<String> for Data {
     fn from(value: String) -> Self {
         Data::STR(value)
     }
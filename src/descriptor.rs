@@ -0,0 +1,385 @@
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashMap;
+
+use crate::Error;
+
+pub(crate) const DESCRIPTOR_DEVICE: u8 = 1;
+pub(crate) const DESCRIPTOR_CONFIG: u8 = 2;
+pub(crate) const DESCRIPTOR_STRING: u8 = 3;
+const DESCRIPTOR_INTERFACE: u8 = 4;
+const DESCRIPTOR_ENDPOINT: u8 = 5;
+
+pub(crate) const DEVICE_DESCRIPTOR_SIZE: usize = 18;
+pub(crate) const CONFIG_DESCRIPTOR_SIZE: usize = 9;
+
+/// USB interface class of PTP devices
+pub const STILL_IMAGE_CLASS: u8 = 6;
+
+/// USB interface class of vendor specific interfaces, used by many MTP devices
+pub const VENDOR_SPECIFIC_CLASS: u8 = 0xFF;
+
+/// Index of the string descriptor announcing Microsoft OS descriptors
+pub const MS_OS_STRING_INDEX: u8 = 0xEE;
+
+/// wIndex of the request for the extended compatible ID OS descriptor
+pub(crate) const MS_OS_COMPATIBLE_ID_INDEX: u16 = 0x0004;
+
+fn malformed(what: &str, detail: impl std::fmt::Display) -> Error {
+    Error::Malformed(format!("{} descriptor: {}", what, detail))
+}
+
+// Checks the length and type of a descriptor at the start of `buf`.
+fn check_header(buf: &[u8], kind: u8, min_len: usize, what: &str) -> Result<(), Error> {
+    if buf.len() < min_len {
+        return Err(malformed(
+            what,
+            format_args!("{} bytes, expected at least {}", buf.len(), min_len),
+        ));
+    }
+    if buf[1] != kind {
+        return Err(malformed(what, format_args!("unexpected type {}", buf[1])));
+    }
+    if (buf[0] as usize) < min_len {
+        return Err(malformed(what, format_args!("bad length {}", buf[0])));
+    }
+    Ok(())
+}
+
+fn u16_at(buf: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([buf[offset], buf[offset + 1]])
+}
+
+/// A USB device descriptor
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct DeviceDescriptor {
+    pub usb_version: u16,
+    pub class: u8,
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub device_version: u16,
+    pub manufacturer_index: u8,
+    pub product_index: u8,
+    pub serial_number_index: u8,
+    pub num_configurations: u8,
+}
+
+impl DeviceDescriptor {
+    pub fn decode(buf: &[u8]) -> Result<DeviceDescriptor, Error> {
+        check_header(buf, DESCRIPTOR_DEVICE, DEVICE_DESCRIPTOR_SIZE, "device")?;
+
+        Ok(DeviceDescriptor {
+            usb_version: u16_at(buf, 2),
+            class: buf[4],
+            vendor_id: u16_at(buf, 8),
+            product_id: u16_at(buf, 10),
+            device_version: u16_at(buf, 12),
+            manufacturer_index: buf[14],
+            product_index: buf[15],
+            serial_number_index: buf[16],
+            num_configurations: buf[17],
+        })
+    }
+}
+
+/// A USB configuration descriptor, along with the interface and endpoint
+/// descriptors that follow it
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ConfigDescriptor {
+    pub value: u8,
+    pub interfaces: Vec<InterfaceDescriptor>,
+}
+
+/// One alternate setting of a USB interface
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct InterfaceDescriptor {
+    pub number: u8,
+    pub alt_setting: u8,
+    pub class: u8,
+    pub subclass: u8,
+    pub protocol: u8,
+    pub string_index: u8,
+    pub endpoints: Vec<EndpointDescriptor>,
+}
+
+/// A USB endpoint descriptor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct EndpointDescriptor {
+    pub address: u8,
+    pub attributes: u8,
+    pub max_packet_size: u16,
+    pub interval: u8,
+}
+
+impl ConfigDescriptor {
+    /// Returns the length of the configuration, including its interface and
+    /// endpoint descriptors, given the first `CONFIG_DESCRIPTOR_SIZE` bytes.
+    pub(crate) fn total_length(buf: &[u8]) -> Result<usize, Error> {
+        check_header(
+            buf,
+            DESCRIPTOR_CONFIG,
+            CONFIG_DESCRIPTOR_SIZE,
+            "configuration",
+        )?;
+        Ok(u16_at(buf, 2) as usize)
+    }
+
+    pub fn decode(buf: &[u8]) -> Result<ConfigDescriptor, Error> {
+        let total = ConfigDescriptor::total_length(buf)?;
+        if buf.len() < total || total < buf[0] as usize {
+            return Err(malformed(
+                "configuration",
+                format_args!("{} of {} bytes", buf.len(), total),
+            ));
+        }
+
+        let mut config = ConfigDescriptor {
+            value: buf[5],
+            interfaces: vec![],
+        };
+
+        let mut rest = &buf[buf[0] as usize..total];
+        while !rest.is_empty() {
+            let len = rest[0] as usize;
+            if len < 2 || len > rest.len() {
+                return Err(malformed(
+                    "configuration",
+                    format_args!("bad length {}", len),
+                ));
+            }
+            let (desc, next) = rest.split_at(len);
+
+            match desc[1] {
+                DESCRIPTOR_INTERFACE => {
+                    check_header(desc, DESCRIPTOR_INTERFACE, 9, "interface")?;
+                    config.interfaces.push(InterfaceDescriptor {
+                        number: desc[2],
+                        alt_setting: desc[3],
+                        class: desc[5],
+                        subclass: desc[6],
+                        protocol: desc[7],
+                        string_index: desc[8],
+                        endpoints: vec![],
+                    });
+                }
+                DESCRIPTOR_ENDPOINT => {
+                    check_header(desc, DESCRIPTOR_ENDPOINT, 7, "endpoint")?;
+                    let interface = config.interfaces.last_mut().ok_or_else(|| {
+                        malformed("configuration", "endpoint outside an interface")
+                    })?;
+                    interface.endpoints.push(EndpointDescriptor {
+                        address: desc[2],
+                        attributes: desc[3],
+                        max_packet_size: u16_at(desc, 4),
+                        interval: desc[6],
+                    });
+                }
+                // class and vendor specific descriptors
+                _ => {}
+            }
+
+            rest = next;
+        }
+
+        Ok(config)
+    }
+
+    /// Finds the interface to talk PTP to. Interfaces of the still image
+    /// class are preferred. Failing that, MTP devices often use a vendor
+    /// specific interface, which is recognised, in this order, by its name
+    /// (looked up in `strings` by string descriptor index), by an "MTP"
+    /// compatible ID in the device's Microsoft OS descriptor, or by having
+    /// exactly a bulk in, a bulk out and an interrupt in endpoint.
+    pub fn find_ptp_interface(
+        &self,
+        strings: &HashMap<u8, String>,
+        compatible_ids: &[CompatibleId],
+    ) -> Option<(&InterfaceDescriptor, InterfaceMatch)> {
+        if let Some(interface) = self
+            .interfaces
+            .iter()
+            .find(|i| i.class == STILL_IMAGE_CLASS)
+        {
+            return Some((interface, InterfaceMatch::StillImageClass));
+        }
+
+        let vendor = || {
+            self.interfaces
+                .iter()
+                .filter(|i| i.class == VENDOR_SPECIFIC_CLASS)
+        };
+        let named_mtp = |i: &&InterfaceDescriptor| {
+            strings
+                .get(&i.string_index)
+                .is_some_and(|name| name.trim().eq_ignore_ascii_case("MTP"))
+        };
+        let compatible_with_mtp = |i: &&InterfaceDescriptor| {
+            compatible_ids
+                .iter()
+                .any(|id| id.first_interface == i.number && id.compatible_id == "MTP")
+        };
+
+        vendor()
+            .find(named_mtp)
+            .map(|i| (i, InterfaceMatch::InterfaceName))
+            .or_else(|| {
+                vendor()
+                    .find(compatible_with_mtp)
+                    .map(|i| (i, InterfaceMatch::CompatibleId))
+            })
+            .or_else(|| {
+                vendor()
+                    .find(|i| i.has_ptp_endpoints())
+                    .map(|i| (i, InterfaceMatch::EndpointLayout))
+            })
+    }
+}
+
+/// How an interface was recognised as a PTP interface, see
+/// `ConfigDescriptor::find_ptp_interface`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum InterfaceMatch {
+    StillImageClass,
+    InterfaceName,
+    CompatibleId,
+    EndpointLayout,
+}
+
+impl InterfaceDescriptor {
+    /// Whether the interface has exactly the endpoints of a PTP interface:
+    /// bulk in, bulk out and interrupt in
+    pub fn has_ptp_endpoints(&self) -> bool {
+        self.endpoints.len() == 3
+            && self
+                .endpoint(rusb::Direction::In, rusb::TransferType::Bulk)
+                .is_some()
+            && self
+                .endpoint(rusb::Direction::Out, rusb::TransferType::Bulk)
+                .is_some()
+            && self
+                .endpoint(rusb::Direction::In, rusb::TransferType::Interrupt)
+                .is_some()
+    }
+
+    /// Returns the first endpoint in the given direction with the given
+    /// transfer type
+    pub fn endpoint(
+        &self,
+        direction: rusb::Direction,
+        transfer_type: rusb::TransferType,
+    ) -> Option<&EndpointDescriptor> {
+        self.endpoints
+            .iter()
+            .find(|ep| ep.direction() == direction && ep.transfer_type() == transfer_type)
+    }
+}
+
+impl EndpointDescriptor {
+    /// The largest packet the endpoint transfers, without the additional
+    /// transactions per microframe of high speed interrupt endpoints
+    pub fn packet_size(&self) -> usize {
+        (self.max_packet_size & 0x07FF).into()
+    }
+
+    pub fn direction(&self) -> rusb::Direction {
+        if self.address & 0x80 != 0 {
+            rusb::Direction::In
+        } else {
+            rusb::Direction::Out
+        }
+    }
+
+    pub fn transfer_type(&self) -> rusb::TransferType {
+        match self.attributes & 0x03 {
+            0 => rusb::TransferType::Control,
+            1 => rusb::TransferType::Isochronous,
+            2 => rusb::TransferType::Bulk,
+            _ => rusb::TransferType::Interrupt,
+        }
+    }
+}
+
+/// Decodes string descriptor 0, which lists the supported language IDs
+pub fn decode_language_ids(buf: &[u8]) -> Result<Vec<u16>, Error> {
+    check_header(buf, DESCRIPTOR_STRING, 2, "string")?;
+    let len = (buf[0] as usize).min(buf.len());
+
+    Ok(buf[2..len].chunks_exact(2).map(|c| u16_at(c, 0)).collect())
+}
+
+/// Decodes a string descriptor, which holds UTF-16LE text
+pub fn decode_string(buf: &[u8]) -> Result<String, Error> {
+    check_header(buf, DESCRIPTOR_STRING, 2, "string")?;
+    let len = (buf[0] as usize).min(buf.len());
+
+    let units: Vec<u16> = buf[2..len].chunks_exact(2).map(|c| u16_at(c, 0)).collect();
+    String::from_utf16(&units).map_err(|e| malformed("string", e))
+}
+
+/// Decodes the Microsoft OS string descriptor, returning the vendor code to
+/// request the other Microsoft OS descriptors with
+pub fn decode_ms_os_string(buf: &[u8]) -> Result<u8, Error> {
+    check_header(buf, DESCRIPTOR_STRING, 0x12, "Microsoft OS string")?;
+
+    let signature: Vec<u16> = buf[2..16].chunks_exact(2).map(|c| u16_at(c, 0)).collect();
+    if String::from_utf16_lossy(&signature) != "MSFT100" {
+        return Err(malformed("Microsoft OS string", "bad signature"));
+    }
+
+    Ok(buf[16])
+}
+
+/// A function of a device, as listed by its extended compatible ID OS
+/// descriptor
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct CompatibleId {
+    pub first_interface: u8,
+    pub compatible_id: String,
+    pub sub_compatible_id: String,
+}
+
+/// Decodes the extended compatible ID OS descriptor
+pub fn decode_compatible_ids(buf: &[u8]) -> Result<Vec<CompatibleId>, Error> {
+    const HEADER_SIZE: usize = 16;
+    const FUNCTION_SIZE: usize = 24;
+    let what = "compatible ID";
+
+    if buf.len() < HEADER_SIZE {
+        return Err(malformed(what, format_args!("{} bytes", buf.len())));
+    }
+    if u16_at(buf, 6) != MS_OS_COMPATIBLE_ID_INDEX {
+        return Err(malformed(
+            what,
+            format_args!("unexpected index {}", u16_at(buf, 6)),
+        ));
+    }
+    let count = buf[8] as usize;
+    let len = HEADER_SIZE + count * FUNCTION_SIZE;
+    if buf.len() < len {
+        return Err(malformed(
+            what,
+            format_args!("{} of {} bytes", buf.len(), len),
+        ));
+    }
+
+    let id = |bytes: &[u8]| {
+        let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+        String::from_utf8_lossy(&bytes[..end]).into_owned()
+    };
+
+    Ok(buf[HEADER_SIZE..len]
+        .chunks_exact(FUNCTION_SIZE)
+        .map(|f| CompatibleId {
+            first_interface: f[0],
+            compatible_id: id(&f[2..10]),
+            sub_compatible_id: id(&f[10..18]),
+        })
+        .collect())
+}
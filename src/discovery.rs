@@ -0,0 +1,399 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::debug;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::descriptor::{
+    CONFIG_DESCRIPTOR_SIZE, DESCRIPTOR_CONFIG, DESCRIPTOR_DEVICE, DESCRIPTOR_STRING,
+    DEVICE_DESCRIPTOR_SIZE, MS_OS_COMPATIBLE_ID_INDEX,
+};
+use crate::{
+    decode_compatible_ids, decode_language_ids, decode_ms_os_string, decode_string, CompatibleId,
+    ConfigDescriptor, Device, DeviceDescriptor, Error, InterfaceDescriptor, InterfaceMatch,
+    UsbTransport, MS_OS_STRING_INDEX, STILL_IMAGE_CLASS, VENDOR_SPECIFIC_CLASS,
+};
+
+const GET_DESCRIPTOR: u8 = 0x06;
+const DESCRIPTOR_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The descriptors of a device needed to find and describe its PTP
+/// interface
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct UsbDescriptors {
+    pub device: DeviceDescriptor,
+    pub configs: Vec<ConfigDescriptor>,
+    /// The strings describing the device and its vendor specific
+    /// interfaces, by index
+    pub strings: HashMap<u8, String>,
+    /// Functions listed in the Microsoft OS descriptor. Only requested from
+    /// devices that have vendor specific but no still image interfaces.
+    pub compatible_ids: Vec<CompatibleId>,
+}
+
+impl UsbDescriptors {
+    /// Reads the descriptors from the device with GET_DESCRIPTOR requests.
+    pub fn read<C: rusb::UsbContext>(
+        handle: &rusb::DeviceHandle<C>,
+    ) -> Result<UsbDescriptors, Error> {
+        let mut buf = [0u8; DEVICE_DESCRIPTOR_SIZE];
+        let n = read_descriptor(handle, DESCRIPTOR_DEVICE, 0, 0, &mut buf)?;
+        let device = DeviceDescriptor::decode(&buf[..n])?;
+
+        let mut configs = Vec::with_capacity(device.num_configurations as usize);
+        for index in 0..device.num_configurations {
+            let mut header = [0u8; CONFIG_DESCRIPTOR_SIZE];
+            let n = read_descriptor(handle, DESCRIPTOR_CONFIG, index, 0, &mut header)?;
+            let mut buf = vec![0u8; ConfigDescriptor::total_length(&header[..n])?];
+            let n = read_descriptor(handle, DESCRIPTOR_CONFIG, index, 0, &mut buf)?;
+            configs.push(ConfigDescriptor::decode(&buf[..n])?);
+        }
+
+        let interfaces = || configs.iter().flat_map(|c| &c.interfaces);
+        let vendor_specific = || interfaces().filter(|i| i.class == VENDOR_SPECIFIC_CLASS);
+
+        let mut buf = [0u8; 255];
+        let language = read_descriptor(handle, DESCRIPTOR_STRING, 0, 0, &mut buf)
+            .and_then(|n| decode_language_ids(&buf[..n]))
+            .ok()
+            .and_then(|ids| ids.first().copied());
+
+        let mut strings = HashMap::new();
+        if let Some(language) = language {
+            let indices = [
+                device.manufacturer_index,
+                device.product_index,
+                device.serial_number_index,
+            ];
+            let indices = indices
+                .iter()
+                .copied()
+                .chain(vendor_specific().map(|i| i.string_index));
+            for index in indices.filter(|&i| i != 0) {
+                let mut buf = [0u8; 255];
+                match read_descriptor(handle, DESCRIPTOR_STRING, index, language, &mut buf)
+                    .and_then(|n| decode_string(&buf[..n]))
+                {
+                    Ok(s) => {
+                        strings.insert(index, s);
+                    }
+                    Err(e) => debug!("reading string {} failed: {}", index, e),
+                }
+            }
+        }
+
+        // some devices misbehave when asked for OS descriptors they don't
+        // have, so only ask devices that might need them
+        let still_image = interfaces().any(|i| i.class == STILL_IMAGE_CLASS);
+        let compatible_ids = if !still_image && vendor_specific().next().is_some() {
+            read_compatible_ids(handle).unwrap_or_else(|e| {
+                debug!("no Microsoft OS descriptor: {}", e);
+                vec![]
+            })
+        } else {
+            vec![]
+        };
+
+        Ok(UsbDescriptors {
+            device,
+            configs,
+            strings,
+            compatible_ids,
+        })
+    }
+
+    /// Finds the PTP interface in the configuration with the given value,
+    /// see `ConfigDescriptor::find_ptp_interface`
+    pub fn ptp_interface(
+        &self,
+        config_value: u8,
+    ) -> Option<(&InterfaceDescriptor, InterfaceMatch)> {
+        self.configs
+            .iter()
+            .find(|c| c.value == config_value)?
+            .find_ptp_interface(&self.strings, &self.compatible_ids)
+    }
+
+    /// Returns the given alternate setting of an interface in the
+    /// configuration with the given value
+    pub fn interface(
+        &self,
+        config_value: u8,
+        number: u8,
+        alt_setting: u8,
+    ) -> Option<&InterfaceDescriptor> {
+        self.configs
+            .iter()
+            .find(|c| c.value == config_value)?
+            .interfaces
+            .iter()
+            .find(|i| i.number == number && i.alt_setting == alt_setting)
+    }
+}
+
+/// Identifying details of a USB device with a PTP interface, read from its
+/// descriptors
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct UsbDeviceInfo {
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub bus_number: u8,
+    pub port_numbers: Vec<u8>,
+    pub manufacturer: Option<String>,
+    pub product: Option<String>,
+    pub serial_number: Option<String>,
+    /// Value of the configuration with the PTP interface
+    pub config: u8,
+    /// Number of the PTP interface
+    pub interface: u8,
+    pub alt_setting: u8,
+    /// How the PTP interface was recognised
+    pub interface_match: InterfaceMatch,
+}
+
+impl UsbDeviceInfo {
+    /// Collects the details of a device from its descriptors. Returns None
+    /// if none of the configurations has a PTP interface.
+    pub fn from_descriptors(
+        descriptors: &UsbDescriptors,
+        bus_number: u8,
+        port_numbers: Vec<u8>,
+    ) -> Option<UsbDeviceInfo> {
+        let (config, (interface, interface_match)) = descriptors.configs.iter().find_map(|c| {
+            c.find_ptp_interface(&descriptors.strings, &descriptors.compatible_ids)
+                .map(|found| (c.value, found))
+        })?;
+
+        let string = |index| descriptors.strings.get(&index).cloned();
+        let device = &descriptors.device;
+
+        Some(UsbDeviceInfo {
+            vendor_id: device.vendor_id,
+            product_id: device.product_id,
+            bus_number,
+            port_numbers,
+            manufacturer: string(device.manufacturer_index),
+            product: string(device.product_index),
+            serial_number: string(device.serial_number_index),
+            config,
+            interface: interface.number,
+            alt_setting: interface.alt_setting,
+            interface_match,
+        })
+    }
+
+    /// Returns the bus and port path in the form used by Linux, e.g. "1-2.3"
+    pub fn port_path(&self) -> String {
+        let ports: Vec<String> = self.port_numbers.iter().map(|p| p.to_string()).collect();
+        format!("{}-{}", self.bus_number, ports.join("."))
+    }
+}
+
+/// Selects devices by the fields of their `UsbDeviceInfo`. Fields that are
+/// None match any device. Manufacturer and product match if they contain the
+/// given text, ignoring case; the other fields must be equal.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct DeviceFilter {
+    pub vendor_id: Option<u16>,
+    pub product_id: Option<u16>,
+    pub bus_number: Option<u8>,
+    pub port_numbers: Option<Vec<u8>>,
+    pub manufacturer: Option<String>,
+    pub product: Option<String>,
+    pub serial_number: Option<String>,
+}
+
+impl DeviceFilter {
+    pub fn matches(&self, info: &UsbDeviceInfo) -> bool {
+        fn equal<T: PartialEq>(wanted: &Option<T>, actual: &T) -> bool {
+            wanted.as_ref().is_none_or(|wanted| wanted == actual)
+        }
+        fn contains(wanted: &Option<String>, actual: &Option<String>) -> bool {
+            match (wanted, actual) {
+                (None, _) => true,
+                (Some(wanted), Some(actual)) => {
+                    actual.to_lowercase().contains(&wanted.to_lowercase())
+                }
+                (Some(_), None) => false,
+            }
+        }
+
+        equal(&self.vendor_id, &info.vendor_id)
+            && equal(&self.product_id, &info.product_id)
+            && equal(&self.bus_number, &info.bus_number)
+            && equal(&self.port_numbers, &info.port_numbers)
+            && contains(&self.manufacturer, &info.manufacturer)
+            && contains(&self.product, &info.product)
+            && match &self.serial_number {
+                None => true,
+                wanted => wanted == &info.serial_number,
+            }
+    }
+}
+
+/// A USB device with a PTP interface, as found by `list_devices`
+#[derive(Debug, Clone)]
+pub struct DeviceCandidate<C: rusb::UsbContext> {
+    pub info: UsbDeviceInfo,
+    device: rusb::Device<C>,
+}
+
+impl<C: rusb::UsbContext> DeviceCandidate<C> {
+    pub fn device(&self) -> &rusb::Device<C> {
+        &self.device
+    }
+
+    /// Opens the device and claims its PTP interface.
+    pub fn open(&self) -> Result<Device<UsbTransport<C>>, Error> {
+        let handle = Arc::new(self.device.open()?);
+        let transport =
+            UsbTransport::with_interface(handle, self.info.interface, self.info.alt_setting)?;
+        Ok(Device::with_transport(transport))
+    }
+}
+
+// Reads a descriptor with a GET_DESCRIPTOR request, returning its length.
+fn read_descriptor<C: rusb::UsbContext>(
+    handle: &rusb::DeviceHandle<C>,
+    kind: u8,
+    index: u8,
+    language: u16,
+    buf: &mut [u8],
+) -> Result<usize, Error> {
+    let request_type = rusb::request_type(
+        rusb::Direction::In,
+        rusb::RequestType::Standard,
+        rusb::Recipient::Device,
+    );
+    let value = (kind as u16) << 8 | index as u16;
+
+    Ok(handle.read_control(
+        request_type,
+        GET_DESCRIPTOR,
+        value,
+        language,
+        buf,
+        DESCRIPTOR_TIMEOUT,
+    )?)
+}
+
+// Reads the extended compatible ID OS descriptor.
+fn read_compatible_ids<C: rusb::UsbContext>(
+    handle: &rusb::DeviceHandle<C>,
+) -> Result<Vec<CompatibleId>, Error> {
+    let mut buf = [0u8; 255];
+    let n = read_descriptor(handle, DESCRIPTOR_STRING, MS_OS_STRING_INDEX, 0, &mut buf)?;
+    let vendor_code = decode_ms_os_string(&buf[..n])?;
+
+    let request_type = rusb::request_type(
+        rusb::Direction::In,
+        rusb::RequestType::Vendor,
+        rusb::Recipient::Device,
+    );
+    let mut buf = vec![0u8; 4096];
+    let n = handle.read_control(
+        request_type,
+        vendor_code,
+        0,
+        MS_OS_COMPATIBLE_ID_INDEX,
+        &mut buf,
+        DESCRIPTOR_TIMEOUT,
+    )?;
+
+    decode_compatible_ids(&buf[..n])
+}
+
+fn probe<C: rusb::UsbContext>(
+    device: &rusb::Device<C>,
+    handle: &rusb::DeviceHandle<C>,
+) -> Result<Option<UsbDeviceInfo>, Error> {
+    let descriptors = UsbDescriptors::read(handle)?;
+
+    Ok(UsbDeviceInfo::from_descriptors(
+        &descriptors,
+        device.bus_number(),
+        device.port_numbers()?,
+    ))
+}
+
+// Whether the cached configuration, which is read without opening the
+// device, has an interface that could be PTP: a still image one, or a
+// vendor specific one that its descriptors may show to be PTP.
+fn may_have_ptp_interface<C: rusb::UsbContext>(device: &rusb::Device<C>) -> bool {
+    let config = match device.active_config_descriptor() {
+        Ok(config) => config,
+        Err(e) => {
+            debug!(
+                "skipping device {} on bus {}: {}",
+                device.address(),
+                device.bus_number(),
+                e
+            );
+            return false;
+        }
+    };
+    let found = config
+        .interfaces()
+        .flat_map(|interface| interface.descriptors())
+        .any(|descriptor| {
+            let class = descriptor.class_code();
+            class == STILL_IMAGE_CLASS || class == VENDOR_SPECIFIC_CLASS
+        });
+    found
+}
+
+/// Lists the USB devices that have a PTP interface. Only devices with a
+/// still image or vendor specific interface are opened and probed; devices
+/// that can't be opened, for example for lack of permissions, are skipped.
+pub fn list_devices<C: rusb::UsbContext>(context: &C) -> Result<Vec<DeviceCandidate<C>>, Error> {
+    let mut candidates = vec![];
+
+    for device in context.devices()?.iter() {
+        if !may_have_ptp_interface(&device) {
+            continue;
+        }
+        let result = device
+            .open()
+            .map_err(Error::from)
+            .and_then(|handle| probe(&device, &handle));
+
+        match result {
+            Ok(Some(info)) => {
+                debug!(
+                    "found PTP device {:04x}:{:04x} at {}",
+                    info.vendor_id,
+                    info.product_id,
+                    info.port_path()
+                );
+                candidates.push(DeviceCandidate { info, device });
+            }
+            Ok(None) => {}
+            Err(e) => debug!(
+                "skipping device {} on bus {}: {}",
+                device.address(),
+                device.bus_number(),
+                e
+            ),
+        }
+    }
+
+    Ok(candidates)
+}
+
+/// Opens the first PTP device matching `filter`. Fails with
+/// `rusb::Error::NotFound` if there is none.
+pub fn open_by<C: rusb::UsbContext>(
+    context: &C,
+    filter: &DeviceFilter,
+) -> Result<Device<UsbTransport<C>>, Error> {
+    list_devices(context)?
+        .into_iter()
+        .find(|candidate| filter.matches(&candidate.info))
+        .ok_or(Error::Usb(rusb::Error::NotFound))?
+        .open()
+}
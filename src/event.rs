@@ -1,15 +1,17 @@
-use std::fmt::{self, LowerHex};
+use std::fmt::{self, Display, LowerHex};
+use std::io::Cursor;
+use std::str::FromStr;
 
 #[cfg(feature = "serde")]
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 use num_derive::{FromPrimitive, ToPrimitive};
 use num_traits::{FromPrimitive, ToPrimitive};
 
-use crate::Error;
+use crate::names::parse_code;
+use crate::{Error, PtpRead, VendorProfile};
 
 #[derive(Debug, Eq, PartialEq, Copy, Clone, Hash)]
-#[cfg_attr(feature = "serde", derive(Serialize))]
 pub enum EventCode {
     Standard(StandardEventCode),
     Vendor(u16),
@@ -59,6 +61,74 @@ impl ToPrimitive for EventCode {
     }
 }
 
+impl EventCode {
+    /// Returns the name of a standard event code
+    pub fn name(&self) -> Option<&'static str> {
+        match self {
+            EventCode::Standard(code) => Some(code.name()),
+            EventCode::Vendor(_) | EventCode::Reserved(_) => None,
+        }
+    }
+
+    /// Returns the name of this code, also looking up MTP and vendor-defined
+    /// events in `vendor`, usually `Device::vendor()`.
+    pub fn name_for(&self, vendor: Option<&VendorProfile>) -> Option<&'static str> {
+        match self {
+            EventCode::Standard(code) => Some(code.name()),
+            EventCode::Vendor(code) => vendor?.event_name(*code),
+            EventCode::Reserved(_) => None,
+        }
+    }
+
+    /// Returns a short description of this code
+    pub fn description(&self) -> &'static str {
+        match self {
+            EventCode::Standard(code) => code.description(),
+            EventCode::Vendor(_) => "vendor-defined event",
+            EventCode::Reserved(_) => "reserved event",
+        }
+    }
+
+    /// Returns a short description of this code, naming the vendor of the
+    /// MTP and vendor-defined events `vendor` knows.
+    pub fn description_for(&self, vendor: Option<&VendorProfile>) -> String {
+        match (self, vendor) {
+            (EventCode::Vendor(code), Some(vendor)) if vendor.event_name(*code).is_some() => {
+                format!("{} event", vendor.name)
+            }
+            _ => self.description().to_owned(),
+        }
+    }
+
+    /// Parses a code given by name or as a number such as `"0x4002"`, also
+    /// accepting the names of MTP and vendor-defined events in `vendor`.
+    pub fn parse_for(s: &str, vendor: Option<&VendorProfile>) -> Result<EventCode, Error> {
+        let s = s.trim();
+        StandardEventCode::from_name(s)
+            .map(EventCode::Standard)
+            .or_else(|| vendor?.event_code(s).map(EventCode::Vendor))
+            .or_else(|| parse_code(s).and_then(EventCode::from_u16))
+            .ok_or_else(|| Error::UnknownCode(s.to_owned()))
+    }
+}
+
+impl Display for EventCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.name() {
+            Some(name) => f.write_str(name),
+            None => write!(f, "0x{:04X}", self.to_u16().unwrap()),
+        }
+    }
+}
+
+impl FromStr for EventCode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        EventCode::parse_for(s, None)
+    }
+}
+
 impl LowerHex for EventCode {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -76,7 +146,7 @@ impl From<StandardEventCode> for EventCode {
 
 #[repr(u16)]
 #[derive(FromPrimitive, ToPrimitive, Debug, Copy, Clone, Eq, PartialEq, Hash)]
-#[cfg_attr(feature = "serde", derive(Serialize))]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum StandardEventCode {
     Undefined = 0x4000,
     CancelTransaction,
@@ -100,11 +170,34 @@ impl LowerHex for StandardEventCode {
     }
 }
 
+code_names!(StandardEventCode {
+    Undefined => "undefined event",
+    CancelTransaction => "the device cancelled a transaction",
+    ObjectAdded => "an object was added",
+    ObjectRemoved => "an object was removed",
+    StoreAdded => "a storage was added",
+    StoreRemoved => "a storage was removed",
+    DevicePropChanged => "a device property changed",
+    ObjectInfoChanged => "the ObjectInfo dataset of an object changed",
+    StoreFull => "a storage is full",
+    DeviceReset => "the device was reset",
+    StorageInfoChanged => "the StorageInfo dataset of a storage changed",
+    CaptureComplete => "a capture completed",
+    UnreportedStatus => "some events were not reported",
+});
+
 #[derive(Debug, Clone, Hash)]
-#[cfg_attr(feature = "serde", derive(Serialize))]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Event {
     pub code: EventCode,
     pub params: Vec<u32>,
+    /// The whole event, for an event too large for the interrupt endpoint
+    /// whose data was fetched with Get Extended Event Data
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub extended: Option<ExtendedEventData>,
 }
 
 impl Event {
@@ -121,6 +214,49 @@ impl Event {
                     u32::from_le_bytes(b)
                 })
                 .collect(),
+            extended: None,
+        })
+    }
+}
+
+/// The data returned by the class request Get Extended Event Data: an event
+/// with parameters of any size.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ExtendedEventData {
+    pub code: EventCode,
+    pub tid: u32,
+    pub params: Vec<Vec<u8>>,
+}
+
+impl ExtendedEventData {
+    pub fn decode(buf: &[u8]) -> Result<ExtendedEventData, Error> {
+        let mut cur = Cursor::new(buf);
+        let code = cur.read_ptp_u16()?;
+        let tid = cur.read_ptp_u32()?;
+        let count = cur.read_ptp_u16()?;
+
+        let params = (0..count)
+            .map(|_| {
+                let len = usize::from(cur.read_ptp_u16()?);
+                let start = cur.position() as usize;
+                let param = buf.get(start..start + len).ok_or_else(|| {
+                    Error::Malformed(format!(
+                        "extended event parameter of {} bytes at offset {} exceeds the {} bytes received",
+                        len,
+                        start,
+                        buf.len()
+                    ))
+                })?;
+                cur.set_position((start + len) as u64);
+                Ok(param.to_vec())
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        Ok(ExtendedEventData {
+            code: EventCode::from_u16(code).ok_or(Error::BadEventCode)?,
+            tid,
+            params,
         })
     }
 }
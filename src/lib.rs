@@ -1,36 +1,80 @@
 #[cfg(feature = "serde")]
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
-use byteorder;
-use log::{debug, error, trace, warn};
+use log::{debug, trace, warn};
 use num_derive::FromPrimitive;
 use num_traits::{FromPrimitive, ToPrimitive};
-use rusb;
 use thiserror::Error;
 
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 
-use std::slice;
-use std::sync::Arc;
-use std::time::Duration;
-use std::{cmp::min, mem::MaybeUninit};
+use std::cmp::min;
+use std::convert::TryFrom;
+use std::fmt;
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
 use std::{io, sync::atomic::Ordering};
 use std::{io::Cursor, sync::atomic::AtomicU32};
 
+#[macro_use]
+mod names;
+#[cfg(feature = "async")]
+mod asynchronous;
+mod buffer;
 mod command;
 mod data;
+mod descriptor;
+mod discovery;
 mod event;
+mod liveview;
+#[cfg(feature = "mjpeg")]
+mod mjpeg;
+mod mtp;
+mod options;
+mod polling;
+mod queue;
+mod recording;
+mod recovery;
 mod response;
+mod retry;
+#[cfg(feature = "serde")]
+mod serialization;
+mod session;
 mod storage;
+mod supervisor;
+mod transport;
+mod vendor;
 
+#[cfg(feature = "async")]
+pub use crate::asynchronous::*;
+pub use crate::buffer::*;
 pub use crate::command::*;
 pub use crate::data::*;
+pub use crate::descriptor::*;
+pub use crate::discovery::*;
 pub use crate::event::*;
+pub use crate::liveview::*;
+#[cfg(feature = "mjpeg")]
+pub use crate::mjpeg::*;
+pub use crate::mtp::*;
+pub use crate::options::*;
+use crate::polling::EventPoller;
+pub use crate::polling::*;
+pub use crate::queue::Priority;
+use crate::queue::TransactionQueue;
+pub use crate::recording::*;
+pub use crate::recovery::*;
 pub use crate::response::*;
+pub use crate::retry::*;
+pub use crate::session::*;
 pub use crate::storage::*;
+pub use crate::supervisor::*;
+pub use crate::transport::*;
+pub use crate::vendor::*;
 
 #[derive(Debug, Clone, Copy, PartialEq, FromPrimitive)]
-#[cfg_attr(feature = "serde", derive(Serialize))]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[repr(u16)]
 pub enum ContainerType {
     Command = 1,
@@ -42,8 +86,9 @@ pub enum ContainerType {
 /// An error in a PTP command
 #[derive(Error, Debug)]
 pub enum Error {
-    /// PTP Responder returned a status code other than Ok, either a constant in StandardResponseCode or a vendor-defined code
-    #[error("the ptp device returned an error code: {0:?}")]
+    /// PTP Responder returned a status code other than Ok, either a constant in StandardResponseCode or a vendor-defined code.
+    /// Errors of a transaction carry this inside `Error::Transaction`, see `Error::response_code`.
+    #[error("the ptp device returned an error code: {0} ({desc})", desc = .0.description())]
     Response(ResponseCode),
 
     /// Data received was malformed
@@ -62,6 +107,62 @@ pub enum Error {
     #[error("received an event with no payload")]
     NoEventPayload,
 
+    /// A code name given as text was not recognised
+    #[error("unknown code {0:?}")]
+    UnknownCode(String),
+
+    /// A property value had a DataType code the specification doesn't define,
+    /// or UNDEF
+    #[error("the data received was malformed: bad data type {0:#06x}")]
+    BadDataType(u16),
+
+    /// A property value was of a different type than requested
+    #[error("expected a value of type {expected:?}, found {actual:?}")]
+    UnexpectedDataType {
+        expected: DataType,
+        actual: DataType,
+    },
+
+    /// A string is too long to be encoded as a PTP string
+    #[error("a string of {0} UTF-16 code units exceeds the PTP limit of 255")]
+    StringTooLong(usize),
+
+    /// The device announced a payload larger than the configured maximum
+    #[error("the payload of {len} bytes exceeds the maximum of {max} bytes")]
+    PayloadTooLarge { len: usize, max: usize },
+
+    /// The device does not support the requested feature
+    #[error("the device does not support {0}")]
+    NotSupported(&'static str),
+
+    /// A live view frame rate that isn't a positive, finite number of
+    /// frames per second
+    #[error("invalid frame rate {0}, expected a positive number of frames per second")]
+    InvalidFrameRate(f64),
+
+    /// The deadline of the call passed before it was done
+    #[error("the deadline of the call was exceeded")]
+    DeadlineExceeded,
+
+    /// The call was cancelled through its `CancelToken`
+    #[error("the call was cancelled")]
+    Cancelled,
+
+    /// The host sent or asked for something other than what a
+    /// `ReplayTransport` recorded next
+    #[error(
+        "replay diverged from entry {index} of the recording: expected {expected}, got {actual}"
+    )]
+    ReplayMismatch {
+        index: usize,
+        expected: String,
+        actual: String,
+    },
+
+    /// The thread performing the I/O of an `AsyncDevice` has stopped
+    #[error("the device worker thread has stopped")]
+    WorkerStopped,
+
     /// Another rusb error
     #[error("a usb error occurred: {0}")]
     Usb(#[from] rusb::Error),
@@ -69,10 +170,171 @@ pub enum Error {
     /// Another IO error
     #[error("an i/o error occurred: {0}")]
     Io(#[from] io::Error),
+
+    /// An error that occurred during a transaction, along with the
+    /// operation, transaction and phase it occurred in
+    #[error("{context}: {source}")]
+    Transaction {
+        context: Box<TransactionContext>,
+        source: Box<Error>,
+    },
 }
 
-#[derive(Debug, Clone)]
-#[cfg_attr(feature = "serde", derive(Serialize))]
+impl Error {
+    /// Returns the underlying error, without any transaction context
+    pub fn root(&self) -> &Error {
+        match self {
+            Error::Transaction { source, .. } => source.root(),
+            other => other,
+        }
+    }
+
+    /// Returns the transaction this error occurred in, if known
+    pub fn context(&self) -> Option<&TransactionContext> {
+        match self {
+            Error::Transaction { context, .. } => Some(context),
+            _ => None,
+        }
+    }
+
+    /// Returns the response code if the device rejected the operation
+    pub fn response_code(&self) -> Option<ResponseCode> {
+        match self.root() {
+            Error::Response(code) => Some(*code),
+            _ => None,
+        }
+    }
+
+    fn standard_response(&self) -> Option<StandardResponseCode> {
+        match self.response_code()? {
+            ResponseCode::Standard(code) => Some(code),
+            ResponseCode::Other(_) => None,
+        }
+    }
+
+    /// Whether the device reported that it is busy
+    pub fn is_device_busy(&self) -> bool {
+        self.standard_response() == Some(StandardResponseCode::DeviceBusy)
+    }
+
+    /// Whether the error means the session is not in the state the operation
+    /// expected, so it needs to be (re)opened
+    pub fn is_session_error(&self) -> bool {
+        matches!(
+            self.standard_response(),
+            Some(
+                StandardResponseCode::SessionNotOpen
+                    | StandardResponseCode::SessionAlreadyOpen
+                    | StandardResponseCode::InvalidTransactionId
+            )
+        )
+    }
+
+    /// Whether a transfer stalled or the device sent more than was asked for
+    /// (babble), which leaves the bulk pipes to be recovered, see
+    /// `Device::recover`
+    pub fn is_stall(&self) -> bool {
+        matches!(
+            self.root(),
+            Error::Usb(rusb::Error::Pipe | rusb::Error::Overflow)
+        )
+    }
+
+    /// Whether the same operation may succeed if tried again: the device was
+    /// busy or a transfer was interrupted, as opposed to the request being
+    /// invalid
+    pub fn is_retryable(&self) -> bool {
+        match self.root() {
+            Error::Response(ResponseCode::Standard(code)) => matches!(
+                code,
+                StandardResponseCode::DeviceBusy
+                    | StandardResponseCode::IncompleteTransfer
+                    | StandardResponseCode::StoreNotAvailable
+                    | StandardResponseCode::TransactionCancelled
+            ),
+            Error::Usb(e) => matches!(
+                e,
+                rusb::Error::Timeout
+                    | rusb::Error::Pipe
+                    | rusb::Error::Busy
+                    | rusb::Error::Interrupted
+            ),
+            _ => false,
+        }
+    }
+
+    fn with_context(self, context: TransactionContext) -> Error {
+        match self {
+            e @ Error::Transaction { .. } => e,
+            e => Error::Transaction {
+                context: Box::new(context),
+                source: Box::new(e),
+            },
+        }
+    }
+}
+
+/// A phase of a PTP transaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum TransactionPhase {
+    /// Sending the operation request
+    Command,
+    /// Sending the data phase to the device
+    DataOut,
+    /// Receiving the data phase from the device. Reads that fail before the
+    /// device sent anything are reported here, since the data phase, if any,
+    /// comes first.
+    DataIn,
+    /// Receiving the response
+    Response,
+}
+
+impl fmt::Display for TransactionPhase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TransactionPhase::Command => "command",
+            TransactionPhase::DataOut => "data-out",
+            TransactionPhase::DataIn => "data-in",
+            TransactionPhase::Response => "response",
+        })
+    }
+}
+
+/// Identifies the transaction, and the phase of it, that an error occurred in
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct TransactionContext {
+    pub code: CommandCode,
+    /// Name of the operation, also looked up in the device's vendor profile
+    pub name: Option<String>,
+    pub params: Vec<u32>,
+    pub tid: u32,
+    pub phase: TransactionPhase,
+}
+
+impl fmt::Display for TransactionContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "{}(", name)?,
+            None => write!(f, "{}(", self.code)?,
+        }
+        for (i, param) in self.params.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "0x{:x}", param)?;
+        }
+        write!(
+            f,
+            ") failed in the {} phase of transaction {}",
+            self.phase, self.tid
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct DeviceInfo {
     pub version: u16,
     pub vendor_ex_id: u32,
@@ -111,10 +373,51 @@ impl DeviceInfo {
             serial_number: cur.read_ptp_str()?,
         })
     }
+
+    pub fn encode<W: WriteBytesExt>(&self, mut w: W) -> Result<(), Error> {
+        w.write_ptp_u16(self.version)?;
+        w.write_ptp_u32(self.vendor_ex_id)?;
+        w.write_ptp_u16(self.vendor_ex_version)?;
+        w.write_ptp_str(&self.vendor_extension_desc)?;
+        w.write_ptp_u16(self.functional_mode)?;
+        w.write_ptp_vec(&self.operations_supported, |w, &v| w.write_ptp_u16(v))?;
+        w.write_ptp_vec(&self.events_supported, |w, &v| w.write_ptp_u16(v))?;
+        w.write_ptp_vec(&self.device_properties_supported, |w, &v| {
+            w.write_ptp_u16(v)
+        })?;
+        w.write_ptp_vec(&self.capture_formats, |w, &v| w.write_ptp_u16(v))?;
+        w.write_ptp_vec(&self.image_formats, |w, &v| w.write_ptp_u16(v))?;
+        w.write_ptp_str(&self.manufacturer)?;
+        w.write_ptp_str(&self.model)?;
+        w.write_ptp_str(&self.device_version)?;
+        w.write_ptp_str(&self.serial_number)?;
+        Ok(())
+    }
+
+    /// Parses `vendor_extension_desc` into its individual extensions.
+    pub fn vendor_extensions(&self) -> Vec<VendorExtension> {
+        VendorExtension::parse_desc(&self.vendor_extension_desc)
+    }
+
+    /// Returns the vendor profile matching this device, if it is known.
+    pub fn vendor_profile(&self) -> Option<&'static VendorProfile> {
+        VendorProfile::detect(self)
+    }
+
+    /// Does the device implement PTP 1.1 (ISO 15740:2008) or later?
+    pub fn is_ptp_1_1(&self) -> bool {
+        self.version >= 110
+    }
+
+    /// Does the device list `code` among its supported operations?
+    pub fn supports_operation(&self, code: CommandCode) -> bool {
+        code.to_u16()
+            .is_some_and(|code| self.operations_supported.contains(&code))
+    }
 }
 
-#[derive(Debug, Clone)]
-#[cfg_attr(feature = "serde", derive(Serialize))]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct ObjectInfo {
     pub storage_id: u32,
     pub object_format: ObjectFormatCode,
@@ -169,10 +472,10 @@ impl ObjectInfo {
 
     pub fn encode<W: WriteBytesExt>(&self, mut w: W) -> Result<(), Error> {
         w.write_ptp_u32(self.storage_id)?;
-        w.write_ptp_u16(self.object_format.to_u16().unwrap())?;
+        w.write_ptp_u16(self.object_format.to_u16().ok_or(Error::BadObjectFormat)?)?;
         w.write_ptp_u16(self.protection_status)?;
         w.write_ptp_u32(self.object_compressed_size)?;
-        w.write_ptp_u16(self.thumb_format.to_u16().unwrap())?;
+        w.write_ptp_u16(self.thumb_format.to_u16().ok_or(Error::BadObjectFormat)?)?;
         w.write_ptp_u32(self.thumb_compressed_size)?;
         w.write_ptp_u32(self.thumb_pix_width)?;
         w.write_ptp_u32(self.thumb_pix_height)?;
@@ -180,7 +483,11 @@ impl ObjectInfo {
         w.write_ptp_u32(self.image_pix_height)?;
         w.write_ptp_u32(self.image_bit_depth)?;
         w.write_ptp_u32(self.parent_object)?;
-        w.write_ptp_u16(self.association_type.to_u16().unwrap())?;
+        w.write_ptp_u16(
+            self.association_type
+                .to_u16()
+                .ok_or(Error::BadAssociationCode)?,
+        )?;
         w.write_ptp_u32(self.association_desc)?;
         w.write_ptp_u32(self.sequence_number)?;
         w.write_ptp_str(&self.filename)?;
@@ -191,8 +498,34 @@ impl ObjectInfo {
     }
 }
 
-#[derive(Debug, Clone)]
-#[cfg_attr(feature = "serde", derive(Serialize))]
+impl Default for ObjectInfo {
+    fn default() -> ObjectInfo {
+        ObjectInfo {
+            storage_id: 0,
+            object_format: ObjectFormatCode::Standard(StandardObjectFormatCode::Undefined),
+            protection_status: 0,
+            object_compressed_size: 0,
+            thumb_format: ObjectFormatCode::Standard(StandardObjectFormatCode::Undefined),
+            thumb_compressed_size: 0,
+            thumb_pix_width: 0,
+            thumb_pix_height: 0,
+            image_pix_width: 0,
+            image_pix_height: 0,
+            image_bit_depth: 0,
+            parent_object: 0,
+            association_type: AssociationCode::Standard(StandardAssociationCode::Undefined),
+            association_desc: 0,
+            sequence_number: 0,
+            filename: String::new(),
+            capture_date: String::new(),
+            modification_date: String::new(),
+            keywords: String::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct StorageInfo {
     pub storage_type: StorageType,
     pub filesystem_type: FilesystemType,
@@ -207,9 +540,41 @@ pub struct StorageInfo {
 impl StorageInfo {
     pub fn decode<T: PtpRead>(cur: &mut T) -> Result<StorageInfo, Error> {
         Ok(StorageInfo {
-            storage_type: StorageType::from_u16(cur.read_ptp_u16()?).unwrap(),
-            filesystem_type: FilesystemType::from_u16(cur.read_ptp_u16()?).unwrap(),
-            access_capability: AccessType::from_u16(cur.read_ptp_u16()?).unwrap(),
+            // codes the standard reserves can't come from a well-formed
+            // dataset, though vendor filesystem types can
+            storage_type: {
+                let code = cur.read_ptp_u16()?;
+                match StorageType::from_u16(code) {
+                    Some(StorageType::Reserved(_)) | None => {
+                        return Err(Error::Malformed(format!(
+                            "Invalid storage type {:x}.",
+                            code
+                        )))
+                    }
+                    Some(storage_type) => storage_type,
+                }
+            },
+            filesystem_type: {
+                let code = cur.read_ptp_u16()?;
+                match FilesystemType::from_u16(code) {
+                    Some(FilesystemType::Reserved(_)) | None => {
+                        return Err(Error::Malformed(format!(
+                            "Invalid filesystem type {:x}.",
+                            code
+                        )))
+                    }
+                    Some(filesystem_type) => filesystem_type,
+                }
+            },
+            access_capability: {
+                let code = cur.read_ptp_u16()?;
+                match AccessType::from_u16(code) {
+                    Some(AccessType::Reserved(_)) | None => {
+                        return Err(Error::Malformed(format!("Invalid access type {:x}.", code)))
+                    }
+                    Some(access_type) => access_type,
+                }
+            },
             max_capacity: cur.read_ptp_u64()?,
             free_space_in_bytes: cur.read_ptp_u64()?,
             free_space_in_images: cur.read_ptp_u32()?,
@@ -217,10 +582,143 @@ impl StorageInfo {
             volume_label: cur.read_ptp_str()?,
         })
     }
+
+    pub fn encode<W: WriteBytesExt>(&self, mut w: W) -> Result<(), Error> {
+        let code =
+            |c: Option<u16>| c.ok_or_else(|| Error::Malformed("Invalid storage code".into()));
+
+        w.write_ptp_u16(code(self.storage_type.to_u16())?)?;
+        w.write_ptp_u16(code(self.filesystem_type.to_u16())?)?;
+        w.write_ptp_u16(code(self.access_capability.to_u16())?)?;
+        w.write_ptp_u64(self.max_capacity)?;
+        w.write_ptp_u64(self.free_space_in_bytes)?;
+        w.write_ptp_u32(self.free_space_in_images)?;
+        w.write_ptp_str(&self.storage_description)?;
+        w.write_ptp_str(&self.volume_label)?;
+        Ok(())
+    }
+}
+
+/// An entry of the filesystem manifest returned by GetFilesystemManifest
+/// (ObjectFilesystemInfo dataset, PTP 1.1).
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ObjectFilesystemInfo {
+    pub object_handle: ObjectHandle,
+    pub storage_id: u32,
+    pub object_format: ObjectFormatCode,
+    pub protection_status: u16,
+    pub object_compressed_size: u64,
+    pub parent_object: u32,
+    pub association_type: AssociationCode,
+    pub association_desc: u32,
+    pub sequence_number: u32,
+    pub filename: String,
+    pub modification_date: String,
+}
+
+impl ObjectFilesystemInfo {
+    pub fn decode<T: PtpRead>(cur: &mut T) -> Result<ObjectFilesystemInfo, Error> {
+        Ok(ObjectFilesystemInfo {
+            object_handle: ObjectHandle(cur.read_ptp_u32()?),
+            storage_id: cur.read_ptp_u32()?,
+            object_format: ObjectFormatCode::from_u16(cur.read_ptp_u16()?)
+                .ok_or(Error::BadObjectFormat)?,
+            protection_status: cur.read_ptp_u16()?,
+            object_compressed_size: cur.read_ptp_u64()?,
+            parent_object: cur.read_ptp_u32()?,
+            association_type: AssociationCode::from_u16(cur.read_ptp_u16()?)
+                .ok_or(Error::BadAssociationCode)?,
+            association_desc: cur.read_ptp_u32()?,
+            sequence_number: cur.read_ptp_u32()?,
+            filename: cur.read_ptp_str()?,
+            modification_date: cur.read_ptp_str()?,
+        })
+    }
+
+    /// Converts the entry into an ObjectInfo. Fields the manifest doesn't
+    /// carry (thumbnail details, dimensions, capture date, keywords) are
+    /// zero or empty, and sizes beyond 4 GiB saturate at u32::MAX.
+    pub fn to_object_info(&self) -> ObjectInfo {
+        ObjectInfo {
+            storage_id: self.storage_id,
+            object_format: self.object_format,
+            protection_status: self.protection_status,
+            object_compressed_size: self.object_compressed_size.min(u32::MAX as u64) as u32,
+            parent_object: self.parent_object,
+            association_type: self.association_type,
+            association_desc: self.association_desc,
+            sequence_number: self.sequence_number,
+            filename: self.filename.clone(),
+            modification_date: self.modification_date.clone(),
+            ..ObjectInfo::default()
+        }
+    }
+
+    /// Decodes a whole manifest: a u64 count followed by that many entries.
+    pub fn decode_manifest(buf: &[u8]) -> Result<Vec<ObjectFilesystemInfo>, Error> {
+        let mut cur = Cursor::new(buf);
+
+        let count = cur.read_ptp_u64()?;
+        let mut entries = vec![];
+        for _ in 0..count {
+            entries.push(ObjectFilesystemInfo::decode(&mut cur)?);
+        }
+        cur.expect_end()?;
+
+        Ok(entries)
+    }
 }
 
+/// Describes a stream offered through GetStream (StreamInfo dataset, PTP 1.1).
 #[derive(Debug, Clone)]
-#[cfg_attr(feature = "serde", derive(Serialize))]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct StreamInfo {
+    pub dataset_size: u64,
+    pub time_resolution: u64,
+    pub frame_header_size: u32,
+    pub frame_max_size: u32,
+    pub packet_header_size: u32,
+    pub packet_max_size: u32,
+    pub packet_alignment: u32,
+}
+
+impl StreamInfo {
+    pub fn decode<T: PtpRead>(cur: &mut T) -> Result<StreamInfo, Error> {
+        Ok(StreamInfo {
+            dataset_size: cur.read_ptp_u64()?,
+            time_resolution: cur.read_ptp_u64()?,
+            frame_header_size: cur.read_ptp_u32()?,
+            frame_max_size: cur.read_ptp_u32()?,
+            packet_header_size: cur.read_ptp_u32()?,
+            packet_max_size: cur.read_ptp_u32()?,
+            packet_alignment: cur.read_ptp_u32()?,
+        })
+    }
+}
+
+/// Maps a code of the device's native vendor extension onto the equivalent
+/// code of another extension (VendorExtensionMap dataset, PTP 1.1).
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct VendorExtensionMap {
+    pub native_code: u16,
+    pub mapped_code: u16,
+    pub mapped_vendor_ex_id: u32,
+}
+
+impl VendorExtensionMap {
+    pub fn decode<T: PtpRead>(cur: &mut T) -> Result<VendorExtensionMap, Error> {
+        Ok(VendorExtensionMap {
+            native_code: cur.read_ptp_u16()?,
+            mapped_code: cur.read_ptp_u16()?,
+            mapped_vendor_ex_id: cur.read_ptp_u32()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum FormData {
     None,
     Range {
@@ -233,11 +731,11 @@ pub enum FormData {
     },
 }
 
-#[derive(Debug, Clone)]
-#[cfg_attr(feature = "serde", derive(Serialize))]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct PropInfo {
     pub property_code: u16,
-    pub data_type: u16,
+    pub data_type: DataType,
     pub get_set: u8,
     pub is_enable: u8,
     pub factory_default: Data,
@@ -251,7 +749,7 @@ impl PropInfo {
         Ok(PropInfo {
             property_code: cur.read_u16::<LittleEndian>()?,
             data_type: {
-                data_type = cur.read_u16::<LittleEndian>()?;
+                data_type = DataType::read(cur)?;
                 data_type
             },
             get_set: cur.read_u8()?,
@@ -281,25 +779,89 @@ impl PropInfo {
             },
         })
     }
+
+    pub fn encode<W: WriteBytesExt>(&self, mut w: W) -> Result<(), Error> {
+        w.write_ptp_u16(self.property_code)?;
+        w.write_ptp_u16(self.data_type as u16)?;
+        w.write_ptp_u8(self.get_set)?;
+        w.write_ptp_u8(self.is_enable)?;
+        self.factory_default.write(&mut w)?;
+        self.current.write(&mut w)?;
+        match &self.form {
+            FormData::None => w.write_ptp_u8(0x00)?,
+            FormData::Range {
+                min_value,
+                max_value,
+                step,
+            } => {
+                w.write_ptp_u8(0x01)?;
+                min_value.write(&mut w)?;
+                max_value.write(&mut w)?;
+                step.write(&mut w)?;
+            }
+            FormData::Enumeration { array } => {
+                w.write_ptp_u8(0x02)?;
+                let len = u16::try_from(array.len()).map_err(|_| {
+                    Error::Malformed(format!("{} enumeration values, at most 65535", array.len()))
+                })?;
+                w.write_ptp_u16(len)?;
+                for item in array {
+                    item.write(&mut w)?;
+                }
+            }
+        }
+        Ok(())
+    }
 }
 
+/// The header of a USB PTP container
 #[derive(Debug, Clone)]
-#[cfg_attr(feature = "serde", derive(Serialize))]
-struct ContainerInfo {
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ContainerInfo {
     /// payload len in bytes, usually relevant for data phases
-    payload_len: usize,
+    pub payload_len: usize,
 
     /// Container kind
-    kind: ContainerType,
+    pub kind: ContainerType,
 
     /// StandardCommandCode or ResponseCode, depending on 'kind'
-    code: u16,
+    pub code: u16,
 
     /// transaction ID that this container belongs to
-    tid: u32,
+    pub tid: u32,
 }
 
-const PTP_CONTAINER_INFO_SIZE: usize = 12;
+pub const PTP_CONTAINER_INFO_SIZE: usize = 12;
+
+/// Largest data phase payload `Device` accepts by default, see
+/// `Device::set_max_payload_size`.
+pub const DEFAULT_MAX_PAYLOAD_SIZE: usize = 1024 * 1024 * 1024;
+
+// Class request of the still image class returning an event too large for
+// the interrupt endpoint, and the most data it may return
+const CANCEL_REQUEST: u8 = 0x64;
+// the cancellation code Cancel Request data starts with
+const CANCEL_CODE: u16 = 0x4001;
+const GET_EXTENDED_EVENT_DATA: u8 = 0x65;
+const DEVICE_RESET: u8 = 0x66;
+const GET_DEVICE_STATUS: u8 = 0x67;
+const DEVICE_STATUS_SIZE: usize = 64;
+// how often Get Device Status is asked again while the device is busy
+const DEVICE_STATUS_INTERVAL: Duration = Duration::from_millis(20);
+const EXTENDED_EVENT_DATA_SIZE: usize = 4096;
+const DEFAULT_CONTROL_TIMEOUT: Duration = Duration::from_secs(1);
+
+// Rounds `len` up to whole packets of `packet_size`, if it is known
+fn round_up(len: usize, packet_size: usize) -> usize {
+    match packet_size {
+        0 => len,
+        _ => len.div_ceil(packet_size) * packet_size,
+    }
+}
+
+/// How often `Device::event` polls a device without an interrupt endpoint by
+/// default, see `Device::set_event_poll_interval`.
+pub const DEFAULT_EVENT_POLL_INTERVAL: Duration = Duration::from_millis(250);
 
 impl ContainerInfo {
     pub fn parse<R: ReadBytesExt>(mut r: R) -> Result<ContainerInfo, Error> {
@@ -310,8 +872,12 @@ impl ContainerInfo {
         let code = r.read_u16::<LittleEndian>()?;
         let tid = r.read_u32::<LittleEndian>()?;
 
+        let payload_len = (len as usize)
+            .checked_sub(PTP_CONTAINER_INFO_SIZE)
+            .ok_or_else(|| Error::Malformed(format!("Container length {} too short.", len)))?;
+
         Ok(ContainerInfo {
-            payload_len: len as usize - PTP_CONTAINER_INFO_SIZE,
+            payload_len,
             kind,
             code,
             tid,
@@ -324,63 +890,75 @@ impl ContainerInfo {
     }
 }
 
-pub struct Device<C: rusb::UsbContext> {
-    iface: u8,
-    ep_in: u8,
-    ep_out: u8,
-    ep_int: u8,
-    current_tid: std::sync::atomic::AtomicU32,
-    handle: Arc<rusb::DeviceHandle<C>>,
+/// A PTP device, driven through a `Transport`.
+///
+/// `Device` is `Sync` and can be shared between threads. Transactions are
+/// run one at a time, in order of their `Priority`; events can be read while
+/// a transaction is running.
+pub struct Device<T: Transport = UsbTransport<rusb::Context>> {
+    max_payload_size: usize,
+    retry_policy: RetryPolicy,
+    recovery_policy: RecoveryPolicy,
+    current_tid: AtomicU32,
+    // ID of the open session, or 0 if there is none
+    session_id: AtomicU32,
+    info: RwLock<Option<DeviceInfo>>,
+    vendor: RwLock<Option<&'static VendorProfile>>,
+    queue: TransactionQueue,
+    buffers: BufferPool,
+    event_poll_interval: Duration,
+    poller: Mutex<EventPoller>,
+    transport: T,
 }
 
-impl<C: rusb::UsbContext> Device<C> {
-    pub fn new(handle: Arc<rusb::DeviceHandle<C>>) -> Result<Device<C>, Error> {
-        let config_desc = handle.device().active_config_descriptor()?;
-
-        let interface_desc = config_desc
-            .interfaces()
-            .flat_map(|i| i.descriptors())
-            .find(|x| x.class_code() == 6)
-            .ok_or(rusb::Error::NotFound)?;
-
-        debug!("Found interface {}", interface_desc.interface_number());
-
-        handle.claim_interface(interface_desc.interface_number())?;
-        handle.set_alternate_setting(
-            interface_desc.interface_number(),
-            interface_desc.setting_number(),
-        )?;
-
-        let find_endpoint = |direction, transfer_type| {
-            interface_desc
-                .endpoint_descriptors()
-                .find(|ep| ep.direction() == direction && ep.transfer_type() == transfer_type)
-                .map(|x| x.address())
-                .ok_or(rusb::Error::NotFound)
-        };
+impl<C: rusb::UsbContext> Device<UsbTransport<C>> {
+    pub fn new(handle: Arc<rusb::DeviceHandle<C>>) -> Result<Device<UsbTransport<C>>, Error> {
+        Ok(Device::with_transport(UsbTransport::new(handle)?))
+    }
+}
 
-        Ok(Device {
-            iface: interface_desc.interface_number(),
-            ep_in: find_endpoint(rusb::Direction::In, rusb::TransferType::Bulk)?,
-            ep_out: find_endpoint(rusb::Direction::Out, rusb::TransferType::Bulk)?,
-            ep_int: find_endpoint(rusb::Direction::In, rusb::TransferType::Interrupt)?,
+impl<T: Transport> Device<T> {
+    pub fn with_transport(transport: T) -> Device<T> {
+        Device {
+            max_payload_size: DEFAULT_MAX_PAYLOAD_SIZE,
+            retry_policy: RetryPolicy::none(),
+            recovery_policy: RecoveryPolicy::default(),
             current_tid: AtomicU32::new(0),
-            handle: handle,
-        })
+            session_id: AtomicU32::new(0),
+            info: RwLock::new(None),
+            vendor: RwLock::new(None),
+            queue: TransactionQueue::default(),
+            buffers: BufferPool::default(),
+            event_poll_interval: DEFAULT_EVENT_POLL_INTERVAL,
+            poller: Mutex::default(),
+            transport,
+        }
+    }
+
+    pub fn transport(&self) -> &T {
+        &self.transport
     }
 
     /// Queries the PTP camera for an event. Returns Ok(None) if the operation
     /// times out without receiving an event.
+    ///
+    /// Devices without an interrupt endpoint are polled for events within
+    /// the open session, see `EventPolling`; without a session this waits
+    /// out the timeout.
     pub fn event(&self, timeout: Option<Duration>) -> Result<Option<Event>, Error> {
         // timeout of 0 means unlimited timeout.
         let timeout = timeout.unwrap_or(Duration::new(0, 0));
 
+        if !self.transport.has_interrupt() {
+            return self.poll_event(timeout);
+        }
+
         // let tid = self.current_tid;
         // self.current_tid += 1;
 
         // read both, check the status on the response, and return the data payload, if any.
         loop {
-            let (container, payload) = match self.read_txn_phase_interrupt(timeout) {
+            let (container, payload, complete) = match self.read_txn_phase_interrupt(timeout) {
                 Ok(v) => v,
                 Err(Error::Usb(rusb::Error::Timeout)) => return Ok(None),
                 Err(e) => return Err(e),
@@ -399,19 +977,121 @@ impl<C: rusb::UsbContext> Device<C> {
             //     )));
             // }
 
-            match container.kind {
-                ContainerType::Event => {
-                    return Event::new(container.code, payload.as_ref()).map(|p| Some(p));
+            if container.kind == ContainerType::Event {
+                let mut event = Event::new(container.code, payload.as_ref())?;
+                if !complete {
+                    debug!("event {:#06x} was cut short", container.code);
+                    match self.get_extended_event_data(None) {
+                        Ok(extended) => event.extended = Some(extended),
+                        Err(e) => warn!("getting extended event data failed: {}", e),
+                    }
                 }
-                _ => {}
+                return Ok(Some(event));
             }
         }
     }
 
+    fn poll_event(&self, timeout: Duration) -> Result<Option<Event>, Error> {
+        let start = Instant::now();
+
+        loop {
+            if self.session_id.load(Ordering::Acquire) != 0 {
+                let mut poller = self.poller.lock().unwrap_or_else(|e| e.into_inner());
+                if let Some(event) = poller.next() {
+                    return Ok(Some(event));
+                }
+
+                let strategy = match poller.strategy {
+                    Some(strategy) => strategy,
+                    None => {
+                        let info = match self.device_info() {
+                            Some(info) => info,
+                            None => self.get_device_info(None)?,
+                        };
+                        let strategy = EventPolling::detect(&info);
+                        debug!("polling for events with {:?}", strategy);
+                        *poller.strategy.insert(strategy)
+                    }
+                };
+                poller.poll(self, strategy)?;
+                if let Some(event) = poller.next() {
+                    return Ok(Some(event));
+                }
+            }
+
+            let elapsed = start.elapsed();
+            if !timeout.is_zero() && elapsed >= timeout {
+                return Ok(None);
+            }
+            let wait = if timeout.is_zero() {
+                self.event_poll_interval
+            } else {
+                self.event_poll_interval.min(timeout - elapsed)
+            };
+            thread::sleep(wait);
+        }
+    }
+
+    /// Sets how often `event` polls a device without an interrupt endpoint.
+    /// Defaults to `DEFAULT_EVENT_POLL_INTERVAL`.
+    pub fn set_event_poll_interval(&mut self, interval: Duration) {
+        self.event_poll_interval = interval;
+    }
+
+    /// Sets how `event` polls a device without an interrupt endpoint,
+    /// instead of picking a strategy from its `DeviceInfo`.
+    pub fn set_event_polling(&mut self, strategy: EventPolling) {
+        self.poller
+            .get_mut()
+            .unwrap_or_else(|e| e.into_inner())
+            .strategy = Some(strategy);
+    }
+
+    /// Sets the largest data phase payload the device may send. Larger
+    /// containers fail with `Error::PayloadTooLarge` before anything is
+    /// allocated for them. Defaults to `DEFAULT_MAX_PAYLOAD_SIZE`.
+    pub fn set_max_payload_size(&mut self, max: usize) {
+        self.max_payload_size = max;
+    }
+
+    /// Sets the policy `command` and `transaction` use to retry transient
+    /// errors. Defaults to `RetryPolicy::none()`.
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.retry_policy = policy;
+    }
+
+    pub fn retry_policy(&self) -> &RetryPolicy {
+        &self.retry_policy
+    }
+
+    /// Sets the pool bulk reads take their buffers from, e.g. one shared
+    /// with other devices. Defaults to `BufferPool::default()`.
+    pub fn set_buffer_pool(&mut self, pool: BufferPool) {
+        self.buffers = pool;
+    }
+
+    pub fn buffer_pool(&self) -> &BufferPool {
+        &self.buffers
+    }
+
+    /// Sets how the bulk pipes are recovered after a transfer stalls or
+    /// babbles. Defaults to `RecoveryPolicy::default()`, which recovers
+    /// automatically.
+    pub fn set_recovery_policy(&mut self, policy: RecoveryPolicy) {
+        self.recovery_policy = policy;
+    }
+
+    pub fn recovery_policy(&self) -> &RecoveryPolicy {
+        &self.recovery_policy
+    }
+
     pub fn reset(&mut self) -> Result<(), Error> {
-        self.handle.reset()?;
+        self.transport.reset()
+    }
 
-        Ok(())
+    /// Returns the number of transactions waiting for the device.
+    pub fn pending_transactions(&self) -> usize {
+        self.queue.waiting()
     }
 
     /// execute a PTP transaction.
@@ -420,8 +1100,17 @@ impl<C: rusb::UsbContext> Device<C> {
     ///  - command data (optional, if `data` is Some)
     ///  - response data (optional, if response contains a payload)
     ///  - response status
+    ///
     /// NB: each phase involves a separate USB transfer, and `timeout` is used for each phase,
-    /// so the total time taken may be greater than `timeout`.
+    /// so the total time taken may be greater than `timeout`. To bound the whole call, give
+    /// it a `Deadline` with `command_with`.
+    ///
+    /// Transient errors are retried according to the device's `RetryPolicy`.
+    ///
+    /// Errors are wrapped in `Error::Transaction`, which tells the operation,
+    /// transaction and phase that failed. Use `Error::root` or
+    /// `Error::response_code` to look at the error itself rather than
+    /// matching `Error::Response` directly.
     pub fn command(
         &self,
         code: CommandCode,
@@ -429,28 +1118,236 @@ impl<C: rusb::UsbContext> Device<C> {
         data: Option<&[u8]>,
         timeout: Option<Duration>,
     ) -> Result<Vec<u8>, Error> {
+        self.transaction(code, params, data, timeout)
+            .map(|(data, _)| data)
+    }
+
+    /// execute a PTP transaction like `command`, but also return the
+    /// parameters of the response phase.
+    pub fn transaction(
+        &self,
+        code: CommandCode,
+        params: &[u32],
+        data: Option<&[u8]>,
+        timeout: Option<Duration>,
+    ) -> Result<(Vec<u8>, Vec<u32>), Error> {
+        self.transaction_with(code, params, data, timeout, &CallOptions::default())
+    }
+
+    /// execute a PTP transaction like `command`, with per-call `options`.
+    pub fn command_with(
+        &self,
+        code: CommandCode,
+        params: &[u32],
+        data: Option<&[u8]>,
+        timeout: Option<Duration>,
+        options: &CallOptions,
+    ) -> Result<Vec<u8>, Error> {
+        self.transaction_with(code, params, data, timeout, options)
+            .map(|(data, _)| data)
+    }
+
+    /// execute a PTP transaction like `transaction`, with per-call `options`.
+    pub fn transaction_with(
+        &self,
+        code: CommandCode,
+        params: &[u32],
+        data: Option<&[u8]>,
+        timeout: Option<Duration>,
+        options: &CallOptions,
+    ) -> Result<(Vec<u8>, Vec<u32>), Error> {
+        self.transaction_pooled(code, params, data, timeout, options)
+            .map(|(data, params)| (data.into_vec(), params))
+    }
+
+    /// execute a PTP transaction like `command_with`, returning the data in
+    /// a buffer of the device's `BufferPool` instead of a new `Vec`. Frames
+    /// and other data read over and over can be used in place, without
+    /// allocating for each.
+    pub fn command_pooled(
+        &self,
+        code: CommandCode,
+        params: &[u32],
+        data: Option<&[u8]>,
+        timeout: Option<Duration>,
+        options: &CallOptions,
+    ) -> Result<PooledBuffer, Error> {
+        self.transaction_pooled(code, params, data, timeout, options)
+            .map(|(data, _)| data)
+    }
+
+    /// execute a PTP transaction like `transaction_with`, returning the
+    /// data in a buffer of the device's `BufferPool`, see `command_pooled`.
+    pub fn transaction_pooled(
+        &self,
+        code: CommandCode,
+        params: &[u32],
+        data: Option<&[u8]>,
+        timeout: Option<Duration>,
+        options: &CallOptions,
+    ) -> Result<(PooledBuffer, Vec<u32>), Error> {
+        let policy = options.retry.as_ref().unwrap_or(&self.retry_policy);
+        let start = Instant::now();
+        let mut retry = 0;
+
+        loop {
+            let err = match self.transaction_once(code, params, data, timeout, options) {
+                Err(e) if policy.allows(code) && policy.is_transient(&e) => e,
+                result => return result,
+            };
+
+            retry += 1;
+            let backoff = policy.backoff(retry);
+            let out_of_time = policy
+                .deadline
+                .is_some_and(|deadline| start.elapsed() + backoff >= deadline)
+                || options.deadline.is_some_and(|deadline| {
+                    deadline.remaining().is_none_or(|left| left <= backoff)
+                });
+            if retry >= policy.max_attempts || out_of_time {
+                return Err(err);
+            }
+
+            debug!(
+                "{}; retrying in {:?} ({}/{})",
+                err,
+                backoff,
+                retry + 1,
+                policy.max_attempts
+            );
+
+            thread::sleep(backoff);
+            if let Error::Usb(_) = err.root() {
+                self.prepare_retry(&err);
+            }
+        }
+    }
+
+    // A failed transfer may leave an endpoint halted, and the device may
+    // still answer the failed attempt after the host gave up on it. Its
+    // late containers would be taken for the retry's. An attempt that failed
+    // after its command phase is cancelled first, since the device may still
+    // be waiting for the rest of its data and would take the retry for it.
+    fn prepare_retry(&self, err: &Error) {
+        let _turn = self.queue.acquire(Priority::High);
+        if let Some(context) = err.context() {
+            if context.phase != TransactionPhase::Command {
+                if let Err(e) = self.cancel_transaction(context.tid) {
+                    warn!("cancelling transaction {} failed: {}", context.tid, e);
+                }
+            }
+        }
+        for ep in [Endpoint::BulkIn, Endpoint::BulkOut] {
+            if let Err(e) = self.transport.clear_halt(ep) {
+                warn!("clearing halt on {:?} failed: {}", ep, e);
+            }
+        }
+        if let Err(e) = self.discard_stale_containers() {
+            warn!("discarding containers before retrying failed: {}", e);
+        }
+    }
+
+    // Sends the class request Cancel for transaction `tid` and waits for the
+    // device to be ready again
+    fn cancel_transaction(&self, tid: u32) -> Result<(), Error> {
+        let mut request = CANCEL_CODE.to_le_bytes().to_vec();
+        request.extend_from_slice(&tid.to_le_bytes());
+        self.transport
+            .class_request_out(CANCEL_REQUEST, &request, DEFAULT_CONTROL_TIMEOUT)?;
+        debug!("cancelled transaction {}", tid);
+        self.wait_ready()
+    }
+
+    // Performs a single attempt of a transaction.
+    fn transaction_once(
+        &self,
+        code: CommandCode,
+        params: &[u32],
+        data: Option<&[u8]>,
+        timeout: Option<Duration>,
+        options: &CallOptions,
+    ) -> Result<(PooledBuffer, Vec<u32>), Error> {
         // timeout of 0 means unlimited timeout.
-        let timeout = timeout.unwrap_or(Duration::new(0, 0));
+        let limits = TransferLimits::new(timeout.unwrap_or(Duration::new(0, 0)), options.deadline);
 
-        let tid = self.current_tid.fetch_add(1, Ordering::AcqRel);
+        // a call that may no longer start doesn't, before and after waiting
+        // for its turn
+        let check = || {
+            if options.cancel.as_ref().is_some_and(|c| c.is_cancelled()) {
+                return Err(Error::Cancelled);
+            }
+            if options.deadline.is_some_and(|d| d.is_expired()) {
+                return Err(Error::DeadlineExceeded);
+            }
+            Ok(())
+        };
+        check()?;
+
+        // the phases of concurrent transactions must not interleave, and
+        // their IDs must be handed out in the order they run
+        let deadline = options.deadline.map(|deadline| deadline.instant());
+        let _turn = self
+            .queue
+            .acquire_until(options.priority, deadline)
+            .ok_or(Error::DeadlineExceeded)?;
+        check()?;
+        let tid = self.next_tid(code);
+
+        let mut phase = TransactionPhase::Command;
+        let result = self.run_transaction(code, params, data, &limits, tid, &mut phase);
+
+        // recover while this transaction still has its turn, so that the
+        // next one finds the pipes usable
+        if let Err(e) = &result {
+            if e.is_stall() && self.recovery_policy.automatic {
+                debug!("{} in {:?}; recovering", e, phase);
+                if let Err(e) = self.recover_pipes() {
+                    warn!("recovering from a stall failed: {}", e);
+                }
+            }
+        }
 
+        result.map_err(|e| {
+            e.with_context(TransactionContext {
+                code,
+                name: code.name_for(self.vendor()).map(str::to_owned),
+                params: params.to_vec(),
+                tid,
+                phase,
+            })
+        })
+    }
+
+    // Runs the phases of a transaction, keeping `phase` up to date so that
+    // errors can be attributed to it.
+    fn run_transaction(
+        &self,
+        code: CommandCode,
+        params: &[u32],
+        data: Option<&[u8]>,
+        limits: &TransferLimits,
+        tid: u32,
+        phase: &mut TransactionPhase,
+    ) -> Result<(PooledBuffer, Vec<u32>), Error> {
         // Prepare payload of the request phase, containing the parameters
         let mut request_payload = Vec::with_capacity(params.len() * 4);
         for p in params {
             request_payload.write_u32::<LittleEndian>(*p).ok();
         }
 
-        self.write_txn_phase(ContainerType::Command, code, tid, &request_payload, timeout)?;
+        self.write_txn_phase(ContainerType::Command, code, tid, &request_payload, limits)?;
 
         if let Some(data) = data {
-            self.write_txn_phase(ContainerType::Data, code, tid, data, timeout)?;
+            *phase = TransactionPhase::DataOut;
+            self.write_txn_phase(ContainerType::Data, code, tid, data, limits)?;
         }
 
         // request phase is followed by data phase (optional) and response phase.
         // read both, check the status on the response, and return the data payload, if any.
-        let mut data_phase_payload = vec![];
+        *phase = TransactionPhase::DataIn;
+        let mut data_phase_payload = PooledBuffer::empty();
         loop {
-            let (container, payload) = self.read_txn_phase_bulk(timeout)?;
+            let (container, payload) = self.read_txn_phase_bulk(limits)?;
 
             if !container.belongs_to(tid) {
                 return Err(Error::Malformed(format!(
@@ -462,13 +1359,22 @@ impl<C: rusb::UsbContext> Device<C> {
             match container.kind {
                 ContainerType::Data => {
                     data_phase_payload = payload;
+                    *phase = TransactionPhase::Response;
                 }
                 ContainerType::Response => {
-                    let code = ResponseCode::from_u16(container.code).unwrap();
+                    *phase = TransactionPhase::Response;
+                    let code = ResponseCode::from_u16(container.code).ok_or_else(|| {
+                        Error::Malformed(format!("Invalid response code {:x}.", container.code))
+                    })?;
                     if code != ResponseCode::Standard(StandardResponseCode::Ok) {
                         return Err(Error::Response(code));
                     }
-                    return Ok(data_phase_payload);
+
+                    let response_params = payload
+                        .chunks_exact(4)
+                        .map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+                        .collect();
+                    return Ok((data_phase_payload, response_params));
                 }
                 _ => {}
             }
@@ -481,7 +1387,7 @@ impl<C: rusb::UsbContext> Device<C> {
         code: CommandCode,
         tid: u32,
         payload: &[u8],
-        timeout: Duration,
+        limits: &TransferLimits,
     ) -> Result<(), Error> {
         trace!("Write {:?} - 0x{1:04x} ({1:?}), tid:{2}", kind, code, tid);
 
@@ -496,275 +1402,304 @@ impl<C: rusb::UsbContext> Device<C> {
         buf.write_u16::<LittleEndian>(code.to_u16().unwrap()).ok();
         buf.write_u32::<LittleEndian>(tid).ok();
         buf.extend_from_slice(&payload[..first_chunk_payload_bytes]);
-        self.handle.write_bulk(self.ep_out, &buf, timeout)?;
+        self.write_bulk(&buf, limits)?;
 
         // Write any subsequent chunks, straight from the source slice
         for chunk in payload[first_chunk_payload_bytes..].chunks(CHUNK_SIZE) {
-            self.handle.write_bulk(self.ep_out, chunk, timeout)?;
+            self.write_bulk(chunk, limits)?;
+        }
+
+        // a container that ends on a packet boundary is terminated by a
+        // zero-length packet, otherwise the device waits for more
+        let packet_size = self.transport.max_packet_size(Endpoint::BulkOut);
+        if packet_size > 0 && (payload.len() + PTP_CONTAINER_INFO_SIZE).is_multiple_of(packet_size)
+        {
+            trace!("  bulk tx zero-length packet");
+            self.write_bulk(&[], limits)?;
         }
 
         Ok(())
     }
 
-    fn read_txn_phase_bulk(&self, timeout: Duration) -> Result<(ContainerInfo, Vec<u8>), Error> {
-        // buf is stack allocated and intended to be large enough to accomodate
-        // most cmd/ctrl data (ie, not media) without allocating. payload
-        // handling below deals with larger media responses. mark it as
-        // uninitalized to avoid paying for zeroing out 8k of memory, since rust
-        // doesn't know what rusb does with this memory.
-
-        const BUF_SIZE: usize = 8192;
-
-        let mut buf: MaybeUninit<[u8; BUF_SIZE]> = MaybeUninit::uninit();
-        let n =
-            self.handle
-                .read_bulk(self.ep_in, unsafe { &mut (*buf.as_mut_ptr())[..] }, timeout)?;
-        let buf = unsafe { buf.assume_init() };
-        let buf = &buf[..n];
+    // Parses the header at the start of the first transfer of a container
+    // and checks that the announced payload is acceptable.
+    fn parse_container(&self, buf: &[u8]) -> Result<ContainerInfo, Error> {
+        if buf.len() < PTP_CONTAINER_INFO_SIZE {
+            return Err(Error::Malformed(format!(
+                "Received {} bytes, expected a {} byte container header.",
+                buf.len(),
+                PTP_CONTAINER_INFO_SIZE
+            )));
+        }
 
         let cinfo = ContainerInfo::parse(&buf[..PTP_CONTAINER_INFO_SIZE])?;
         trace!("container {:?}", cinfo);
 
-        // no payload? we're done
-        if cinfo.payload_len == 0 {
-            return Ok((cinfo, vec![]));
-        }
-
-        // allocate one extra to avoid a separate read for trailing short packet
-        let mut payload = Vec::with_capacity(cinfo.payload_len + 1);
-        payload.extend_from_slice(&buf[PTP_CONTAINER_INFO_SIZE..]);
-
-        // response didn't fit into our original buf? read the rest
-        // or if our original read were satisfied exactly, so there is still a ZLP to read
-        if payload.len() < cinfo.payload_len || buf.len() == BUF_SIZE {
-            // read in 1MB blocks
-            loop {
-                unsafe {
-                    let p = payload.as_mut_ptr().offset(payload.len() as isize);
-                    let pslice = slice::from_raw_parts_mut(
-                        p,
-                        min(payload.capacity() - payload.len(), 1048576),
-                    );
-                    let n = self.handle.read_bulk(self.ep_in, pslice, timeout)?;
-                    let sz = payload.len();
-                    payload.set_len(sz + n);
-                    trace!(
-                        "  bulk rx {}, ({}/{})",
-                        n,
-                        payload.len(),
-                        payload.capacity()
-                    );
-
-                    if n < pslice.len() {
-                        break;
-                    }
-                }
-            }
+        if cinfo.payload_len > self.max_payload_size {
+            return Err(Error::PayloadTooLarge {
+                len: cinfo.payload_len,
+                max: self.max_payload_size,
+            });
         }
 
-        Ok((cinfo, payload))
+        Ok(cinfo)
     }
 
-    fn read_txn_phase_interrupt(
+    fn read_txn_phase_bulk(
         &self,
-        timeout: Duration,
-    ) -> Result<(ContainerInfo, Vec<u8>), Error> {
-        let mut buf: [u8; 24] = [0u8; 24];
-        let buf = {
-            let n = self
-                .handle
-                .read_interrupt(self.ep_int, &mut buf[..], timeout)?;
-            &buf[..n]
-        };
+        limits: &TransferLimits,
+    ) -> Result<(ContainerInfo, PooledBuffer), Error> {
+        // the first transfer is large enough to accomodate most cmd/ctrl
+        // data (ie, not media) at once; the buffer grows for larger media
+        // responses once the header says how large they are. buffers come
+        // from the pool already initialised, so they are read into directly.
 
-        let cinfo = ContainerInfo::parse(&buf[..])?;
-        trace!("container {:?}", cinfo);
+        const BUF_SIZE: usize = 8192;
+        // largest transfer for the rest of the container, a multiple of any
+        // packet size
+        const CHUNK_SIZE: usize = 1024 * 1024;
+
+        let packet_size = self.transport.max_packet_size(Endpoint::BulkIn);
+
+        let mut buf = self.buffers.take(BUF_SIZE);
+        let n = self.read_bulk(&mut buf.storage_mut()[..BUF_SIZE], limits)?;
+
+        let cinfo = self.parse_container(&buf.storage_mut()[..n])?;
+        let len = PTP_CONTAINER_INFO_SIZE + cinfo.payload_len;
+        if n > len {
+            return Err(Error::Malformed(format!(
+                "received {} bytes of a {} byte container",
+                n, len
+            )));
+        }
 
-        // no payload? we're done
-        if cinfo.payload_len == 0 {
-            warn!("received interrupt data with no payload");
+        // the rest is read in transfers of whole packets, so the buffer
+        // needs room for the last one to be short of the container's end,
+        // and for a zero-length packet after it
+        buf.reserve(n + round_up(len - n, packet_size) + packet_size);
+
+        let mut received = n;
+        let mut filled = n == BUF_SIZE;
+        while received < len {
+            let want = min(round_up(len - received, packet_size), CHUNK_SIZE);
+            let n = self.read_bulk(&mut buf.storage_mut()[received..received + want], limits)?;
+            received += n;
+            trace!("  bulk rx {}, ({}/{})", n, received, len);
+
+            filled = n == want;
+            if received > len || (n < want && received < len) {
+                return Err(Error::Malformed(format!(
+                    "received {} bytes of a {} byte container",
+                    received, len
+                )));
+            }
+        }
 
-            return Err(Error::NoEventPayload);
+        // a transfer that ended exactly on a packet boundary at the end of
+        // the container is followed by a zero-length packet
+        if filled && packet_size > 0 && len.is_multiple_of(packet_size) {
+            let n = self.read_bulk(&mut buf.storage_mut()[len..len + packet_size], limits)?;
+            trace!("  bulk rx {} for the zero-length packet", n);
+            if n != 0 {
+                return Err(Error::Malformed(format!(
+                    "expected a zero-length packet after the container, received {} bytes",
+                    n
+                )));
+            }
         }
 
-        // allocate one extra to avoid a separate read for trailing short packet
-        let mut payload = Vec::with_capacity(cinfo.payload_len + 1);
-        payload.extend_from_slice(&buf[PTP_CONTAINER_INFO_SIZE..]);
+        buf.set_payload(PTP_CONTAINER_INFO_SIZE, len);
+        Ok((cinfo, buf))
+    }
 
-        Ok((cinfo, payload))
+    // Bulk transfers, each within what remains of its call's time
+    fn write_bulk(&self, buf: &[u8], limits: &TransferLimits) -> Result<usize, Error> {
+        let timeout = limits.next()?;
+        self.transport
+            .write_bulk(buf, timeout)
+            .map_err(|e| limits.check(e))
     }
 
-    pub fn get_object_info(
-        &self,
-        handle: ObjectHandle,
-        timeout: Option<Duration>,
-    ) -> Result<ObjectInfo, Error> {
-        let data = self.command(
-            StandardCommandCode::GetObjectInfo.into(),
-            &[handle.0],
-            None,
-            timeout,
-        )?;
-        Ok(ObjectInfo::decode(&data)?)
+    fn read_bulk(&self, buf: &mut [u8], limits: &TransferLimits) -> Result<usize, Error> {
+        let timeout = limits.next()?;
+        self.transport
+            .read_bulk(buf, timeout)
+            .map_err(|e| limits.check(e))
     }
 
-    pub fn send_object_info(
+    // Reads an event container. A container longer than a packet arrives in
+    // several packets, ending with a short one; returns false if the device
+    // ended it before its length, leaving the rest to Get Extended Event
+    // Data.
+    fn read_txn_phase_interrupt(
         &self,
-        handle: StorageId,
-        parent: ObjectHandle,
-        info: ObjectInfo,
-        timeout: Option<Duration>,
-    ) -> Result<ObjectHandle, Error> {
-        let mut data = vec![];
-        info.encode(&mut data)?;
-
-        let data = self.command(
-            StandardCommandCode::SendObjectInfo.into(),
-            &[handle.0, parent.0],
-            Some(&data[..]),
-            timeout,
-        )?;
+        timeout: Duration,
+    ) -> Result<(ContainerInfo, Vec<u8>, bool), Error> {
+        let packet_size = self
+            .transport
+            .max_packet_size(Endpoint::Interrupt)
+            .max(PTP_CONTAINER_INFO_SIZE);
+        let mut buf = vec![0u8; packet_size];
+
+        // skip the zero-length packet that may end an event whose length is
+        // a multiple of the packet size
+        let mut n = 0;
+        while n == 0 {
+            n = self.transport.read_interrupt(&mut buf, timeout)?;
+        }
 
-        let mut cur = Cursor::new(data);
-        // storage ID
-        let _ = cur.read_ptp_u32()?;
-        // parent object handle
-        let _ = cur.read_ptp_u32()?;
-        let object_handle = cur.read_ptp_u32()?;
-        cur.expect_end()?;
+        let cinfo = self.parse_container(&buf[..n])?;
+        let len = PTP_CONTAINER_INFO_SIZE + cinfo.payload_len;
+        let mut container = buf[..n].to_vec();
 
-        Ok(ObjectHandle(object_handle))
-    }
+        let mut requested = buf.len();
+        while container.len() < len && n == requested {
+            requested = round_up(len - container.len(), packet_size);
+            buf.resize(requested, 0);
+            n = self.transport.read_interrupt(&mut buf, timeout)?;
+            container.extend_from_slice(&buf[..n]);
+        }
+        let complete = container.len() >= len;
+        container.truncate(len);
 
-    pub fn send_object(&self, data: &[u8], timeout: Option<Duration>) -> Result<(), Error> {
-        self.command(
-            StandardCommandCode::SendObject.into(),
-            &[],
-            Some(data),
-            timeout,
-        )?;
+        // no payload? we're done
+        if cinfo.payload_len == 0 {
+            warn!("received interrupt data with no payload");
 
-        Ok(())
-    }
+            return Err(Error::NoEventPayload);
+        }
 
-    pub fn get_object(
-        &self,
-        handle: ObjectHandle,
-        timeout: Option<Duration>,
-    ) -> Result<Vec<u8>, Error> {
-        self.command(
-            StandardCommandCode::GetObject.into(),
-            &[handle.0],
-            None,
-            timeout,
-        )
+        Ok((
+            cinfo,
+            container.split_off(PTP_CONTAINER_INFO_SIZE),
+            complete,
+        ))
     }
 
-    pub fn get_partial_object(
+    /// Fetches the data of an event too large for the interrupt endpoint
+    /// with the class request Get Extended Event Data. `event` does this
+    /// when an event arrives cut short.
+    pub fn get_extended_event_data(
         &self,
-        handle: ObjectHandle,
-        offset: u32,
-        len: u32,
         timeout: Option<Duration>,
-    ) -> Result<Vec<u8>, Error> {
-        self.command(
-            StandardCommandCode::GetPartialObject.into(),
-            &[handle.0, offset, len],
-            None,
-            timeout,
-        )
+    ) -> Result<ExtendedEventData, Error> {
+        let mut buf = vec![0u8; EXTENDED_EVENT_DATA_SIZE];
+        let n = self.transport.class_request_in(
+            GET_EXTENDED_EVENT_DATA,
+            &mut buf,
+            timeout.unwrap_or(DEFAULT_CONTROL_TIMEOUT),
+        )?;
+        ExtendedEventData::decode(&buf[..n])
     }
 
-    /// Gets the object handles contained in a storage. If `parent` is not
-    /// specified, it will return every object in the storage. If `parent` is
-    /// ObjectHandle::root(), then it will return only those at the "root"
-    /// level.
-    pub fn get_object_handles(
-        &self,
-        storage_id: StorageId,
-        format: Option<ObjectFormatCode>,
-        parent: Option<ObjectHandle>,
-        timeout: Option<Duration>,
-    ) -> Result<Vec<ObjectHandle>, Error> {
-        let data = self.command(
-            StandardCommandCode::GetObjectHandles.into(),
-            &[
-                storage_id.0,
-                format.map_or(0x0, |fmt| fmt.to_u32().unwrap()),
-                parent.map_or(0x0, |p| p.0),
-            ],
-            None,
-            timeout,
+    /// Asks the device whether it is ready, and which endpoints it has
+    /// halted, with the class request Get Device Status.
+    pub fn get_device_status(&self, timeout: Option<Duration>) -> Result<DeviceStatus, Error> {
+        let mut buf = vec![0u8; DEVICE_STATUS_SIZE];
+        let n = self.transport.class_request_in(
+            GET_DEVICE_STATUS,
+            &mut buf,
+            timeout.unwrap_or(DEFAULT_CONTROL_TIMEOUT),
         )?;
-        // Parse ObjectHandleArrray
-        let mut cur = Cursor::new(data);
-        let value = cur.read_ptp_u32_vec()?;
-        cur.expect_end()?;
+        DeviceStatus::decode(&buf[..n])
+    }
 
-        Ok(value.into_iter().map(|oh| ObjectHandle(oh)).collect())
+    /// Brings the bulk pipes back into step after a transfer stalled or
+    /// babbled: clears the halts on both bulk endpoints, waits for Get
+    /// Device Status to report the device ready, and discards containers
+    /// left over from earlier transactions. With `RecoveryPolicy::device_reset`
+    /// the device is also sent Device Reset, which closes the session.
+    ///
+    /// `command` does this by itself unless the recovery policy is manual.
+    /// Devices that don't implement Get Device Status are assumed ready.
+    pub fn recover(&self) -> Result<(), Error> {
+        let _turn = self.queue.acquire(Priority::High);
+        self.recover_pipes()
     }
 
-    // handle_id: None == root of store
-    pub fn get_num_objects(
-        &self,
-        storage_id: Option<StorageId>,
-        format: Option<ObjectFormatCode>,
-        parent: Option<ObjectHandle>,
-        timeout: Option<Duration>,
-    ) -> Result<u32, Error> {
-        let data = self.command(
-            StandardCommandCode::GetNumObjects.into(),
-            &[
-                storage_id.map_or(0xFFFFFFFF, |sid| sid.0),
-                format.map_or(0x0, |fmt| fmt.to_u32().unwrap()),
-                parent.map_or(0x0, |oh| oh.0),
-            ],
-            None,
-            timeout,
-        )?;
+    // Recovery proper, for the holder of the queue's turn
+    fn recover_pipes(&self) -> Result<(), Error> {
+        for ep in [Endpoint::BulkIn, Endpoint::BulkOut] {
+            self.transport.clear_halt(ep)?;
+        }
 
-        // Parse ObjectHandleArrray
-        let mut cur = Cursor::new(data);
-        let value = cur.read_ptp_u32()?;
-        cur.expect_end()?;
+        if self.recovery_policy.device_reset {
+            self.transport
+                .class_request_out(DEVICE_RESET, &[], DEFAULT_CONTROL_TIMEOUT)?;
+            debug!("reset the device, which closed the session");
+            self.end_session();
+        }
 
-        Ok(value)
+        self.wait_ready()?;
+        self.discard_stale_containers()
     }
 
-    pub fn get_storage_info(
-        &self,
-        storage_id: StorageId,
-        timeout: Option<Duration>,
-    ) -> Result<StorageInfo, Error> {
-        let data = self.command(
-            StandardCommandCode::GetStorageInfo.into(),
-            &[storage_id.0],
-            None,
-            timeout,
-        )?;
+    // Asks for the device status until the device is no longer busy,
+    // clearing any halts it still reports
+    fn wait_ready(&self) -> Result<(), Error> {
+        let start = Instant::now();
+        loop {
+            let status = match self.get_device_status(None) {
+                Ok(status) => status,
+                // stalling the request is how a device says it doesn't
+                // know it
+                Err(Error::NotSupported(_)) | Err(Error::Usb(rusb::Error::Pipe)) => {
+                    debug!("the device has no Get Device Status");
+                    return Ok(());
+                }
+                Err(e) => return Err(e),
+            };
+            trace!("device status {:?}", status);
 
-        // Parse ObjectHandleArrray
-        let mut cur = Cursor::new(data);
-        let res = StorageInfo::decode(&mut cur)?;
-        cur.expect_end()?;
+            if !status.params.is_empty() {
+                for ep in [Endpoint::BulkIn, Endpoint::BulkOut] {
+                    self.transport.clear_halt(ep)?;
+                }
+            } else if !status.is_busy() {
+                if !status.is_ok() {
+                    debug!("device status {} after recovering", status.code);
+                }
+                return Ok(());
+            }
 
-        Ok(res)
+            if start.elapsed() >= self.recovery_policy.ready_timeout {
+                return Err(Error::Response(StandardResponseCode::DeviceBusy.into()));
+            }
+            thread::sleep(DEVICE_STATUS_INTERVAL);
+        }
     }
 
-    pub fn get_storage_ids(&self, timeout: Option<Duration>) -> Result<Vec<StorageId>, Error> {
-        let data = self.command(
-            StandardCommandCode::GetStorageIDs.into(),
-            &[],
-            None,
-            timeout,
-        )?;
-
-        // Parse ObjectHandleArrray
-        let mut cur = Cursor::new(data);
-        let value = cur.read_ptp_u32_vec()?;
-        cur.expect_end()?;
+    // Reads the bulk in endpoint until it has nothing more to give,
+    // dropping the containers of transactions that have already ended
+    fn discard_stale_containers(&self) -> Result<(), Error> {
+        let current = self.current_tid.load(Ordering::Acquire);
+        let in_session = self.session_id.load(Ordering::Acquire) != 0;
+        let drain = TransferLimits::new(self.recovery_policy.drain_timeout, None);
+        let start = Instant::now();
+
+        while start.elapsed() < self.recovery_policy.ready_timeout {
+            match self.read_txn_phase_bulk(&drain) {
+                // outside a session every transaction has ID 0
+                Ok((container, _)) if !in_session || container.tid < current => {
+                    debug!(
+                        "discarding {:?} container of transaction {}",
+                        container.kind, container.tid
+                    );
+                }
+                Ok((container, _)) => {
+                    return Err(Error::Malformed(format!(
+                        "{:?} container of transaction {} while recovering before transaction {}",
+                        container.kind, container.tid, current
+                    )));
+                }
+                Err(Error::Usb(rusb::Error::Timeout)) => return Ok(()),
+                Err(e @ Error::Usb(_)) => return Err(e),
+                // the rest of a container cut short, or garbage
+                Err(e) => debug!("discarding {}", e),
+            }
+        }
 
-        Ok(value.into_iter().map(|sid| StorageId(sid)).collect())
+        Ok(())
     }
 
     pub fn get_device_info(&self, timeout: Option<Duration>) -> Result<DeviceInfo, Error> {
@@ -777,36 +1712,158 @@ impl<C: rusb::UsbContext> Device<C> {
 
         let device_info = DeviceInfo::decode(&data)?;
         debug!("device_info {:?}", device_info);
+
+        let vendor = device_info.vendor_profile();
+        debug!("vendor profile {:?}", vendor.map(|v| v.name));
+        *self.vendor.write().unwrap() = vendor;
+        *self.info.write().unwrap() = Some(device_info.clone());
+
         Ok(device_info)
     }
 
-    pub fn open_session(&self, timeout: Option<Duration>) -> Result<(), Error> {
-        let session_id = 3;
+    /// Returns the DeviceInfo retrieved by the last call to `get_device_info`.
+    pub fn device_info(&self) -> Option<DeviceInfo> {
+        self.info.read().unwrap().clone()
+    }
 
-        self.command(
-            StandardCommandCode::OpenSession.into(),
-            &vec![session_id, 0, 0],
-            None,
-            timeout,
-        )?;
+    /// Returns whether the device supports `code`, as advertised by the
+    /// DeviceInfo from `get_device_info`. Operations introduced by PTP 1.1
+    /// additionally require the device to claim PTP 1.1. Returns None if
+    /// `get_device_info` has not been called yet.
+    pub fn supports(&self, code: CommandCode) -> Option<bool> {
+        let info = self.info.read().unwrap();
+        let info = info.as_ref()?;
 
-        Ok(())
+        let ptp_1_1_only = code
+            .to_u16()
+            .is_some_and(|c| (0x101D..=0x1025).contains(&c));
+
+        Some(info.supports_operation(code) && (!ptp_1_1_only || info.is_ptp_1_1()))
     }
 
-    pub fn close_session(&self, timeout: Option<Duration>) -> Result<(), Error> {
-        self.command(StandardCommandCode::CloseSession.into(), &[], None, timeout)?;
+    // Fails early for operations the device has told us it doesn't support.
+    // If we don't know yet, try anyway and let the device answer.
+    fn require(&self, code: StandardCommandCode, name: &'static str) -> Result<(), Error> {
+        match self.supports(code.into()) {
+            Some(false) => Err(Error::NotSupported(name)),
+            _ => Ok(()),
+        }
+    }
 
-        Ok(())
+    /// Returns the vendor profile selected by the last call to
+    /// `get_device_info`, if the device's vendor extension is known.
+    pub fn vendor(&self) -> Option<&'static VendorProfile> {
+        *self.vendor.read().unwrap()
     }
 
-    pub fn disconnect(&mut self, timeout: Option<Duration>) -> Result<(), Error> {
-        self.close_session(timeout)?;
-        self.handle.release_interface(self.iface)?;
-        Ok(())
+    /// Opens a session. Operations that require a session are available on
+    /// the returned `Session`, which closes the session when dropped.
+    ///
+    /// A device has at most one session at a time, so this fails with
+    /// `SessionAlreadyOpen` while another `Session` of this device is alive.
+    pub fn open_session(&self, config: SessionConfig) -> Result<Session<'_, T>, Error> {
+        let id = config.session_id.get();
+        let already_open = || {
+            Error::Response(ResponseCode::Standard(
+                StandardResponseCode::SessionAlreadyOpen,
+            ))
+        };
+
+        self.session_id
+            .compare_exchange(0, id, Ordering::AcqRel, Ordering::Acquire)
+            .map_err(|_| already_open())?;
+
+        let open = || {
+            self.command(
+                StandardCommandCode::OpenSession.into(),
+                &[id],
+                None,
+                config.timeout,
+            )
+        };
+
+        let result = match open() {
+            Err(e)
+                if e.response_code() == Some(StandardResponseCode::SessionAlreadyOpen.into())
+                    && config.close_existing =>
+            {
+                debug!("closing the session left open on the device");
+                self.current_tid.store(1, Ordering::Release);
+                match self.command(
+                    StandardCommandCode::CloseSession.into(),
+                    &[],
+                    None,
+                    config.timeout,
+                ) {
+                    Ok(_) => open(),
+                    Err(close_err) => {
+                        debug!("closing the existing session failed: {}", close_err);
+                        Err(e)
+                    }
+                }
+            }
+            result => result,
+        };
+
+        if let Err(e) = result {
+            self.end_session();
+            return Err(e);
+        }
+
+        // the first transaction in a session has ID 1
+        self.current_tid.store(1, Ordering::Release);
+        debug!("opened session {}", id);
+
+        Ok(Session::new(self, &config))
+    }
+
+    pub(crate) fn end_session(&self) {
+        self.session_id.store(0, Ordering::Release);
+        self.current_tid.store(0, Ordering::Release);
+        // object handles are only valid within a session
+        self.poller
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .reset();
+    }
+
+    // Transaction IDs are 0 outside of a session and for OpenSession, and
+    // count up from 1 within a session, skipping 0 and 0xFFFFFFFF on wrap.
+    fn next_tid(&self, code: CommandCode) -> u32 {
+        if code == StandardCommandCode::OpenSession.into()
+            || self.session_id.load(Ordering::Acquire) == 0
+        {
+            return 0;
+        }
+
+        self.current_tid
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |tid| {
+                Some(if tid >= 0xFFFF_FFFE { 1 } else { tid + 1 })
+            })
+            .unwrap()
+    }
+
+    /// Releases the interface. A session that was leaked rather than
+    /// dropped is closed first if possible.
+    pub fn disconnect(self) -> Result<(), Error> {
+        if self.session_id.load(Ordering::Acquire) != 0 {
+            if let Err(e) = self.command(
+                StandardCommandCode::CloseSession.into(),
+                &[],
+                None,
+                Some(Duration::from_secs(1)),
+            ) {
+                warn!("closing the session failed: {}", e);
+            }
+            self.end_session();
+        }
+
+        self.transport.release()
     }
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct ObjectTree {
     pub handle: ObjectHandle,
     pub info: ObjectInfo,
@@ -0,0 +1,467 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+use byteorder::{BigEndian, ByteOrder, LittleEndian, WriteBytesExt};
+use log::trace;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    CommandCode, Error, ObjectHandle, ResponseCode, Session, StandardCommandCode, Transport, CANON,
+    NIKON, SONY,
+};
+
+/// A rectangle within the live view image, in image pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct FocusArea {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// A single viewfinder frame.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct LiveViewFrame {
+    /// JPEG-encoded image
+    pub jpeg: Vec<u8>,
+
+    /// Active autofocus area, if reported by the device
+    pub focus_area: Option<FocusArea>,
+
+    /// Histogram of the image, if reported by the device
+    pub histogram: Option<Histogram>,
+
+    /// Vendor-specific metadata blocks that were not decoded, as (type, data),
+    /// e.g. Canon's zoom blocks
+    pub extra: Vec<(u32, Vec<u8>)>,
+}
+
+impl LiveViewFrame {
+    fn from_jpeg(jpeg: Vec<u8>) -> LiveViewFrame {
+        LiveViewFrame {
+            jpeg,
+            focus_area: None,
+            histogram: None,
+            extra: vec![],
+        }
+    }
+}
+
+/// Pixel counts of a live view image, 256 bins per channel from dark to
+/// bright.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Histogram {
+    pub luminance: Vec<u32>,
+
+    /// Red, green and blue, if reported by the device
+    pub rgb: Option<[Vec<u32>; 3]>,
+}
+
+/// Returns the time between frames at `fps` frames per second, which must
+/// be positive and finite, and not so small that the time overflows.
+pub(crate) fn frame_interval(fps: f64) -> Result<Duration, Error> {
+    if !fps.is_finite() || fps <= 0.0 {
+        return Err(Error::InvalidFrameRate(fps));
+    }
+    Duration::try_from_secs_f64(1.0 / fps).map_err(|_| Error::InvalidFrameRate(fps))
+}
+
+/// A vendor-agnostic viewfinder stream.
+pub trait LiveView {
+    /// Switches the device into live view mode.
+    fn start(&mut self, timeout: Option<Duration>) -> Result<(), Error>;
+
+    /// Fetches the latest frame. Returns Ok(None) if the device has no frame
+    /// ready yet, which is common right after `start`.
+    fn next_frame(&mut self, timeout: Option<Duration>) -> Result<Option<LiveViewFrame>, Error>;
+
+    /// Leaves live view mode.
+    fn stop(&mut self, timeout: Option<Duration>) -> Result<(), Error>;
+
+    /// Returns an iterator that yields frames at no more than `fps` frames
+    /// per second. Live view must already be started. Fails with
+    /// `Error::InvalidFrameRate` unless `fps` is positive and finite.
+    fn frames(self, fps: f64, timeout: Option<Duration>) -> Result<LiveViewFrames<Self>, Error>
+    where
+        Self: Sized,
+    {
+        LiveViewFrames::new(self, fps, timeout)
+    }
+}
+
+impl<L: LiveView + ?Sized> LiveView for Box<L> {
+    fn start(&mut self, timeout: Option<Duration>) -> Result<(), Error> {
+        (**self).start(timeout)
+    }
+
+    fn next_frame(&mut self, timeout: Option<Duration>) -> Result<Option<LiveViewFrame>, Error> {
+        (**self).next_frame(timeout)
+    }
+
+    fn stop(&mut self, timeout: Option<Duration>) -> Result<(), Error> {
+        (**self).stop(timeout)
+    }
+}
+
+/// Iterator over live view frames, paced to a target frame rate.
+pub struct LiveViewFrames<L: LiveView> {
+    view: L,
+    interval: Duration,
+    next_due: Instant,
+    timeout: Option<Duration>,
+}
+
+impl<L: LiveView> LiveViewFrames<L> {
+    pub fn new(view: L, fps: f64, timeout: Option<Duration>) -> Result<LiveViewFrames<L>, Error> {
+        Ok(LiveViewFrames {
+            view,
+            interval: frame_interval(fps)?,
+            next_due: Instant::now(),
+            timeout,
+        })
+    }
+
+    /// Returns the underlying live view, e.g. to stop it.
+    pub fn into_inner(self) -> L {
+        self.view
+    }
+}
+
+impl<L: LiveView> Iterator for LiveViewFrames<L> {
+    type Item = Result<LiveViewFrame, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let now = Instant::now();
+            if now < self.next_due {
+                thread::sleep(self.next_due - now);
+            }
+
+            // schedule from the previous deadline so the average rate holds,
+            // but don't try to catch up after falling far behind
+            self.next_due = (self.next_due + self.interval).max(Instant::now());
+
+            match self.view.next_frame(self.timeout) {
+                Ok(Some(frame)) => return Some(Ok(frame)),
+                Ok(None) => continue,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+// Response codes used by devices to say that a frame is not available yet.
+fn is_not_ready(err: &Error) -> bool {
+    const CANON_NOT_READY: u16 = 0xA102;
+
+    err.is_device_busy() || err.response_code() == Some(ResponseCode::Other(CANON_NOT_READY))
+}
+
+/// Live view for Canon EOS cameras, using GetViewFinderData.
+pub struct CanonLiveView<'a, T: Transport> {
+    session: &'a Session<'a, T>,
+}
+
+impl<'a, T: Transport> CanonLiveView<'a, T> {
+    const SET_DEVICE_PROP_VALUE_EX: u16 = 0x9110;
+    const GET_VIEWFINDER_DATA: u16 = 0x9153;
+    const EVF_OUTPUT_DEVICE: u32 = 0xD1B0;
+    const EVF_OUTPUT_PC: u32 = 2;
+    const EVF_OUTPUT_NONE: u32 = 0;
+
+    const BLOCK_JPEG: u32 = 1;
+    const BLOCK_HISTOGRAM: u32 = 3;
+    const HISTOGRAM_BINS: usize = 256;
+
+    pub fn new(session: &'a Session<'a, T>) -> CanonLiveView<'a, T> {
+        CanonLiveView { session }
+    }
+
+    fn set_output_device(&self, value: u32, timeout: Option<Duration>) -> Result<(), Error> {
+        let mut data = vec![];
+        data.write_u32::<LittleEndian>(12)?;
+        data.write_u32::<LittleEndian>(Self::EVF_OUTPUT_DEVICE)?;
+        data.write_u32::<LittleEndian>(value)?;
+
+        self.session.command(
+            CommandCode::Other(Self::SET_DEVICE_PROP_VALUE_EX),
+            &[],
+            Some(&data),
+            timeout,
+        )?;
+        Ok(())
+    }
+
+    /// Splits GetViewFinderData's payload, a sequence of
+    /// `[u32 length][u32 type][data]` blocks, into a frame. Type 1 is the
+    /// JPEG and type 3 the histogram, 256 u32 bins of luminance followed by
+    /// 256 each of red, green and blue on bodies that report them.
+    pub fn parse_frame(data: &[u8]) -> Result<Option<LiveViewFrame>, Error> {
+        let mut jpeg = None;
+        let mut histogram = None;
+        let mut extra = vec![];
+
+        let mut rest = data;
+        while rest.len() >= 8 {
+            let len = LittleEndian::read_u32(&rest[0..4]) as usize;
+            let kind = LittleEndian::read_u32(&rest[4..8]);
+            if len < 8 || len > rest.len() {
+                return Err(Error::Malformed(format!(
+                    "viewfinder block length {} out of range ({} bytes left)",
+                    len,
+                    rest.len()
+                )));
+            }
+
+            let block = &rest[8..len];
+            match kind {
+                Self::BLOCK_JPEG if jpeg.is_none() => jpeg = Some(block.to_vec()),
+                Self::BLOCK_HISTOGRAM if histogram.is_none() => {
+                    histogram = Some(Self::parse_histogram(block)?)
+                }
+                _ => extra.push((kind, block.to_vec())),
+            }
+            rest = &rest[len..];
+        }
+
+        Ok(jpeg.map(|jpeg| LiveViewFrame {
+            histogram,
+            extra,
+            ..LiveViewFrame::from_jpeg(jpeg)
+        }))
+    }
+
+    fn parse_histogram(block: &[u8]) -> Result<Histogram, Error> {
+        let channel_size = Self::HISTOGRAM_BINS * 4;
+        let mut channels = block.chunks_exact(channel_size).map(|channel| {
+            channel
+                .chunks_exact(4)
+                .map(LittleEndian::read_u32)
+                .collect::<Vec<_>>()
+        });
+
+        match block.len() / channel_size {
+            n if block.len().is_multiple_of(channel_size) && (n == 1 || n == 4) => {
+                let luminance = channels.next().unwrap_or_default();
+                let rgb = match (channels.next(), channels.next(), channels.next()) {
+                    (Some(red), Some(green), Some(blue)) => Some([red, green, blue]),
+                    _ => None,
+                };
+                Ok(Histogram { luminance, rgb })
+            }
+            _ => Err(Error::Malformed(format!(
+                "viewfinder histogram of {} bytes",
+                block.len()
+            ))),
+        }
+    }
+}
+
+impl<T: Transport> LiveView for CanonLiveView<'_, T> {
+    fn start(&mut self, timeout: Option<Duration>) -> Result<(), Error> {
+        self.set_output_device(Self::EVF_OUTPUT_PC, timeout)
+    }
+
+    fn next_frame(&mut self, timeout: Option<Duration>) -> Result<Option<LiveViewFrame>, Error> {
+        let data = match self.session.command_pooled(
+            CommandCode::Other(Self::GET_VIEWFINDER_DATA),
+            &[0x0010_0000, 0, 0],
+            None,
+            timeout,
+        ) {
+            Ok(data) => data,
+            Err(ref e) if is_not_ready(e) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        trace!("canon viewfinder data: {} bytes", data.len());
+
+        Self::parse_frame(&data)
+    }
+
+    fn stop(&mut self, timeout: Option<Duration>) -> Result<(), Error> {
+        self.set_output_device(Self::EVF_OUTPUT_NONE, timeout)
+    }
+}
+
+/// Live view for Nikon cameras, using GetLiveViewImg.
+pub struct NikonLiveView<'a, T: Transport> {
+    session: &'a Session<'a, T>,
+}
+
+impl<'a, T: Transport> NikonLiveView<'a, T> {
+    const START_LIVE_VIEW: u16 = 0x9201;
+    const END_LIVE_VIEW: u16 = 0x9202;
+    const GET_LIVE_VIEW_IMG: u16 = 0x9203;
+    const DEVICE_READY: u16 = 0x90C8;
+
+    pub fn new(session: &'a Session<'a, T>) -> NikonLiveView<'a, T> {
+        NikonLiveView { session }
+    }
+
+    /// Splits GetLiveViewImg's payload into its header and JPEG. The header
+    /// length varies by model, so the image is located by its SOI marker.
+    /// The header starts with big-endian u16 pairs: image size, whole size,
+    /// display area size, display center, AF frame size and AF frame center.
+    pub fn parse_frame(data: &[u8]) -> Result<Option<LiveViewFrame>, Error> {
+        let start = match data.windows(3).position(|w| w == [0xFF, 0xD8, 0xFF]) {
+            Some(start) => start,
+            None => return Ok(None),
+        };
+
+        let header = &data[..start];
+        let focus_area = if header.len() >= 24 {
+            let field = |i: usize| BigEndian::read_u16(&header[i * 2..i * 2 + 2]) as u32;
+            let (width, height) = (field(8), field(9));
+            let (center_x, center_y) = (field(10), field(11));
+            Some(FocusArea {
+                x: center_x.saturating_sub(width / 2),
+                y: center_y.saturating_sub(height / 2),
+                width,
+                height,
+            })
+        } else {
+            None
+        };
+
+        Ok(Some(LiveViewFrame {
+            focus_area,
+            ..LiveViewFrame::from_jpeg(data[start..].to_vec())
+        }))
+    }
+}
+
+impl<T: Transport> LiveView for NikonLiveView<'_, T> {
+    fn start(&mut self, timeout: Option<Duration>) -> Result<(), Error> {
+        self.session.command(
+            CommandCode::Other(Self::START_LIVE_VIEW),
+            &[],
+            None,
+            timeout,
+        )?;
+
+        // the camera answers DeviceBusy until the mirror is up
+        let deadline = Instant::now() + timeout.unwrap_or(Duration::from_secs(5));
+        loop {
+            match self
+                .session
+                .command(CommandCode::Other(Self::DEVICE_READY), &[], None, timeout)
+            {
+                Ok(_) => return Ok(()),
+                Err(ref e) if is_not_ready(e) && Instant::now() < deadline => {
+                    thread::sleep(Duration::from_millis(50));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn next_frame(&mut self, timeout: Option<Duration>) -> Result<Option<LiveViewFrame>, Error> {
+        let data = match self.session.command_pooled(
+            CommandCode::Other(Self::GET_LIVE_VIEW_IMG),
+            &[],
+            None,
+            timeout,
+        ) {
+            Ok(data) => data,
+            Err(ref e) if is_not_ready(e) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        trace!("nikon live view data: {} bytes", data.len());
+
+        Self::parse_frame(&data)
+    }
+
+    fn stop(&mut self, timeout: Option<Duration>) -> Result<(), Error> {
+        self.session
+            .command(CommandCode::Other(Self::END_LIVE_VIEW), &[], None, timeout)?;
+        Ok(())
+    }
+}
+
+/// Live view for Sony cameras, which expose the viewfinder as a virtual
+/// object fetched with GetObject.
+pub struct SonyLiveView<'a, T: Transport> {
+    session: &'a Session<'a, T>,
+}
+
+impl<'a, T: Transport> SonyLiveView<'a, T> {
+    const LIVE_VIEW_OBJECT: ObjectHandle = ObjectHandle(0xFFFF_C002);
+
+    pub fn new(session: &'a Session<'a, T>) -> SonyLiveView<'a, T> {
+        SonyLiveView { session }
+    }
+
+    /// Splits the live view object, which starts with the little-endian
+    /// offset and size of the JPEG within it.
+    pub fn parse_frame(data: &[u8]) -> Result<Option<LiveViewFrame>, Error> {
+        if data.len() < 8 {
+            return Ok(None);
+        }
+
+        let offset = LittleEndian::read_u32(&data[0..4]) as usize;
+        let size = LittleEndian::read_u32(&data[4..8]) as usize;
+        let jpeg = offset
+            .checked_add(size)
+            .and_then(|end| data.get(offset..end))
+            .ok_or_else(|| {
+                Error::Malformed(format!(
+                    "live view image {}+{} exceeds {} bytes",
+                    offset,
+                    size,
+                    data.len()
+                ))
+            })?;
+
+        if jpeg.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(LiveViewFrame::from_jpeg(jpeg.to_vec())))
+    }
+}
+
+impl<T: Transport> LiveView for SonyLiveView<'_, T> {
+    fn start(&mut self, _timeout: Option<Duration>) -> Result<(), Error> {
+        // the live view object is always available while the camera is in
+        // PC remote mode
+        Ok(())
+    }
+
+    fn next_frame(&mut self, timeout: Option<Duration>) -> Result<Option<LiveViewFrame>, Error> {
+        let data = match self.session.command_pooled(
+            StandardCommandCode::GetObject.into(),
+            &[Self::LIVE_VIEW_OBJECT.0],
+            None,
+            timeout,
+        ) {
+            Ok(data) => data,
+            Err(ref e) if is_not_ready(e) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        trace!("sony live view data: {} bytes", data.len());
+
+        Self::parse_frame(&data)
+    }
+
+    fn stop(&mut self, _timeout: Option<Duration>) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl<T: Transport> Session<'_, T> {
+    /// Returns the live view implementation for this device's vendor, as
+    /// detected by `Device::get_device_info`.
+    pub fn live_view(&self) -> Result<Box<dyn LiveView + '_>, Error> {
+        match self.device().vendor() {
+            Some(v) if std::ptr::eq(v, &CANON) => Ok(Box::new(CanonLiveView::new(self))),
+            Some(v) if std::ptr::eq(v, &NIKON) => Ok(Box::new(NikonLiveView::new(self))),
+            Some(v) if std::ptr::eq(v, &SONY) => Ok(Box::new(SonyLiveView::new(self))),
+            _ => Err(Error::NotSupported("live view")),
+        }
+    }
+}
@@ -0,0 +1,275 @@
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::{debug, trace, warn};
+
+use crate::liveview::frame_interval;
+use crate::{Error, LiveView};
+
+const BOUNDARY: &str = "ptpframe";
+
+// How often idle loops re-check the stop flag.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// How long a client may take to send its request head, and a viewer to
+// accept a frame, before it is dropped.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Default)]
+struct Shared {
+    /// latest frame and its sequence number
+    frame: Mutex<(u64, Arc<Vec<u8>>)>,
+    frame_ready: Condvar,
+    viewers: AtomicUsize,
+    stopped: AtomicBool,
+}
+
+impl Shared {
+    fn stop(&self) {
+        self.stopped.store(true, Ordering::Release);
+        self.frame_ready.notify_all();
+    }
+
+    fn publish(&self, jpeg: Vec<u8>) {
+        let mut frame = self.frame.lock().unwrap();
+        *frame = (frame.0 + 1, Arc::new(jpeg));
+        self.frame_ready.notify_all();
+    }
+
+    // Blocks until a frame newer than `seq` is available, or the server stops.
+    fn wait_newer(&self, seq: u64) -> Option<(u64, Arc<Vec<u8>>)> {
+        let mut frame = self.frame.lock().unwrap();
+        loop {
+            if self.stopped.load(Ordering::Acquire) {
+                return None;
+            }
+            if frame.0 > seq {
+                return Some(frame.clone());
+            }
+            frame = self
+                .frame_ready
+                .wait_timeout(frame, POLL_INTERVAL)
+                .unwrap()
+                .0;
+        }
+    }
+}
+
+/// Stops a running `MjpegServer` from another thread.
+#[derive(Clone)]
+pub struct MjpegHandle {
+    shared: Arc<Shared>,
+}
+
+impl MjpegHandle {
+    pub fn stop(&self) {
+        self.shared.stop();
+    }
+
+    /// Number of currently connected viewers
+    pub fn viewers(&self) -> usize {
+        self.shared.viewers.load(Ordering::Acquire)
+    }
+}
+
+/// A minimal HTTP server that streams live view frames as
+/// `multipart/x-mixed-replace` MJPEG. Every request path serves the stream.
+///
+/// A single poll loop reads frames from the camera and fans them out to all
+/// connected viewers; the camera is not polled while nobody is watching.
+pub struct MjpegServer {
+    listener: TcpListener,
+    shared: Arc<Shared>,
+}
+
+impl MjpegServer {
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> Result<MjpegServer, Error> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+
+        Ok(MjpegServer {
+            listener,
+            shared: Arc::default(),
+        })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, Error> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    pub fn handle(&self) -> MjpegHandle {
+        MjpegHandle {
+            shared: self.shared.clone(),
+        }
+    }
+
+    /// Serves frames from `view` at up to `fps` frames per second until
+    /// stopped through a `MjpegHandle`, or until reading a frame fails with
+    /// an error that isn't `Error::is_retryable`; retryable ones are logged
+    /// and the frame is skipped.
+    /// Live view must already be started. Fails with
+    /// `Error::InvalidFrameRate` unless `fps` is positive and finite.
+    pub fn run<L: LiveView + ?Sized>(
+        self,
+        view: &mut L,
+        fps: f64,
+        timeout: Option<Duration>,
+    ) -> Result<(), Error> {
+        let interval = frame_interval(fps)?;
+        let MjpegServer { listener, shared } = self;
+
+        let acceptor = {
+            let shared = shared.clone();
+            thread::spawn(move || accept_loop(listener, shared))
+        };
+
+        let result = poll_loop(&shared, view, interval, timeout);
+
+        shared.stop();
+        acceptor.join().ok();
+
+        result
+    }
+}
+
+fn poll_loop<L: LiveView + ?Sized>(
+    shared: &Shared,
+    view: &mut L,
+    interval: Duration,
+    timeout: Option<Duration>,
+) -> Result<(), Error> {
+    let mut next_due = Instant::now();
+
+    while !shared.stopped.load(Ordering::Acquire) {
+        if shared.viewers.load(Ordering::Acquire) == 0 {
+            thread::sleep(POLL_INTERVAL);
+            continue;
+        }
+
+        let now = Instant::now();
+        if now < next_due {
+            thread::sleep(next_due - now);
+        }
+        next_due = (next_due + interval).max(Instant::now());
+
+        match view.next_frame(timeout) {
+            Ok(Some(frame)) => {
+                trace!("mjpeg frame: {} bytes", frame.jpeg.len());
+                shared.publish(frame.jpeg);
+            }
+            Ok(None) => {}
+            // viewers keep the last frame until the camera recovers
+            Err(e) if e.is_retryable() => warn!("mjpeg frame skipped: {}", e),
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(())
+}
+
+fn accept_loop(listener: TcpListener, shared: Arc<Shared>) {
+    while !shared.stopped.load(Ordering::Acquire) {
+        match listener.accept() {
+            Ok((stream, peer)) => {
+                debug!("mjpeg client connected: {}", peer);
+                // viewer threads are not joined: each one exits by itself
+                // once the server stops or its client times out
+                let shared = shared.clone();
+                thread::spawn(move || {
+                    if let Err(e) = serve_viewer(stream, &shared) {
+                        debug!("mjpeg viewer {} disconnected: {}", peer, e);
+                    }
+                });
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                thread::sleep(POLL_INTERVAL);
+            }
+            Err(e) => {
+                warn!("mjpeg accept failed: {}", e);
+                thread::sleep(POLL_INTERVAL);
+            }
+        }
+    }
+}
+
+// Counts a viewer for as long as it is alive
+struct ViewerGuard<'a>(&'a Shared);
+
+impl<'a> ViewerGuard<'a> {
+    fn new(shared: &'a Shared) -> ViewerGuard<'a> {
+        shared.viewers.fetch_add(1, Ordering::AcqRel);
+        ViewerGuard(shared)
+    }
+}
+
+impl Drop for ViewerGuard<'_> {
+    fn drop(&mut self) {
+        self.0.viewers.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+// Skips the request head, since we serve the stream regardless of method or
+// path. Gives up when the server stops or the client is too slow.
+fn skip_request_head(stream: &TcpStream, shared: &Shared) -> io::Result<()> {
+    stream.set_read_timeout(Some(POLL_INTERVAL))?;
+    let deadline = Instant::now() + CLIENT_TIMEOUT;
+
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    loop {
+        match reader.read_line(&mut line) {
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(_) if line == "\r\n" || line == "\n" => return Ok(()),
+            Ok(_) => line.clear(),
+            // a partial line stays in `line` and is completed by the next read
+            Err(ref e)
+                if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut =>
+            {
+                if shared.stopped.load(Ordering::Acquire) {
+                    return Err(io::ErrorKind::Interrupted.into());
+                }
+                if Instant::now() >= deadline {
+                    return Err(io::ErrorKind::TimedOut.into());
+                }
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn serve_viewer(mut stream: TcpStream, shared: &Shared) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    skip_request_head(&stream, shared)?;
+    // a viewer that stops reading is dropped rather than blocking forever
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+    let _viewer = ViewerGuard::new(shared);
+
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: multipart/x-mixed-replace; boundary={}\r\n\
+         Cache-Control: no-cache, no-store\r\n\
+         Connection: close\r\n\r\n",
+        BOUNDARY
+    )?;
+
+    let mut seq = 0;
+    while let Some((next, jpeg)) = shared.wait_newer(seq) {
+        seq = next;
+        write!(
+            stream,
+            "--{}\r\nContent-Type: image/jpeg\r\nContent-Length: {}\r\n\r\n",
+            BOUNDARY,
+            jpeg.len()
+        )?;
+        stream.write_all(&jpeg)?;
+        stream.write_all(b"\r\n")?;
+        stream.flush()?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+use std::io::Cursor;
+
+use num_traits::{FromPrimitive, ToPrimitive};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    AssociationCode, Data, DataType, Error, ObjectFormatCode, ObjectHandle, ObjectInfo, PtpRead,
+};
+
+/// MTP operation GetObjectPropList
+pub const MTP_GET_OBJECT_PROP_LIST: u16 = 0x9805;
+
+/// MTP event ObjectPropChanged, with the object handle and property code as
+/// parameters
+pub const MTP_OBJECT_PROP_CHANGED: u16 = 0xC801;
+
+/// Object property code standing for "all properties" in GetObjectPropList
+pub const MTP_ALL_PROPERTIES: u32 = 0xFFFF_FFFF;
+
+/// Object handle standing for "all objects" in GetObjectPropList
+pub const MTP_ALL_OBJECTS: u32 = 0xFFFF_FFFF;
+
+/// MTP object property codes that correspond to ObjectInfo fields.
+pub mod object_prop {
+    pub const STORAGE_ID: u16 = 0xDC01;
+    pub const OBJECT_FORMAT: u16 = 0xDC02;
+    pub const PROTECTION_STATUS: u16 = 0xDC03;
+    pub const OBJECT_SIZE: u16 = 0xDC04;
+    pub const ASSOCIATION_TYPE: u16 = 0xDC05;
+    pub const ASSOCIATION_DESC: u16 = 0xDC06;
+    pub const OBJECT_FILE_NAME: u16 = 0xDC07;
+    pub const DATE_CREATED: u16 = 0xDC08;
+    pub const DATE_MODIFIED: u16 = 0xDC09;
+    pub const KEYWORDS: u16 = 0xDC0A;
+    pub const PARENT_OBJECT: u16 = 0xDC0B;
+    pub const WIDTH: u16 = 0xDC87;
+    pub const HEIGHT: u16 = 0xDC88;
+}
+
+/// One element of an MTP ObjectPropList dataset.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ObjectPropListEntry {
+    pub object_handle: ObjectHandle,
+    pub property_code: u16,
+    pub value: Data,
+}
+
+impl ObjectPropListEntry {
+    pub fn decode<T: PtpRead>(cur: &mut T) -> Result<ObjectPropListEntry, Error> {
+        let object_handle = ObjectHandle(cur.read_ptp_u32()?);
+        let property_code = cur.read_ptp_u16()?;
+        let data_type = DataType::read(cur)?;
+
+        Ok(ObjectPropListEntry {
+            object_handle,
+            property_code,
+            value: Data::read_type(data_type, cur)?,
+        })
+    }
+
+    /// Decodes a whole ObjectPropList: a u32 count followed by that many
+    /// elements.
+    pub fn decode_list(buf: &[u8]) -> Result<Vec<ObjectPropListEntry>, Error> {
+        let mut cur = Cursor::new(buf);
+        let value = cur.read_ptp_vec(ObjectPropListEntry::decode)?;
+        cur.expect_end()?;
+
+        Ok(value)
+    }
+
+    /// Groups a property list by object and builds an ObjectInfo for each
+    /// object, in the order the objects first appear. Fields that have no
+    /// MTP property equivalent (thumbnail details, bit depth, ...) are zero.
+    pub fn into_object_infos(
+        entries: Vec<ObjectPropListEntry>,
+    ) -> Result<Vec<(ObjectHandle, ObjectInfo)>, Error> {
+        let mut order = vec![];
+        let mut infos: HashMap<ObjectHandle, ObjectInfo> = HashMap::new();
+
+        for entry in entries {
+            let info = infos.entry(entry.object_handle).or_insert_with(|| {
+                order.push(entry.object_handle);
+                ObjectInfo::default()
+            });
+
+            let value = &entry.value;
+            match entry.property_code {
+                object_prop::STORAGE_ID => info.storage_id = prop_u32(value)?,
+                object_prop::OBJECT_FORMAT => {
+                    info.object_format = ObjectFormatCode::from_u32(prop_u32(value)?)
+                        .ok_or(Error::BadObjectFormat)?
+                }
+                object_prop::PROTECTION_STATUS => info.protection_status = prop_u32(value)? as u16,
+                object_prop::OBJECT_SIZE => {
+                    let size = value.to_u64().ok_or_else(|| bad_prop(value))?;
+                    info.object_compressed_size = size.min(u32::MAX as u64) as u32
+                }
+                object_prop::ASSOCIATION_TYPE => {
+                    info.association_type = AssociationCode::from_u32(prop_u32(value)?)
+                        .ok_or(Error::BadAssociationCode)?
+                }
+                object_prop::ASSOCIATION_DESC => info.association_desc = prop_u32(value)?,
+                object_prop::OBJECT_FILE_NAME => info.filename = prop_str(value)?,
+                object_prop::DATE_CREATED => info.capture_date = prop_str(value)?,
+                object_prop::DATE_MODIFIED => info.modification_date = prop_str(value)?,
+                object_prop::KEYWORDS => info.keywords = prop_str(value)?,
+                object_prop::PARENT_OBJECT => info.parent_object = prop_u32(value)?,
+                object_prop::WIDTH => info.image_pix_width = prop_u32(value)?,
+                object_prop::HEIGHT => info.image_pix_height = prop_u32(value)?,
+                _ => {}
+            }
+        }
+
+        Ok(order
+            .into_iter()
+            .map(|handle| (handle, infos.remove(&handle).unwrap()))
+            .collect())
+    }
+}
+
+fn bad_prop(value: &Data) -> Error {
+    Error::Malformed(format!("unexpected object property value {:?}", value))
+}
+
+fn prop_u32(value: &Data) -> Result<u32, Error> {
+    value.to_u32().ok_or_else(|| bad_prop(value))
+}
+
+fn prop_str(value: &Data) -> Result<String, Error> {
+    match value {
+        Data::STR(s) => Ok(s.clone()),
+        _ => Err(bad_prop(value)),
+    }
+}
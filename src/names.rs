@@ -0,0 +1,37 @@
+/// Implements `name()`, `description()` and `from_name()` for a fieldless
+/// code enum. Every variant must be listed along with its description.
+macro_rules! code_names {
+    ($ty:ident { $($variant:ident => $desc:expr,)* }) => {
+        impl $ty {
+            /// Returns the name of this code as given in the specification
+            pub fn name(&self) -> &'static str {
+                match self {
+                    $($ty::$variant => stringify!($variant),)*
+                }
+            }
+
+            /// Returns a short description of this code
+            pub fn description(&self) -> &'static str {
+                match self {
+                    $($ty::$variant => $desc,)*
+                }
+            }
+
+            /// Looks up a code by its name, ignoring case
+            pub fn from_name(name: &str) -> Option<$ty> {
+                [$($ty::$variant),*]
+                    .iter()
+                    .copied()
+                    .find(|code| code.name().eq_ignore_ascii_case(name))
+            }
+        }
+    };
+}
+
+/// Parses a numeric code given as hex (`"0x1009"`) or decimal.
+pub(crate) fn parse_code(s: &str) -> Option<u16> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
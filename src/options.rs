@@ -0,0 +1,146 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::{Error, Priority, RetryPolicy};
+
+// Shortest timeout given to a transfer, as a timeout of 0 means none at all
+const MIN_TRANSFER_TIMEOUT: Duration = Duration::from_millis(1);
+
+/// Per-call settings for `Device::command_with` and `Device::transaction_with`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CallOptions {
+    /// Policy to retry transient errors with, instead of the device's policy
+    pub retry: Option<RetryPolicy>,
+
+    /// Position of the transaction among those waiting for the device
+    pub priority: Priority,
+
+    /// Time by which the whole call, including waiting for its turn and any
+    /// retries, must be done. Each transfer gets at most the time remaining,
+    /// and the call fails with `Error::DeadlineExceeded` once it is up.
+    pub deadline: Option<Deadline>,
+
+    /// Stops the call before its transaction starts, or before a retry,
+    /// with `Error::Cancelled`. A transaction that has started runs to
+    /// completion, so that the device is never left halfway through one.
+    pub cancel: Option<CancelToken>,
+}
+
+impl CallOptions {
+    /// Options that only raise the priority of the call, so that short
+    /// operations such as property reads don't wait behind long downloads.
+    pub fn high_priority() -> CallOptions {
+        CallOptions {
+            priority: Priority::High,
+            ..CallOptions::default()
+        }
+    }
+
+    /// Options that only give the call a deadline.
+    pub fn deadline(deadline: Deadline) -> CallOptions {
+        CallOptions {
+            deadline: Some(deadline),
+            ..CallOptions::default()
+        }
+    }
+}
+
+/// A point in time by which an operation must be done, as opposed to the
+/// timeout of a single transfer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Deadline(Instant);
+
+impl Deadline {
+    /// The deadline `timeout` from now
+    pub fn after(timeout: Duration) -> Deadline {
+        Deadline(Instant::now() + timeout)
+    }
+
+    pub fn at(instant: Instant) -> Deadline {
+        Deadline(instant)
+    }
+
+    pub fn instant(&self) -> Instant {
+        self.0
+    }
+
+    /// Returns the time left, or None once the deadline has passed
+    pub fn remaining(&self) -> Option<Duration> {
+        self.0
+            .checked_duration_since(Instant::now())
+            .filter(|left| !left.is_zero())
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.remaining().is_none()
+    }
+}
+
+/// Cancels the calls it was given to in `CallOptions`. Clones cancel the
+/// same calls.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Release);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Acquire)
+    }
+}
+
+impl PartialEq for CancelToken {
+    fn eq(&self, other: &CancelToken) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+/// The time each transfer of a call may take: the per-transfer timeout (0
+/// for none), cut short by the call's deadline
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct TransferLimits {
+    timeout: Duration,
+    deadline: Option<Deadline>,
+}
+
+impl TransferLimits {
+    pub(crate) fn new(timeout: Duration, deadline: Option<Deadline>) -> TransferLimits {
+        TransferLimits { timeout, deadline }
+    }
+
+    /// Returns the timeout for the next transfer
+    pub(crate) fn next(&self) -> Result<Duration, Error> {
+        let deadline = match self.deadline {
+            Some(deadline) => deadline,
+            None => return Ok(self.timeout),
+        };
+        let remaining = deadline
+            .remaining()
+            .ok_or(Error::DeadlineExceeded)?
+            .max(MIN_TRANSFER_TIMEOUT);
+        Ok(match self.timeout {
+            timeout if timeout.is_zero() => remaining,
+            timeout => timeout.min(remaining),
+        })
+    }
+
+    /// Tells a transfer that timed out because the deadline passed from one
+    /// that ran out of its own timeout
+    pub(crate) fn check(&self, err: Error) -> Error {
+        match err {
+            Error::Usb(rusb::Error::Timeout)
+                if self.deadline.is_some_and(|deadline| deadline.is_expired()) =>
+            {
+                Error::DeadlineExceeded
+            }
+            err => err,
+        }
+    }
+}
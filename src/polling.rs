@@ -0,0 +1,259 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::convert::TryFrom;
+use std::io::Cursor;
+use std::ptr;
+
+use log::debug;
+use num_traits::FromPrimitive;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    CallOptions, CommandCode, Data, Device, DeviceInfo, Error, Event, EventCode,
+    ObjectPropListEntry, Priority, PtpRead, StandardCommandCode, StandardEventCode, Transport,
+    CANON, MTP_ALL_OBJECTS, MTP_ALL_PROPERTIES, MTP_GET_OBJECT_PROP_LIST, MTP_OBJECT_PROP_CHANGED,
+    NIKON,
+};
+
+/// Canon EOS operation GetEvent
+pub const CANON_EOS_GET_EVENT: u16 = 0x9116;
+
+/// Nikon operation GetEvent
+pub const NIKON_GET_EVENT: u16 = 0x90C7;
+
+/// How `Device::event` finds out about events on a device without an
+/// interrupt endpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum EventPolling {
+    /// Canon EOS GetEvent, which returns the events queued since the last
+    /// call
+    CanonGetEvent,
+
+    /// Nikon GetEvent, which returns the events queued since the last call
+    NikonGetEvent,
+
+    /// Compares the MTP object property lists of successive polls,
+    /// reporting ObjectAdded, ObjectRemoved and ObjectPropChanged
+    ObjectPropList,
+
+    /// Compares the object handles of successive polls, reporting
+    /// ObjectAdded and ObjectRemoved
+    ObjectHandles,
+}
+
+impl EventPolling {
+    /// Picks the strategy for a device: its vendor's GetEvent operation if
+    /// it has one, else the most detailed comparison it supports.
+    pub fn detect(info: &DeviceInfo) -> EventPolling {
+        let vendor = info.vendor_profile();
+        let is = |profile| vendor.is_some_and(|v| ptr::eq(v, profile));
+        let supports = |code| info.supports_operation(CommandCode::Other(code));
+
+        if is(&CANON) && supports(CANON_EOS_GET_EVENT) {
+            EventPolling::CanonGetEvent
+        } else if is(&NIKON) && supports(NIKON_GET_EVENT) {
+            EventPolling::NikonGetEvent
+        } else if supports(MTP_GET_OBJECT_PROP_LIST) {
+            EventPolling::ObjectPropList
+        } else {
+            EventPolling::ObjectHandles
+        }
+    }
+}
+
+/// Decodes the data of a Canon EOS GetEvent: records of a u32 length, a u32
+/// event code and parameters, ending with an empty record. Records whose
+/// code doesn't fit an event code are skipped.
+pub fn decode_canon_events(buf: &[u8]) -> Result<Vec<Event>, Error> {
+    let mut events = vec![];
+    let mut cur = Cursor::new(buf);
+
+    while (cur.position() as usize) < buf.len() {
+        let start = cur.position() as usize;
+        let len = cur.read_ptp_u32()? as usize;
+        let code = cur.read_ptp_u32()?;
+        if len < 8 || start + len > buf.len() {
+            return Err(Error::Malformed(format!(
+                "Canon event record of {} bytes at offset {}",
+                len, start
+            )));
+        }
+        if len == 8 && code == 0 {
+            break;
+        }
+
+        match u16::try_from(code).ok().and_then(EventCode::from_u16) {
+            Some(code) => events.push(Event {
+                code,
+                params: buf[start + 8..start + len]
+                    .chunks_exact(4)
+                    .map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+                    .collect(),
+                extended: None,
+            }),
+            None => debug!("skipping Canon event record {:#x}", code),
+        }
+        cur.set_position((start + len) as u64);
+    }
+
+    Ok(events)
+}
+
+/// Decodes the data of a Nikon GetEvent: a u16 count followed by that many
+/// pairs of a u16 event code and a u32 parameter.
+pub fn decode_nikon_events(buf: &[u8]) -> Result<Vec<Event>, Error> {
+    let mut cur = Cursor::new(buf);
+    let count = cur.read_ptp_u16()?;
+
+    let events = (0..count)
+        .map(|_| {
+            let code = cur.read_ptp_u16()?;
+            let param = cur.read_ptp_u32()?;
+            Ok(Event {
+                code: EventCode::from_u16(code).ok_or(Error::BadEventCode)?,
+                params: vec![param],
+                extended: None,
+            })
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    cur.expect_end()?;
+
+    Ok(events)
+}
+
+fn object_event(code: StandardEventCode, handle: u32) -> Event {
+    Event {
+        code: code.into(),
+        params: vec![handle],
+        extended: None,
+    }
+}
+
+// What the device looked like at the last poll
+enum Snapshot {
+    Handles(BTreeSet<u32>),
+    Props(BTreeMap<(u32, u16), Data>),
+}
+
+impl Snapshot {
+    // Events for the differences from `old` to `self`
+    fn diff(&self, old: &Snapshot) -> Vec<Event> {
+        use StandardEventCode::{ObjectAdded, ObjectRemoved};
+
+        match (old, self) {
+            (Snapshot::Handles(old), Snapshot::Handles(new)) => new
+                .difference(old)
+                .map(|&h| object_event(ObjectAdded, h))
+                .chain(old.difference(new).map(|&h| object_event(ObjectRemoved, h)))
+                .collect(),
+            (Snapshot::Props(old), Snapshot::Props(new)) => {
+                let handles = |props: &BTreeMap<(u32, u16), Data>| -> BTreeSet<u32> {
+                    props.keys().map(|&(handle, _)| handle).collect()
+                };
+                let (old_handles, new_handles) = (handles(old), handles(new));
+
+                let mut events: Vec<Event> = new_handles
+                    .difference(&old_handles)
+                    .map(|&h| object_event(ObjectAdded, h))
+                    .chain(
+                        old_handles
+                            .difference(&new_handles)
+                            .map(|&h| object_event(ObjectRemoved, h)),
+                    )
+                    .collect();
+                events.extend(
+                    new.iter()
+                        .filter(|(key, value)| {
+                            old_handles.contains(&key.0) && old.get(key) != Some(value)
+                        })
+                        .map(|(&(handle, prop), _)| Event {
+                            code: EventCode::Vendor(MTP_OBJECT_PROP_CHANGED),
+                            params: vec![handle, prop.into()],
+                            extended: None,
+                        }),
+                );
+                events
+            }
+            _ => vec![],
+        }
+    }
+}
+
+/// State of event polling on a device without an interrupt endpoint
+#[derive(Default)]
+pub(crate) struct EventPoller {
+    pub(crate) strategy: Option<EventPolling>,
+    pending: VecDeque<Event>,
+    snapshot: Option<Snapshot>,
+}
+
+impl EventPoller {
+    pub(crate) fn next(&mut self) -> Option<Event> {
+        self.pending.pop_front()
+    }
+
+    /// Asks the device for events once, queueing any it reports. The first
+    /// poll of a strategy that compares snapshots only takes the snapshot.
+    pub(crate) fn poll<T: Transport>(
+        &mut self,
+        device: &Device<T>,
+        strategy: EventPolling,
+    ) -> Result<(), Error> {
+        // events shouldn't hold up the caller's own transactions
+        let options = CallOptions {
+            priority: Priority::Low,
+            ..CallOptions::default()
+        };
+        let command = |code: CommandCode, params: &[u32]| {
+            device.command_with(code, params, None, None, &options)
+        };
+
+        let snapshot = match strategy {
+            EventPolling::CanonGetEvent => {
+                let data = command(CommandCode::Other(CANON_EOS_GET_EVENT), &[])?;
+                self.pending.extend(decode_canon_events(&data)?);
+                return Ok(());
+            }
+            EventPolling::NikonGetEvent => {
+                let data = command(CommandCode::Other(NIKON_GET_EVENT), &[])?;
+                self.pending.extend(decode_nikon_events(&data)?);
+                return Ok(());
+            }
+            EventPolling::ObjectPropList => {
+                let data = command(
+                    CommandCode::Other(MTP_GET_OBJECT_PROP_LIST),
+                    &[MTP_ALL_OBJECTS, 0, MTP_ALL_PROPERTIES, 0, 0],
+                )?;
+                let props = ObjectPropListEntry::decode_list(&data)?
+                    .into_iter()
+                    .map(|e| ((e.object_handle.0, e.property_code), e.value))
+                    .collect();
+                Snapshot::Props(props)
+            }
+            EventPolling::ObjectHandles => {
+                let data = command(
+                    StandardCommandCode::GetObjectHandles.into(),
+                    &[0xFFFF_FFFF, 0, 0],
+                )?;
+                let mut cur = Cursor::new(data);
+                let handles = cur.read_ptp_u32_vec()?.into_iter().collect();
+                cur.expect_end()?;
+                Snapshot::Handles(handles)
+            }
+        };
+
+        if let Some(old) = &self.snapshot {
+            self.pending.extend(snapshot.diff(old));
+        }
+        self.snapshot = Some(snapshot);
+        Ok(())
+    }
+
+    /// Forgets the snapshot, e.g. when the session it was taken in ends
+    pub(crate) fn reset(&mut self) {
+        self.pending.clear();
+        self.snapshot = None;
+    }
+}
@@ -0,0 +1,96 @@
+use std::cmp::Reverse;
+use std::collections::BTreeSet;
+use std::sync::{Condvar, Mutex};
+use std::time::Instant;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// How soon a transaction gets the device when several threads are waiting
+/// for it. Waiting transactions run highest priority first, and in the order
+/// they were started within a priority. A running transaction is never
+/// interrupted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+// Waiting transactions, ordered by priority and then by ticket
+type Ticket = (Reverse<Priority>, u64);
+
+#[derive(Default)]
+struct QueueState {
+    busy: bool,
+    next_ticket: u64,
+    waiting: BTreeSet<Ticket>,
+}
+
+/// Lets one transaction at a time use the bulk endpoints.
+#[derive(Default)]
+pub(crate) struct TransactionQueue {
+    state: Mutex<QueueState>,
+    ready: Condvar,
+}
+
+/// The right to use the bulk endpoints, handed to the next waiting
+/// transaction when dropped.
+pub(crate) struct Turn<'a> {
+    queue: &'a TransactionQueue,
+}
+
+impl TransactionQueue {
+    /// Blocks until it is the caller's turn.
+    pub fn acquire(&self, priority: Priority) -> Turn<'_> {
+        self.acquire_until(priority, None).unwrap()
+    }
+
+    /// Blocks until it is the caller's turn, or returns None if that is
+    /// not before `deadline`.
+    pub fn acquire_until(&self, priority: Priority, deadline: Option<Instant>) -> Option<Turn<'_>> {
+        let mut state = self.state.lock().unwrap();
+        let ticket = (Reverse(priority), state.next_ticket);
+        state.next_ticket += 1;
+        state.waiting.insert(ticket);
+
+        while state.busy || state.waiting.first() != Some(&ticket) {
+            state = match deadline {
+                None => self.ready.wait(state).unwrap(),
+                Some(deadline) => {
+                    let left = deadline.saturating_duration_since(Instant::now());
+                    if left.is_zero() {
+                        // the next in line may be waiting for this one to go
+                        state.waiting.remove(&ticket);
+                        self.ready.notify_all();
+                        return None;
+                    }
+                    self.ready.wait_timeout(state, left).unwrap().0
+                }
+            };
+        }
+
+        state.waiting.remove(&ticket);
+        state.busy = true;
+        Some(Turn { queue: self })
+    }
+
+    /// Number of transactions waiting for their turn
+    pub fn waiting(&self) -> usize {
+        self.state.lock().unwrap().waiting.len()
+    }
+}
+
+impl Drop for Turn<'_> {
+    fn drop(&mut self) {
+        // a transaction that panicked still gives up its turn
+        let mut state = match self.queue.state.lock() {
+            Ok(state) => state,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        state.busy = false;
+        self.queue.ready.notify_all();
+    }
+}
@@ -0,0 +1,805 @@
+//! Recording the containers a `Device` exchanges with a camera, and replaying
+//! them without the camera.
+//!
+//! A recording file starts with the magic `PTPREC`, a u16 format version and
+//! the packet sizes of the bulk in, bulk out and interrupt endpoints as u32s,
+//! followed by a u8 that is 1 if the device has an interrupt endpoint. Then
+//! come the entries until the end of the file, each a u8 tag, a u8 endpoint
+//! (0 bulk out, 1 bulk in, 2 interrupt) and the u64 nanoseconds since the
+//! recording started, followed by
+//!
+//! - for a container (tag 1) or a fragment (tag 2): a u32 count and as many
+//!   bytes, as transferred, header included
+//! - for a fault (tag 3): a u8 code of the `rusb::Error`
+//!
+//! All integers are little endian, as in PTP itself.
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use num_traits::FromPrimitive;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{ContainerType, Endpoint, Error, Transport, PTP_CONTAINER_INFO_SIZE};
+
+const MAGIC: &[u8; 6] = b"PTPREC";
+
+/// Version of the recording file format that `Recording::write_to` writes
+pub const RECORDING_VERSION: u16 = 1;
+
+const TAG_CONTAINER: u8 = 1;
+const TAG_FRAGMENT: u8 = 2;
+const TAG_FAULT: u8 = 3;
+
+// Longest a replayed interrupt read waits for an event that will never come
+const MAX_IDLE: Duration = Duration::from_millis(50);
+
+/// A container as it went over one of the pipes
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct RecordedContainer {
+    /// The pipe it went over, `BulkOut` from the host and `BulkIn` or
+    /// `Interrupt` from the device
+    pub endpoint: Endpoint,
+    pub kind: ContainerType,
+    pub code: u16,
+    pub tid: u32,
+    /// The length the header announced, which is longer than the header
+    /// and payload if the container was cut short
+    pub length: u32,
+    pub payload: Vec<u8>,
+    /// Time since the recording started, when the container was complete
+    pub elapsed: Duration,
+}
+
+impl RecordedContainer {
+    /// Whether the whole container arrived, rather than being cut short by
+    /// a fault or a transfer that ended early
+    pub fn is_complete(&self) -> bool {
+        self.length as usize == PTP_CONTAINER_INFO_SIZE + self.payload.len()
+    }
+
+    // Decodes a container from the bytes transferred, if they start with a
+    // valid header
+    fn decode(endpoint: Endpoint, bytes: &[u8], elapsed: Duration) -> Option<RecordedContainer> {
+        if bytes.len() < PTP_CONTAINER_INFO_SIZE {
+            return None;
+        }
+        let length = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        let kind = ContainerType::from_u16(u16::from_le_bytes([bytes[4], bytes[5]]))?;
+        if (length as usize) < bytes.len() {
+            return None;
+        }
+        Some(RecordedContainer {
+            endpoint,
+            kind,
+            code: u16::from_le_bytes([bytes[6], bytes[7]]),
+            tid: u32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]),
+            length,
+            payload: bytes[PTP_CONTAINER_INFO_SIZE..].to_vec(),
+            elapsed,
+        })
+    }
+
+    // The bytes transferred, header included
+    fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(PTP_CONTAINER_INFO_SIZE + self.payload.len());
+        bytes.extend_from_slice(&self.length.to_le_bytes());
+        bytes.extend_from_slice(&(self.kind as u16).to_le_bytes());
+        bytes.extend_from_slice(&self.code.to_le_bytes());
+        bytes.extend_from_slice(&self.tid.to_le_bytes());
+        bytes.extend_from_slice(&self.payload);
+        bytes
+    }
+
+    fn describe(&self) -> String {
+        format!(
+            "{:?} container 0x{:04x} of {} bytes, tid {}",
+            self.kind, self.code, self.length, self.tid
+        )
+    }
+}
+
+/// Something that went over, or failed on, one of the pipes
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum RecordEntry {
+    Container(RecordedContainer),
+
+    /// Bytes that did not make up a container header, e.g. a device's
+    /// garbled reply
+    Fragment {
+        endpoint: Endpoint,
+        bytes: Vec<u8>,
+        elapsed: Duration,
+    },
+
+    /// A transfer that failed. Interrupt reads timing out are not
+    /// recorded, as polling for events does so all the time.
+    Fault {
+        endpoint: Endpoint,
+        #[cfg_attr(feature = "serde", serde(with = "crate::serialization::usb_error"))]
+        error: rusb::Error,
+        elapsed: Duration,
+    },
+}
+
+impl RecordEntry {
+    pub fn endpoint(&self) -> Endpoint {
+        match self {
+            RecordEntry::Container(container) => container.endpoint,
+            RecordEntry::Fragment { endpoint, .. } | RecordEntry::Fault { endpoint, .. } => {
+                *endpoint
+            }
+        }
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        match self {
+            RecordEntry::Container(container) => container.elapsed,
+            RecordEntry::Fragment { elapsed, .. } | RecordEntry::Fault { elapsed, .. } => *elapsed,
+        }
+    }
+
+    // The bytes transferred, for containers and fragments
+    fn bytes(&self) -> Option<Vec<u8>> {
+        match self {
+            RecordEntry::Container(container) => Some(container.encode()),
+            RecordEntry::Fragment { bytes, .. } => Some(bytes.clone()),
+            RecordEntry::Fault { .. } => None,
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            RecordEntry::Container(container) => container.describe(),
+            RecordEntry::Fragment { bytes, .. } => format!("a fragment of {} bytes", bytes.len()),
+            RecordEntry::Fault { error, .. } => format!("a transfer failing with {}", error),
+        }
+    }
+}
+
+/// The containers a `RecordingTransport` saw, in the order they were
+/// complete, along with what a `ReplayTransport` needs to stand in for the
+/// device.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Recording {
+    pub bulk_in_packet_size: usize,
+    pub bulk_out_packet_size: usize,
+    pub interrupt_packet_size: usize,
+    pub has_interrupt: bool,
+    pub entries: Vec<RecordEntry>,
+}
+
+impl Recording {
+    /// Writes the recording in the current file format, see the module
+    /// documentation.
+    pub fn write_to<W: Write>(&self, mut w: W) -> Result<(), Error> {
+        w.write_all(MAGIC)?;
+        w.write_u16::<LittleEndian>(RECORDING_VERSION)?;
+        w.write_u32::<LittleEndian>(self.bulk_in_packet_size as u32)?;
+        w.write_u32::<LittleEndian>(self.bulk_out_packet_size as u32)?;
+        w.write_u32::<LittleEndian>(self.interrupt_packet_size as u32)?;
+        w.write_u8(self.has_interrupt as u8)?;
+
+        for entry in &self.entries {
+            let tag = match entry {
+                RecordEntry::Container(_) => TAG_CONTAINER,
+                RecordEntry::Fragment { .. } => TAG_FRAGMENT,
+                RecordEntry::Fault { .. } => TAG_FAULT,
+            };
+            w.write_u8(tag)?;
+            w.write_u8(endpoint_code(entry.endpoint()))?;
+            w.write_u64::<LittleEndian>(entry.elapsed().as_nanos() as u64)?;
+            match entry {
+                RecordEntry::Fault { error, .. } => w.write_u8(error_code(*error))?,
+                _ => {
+                    let bytes = entry.bytes().unwrap_or_default();
+                    w.write_u32::<LittleEndian>(bytes.len() as u32)?;
+                    w.write_all(&bytes)?;
+                }
+            }
+        }
+        w.flush()?;
+        Ok(())
+    }
+
+    /// Reads a recording written by `write_to`, of this or an earlier
+    /// version of the format.
+    pub fn read_from<R: Read>(mut r: R) -> Result<Recording, Error> {
+        let mut magic = [0; 6];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(Error::Malformed("not a PTP recording".to_owned()));
+        }
+        let version = r.read_u16::<LittleEndian>()?;
+        if version == 0 || version > RECORDING_VERSION {
+            return Err(Error::Malformed(format!(
+                "recording format version {} is not supported",
+                version
+            )));
+        }
+
+        let mut recording = Recording {
+            bulk_in_packet_size: r.read_u32::<LittleEndian>()? as usize,
+            bulk_out_packet_size: r.read_u32::<LittleEndian>()? as usize,
+            interrupt_packet_size: r.read_u32::<LittleEndian>()? as usize,
+            has_interrupt: r.read_u8()? != 0,
+            entries: Vec::new(),
+        };
+
+        loop {
+            let tag = match r.read_u8() {
+                Ok(tag) => tag,
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e.into()),
+            };
+            let endpoint = endpoint_from_code(r.read_u8()?)?;
+            let elapsed = Duration::from_nanos(r.read_u64::<LittleEndian>()?);
+
+            let entry = match tag {
+                TAG_CONTAINER | TAG_FRAGMENT => {
+                    let len = r.read_u32::<LittleEndian>()? as usize;
+                    let mut bytes = Vec::new();
+                    r.by_ref().take(len as u64).read_to_end(&mut bytes)?;
+                    if bytes.len() != len {
+                        return Err(Error::Malformed(format!(
+                            "recorded transfer of {} bytes cut short at {}",
+                            len,
+                            bytes.len()
+                        )));
+                    }
+                    record_bytes(endpoint, bytes, elapsed, tag == TAG_CONTAINER)?
+                }
+                TAG_FAULT => RecordEntry::Fault {
+                    endpoint,
+                    error: error_from_code(r.read_u8()?),
+                    elapsed,
+                },
+                tag => return Err(Error::Malformed(format!("unknown recording entry {}", tag))),
+            };
+            recording.entries.push(entry);
+        }
+
+        Ok(recording)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        self.write_to(BufWriter::new(File::create(path)?))
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Recording, Error> {
+        Recording::read_from(BufReader::new(File::open(path)?))
+    }
+}
+
+fn record_bytes(
+    endpoint: Endpoint,
+    bytes: Vec<u8>,
+    elapsed: Duration,
+    container: bool,
+) -> Result<RecordEntry, Error> {
+    if !container {
+        return Ok(RecordEntry::Fragment {
+            endpoint,
+            bytes,
+            elapsed,
+        });
+    }
+    RecordedContainer::decode(endpoint, &bytes, elapsed)
+        .map(RecordEntry::Container)
+        .ok_or_else(|| Error::Malformed("recorded container with an invalid header".to_owned()))
+}
+
+fn endpoint_code(endpoint: Endpoint) -> u8 {
+    match endpoint {
+        Endpoint::BulkOut => 0,
+        Endpoint::BulkIn => 1,
+        Endpoint::Interrupt => 2,
+    }
+}
+
+fn endpoint_from_code(code: u8) -> Result<Endpoint, Error> {
+    match code {
+        0 => Ok(Endpoint::BulkOut),
+        1 => Ok(Endpoint::BulkIn),
+        2 => Ok(Endpoint::Interrupt),
+        code => Err(Error::Malformed(format!(
+            "unknown recorded endpoint {}",
+            code
+        ))),
+    }
+}
+
+const ERRORS: [rusb::Error; 14] = [
+    rusb::Error::Io,
+    rusb::Error::InvalidParam,
+    rusb::Error::Access,
+    rusb::Error::NoDevice,
+    rusb::Error::NotFound,
+    rusb::Error::Busy,
+    rusb::Error::Timeout,
+    rusb::Error::Overflow,
+    rusb::Error::Pipe,
+    rusb::Error::Interrupted,
+    rusb::Error::NoMem,
+    rusb::Error::NotSupported,
+    rusb::Error::BadDescriptor,
+    rusb::Error::Other,
+];
+
+fn error_code(error: rusb::Error) -> u8 {
+    ERRORS
+        .iter()
+        .position(|e| *e == error)
+        .unwrap_or(ERRORS.len() - 1) as u8
+}
+
+fn error_from_code(code: u8) -> rusb::Error {
+    ERRORS
+        .get(usize::from(code))
+        .copied()
+        .unwrap_or(rusb::Error::Other)
+}
+
+fn pipe(endpoint: Endpoint) -> usize {
+    usize::from(endpoint_code(endpoint))
+}
+
+struct RecorderState {
+    entries: Vec<RecordEntry>,
+    // bytes of the container each pipe is in the middle of
+    pending: [Vec<u8>; 3],
+}
+
+impl RecorderState {
+    // Adds the bytes of a transfer, recording the containers they complete.
+    // A transfer that ended `short` ends the container it is in.
+    fn feed(&mut self, endpoint: Endpoint, data: &[u8], short: bool, elapsed: Duration) {
+        let pending = &mut self.pending[pipe(endpoint)];
+        pending.extend_from_slice(data);
+
+        while pending.len() >= PTP_CONTAINER_INFO_SIZE {
+            let length =
+                u32::from_le_bytes([pending[0], pending[1], pending[2], pending[3]]) as usize;
+            if length < PTP_CONTAINER_INFO_SIZE || pending.len() < length {
+                break;
+            }
+            let rest = pending.split_off(length);
+            let bytes = std::mem::replace(pending, rest);
+            self.entries.push(entry(endpoint, bytes, elapsed));
+        }
+
+        let pending = &self.pending[pipe(endpoint)];
+        let malformed = pending.len() >= PTP_CONTAINER_INFO_SIZE
+            && u32::from_le_bytes([pending[0], pending[1], pending[2], pending[3]])
+                < PTP_CONTAINER_INFO_SIZE as u32;
+        if short || malformed {
+            self.flush(endpoint, elapsed);
+        }
+    }
+
+    // Records what there is of the container a pipe is in the middle of
+    fn flush(&mut self, endpoint: Endpoint, elapsed: Duration) {
+        let bytes = std::mem::take(&mut self.pending[pipe(endpoint)]);
+        if !bytes.is_empty() {
+            self.entries.push(entry(endpoint, bytes, elapsed));
+        }
+    }
+}
+
+fn entry(endpoint: Endpoint, bytes: Vec<u8>, elapsed: Duration) -> RecordEntry {
+    match RecordedContainer::decode(endpoint, &bytes, elapsed) {
+        Some(container) => RecordEntry::Container(container),
+        None => RecordEntry::Fragment {
+            endpoint,
+            bytes,
+            elapsed,
+        },
+    }
+}
+
+/// A transport that records every container going through another one, so
+/// that a misbehaving device's session can be saved and replayed with a
+/// `ReplayTransport`.
+///
+/// Recordings are kept in memory, payloads and all, until taken with
+/// `take_recording`. Control requests such as Get Device Status are not
+/// recorded.
+pub struct RecordingTransport<T: Transport> {
+    inner: T,
+    start: Instant,
+    state: Mutex<RecorderState>,
+}
+
+impl<T: Transport> RecordingTransport<T> {
+    pub fn new(inner: T) -> RecordingTransport<T> {
+        RecordingTransport {
+            inner,
+            start: Instant::now(),
+            state: Mutex::new(RecorderState {
+                entries: Vec::new(),
+                pending: Default::default(),
+            }),
+        }
+    }
+
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    /// Returns what has been recorded so far
+    pub fn recording(&self) -> Recording {
+        let entries = self.state().entries.clone();
+        self.with_entries(entries)
+    }
+
+    /// Returns what has been recorded so far, and starts over with an empty
+    /// recording
+    pub fn take_recording(&self) -> Recording {
+        let entries = std::mem::take(&mut self.state().entries);
+        self.with_entries(entries)
+    }
+
+    fn with_entries(&self, entries: Vec<RecordEntry>) -> Recording {
+        Recording {
+            bulk_in_packet_size: self.inner.max_packet_size(Endpoint::BulkIn),
+            bulk_out_packet_size: self.inner.max_packet_size(Endpoint::BulkOut),
+            interrupt_packet_size: self.inner.max_packet_size(Endpoint::Interrupt),
+            has_interrupt: self.inner.has_interrupt(),
+            entries,
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, RecorderState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // Records the outcome of a transfer of at most `requested` bytes
+    fn record(&self, endpoint: Endpoint, requested: usize, result: Result<&[u8], &Error>) {
+        let elapsed = self.start.elapsed();
+        let mut state = self.state();
+        match result {
+            Ok(data) => {
+                let short = endpoint != Endpoint::BulkOut && data.len() < requested;
+                state.feed(endpoint, data, short, elapsed);
+            }
+            Err(Error::Usb(rusb::Error::Timeout)) if endpoint == Endpoint::Interrupt => {}
+            // errors other than USB ones are recorded as rusb::Error::Other
+            Err(err) => {
+                state.flush(endpoint, elapsed);
+                let error = match err {
+                    Error::Usb(error) => *error,
+                    _ => rusb::Error::Other,
+                };
+                state.entries.push(RecordEntry::Fault {
+                    endpoint,
+                    error,
+                    elapsed,
+                });
+            }
+        }
+    }
+}
+
+impl<T: Transport> Transport for RecordingTransport<T> {
+    fn write_bulk(&self, buf: &[u8], timeout: Duration) -> Result<usize, Error> {
+        let result = self.inner.write_bulk(buf, timeout);
+        let written = result.as_ref().map(|&n| &buf[..n.min(buf.len())]);
+        self.record(Endpoint::BulkOut, buf.len(), written);
+        result
+    }
+
+    fn read_bulk(&self, buf: &mut [u8], timeout: Duration) -> Result<usize, Error> {
+        let result = self.inner.read_bulk(buf, timeout);
+        let read = result.as_ref().map(|&n| &buf[..n]);
+        self.record(Endpoint::BulkIn, buf.len(), read);
+        result
+    }
+
+    fn has_interrupt(&self) -> bool {
+        self.inner.has_interrupt()
+    }
+
+    fn read_interrupt(&self, buf: &mut [u8], timeout: Duration) -> Result<usize, Error> {
+        let result = self.inner.read_interrupt(buf, timeout);
+        let read = result.as_ref().map(|&n| &buf[..n]);
+        self.record(Endpoint::Interrupt, buf.len(), read);
+        result
+    }
+
+    fn max_packet_size(&self, endpoint: Endpoint) -> usize {
+        self.inner.max_packet_size(endpoint)
+    }
+
+    fn class_request_in(
+        &self,
+        request: u8,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> Result<usize, Error> {
+        self.inner.class_request_in(request, buf, timeout)
+    }
+
+    fn class_request_out(
+        &self,
+        request: u8,
+        buf: &[u8],
+        timeout: Duration,
+    ) -> Result<usize, Error> {
+        self.inner.class_request_out(request, buf, timeout)
+    }
+
+    fn clear_halt(&self, endpoint: Endpoint) -> Result<(), Error> {
+        self.inner.clear_halt(endpoint)
+    }
+
+    fn reset(&self) -> Result<(), Error> {
+        self.inner.reset()
+    }
+
+    fn release(&self) -> Result<(), Error> {
+        self.inner.release()
+    }
+}
+
+// An entry to replay, along with its index in the recording
+struct Replayed {
+    index: usize,
+    entry: RecordEntry,
+    // the bytes transferred, for containers and fragments
+    bytes: Vec<u8>,
+}
+
+struct ReplayState {
+    bulk: VecDeque<Replayed>,
+    interrupt: VecDeque<Replayed>,
+    // how far into the front entry of each queue the host has got
+    bulk_offset: usize,
+    interrupt_offset: usize,
+    zlp_due: bool,
+}
+
+/// A transport that plays a `Recording` back to `Device`, in place of the
+/// device it was made with.
+///
+/// Each write must match the next container the host sent when recording,
+/// and each read gets the next container the device sent, or the fault it
+/// failed with. A host whose requests diverge from the recording gets
+/// `Error::ReplayMismatch`. Replay doesn't wait for the recorded timing,
+/// and Get Device Status is answered as not supported.
+pub struct ReplayTransport {
+    recording: Recording,
+    state: Mutex<ReplayState>,
+}
+
+impl ReplayTransport {
+    pub fn new(recording: Recording) -> ReplayTransport {
+        let (interrupt, bulk) = recording
+            .entries
+            .iter()
+            .enumerate()
+            .map(|(index, entry)| Replayed {
+                index,
+                bytes: entry.bytes().unwrap_or_default(),
+                entry: entry.clone(),
+            })
+            .partition(|replayed| replayed.entry.endpoint() == Endpoint::Interrupt);
+        ReplayTransport {
+            state: Mutex::new(ReplayState {
+                bulk,
+                interrupt,
+                bulk_offset: 0,
+                interrupt_offset: 0,
+                zlp_due: false,
+            }),
+            recording,
+        }
+    }
+
+    pub fn recording(&self) -> &Recording {
+        &self.recording
+    }
+
+    /// Returns how many entries have yet to be replayed
+    pub fn remaining(&self) -> usize {
+        let state = self.state();
+        state.bulk.len() + state.interrupt.len()
+    }
+
+    fn state(&self) -> MutexGuard<'_, ReplayState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn mismatch(&self, index: Option<usize>, expected: String, actual: String) -> Error {
+        Error::ReplayMismatch {
+            index: index.unwrap_or(self.recording.entries.len()),
+            expected,
+            actual,
+        }
+    }
+}
+
+// Copies the next bytes of the front entry of `queue` into `buf`, returning
+// the number copied and whether that was the end of the entry
+fn serve(queue: &mut VecDeque<Replayed>, offset: &mut usize, buf: &mut [u8]) -> (usize, bool) {
+    let bytes = match queue.front() {
+        Some(replayed) => &replayed.bytes,
+        None => return (0, false),
+    };
+    let n = buf.len().min(bytes.len() - *offset);
+    buf[..n].copy_from_slice(&bytes[*offset..*offset + n]);
+    *offset += n;
+
+    let done = *offset == bytes.len();
+    if done {
+        queue.pop_front();
+        *offset = 0;
+    }
+    (n, done)
+}
+
+impl Transport for ReplayTransport {
+    fn write_bulk(&self, buf: &[u8], _timeout: Duration) -> Result<usize, Error> {
+        // zero-length packets are not recorded
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let mut guard = self.state();
+        let state = &mut *guard;
+        let next = match state.bulk.front() {
+            Some(next) => next,
+            None => {
+                return Err(self.mismatch(
+                    None,
+                    "the end of the recording".to_owned(),
+                    format!("{} bytes written", buf.len()),
+                ))
+            }
+        };
+
+        match next.entry {
+            RecordEntry::Fault {
+                endpoint: Endpoint::BulkOut,
+                error,
+                ..
+            } => {
+                state.bulk.pop_front();
+                Err(Error::Usb(error))
+            }
+            ref entry if entry.endpoint() == Endpoint::BulkOut => {
+                let end = state.bulk_offset + buf.len();
+                if end > next.bytes.len() || next.bytes[state.bulk_offset..end] != *buf {
+                    let sent = RecordedContainer::decode(Endpoint::BulkOut, buf, Duration::ZERO);
+                    let actual = match (state.bulk_offset, sent) {
+                        (0, Some(container)) => container.describe(),
+                        (offset, _) => format!("{} different bytes at {}", buf.len(), offset),
+                    };
+                    return Err(self.mismatch(Some(next.index), entry.describe(), actual));
+                }
+
+                state.bulk_offset = end;
+                if end == next.bytes.len() {
+                    state.bulk.pop_front();
+                    state.bulk_offset = 0;
+                }
+                Ok(buf.len())
+            }
+            ref entry => Err(self.mismatch(
+                Some(next.index),
+                entry.describe(),
+                format!("{} bytes written", buf.len()),
+            )),
+        }
+    }
+
+    fn read_bulk(&self, buf: &mut [u8], _timeout: Duration) -> Result<usize, Error> {
+        let mut guard = self.state();
+        let state = &mut *guard;
+        if state.zlp_due {
+            state.zlp_due = false;
+            return Ok(0);
+        }
+
+        let next = match state.bulk.front() {
+            Some(next) => next,
+            None => {
+                return Err(self.mismatch(
+                    None,
+                    "the end of the recording".to_owned(),
+                    "a read".to_owned(),
+                ))
+            }
+        };
+
+        match next.entry {
+            RecordEntry::Fault {
+                endpoint: Endpoint::BulkIn,
+                error,
+                ..
+            } => {
+                state.bulk.pop_front();
+                Err(Error::Usb(error))
+            }
+            ref entry if entry.endpoint() == Endpoint::BulkIn => {
+                let complete = match entry {
+                    RecordEntry::Container(container) => container.is_complete(),
+                    _ => false,
+                };
+                let len = next.bytes.len();
+                let (n, done) = serve(&mut state.bulk, &mut state.bulk_offset, buf);
+
+                // a read that fills its buffer at the end of a container
+                // that ends on a packet boundary is followed by a
+                // zero-length packet
+                let packet_size = self.recording.bulk_in_packet_size;
+                state.zlp_due = done
+                    && complete
+                    && n == buf.len()
+                    && packet_size > 0
+                    && len.is_multiple_of(packet_size);
+                Ok(n)
+            }
+            ref entry => {
+                Err(self.mismatch(Some(next.index), entry.describe(), "a read".to_owned()))
+            }
+        }
+    }
+
+    fn has_interrupt(&self) -> bool {
+        self.recording.has_interrupt
+    }
+
+    fn read_interrupt(&self, buf: &mut [u8], timeout: Duration) -> Result<usize, Error> {
+        let mut guard = self.state();
+        let state = &mut *guard;
+        match state.interrupt.front().map(|next| &next.entry) {
+            Some(RecordEntry::Fault { error, .. }) => {
+                let error = *error;
+                state.interrupt.pop_front();
+                Err(Error::Usb(error))
+            }
+            Some(_) => Ok(serve(&mut state.interrupt, &mut state.interrupt_offset, buf).0),
+            None => {
+                // nothing more will come; wait as a device without events
+                // would, up to a limit
+                drop(guard);
+                thread::sleep(if timeout.is_zero() {
+                    MAX_IDLE
+                } else {
+                    timeout.min(MAX_IDLE)
+                });
+                Err(Error::Usb(rusb::Error::Timeout))
+            }
+        }
+    }
+
+    fn max_packet_size(&self, endpoint: Endpoint) -> usize {
+        match endpoint {
+            Endpoint::BulkIn => self.recording.bulk_in_packet_size,
+            Endpoint::BulkOut => self.recording.bulk_out_packet_size,
+            Endpoint::Interrupt => self.recording.interrupt_packet_size,
+        }
+    }
+
+    fn clear_halt(&self, _endpoint: Endpoint) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn reset(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
@@ -0,0 +1,100 @@
+use std::io::Cursor;
+use std::time::Duration;
+
+use num_traits::FromPrimitive;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{Error, PtpRead, ResponseCode, StandardResponseCode};
+
+/// What the device answers to the class request Get Device Status
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct DeviceStatus {
+    /// Ok once the device is ready for the next transaction, DeviceBusy
+    /// while it is still cancelling or recovering
+    pub code: ResponseCode,
+
+    /// Addresses of the endpoints the device has halted
+    pub params: Vec<u32>,
+}
+
+impl DeviceStatus {
+    /// Decodes the data stage: a u16 length of the whole status, a u16
+    /// response code, then u32 parameters.
+    pub fn decode(buf: &[u8]) -> Result<DeviceStatus, Error> {
+        let mut cur = Cursor::new(buf);
+        let len = usize::from(cur.read_ptp_u16()?);
+        let code = cur.read_ptp_u16()?;
+        if len < 4 || len > buf.len() || (len - 4) % 4 != 0 {
+            return Err(Error::Malformed(format!(
+                "device status of {} bytes in {} bytes",
+                len,
+                buf.len()
+            )));
+        }
+
+        Ok(DeviceStatus {
+            code: ResponseCode::from_u16(code)
+                .ok_or_else(|| Error::Malformed(format!("Invalid response code {:x}.", code)))?,
+            params: buf[4..len]
+                .chunks_exact(4)
+                .map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+                .collect(),
+        })
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.code == StandardResponseCode::Ok.into()
+    }
+
+    pub fn is_busy(&self) -> bool {
+        self.code == StandardResponseCode::DeviceBusy.into()
+    }
+}
+
+/// Decides how `Device` brings its bulk pipes back after a transfer stalled
+/// or babbled, see `Device::recover`.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct RecoveryPolicy {
+    /// Recover within the failed transaction, before `command` returns its
+    /// error
+    pub automatic: bool,
+
+    /// Also send the class request Device Reset, which returns the device
+    /// to its idle state and closes the session
+    pub device_reset: bool,
+
+    /// How long to wait for Get Device Status to report the device ready
+    pub ready_timeout: Duration,
+
+    /// How long to wait for each stale container still queued on the bulk
+    /// in endpoint; a read timing out ends the recovery
+    pub drain_timeout: Duration,
+}
+
+impl RecoveryPolicy {
+    /// A policy that leaves recovery to explicit calls of
+    /// `Device::recover`.
+    pub fn manual() -> RecoveryPolicy {
+        RecoveryPolicy {
+            automatic: false,
+            ..RecoveryPolicy::default()
+        }
+    }
+}
+
+impl Default for RecoveryPolicy {
+    /// Automatic recovery without Device Reset, waiting up to 5s for the
+    /// device and 100ms for each stale container.
+    fn default() -> RecoveryPolicy {
+        RecoveryPolicy {
+            automatic: true,
+            device_reset: false,
+            ready_timeout: Duration::from_secs(5),
+            drain_timeout: Duration::from_millis(100),
+        }
+    }
+}
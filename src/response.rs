@@ -1,12 +1,15 @@
-use std::fmt::{self, LowerHex};
-use num_traits::{FromPrimitive, ToPrimitive};
 use num_derive::{FromPrimitive, ToPrimitive};
+use num_traits::{FromPrimitive, ToPrimitive};
+use std::fmt::{self, Display, LowerHex};
+use std::str::FromStr;
 
 #[cfg(feature = "serde")]
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+
+use crate::names::parse_code;
+use crate::{Error, VendorProfile};
 
 #[derive(Debug, Eq, PartialEq, Copy, Clone, Hash)]
-#[cfg_attr(feature = "serde", derive(Serialize))]
 pub enum ResponseCode {
     Standard(StandardResponseCode),
     Other(u16),
@@ -14,17 +17,99 @@ pub enum ResponseCode {
 
 impl FromPrimitive for ResponseCode {
     fn from_i64(n: i64) -> Option<Self> {
-        Some(StandardResponseCode::from_i64(n).map_or_else(
-            || ResponseCode::Other(n as u16),
-            |code| ResponseCode::Standard(code),
-        ))
+        Some(
+            StandardResponseCode::from_i64(n)
+                .map_or_else(|| ResponseCode::Other(n as u16), ResponseCode::Standard),
+        )
     }
 
     fn from_u64(n: u64) -> Option<Self> {
-        Some(StandardResponseCode::from_u64(n).map_or_else(
-            || ResponseCode::Other(n as u16),
-            |code| ResponseCode::Standard(code),
-        ))
+        Some(
+            StandardResponseCode::from_u64(n)
+                .map_or_else(|| ResponseCode::Other(n as u16), ResponseCode::Standard),
+        )
+    }
+}
+
+impl ToPrimitive for ResponseCode {
+    fn to_i64(&self) -> Option<i64> {
+        match self {
+            ResponseCode::Standard(code) => code.to_i64(),
+            ResponseCode::Other(code) => Some(*code as i64),
+        }
+    }
+
+    fn to_u64(&self) -> Option<u64> {
+        match self {
+            ResponseCode::Standard(code) => code.to_u64(),
+            ResponseCode::Other(code) => Some(*code as u64),
+        }
+    }
+}
+
+impl ResponseCode {
+    /// Returns the name of a standard response code
+    pub fn name(&self) -> Option<&'static str> {
+        match self {
+            ResponseCode::Standard(code) => Some(code.name()),
+            ResponseCode::Other(_) => None,
+        }
+    }
+
+    /// Returns the name of this code, also looking up MTP and vendor-defined
+    /// responses in `vendor`, usually `Device::vendor()`.
+    pub fn name_for(&self, vendor: Option<&VendorProfile>) -> Option<&'static str> {
+        match self {
+            ResponseCode::Standard(code) => Some(code.name()),
+            ResponseCode::Other(code) => vendor?.response_name(*code),
+        }
+    }
+
+    /// Returns a short description of this code
+    pub fn description(&self) -> &'static str {
+        match self {
+            ResponseCode::Standard(code) => code.description(),
+            ResponseCode::Other(_) => "vendor-defined response",
+        }
+    }
+
+    /// Returns a short description of this code, naming the vendor of the
+    /// MTP and vendor-defined responses `vendor` knows.
+    pub fn description_for(&self, vendor: Option<&VendorProfile>) -> String {
+        match (self, vendor) {
+            (ResponseCode::Other(code), Some(vendor)) if vendor.response_name(*code).is_some() => {
+                format!("{} response", vendor.name)
+            }
+            _ => self.description().to_owned(),
+        }
+    }
+
+    /// Parses a code given by name or as a number such as `"0x2019"`, also
+    /// accepting the names of MTP and vendor-defined responses in `vendor`.
+    pub fn parse_for(s: &str, vendor: Option<&VendorProfile>) -> Result<ResponseCode, Error> {
+        let s = s.trim();
+        StandardResponseCode::from_name(s)
+            .map(ResponseCode::Standard)
+            .or_else(|| vendor?.response_code(s).map(ResponseCode::Other))
+            .or_else(|| parse_code(s).and_then(ResponseCode::from_u16))
+            .ok_or_else(|| Error::UnknownCode(s.to_owned()))
+    }
+}
+
+impl Display for ResponseCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.name() {
+            Some(name) => f.write_str(name),
+            None => write!(f, "0x{:04X}", self.to_u16().unwrap()),
+        }
+    }
+}
+
+impl FromStr for ResponseCode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        ResponseCode::parse_for(s, None)
     }
 }
 
@@ -45,7 +130,7 @@ impl From<StandardResponseCode> for ResponseCode {
 
 #[repr(u16)]
 #[derive(FromPrimitive, ToPrimitive, Debug, Copy, Clone, Eq, PartialEq, Hash)]
-#[cfg_attr(feature = "serde", derive(Serialize))]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum StandardResponseCode {
     Undefined = 0x2000,
     Ok = 0x2001,
@@ -88,3 +173,39 @@ impl LowerHex for StandardResponseCode {
         fmt::LowerHex::fmt(&val, f)
     }
 }
+
+code_names!(StandardResponseCode {
+    Undefined => "undefined response",
+    Ok => "the operation succeeded",
+    GeneralError => "the operation failed",
+    SessionNotOpen => "no session is open",
+    InvalidTransactionId => "the transaction ID is invalid",
+    OperationNotSupported => "the operation is not supported",
+    ParameterNotSupported => "a parameter is not supported",
+    IncompleteTransfer => "the transfer did not complete",
+    InvalidStorageId => "the storage ID is invalid",
+    InvalidObjectHandle => "the object handle is invalid",
+    DevicePropNotSupported => "the device property is not supported",
+    InvalidObjectFormatCode => "the object format is invalid",
+    StoreFull => "the storage is full",
+    ObjectWriteProtected => "the object is write-protected",
+    StoreReadOnly => "the storage is read-only",
+    AccessDenied => "access was denied",
+    NoThumbnailPresent => "the object has no thumbnail",
+    SelfTestFailed => "the self test failed",
+    PartialDeletion => "only some of the objects were deleted",
+    StoreNotAvailable => "the storage is not available",
+    SpecificationByFormatUnsupported => "filtering by object format is not supported",
+    NoValidObjectInfo => "no valid ObjectInfo was sent",
+    InvalidCodeFormat => "the code is outside the valid ranges",
+    UnknownVendorCode => "the vendor-defined code is unknown",
+    CaptureAlreadyTerminated => "the capture has already ended",
+    DeviceBusy => "the device is busy",
+    InvalidParentObject => "the parent object is invalid",
+    InvalidDevicePropFormat => "the device property format is invalid",
+    InvalidDevicePropValue => "the device property value is invalid",
+    InvalidParameter => "a parameter is invalid",
+    SessionAlreadyOpen => "a session is already open",
+    TransactionCancelled => "the transaction was cancelled",
+    SpecificationOfDestinationUnsupported => "specifying a destination is not supported",
+});
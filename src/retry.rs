@@ -0,0 +1,149 @@
+use std::time::Duration;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{CommandCode, Error, ResponseCode, StandardCommandCode, StandardResponseCode};
+
+/// Decides whether, and how often, a failed transaction is tried again.
+///
+/// Only errors listed as transient are retried, and operations that change
+/// state on the device (see `is_idempotent`) are never re-sent unless
+/// `retry_non_idempotent` is set. Before an operation is re-sent after a USB
+/// error, an attempt that got past its command phase is cancelled with the
+/// class request Cancel.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first one
+    pub max_attempts: u32,
+
+    /// Delay before the first retry
+    pub initial_backoff: Duration,
+
+    /// Factor the delay grows by after each retry
+    pub backoff_multiplier: f64,
+
+    /// Upper bound for the delay between attempts
+    pub max_backoff: Duration,
+
+    /// Time after the first attempt started past which no new attempt is
+    /// made, regardless of `max_attempts`
+    pub deadline: Option<Duration>,
+
+    /// Response codes worth retrying
+    pub transient_responses: Vec<ResponseCode>,
+
+    /// USB errors worth retrying
+    #[cfg_attr(feature = "serde", serde(with = "crate::serialization::usb_errors"))]
+    pub transient_usb_errors: Vec<rusb::Error>,
+
+    /// Also retry operations that are not idempotent, such as SendObject
+    pub retry_non_idempotent: bool,
+}
+
+impl RetryPolicy {
+    /// A policy that never retries. This is what `Device` uses unless told
+    /// otherwise.
+    pub fn none() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 1,
+            ..RetryPolicy::default()
+        }
+    }
+
+    /// Returns the delay before the given retry, counting from 1
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let exponent = retry.saturating_sub(1).min(i32::MAX as u32) as i32;
+        let secs =
+            self.initial_backoff.as_secs_f64() * self.backoff_multiplier.max(1.0).powi(exponent);
+        Duration::from_secs_f64(secs.min(self.max_backoff.as_secs_f64()))
+    }
+
+    /// Whether `err` is one of the transient errors of this policy
+    pub fn is_transient(&self, err: &Error) -> bool {
+        match err.root() {
+            Error::Response(code) => self.transient_responses.contains(code),
+            Error::Usb(e) => self.transient_usb_errors.contains(e),
+            _ => false,
+        }
+    }
+
+    /// Whether this policy allows retrying the given operation at all
+    pub fn allows(&self, code: CommandCode) -> bool {
+        self.max_attempts > 1 && (self.retry_non_idempotent || is_idempotent(code))
+    }
+}
+
+impl Default for RetryPolicy {
+    /// Three attempts, backing off from 100ms, for the errors that
+    /// `Error::is_retryable` considers transient.
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(100),
+            backoff_multiplier: 2.0,
+            max_backoff: Duration::from_secs(2),
+            deadline: None,
+            transient_responses: vec![
+                StandardResponseCode::DeviceBusy.into(),
+                StandardResponseCode::IncompleteTransfer.into(),
+                StandardResponseCode::StoreNotAvailable.into(),
+                StandardResponseCode::TransactionCancelled.into(),
+            ],
+            transient_usb_errors: vec![
+                rusb::Error::Timeout,
+                rusb::Error::Pipe,
+                rusb::Error::Busy,
+                rusb::Error::Interrupted,
+            ],
+            retry_non_idempotent: false,
+        }
+    }
+}
+
+// MTP operations that can safely be repeated
+const MTP_IDEMPOTENT: &[u16] = &[
+    0x9801, // GetObjectPropsSupported
+    0x9802, // GetObjectPropDesc
+    0x9803, // GetObjectPropValue
+    0x9804, // SetObjectPropValue
+    0x9805, // GetObjectPropList
+    0x9807, // GetInterdependentPropDesc
+    0x9810, // GetObjectReferences
+    0x9811, // SetObjectReferences
+];
+
+/// Whether performing an operation twice has the same effect as performing
+/// it once, so it is safe to re-send after a failure whose outcome is unknown.
+/// Vendor operations other than the MTP property and reference operations are
+/// assumed not to be.
+pub fn is_idempotent(code: CommandCode) -> bool {
+    use StandardCommandCode::*;
+
+    match code {
+        CommandCode::Standard(code) => matches!(
+            code,
+            GetDeviceInfo
+                | GetStorageIDs
+                | GetStorageInfo
+                | GetNumObjects
+                | GetObjectHandles
+                | GetObjectInfo
+                | GetObject
+                | GetThumb
+                | SetObjectProtection
+                | GetDevicePropDesc
+                | GetDevicePropValue
+                | SetDevicePropValue
+                | ResetDevicePropValue
+                | GetPartialObject
+                | GetVendorExtensionMaps
+                | GetVendorDeviceInfo
+                | GetResizedImageObject
+                | GetFilesystemManifest
+                | GetStreamInfo
+        ),
+        CommandCode::Other(code) => MTP_IDEMPOTENT.contains(&code),
+    }
+}
@@ -0,0 +1,222 @@
+//! Hand-written serde representations for types whose derived form would not
+//! be stable.
+//!
+//! The Standard/Vendor/Reserved code enums serialize as an object holding the
+//! code as a hex string, plus the name of the code when it is a standard one:
+//!
+//! ```text
+//! {"code": "0x4002", "name": "ObjectAdded"}
+//! {"code": "0xC101"}
+//! ```
+//!
+//! Only `code` is read back; `name` is informational. `ObjectHandle` and
+//! `StorageId` serialize as 8-digit hex strings such as `"0x00010001"`. Both
+//! forms also accept plain integers when deserializing.
+//!
+//! USB errors, in `RetryPolicy` and `RecordEntry`, serialize as the name of
+//! their `rusb::Error` variant, such as `"Timeout"`.
+
+use std::convert::TryFrom;
+use std::fmt;
+
+use num_traits::{FromPrimitive, ToPrimitive};
+use serde::de::{self, Deserializer, Visitor};
+use serde::{Deserialize, Serialize, Serializer};
+
+use crate::{
+    AccessType, AssociationCode, CommandCode, EventCode, FilesystemType, ObjectFormatCode,
+    ObjectHandle, ResponseCode, StorageId, StorageType,
+};
+
+fn parse_hex(s: &str) -> Option<u64> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+/// A u16 or u32 code, written as a hex string and read from either a hex
+/// string or an integer.
+struct Hex<const DIGITS: usize>(u64);
+
+impl<const DIGITS: usize> Serialize for Hex<DIGITS> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&format_args!("0x{:0width$X}", self.0, width = DIGITS))
+    }
+}
+
+impl<'de, const DIGITS: usize> Deserialize<'de> for Hex<DIGITS> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct HexVisitor;
+
+        impl Visitor<'_> for HexVisitor {
+            type Value = u64;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("an integer or a hex string such as \"0x4002\"")
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<u64, E> {
+                Ok(v)
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<u64, E> {
+                u64::try_from(v).map_err(|_| E::invalid_value(de::Unexpected::Signed(v), &self))
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<u64, E> {
+                parse_hex(v).ok_or_else(|| E::invalid_value(de::Unexpected::Str(v), &self))
+            }
+        }
+
+        let value = deserializer.deserialize_any(HexVisitor)?;
+        if DIGITS < 16 && value >> (DIGITS * 4) != 0 {
+            return Err(de::Error::custom(format!("code {:#x} out of range", value)));
+        }
+        Ok(Hex(value))
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct CodeRepr {
+    code: Hex<4>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+}
+
+macro_rules! code_serde {
+    ($($ty:ident $(| $unit:ident)*),* $(,)?) => {$(
+        impl Serialize for $ty {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                let name = match self {
+                    $ty::Standard(code) => Some(format!("{:?}", code)),
+                    $($ty::$unit => Some(stringify!($unit).to_owned()),)*
+                    #[allow(unreachable_patterns)]
+                    _ => None,
+                };
+                CodeRepr {
+                    code: Hex(self.to_u16().unwrap() as u64),
+                    name,
+                }
+                .serialize(serializer)
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $ty {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                let code = CodeRepr::deserialize(deserializer)?.code.0;
+                $ty::from_u64(code).ok_or_else(|| {
+                    de::Error::custom(format!(concat!("invalid ", stringify!($ty), " {:#06x}"), code))
+                })
+            }
+        }
+    )*};
+}
+
+code_serde! {
+    CommandCode,
+    ResponseCode,
+    EventCode,
+    ObjectFormatCode | ImageOnly,
+    AssociationCode,
+    AccessType,
+    FilesystemType,
+    StorageType,
+}
+
+macro_rules! id_serde {
+    ($($ty:ident),*) => {$(
+        impl Serialize for $ty {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                Hex::<8>(self.0 as u64).serialize(serializer)
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $ty {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                Ok($ty(Hex::<8>::deserialize(deserializer)?.0 as u32))
+            }
+        }
+    )*};
+}
+
+id_serde!(ObjectHandle, StorageId);
+
+const USB_ERRORS: &[(rusb::Error, &str)] = &[
+    (rusb::Error::Io, "Io"),
+    (rusb::Error::InvalidParam, "InvalidParam"),
+    (rusb::Error::Access, "Access"),
+    (rusb::Error::NoDevice, "NoDevice"),
+    (rusb::Error::NotFound, "NotFound"),
+    (rusb::Error::Busy, "Busy"),
+    (rusb::Error::Timeout, "Timeout"),
+    (rusb::Error::Overflow, "Overflow"),
+    (rusb::Error::Pipe, "Pipe"),
+    (rusb::Error::Interrupted, "Interrupted"),
+    (rusb::Error::NoMem, "NoMem"),
+    (rusb::Error::NotSupported, "NotSupported"),
+    (rusb::Error::BadDescriptor, "BadDescriptor"),
+    (rusb::Error::Other, "Other"),
+];
+
+/// A `rusb::Error`, written as the name of its variant.
+struct UsbError(rusb::Error);
+
+impl Serialize for UsbError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let name = USB_ERRORS
+            .iter()
+            .find(|&&(e, _)| e == self.0)
+            .map_or("Other", |&(_, name)| name);
+        serializer.serialize_str(name)
+    }
+}
+
+impl<'de> Deserialize<'de> for UsbError {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        USB_ERRORS
+            .iter()
+            .find(|&&(_, n)| n == name)
+            .map(|&(e, _)| UsbError(e))
+            .ok_or_else(|| de::Error::custom(format!("unknown USB error {:?}", name)))
+    }
+}
+
+/// For `#[serde(with)]` on a `rusb::Error` field.
+pub(crate) mod usb_error {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::UsbError;
+
+    pub fn serialize<S: Serializer>(error: &rusb::Error, serializer: S) -> Result<S::Ok, S::Error> {
+        UsbError(*error).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<rusb::Error, D::Error> {
+        Ok(UsbError::deserialize(deserializer)?.0)
+    }
+}
+
+/// For `#[serde(with)]` on a `Vec<rusb::Error>` field.
+pub(crate) mod usb_errors {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    use super::UsbError;
+
+    pub fn serialize<S: Serializer>(
+        errors: &[rusb::Error],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(errors.iter().map(|&e| UsbError(e)))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<rusb::Error>, D::Error> {
+        let errors = Vec::<UsbError>::deserialize(deserializer)?;
+        Ok(errors.into_iter().map(|e| e.0).collect())
+    }
+}
@@ -0,0 +1,692 @@
+use std::io::Cursor;
+use std::num::NonZeroU32;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use log::{debug, warn};
+use num_traits::ToPrimitive;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    CallOptions, CommandCode, Data, Device, DeviceInfo, Error, ObjectFilesystemInfo,
+    ObjectFormatCode, ObjectHandle, ObjectInfo, ObjectPropListEntry, PooledBuffer, PtpRead,
+    StandardCommandCode, StorageId, StorageInfo, StreamInfo, Transport, VendorExtensionMap,
+    MTP_ALL_OBJECTS, MTP_ALL_PROPERTIES, MTP_GET_OBJECT_PROP_LIST,
+};
+
+/// Options for `Device::open_session`.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SessionConfig {
+    /// ID identifying the session to the device
+    pub session_id: NonZeroU32,
+
+    /// If the device still has a session open, e.g. one left behind by a
+    /// process that exited without closing it, close that session and open
+    /// ours instead of failing with `SessionAlreadyOpen`
+    pub close_existing: bool,
+
+    /// Timeout for opening the session
+    pub timeout: Option<Duration>,
+
+    /// Timeout for closing the session when the `Session` is dropped
+    pub close_timeout: Option<Duration>,
+}
+
+impl Default for SessionConfig {
+    fn default() -> SessionConfig {
+        SessionConfig {
+            session_id: NonZeroU32::new(1).unwrap(),
+            close_existing: true,
+            timeout: None,
+            close_timeout: Some(Duration::from_secs(5)),
+        }
+    }
+}
+
+/// An open PTP session, returned by `Device::open_session`. Operations that
+/// the specification only allows within a session are methods of this type.
+///
+/// The session is closed when this is dropped; use `close` to find out
+/// whether closing succeeded.
+pub struct Session<'a, T: Transport> {
+    device: &'a Device<T>,
+    id: NonZeroU32,
+    close_timeout: Option<Duration>,
+    closed: bool,
+    options: CallOptions,
+}
+
+impl<'a, T: Transport> Session<'a, T> {
+    pub(crate) fn new(device: &'a Device<T>, config: &SessionConfig) -> Session<'a, T> {
+        Session {
+            device,
+            id: config.session_id,
+            close_timeout: config.close_timeout,
+            closed: false,
+            options: CallOptions::default(),
+        }
+    }
+
+    // A handle to a session that stays open when the handle is dropped, such
+    // as the session a `Supervisor` keeps open across calls
+    pub(crate) fn attach(device: &'a Device<T>, config: &SessionConfig) -> Session<'a, T> {
+        Session {
+            device,
+            id: config.session_id,
+            close_timeout: config.close_timeout,
+            closed: true,
+            options: CallOptions::default(),
+        }
+    }
+
+    // Leaves the session open on the device
+    pub(crate) fn detach(mut self) {
+        self.closed = true;
+    }
+
+    pub fn id(&self) -> NonZeroU32 {
+        self.id
+    }
+
+    pub fn device(&self) -> &'a Device<T> {
+        self.device
+    }
+
+    /// Returns a handle to this session whose operations all use
+    /// `options`, e.g. to give a sequence of them one `Deadline`. Each
+    /// operation's own timeout still bounds every transfer. Dropping the
+    /// handle leaves the session open.
+    pub fn with_options(&self, options: CallOptions) -> Session<'a, T> {
+        Session {
+            device: self.device,
+            id: self.id,
+            close_timeout: self.close_timeout,
+            closed: true,
+            options,
+        }
+    }
+
+    /// The options this session's operations use, see `with_options`
+    pub fn options(&self) -> &CallOptions {
+        &self.options
+    }
+
+    /// Closes the session.
+    pub fn close(mut self) -> Result<(), Error> {
+        self.close_inner()
+    }
+
+    fn close_inner(&mut self) -> Result<(), Error> {
+        if self.closed {
+            return Ok(());
+        }
+        self.closed = true;
+
+        // a Device Reset has already closed it on the device
+        if self.device.session_id.load(Ordering::Acquire) != self.id.get() {
+            debug!("session {} was closed by the device", self.id);
+            return Ok(());
+        }
+
+        let result = self.device.command(
+            StandardCommandCode::CloseSession.into(),
+            &[],
+            None,
+            self.close_timeout,
+        );
+        self.device.end_session();
+        debug!("closed session {}", self.id);
+
+        result.map(|_| ())
+    }
+
+    /// Executes a transaction within this session, see `Device::command`.
+    pub fn command(
+        &self,
+        code: CommandCode,
+        params: &[u32],
+        data: Option<&[u8]>,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<u8>, Error> {
+        self.device
+            .command_with(code, params, data, timeout, &self.options)
+    }
+
+    /// Executes a transaction within this session, see `Device::transaction`.
+    pub fn transaction(
+        &self,
+        code: CommandCode,
+        params: &[u32],
+        data: Option<&[u8]>,
+        timeout: Option<Duration>,
+    ) -> Result<(Vec<u8>, Vec<u32>), Error> {
+        self.device
+            .transaction_with(code, params, data, timeout, &self.options)
+    }
+
+    /// Executes a transaction within this session, returning the data in a
+    /// pooled buffer, see `Device::command_pooled`.
+    pub fn command_pooled(
+        &self,
+        code: CommandCode,
+        params: &[u32],
+        data: Option<&[u8]>,
+        timeout: Option<Duration>,
+    ) -> Result<PooledBuffer, Error> {
+        self.device
+            .command_pooled(code, params, data, timeout, &self.options)
+    }
+
+    /// Executes a transaction within this session, see `Device::command_with`.
+    pub fn command_with(
+        &self,
+        code: CommandCode,
+        params: &[u32],
+        data: Option<&[u8]>,
+        timeout: Option<Duration>,
+        options: &CallOptions,
+    ) -> Result<Vec<u8>, Error> {
+        self.device
+            .command_with(code, params, data, timeout, options)
+    }
+
+    /// Executes a transaction within this session, see
+    /// `Device::transaction_with`.
+    pub fn transaction_with(
+        &self,
+        code: CommandCode,
+        params: &[u32],
+        data: Option<&[u8]>,
+        timeout: Option<Duration>,
+        options: &CallOptions,
+    ) -> Result<(Vec<u8>, Vec<u32>), Error> {
+        self.device
+            .transaction_with(code, params, data, timeout, options)
+    }
+
+    pub fn get_object_info(
+        &self,
+        handle: ObjectHandle,
+        timeout: Option<Duration>,
+    ) -> Result<ObjectInfo, Error> {
+        let data = self.command(
+            StandardCommandCode::GetObjectInfo.into(),
+            &[handle.0],
+            None,
+            timeout,
+        )?;
+        ObjectInfo::decode(&data)
+    }
+
+    pub fn send_object_info(
+        &self,
+        handle: StorageId,
+        parent: ObjectHandle,
+        info: ObjectInfo,
+        timeout: Option<Duration>,
+    ) -> Result<ObjectHandle, Error> {
+        let mut data = vec![];
+        info.encode(&mut data)?;
+
+        let data = self.command(
+            StandardCommandCode::SendObjectInfo.into(),
+            &[handle.0, parent.0],
+            Some(&data[..]),
+            timeout,
+        )?;
+
+        let mut cur = Cursor::new(data);
+        // storage ID
+        let _ = cur.read_ptp_u32()?;
+        // parent object handle
+        let _ = cur.read_ptp_u32()?;
+        let object_handle = cur.read_ptp_u32()?;
+        cur.expect_end()?;
+
+        Ok(ObjectHandle(object_handle))
+    }
+
+    pub fn send_object(&self, data: &[u8], timeout: Option<Duration>) -> Result<(), Error> {
+        self.command(
+            StandardCommandCode::SendObject.into(),
+            &[],
+            Some(data),
+            timeout,
+        )?;
+
+        Ok(())
+    }
+
+    /// Sets a device property to `value`, which must be of the property's
+    /// data type.
+    pub fn set_device_prop_value(
+        &self,
+        code: u16,
+        value: Data,
+        timeout: Option<Duration>,
+    ) -> Result<(), Error> {
+        self.command(
+            StandardCommandCode::SetDevicePropValue.into(),
+            &[code.into()],
+            Some(&value.encode()?),
+            timeout,
+        )?;
+
+        Ok(())
+    }
+
+    pub fn get_object(
+        &self,
+        handle: ObjectHandle,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<u8>, Error> {
+        self.command(
+            StandardCommandCode::GetObject.into(),
+            &[handle.0],
+            None,
+            timeout,
+        )
+    }
+
+    pub fn get_partial_object(
+        &self,
+        handle: ObjectHandle,
+        offset: u32,
+        len: u32,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<u8>, Error> {
+        self.command(
+            StandardCommandCode::GetPartialObject.into(),
+            &[handle.0, offset, len],
+            None,
+            timeout,
+        )
+    }
+
+    /// Gets the object handles contained in a storage. If `parent` is not
+    /// specified, it will return every object in the storage. If `parent` is
+    /// ObjectHandle::root(), then it will return only those at the "root"
+    /// level.
+    pub fn get_object_handles(
+        &self,
+        storage_id: StorageId,
+        format: Option<ObjectFormatCode>,
+        parent: Option<ObjectHandle>,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<ObjectHandle>, Error> {
+        let data = self.command(
+            StandardCommandCode::GetObjectHandles.into(),
+            &[
+                storage_id.0,
+                format.map_or(0x0, |fmt| fmt.to_u32().unwrap()),
+                parent.map_or(0x0, |p| p.0),
+            ],
+            None,
+            timeout,
+        )?;
+        // Parse ObjectHandleArrray
+        let mut cur = Cursor::new(data);
+        let value = cur.read_ptp_u32_vec()?;
+        cur.expect_end()?;
+
+        Ok(value.into_iter().map(ObjectHandle).collect())
+    }
+
+    // handle_id: None == root of store
+    pub fn get_num_objects(
+        &self,
+        storage_id: Option<StorageId>,
+        format: Option<ObjectFormatCode>,
+        parent: Option<ObjectHandle>,
+        timeout: Option<Duration>,
+    ) -> Result<u32, Error> {
+        let data = self.command(
+            StandardCommandCode::GetNumObjects.into(),
+            &[
+                storage_id.map_or(0xFFFFFFFF, |sid| sid.0),
+                format.map_or(0x0, |fmt| fmt.to_u32().unwrap()),
+                parent.map_or(0x0, |oh| oh.0),
+            ],
+            None,
+            timeout,
+        )?;
+
+        // Parse ObjectHandleArrray
+        let mut cur = Cursor::new(data);
+        let value = cur.read_ptp_u32()?;
+        cur.expect_end()?;
+
+        Ok(value)
+    }
+
+    pub fn get_storage_info(
+        &self,
+        storage_id: StorageId,
+        timeout: Option<Duration>,
+    ) -> Result<StorageInfo, Error> {
+        let data = self.command(
+            StandardCommandCode::GetStorageInfo.into(),
+            &[storage_id.0],
+            None,
+            timeout,
+        )?;
+
+        // Parse ObjectHandleArrray
+        let mut cur = Cursor::new(data);
+        let res = StorageInfo::decode(&mut cur)?;
+        cur.expect_end()?;
+
+        Ok(res)
+    }
+
+    pub fn get_storage_ids(&self, timeout: Option<Duration>) -> Result<Vec<StorageId>, Error> {
+        let data = self.command(
+            StandardCommandCode::GetStorageIDs.into(),
+            &[],
+            None,
+            timeout,
+        )?;
+
+        // Parse ObjectHandleArrray
+        let mut cur = Cursor::new(data);
+        let value = cur.read_ptp_u32_vec()?;
+        cur.expect_end()?;
+
+        Ok(value.into_iter().map(StorageId).collect())
+    }
+
+    /// Lists every object in a storage together with its ObjectInfo, using
+    /// the fastest method the device supports:
+    ///  - GetFilesystemManifest (PTP 1.1), one transaction for the storage
+    ///  - MTP GetObjectPropList for all properties, one transaction
+    ///  - GetObjectHandles followed by GetObjectInfo for each handle, one
+    ///    transaction after the other
+    ///
+    /// The fast paths are only used if `Device::get_device_info` has reported them
+    /// as supported, and fall through to the next method if they fail. The
+    /// fast paths can't fill every ObjectInfo field, see
+    /// `ObjectFilesystemInfo::to_object_info` and
+    /// `ObjectPropListEntry::into_object_infos`.
+    pub fn list_storage(
+        &self,
+        storage_id: StorageId,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<(ObjectHandle, ObjectInfo)>, Error> {
+        if self
+            .device
+            .supports(StandardCommandCode::GetFilesystemManifest.into())
+            == Some(true)
+        {
+            match self.get_filesystem_manifest(storage_id, None, None, timeout) {
+                Ok(manifest) => {
+                    return Ok(manifest
+                        .iter()
+                        .map(|entry| (entry.object_handle, entry.to_object_info()))
+                        .collect())
+                }
+                Err(e) if matches!(e.root(), Error::Response(_) | Error::Malformed(_)) => {
+                    warn!("GetFilesystemManifest failed, falling back: {}", e)
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        if self
+            .device
+            .supports(CommandCode::Other(MTP_GET_OBJECT_PROP_LIST))
+            == Some(true)
+        {
+            match self.get_object_prop_list_all(storage_id, timeout) {
+                Ok(list) => return Ok(list),
+                Err(e) if matches!(e.root(), Error::Response(_) | Error::Malformed(_)) => {
+                    warn!("GetObjectPropList failed, falling back: {}", e)
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        // PTP allows only one transaction at a time, so the GetObjectInfo
+        // requests can't be pipelined; each costs a full round trip.
+        let handles = self.get_object_handles(storage_id, None, None, timeout)?;
+        let mut res = Vec::with_capacity(handles.len());
+        for handle in handles {
+            res.push((handle, self.get_object_info(handle, timeout)?));
+        }
+
+        Ok(res)
+    }
+
+    fn get_object_prop_list_all(
+        &self,
+        storage_id: StorageId,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<(ObjectHandle, ObjectInfo)>, Error> {
+        let data = self.command(
+            CommandCode::Other(MTP_GET_OBJECT_PROP_LIST),
+            &[MTP_ALL_OBJECTS, 0, MTP_ALL_PROPERTIES, 0, 0],
+            None,
+            timeout,
+        )?;
+
+        let entries = ObjectPropListEntry::decode_list(&data)?;
+        let mut infos = ObjectPropListEntry::into_object_infos(entries)?;
+
+        // the wildcard handle covers all storages
+        if storage_id != StorageId::all() {
+            infos.retain(|(_, info)| info.storage_id == storage_id.0);
+        }
+
+        Ok(infos)
+    }
+
+    /// Starts a PTP 1.1 handle enumeration, an incremental alternative to
+    /// `get_object_handles` for stores with many objects. Arguments have the
+    /// same meaning as for `get_object_handles`.
+    pub fn start_enum_handles(
+        &self,
+        storage_id: StorageId,
+        format: Option<ObjectFormatCode>,
+        parent: Option<ObjectHandle>,
+        timeout: Option<Duration>,
+    ) -> Result<(), Error> {
+        self.device
+            .require(StandardCommandCode::StartEnumHandles, "StartEnumHandles")?;
+
+        self.command(
+            StandardCommandCode::StartEnumHandles.into(),
+            &[
+                storage_id.0,
+                format.map_or(0x0, |fmt| fmt.to_u32().unwrap()),
+                parent.map_or(0x0, |p| p.0),
+            ],
+            None,
+            timeout,
+        )?;
+
+        Ok(())
+    }
+
+    /// Returns up to `max` further handles of the running enumeration. An
+    /// empty result means the enumeration is complete.
+    pub fn enum_handles(
+        &self,
+        max: u32,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<ObjectHandle>, Error> {
+        self.device
+            .require(StandardCommandCode::EnumHandles, "EnumHandles")?;
+
+        let data = self.command(
+            StandardCommandCode::EnumHandles.into(),
+            &[max],
+            None,
+            timeout,
+        )?;
+
+        let mut cur = Cursor::new(data);
+        let value = cur.read_ptp_u32_vec()?;
+        cur.expect_end()?;
+
+        Ok(value.into_iter().map(ObjectHandle).collect())
+    }
+
+    pub fn stop_enum_handles(&self, timeout: Option<Duration>) -> Result<(), Error> {
+        self.device
+            .require(StandardCommandCode::StopEnumHandles, "StopEnumHandles")?;
+
+        self.command(
+            StandardCommandCode::StopEnumHandles.into(),
+            &[],
+            None,
+            timeout,
+        )?;
+
+        Ok(())
+    }
+
+    pub fn get_vendor_extension_maps(
+        &self,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<VendorExtensionMap>, Error> {
+        self.device.require(
+            StandardCommandCode::GetVendorExtensionMaps,
+            "GetVendorExtensionMaps",
+        )?;
+
+        let data = self.command(
+            StandardCommandCode::GetVendorExtensionMaps.into(),
+            &[],
+            None,
+            timeout,
+        )?;
+
+        let mut cur = Cursor::new(data);
+        let value = cur.read_ptp_vec(VendorExtensionMap::decode)?;
+        cur.expect_end()?;
+
+        Ok(value)
+    }
+
+    /// Gets the DeviceInfo the device presents for one of its additional
+    /// vendor extensions, as listed by `get_vendor_extension_maps`.
+    pub fn get_vendor_device_info(
+        &self,
+        vendor_ex_id: u32,
+        timeout: Option<Duration>,
+    ) -> Result<DeviceInfo, Error> {
+        self.device.require(
+            StandardCommandCode::GetVendorDeviceInfo,
+            "GetVendorDeviceInfo",
+        )?;
+
+        let data = self.command(
+            StandardCommandCode::GetVendorDeviceInfo.into(),
+            &[vendor_ex_id],
+            None,
+            timeout,
+        )?;
+
+        DeviceInfo::decode(&data)
+    }
+
+    /// Gets an image object scaled down by the device. A `width` or `height`
+    /// of 0 lets the device choose it so as to preserve the aspect ratio.
+    pub fn get_resized_image_object(
+        &self,
+        handle: ObjectHandle,
+        width: u32,
+        height: u32,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<u8>, Error> {
+        self.device.require(
+            StandardCommandCode::GetResizedImageObject,
+            "GetResizedImageObject",
+        )?;
+
+        self.command(
+            StandardCommandCode::GetResizedImageObject.into(),
+            &[handle.0, width, height],
+            None,
+            timeout,
+        )
+    }
+
+    /// Gets the basic information of every matching object in one
+    /// transaction. Arguments have the same meaning as for
+    /// `get_object_handles`.
+    pub fn get_filesystem_manifest(
+        &self,
+        storage_id: StorageId,
+        format: Option<ObjectFormatCode>,
+        parent: Option<ObjectHandle>,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<ObjectFilesystemInfo>, Error> {
+        self.device.require(
+            StandardCommandCode::GetFilesystemManifest,
+            "GetFilesystemManifest",
+        )?;
+
+        let data = self.command(
+            StandardCommandCode::GetFilesystemManifest.into(),
+            &[
+                storage_id.0,
+                format.map_or(0x0, |fmt| fmt.to_u32().unwrap()),
+                parent.map_or(0x0, |p| p.0),
+            ],
+            None,
+            timeout,
+        )?;
+
+        ObjectFilesystemInfo::decode_manifest(&data)
+    }
+
+    pub fn get_stream_info(
+        &self,
+        stream_type: u32,
+        timeout: Option<Duration>,
+    ) -> Result<StreamInfo, Error> {
+        self.device
+            .require(StandardCommandCode::GetStreamInfo, "GetStreamInfo")?;
+
+        let data = self.command(
+            StandardCommandCode::GetStreamInfo.into(),
+            &[stream_type],
+            None,
+            timeout,
+        )?;
+
+        let mut cur = Cursor::new(data);
+        let res = StreamInfo::decode(&mut cur)?;
+        cur.expect_end()?;
+
+        Ok(res)
+    }
+
+    /// Reads stream data. The payload is framed as described by the
+    /// `StreamInfo` for the same `stream_type`.
+    pub fn get_stream(
+        &self,
+        stream_type: u32,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<u8>, Error> {
+        self.device
+            .require(StandardCommandCode::GetStream, "GetStream")?;
+
+        self.command(
+            StandardCommandCode::GetStream.into(),
+            &[stream_type],
+            None,
+            timeout,
+        )
+    }
+}
+
+impl<T: Transport> Drop for Session<'_, T> {
+    fn drop(&mut self) {
+        if let Err(e) = self.close_inner() {
+            warn!("closing session {} failed: {}", self.id, e);
+        }
+    }
+}
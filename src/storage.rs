@@ -1,12 +1,16 @@
 use num_derive::{FromPrimitive, ToPrimitive};
 use num_traits::{FromPrimitive, ToPrimitive};
-use std::fmt::Display;
+use std::fmt::{self, Display};
+use std::str::FromStr;
 
 #[cfg(feature = "serde")]
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
+use crate::names::parse_code;
+use crate::{Error, VendorProfile};
+
+/// With the `serde` feature this serializes as a hex string, e.g. `"0x0001002A"`.
 #[derive(Debug, Clone, Copy, PartialEq, FromPrimitive, ToPrimitive, Ord, PartialOrd, Eq, Hash)]
-#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct ObjectHandle(pub(crate) u32);
 
 impl ObjectHandle {
@@ -14,9 +18,9 @@ impl ObjectHandle {
     pub const UNSPECIFIED: ObjectHandle = ObjectHandle(0);
 }
 
-impl Into<u32> for ObjectHandle {
-    fn into(self) -> u32 {
-        self.0
+impl From<ObjectHandle> for u32 {
+    fn from(val: ObjectHandle) -> Self {
+        val.0
     }
 }
 
@@ -27,14 +31,14 @@ impl From<u32> for ObjectHandle {
 }
 
 impl Display for ObjectHandle {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "0x{:08x}", self.0)
     }
 }
 
 #[repr(u16)]
 #[derive(Debug, Clone, Copy, PartialEq, FromPrimitive, ToPrimitive, Ord, PartialOrd, Eq, Hash)]
-#[cfg_attr(feature = "serde", derive(Serialize))]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum StandardObjectFormatCode {
     Undefined = 0x0000,
     UndefinedNonImage = 0x3000,
@@ -70,7 +74,6 @@ pub enum StandardObjectFormatCode {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-#[cfg_attr(feature = "serde", derive(Serialize))]
 pub enum ObjectFormatCode {
     Standard(StandardObjectFormatCode),
     Reserved(u16),
@@ -78,8 +81,42 @@ pub enum ObjectFormatCode {
     ImageOnly,
 }
 
+code_names!(StandardObjectFormatCode {
+    Undefined => "undefined",
+    UndefinedNonImage => "undefined non-image object",
+    Association => "association, such as a folder",
+    Script => "device script",
+    Executable => "executable",
+    Text => "text file",
+    Html => "HTML file",
+    Dpof => "DPOF print order",
+    Aiff => "AIFF audio",
+    Wav => "WAV audio",
+    Mp3 => "MP3 audio",
+    Avi => "AVI video",
+    Mpeg => "MPEG video",
+    Asf => "ASF video",
+    UndefinedImage => "undefined image",
+    ExifJpeg => "EXIF/JPEG image",
+    TiffEp => "TIFF/EP image",
+    FlashPix => "FlashPix image",
+    Bmp => "BMP image",
+    Ciff => "Canon CIFF image",
+    UndefinedReserved => "reserved",
+    Gif => "GIF image",
+    Jfif => "JFIF image",
+    Pcd => "PhotoCD image",
+    Pict => "PICT image",
+    Png => "PNG image",
+    UndefinedReserved2 => "reserved",
+    Tiff => "TIFF image",
+    TiffIt => "TIFF/IT image",
+    Jp2 => "JPEG 2000 image",
+    Jpx => "JPEG 2000 extended image",
+});
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-#[cfg_attr(feature = "serde", derive(Serialize))]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum ObjectFormatCategory {
     Ancillary,
     Image,
@@ -128,6 +165,83 @@ impl ObjectFormatCode {
     }
 }
 
+impl ObjectFormatCode {
+    /// Returns the name of a standard object format code
+    pub fn name(&self) -> Option<&'static str> {
+        match self {
+            ObjectFormatCode::Standard(code) => Some(code.name()),
+            ObjectFormatCode::ImageOnly => Some("ImageOnly"),
+            ObjectFormatCode::Vendor(_) | ObjectFormatCode::Reserved(_) => None,
+        }
+    }
+
+    /// Returns the name of this code, also looking up MTP and vendor-defined
+    /// formats in `vendor`, usually `Device::vendor()`.
+    pub fn name_for(&self, vendor: Option<&VendorProfile>) -> Option<&'static str> {
+        match self {
+            ObjectFormatCode::Vendor(code) | ObjectFormatCode::Reserved(code) => {
+                vendor?.format_name(*code)
+            }
+            _ => self.name(),
+        }
+    }
+
+    /// Returns a short description of this code
+    pub fn description(&self) -> &'static str {
+        match self {
+            ObjectFormatCode::Standard(code) => code.description(),
+            ObjectFormatCode::ImageOnly => "any image format",
+            ObjectFormatCode::Vendor(_) => "vendor-defined format",
+            ObjectFormatCode::Reserved(_) => "reserved format",
+        }
+    }
+
+    /// Returns a short description of this code, naming the vendor of the
+    /// MTP and vendor-defined formats `vendor` knows.
+    pub fn description_for(&self, vendor: Option<&VendorProfile>) -> String {
+        match (self, vendor) {
+            (ObjectFormatCode::Vendor(code) | ObjectFormatCode::Reserved(code), Some(vendor))
+                if vendor.format_name(*code).is_some() =>
+            {
+                format!("{} format", vendor.name)
+            }
+            _ => self.description().to_owned(),
+        }
+    }
+
+    /// Parses a code given by name or as a number such as `"0x3801"`, also
+    /// accepting the names of MTP and vendor-defined formats in `vendor`.
+    pub fn parse_for(s: &str, vendor: Option<&VendorProfile>) -> Result<ObjectFormatCode, Error> {
+        let s = s.trim();
+        if s.eq_ignore_ascii_case("ImageOnly") {
+            return Ok(ObjectFormatCode::ImageOnly);
+        }
+
+        StandardObjectFormatCode::from_name(s)
+            .map(ObjectFormatCode::Standard)
+            .or_else(|| vendor?.format_code(s).and_then(ObjectFormatCode::from_u16))
+            .or_else(|| parse_code(s).and_then(ObjectFormatCode::from_u16))
+            .ok_or_else(|| Error::UnknownCode(s.to_owned()))
+    }
+}
+
+impl Display for ObjectFormatCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.name() {
+            Some(name) => f.write_str(name),
+            None => write!(f, "0x{:04X}", self.to_u16().unwrap()),
+        }
+    }
+}
+
+impl FromStr for ObjectFormatCode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        ObjectFormatCode::parse_for(s, None)
+    }
+}
+
 impl FromPrimitive for ObjectFormatCode {
     fn from_i64(_: i64) -> Option<Self> {
         None
@@ -173,11 +287,19 @@ impl ToPrimitive for ObjectFormatCode {
             ObjectFormatCode::ImageOnly => Some(0xFFFFFFFF),
         }
     }
+
+    // ImageOnly is 0xFFFFFFFF as an operation parameter, but 0xFFFF in datasets
+    fn to_u16(&self) -> Option<u16> {
+        match self {
+            ObjectFormatCode::ImageOnly => Some(0xFFFF),
+            _ => self.to_u64().and_then(|n| n.to_u16()),
+        }
+    }
 }
 
 #[repr(u16)]
 #[derive(Debug, Clone, Copy, PartialEq, FromPrimitive, ToPrimitive, Ord, PartialOrd, Eq, Hash)]
-#[cfg_attr(feature = "serde", derive(Serialize))]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum StandardAssociationCode {
     Undefined = 0x0000,
     GenericFolder,
@@ -189,14 +311,64 @@ pub enum StandardAssociationCode {
     AncillaryData,
 }
 
+code_names!(StandardAssociationCode {
+    Undefined => "undefined",
+    GenericFolder => "folder",
+    Album => "album",
+    TimeSequence => "time sequence",
+    PanoramicHorizontal => "horizontal panorama",
+    PanoramicVertical => "vertical panorama",
+    Panoramic2D => "2D panorama",
+    AncillaryData => "ancillary data",
+});
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-#[cfg_attr(feature = "serde", derive(Serialize))]
 pub enum AssociationCode {
     Standard(StandardAssociationCode),
     Reserved(u16),
     Vendor(u16),
 }
 
+impl AssociationCode {
+    /// Returns the name of a standard association code
+    pub fn name(&self) -> Option<&'static str> {
+        match self {
+            AssociationCode::Standard(code) => Some(code.name()),
+            AssociationCode::Vendor(_) | AssociationCode::Reserved(_) => None,
+        }
+    }
+
+    /// Returns a short description of this code
+    pub fn description(&self) -> &'static str {
+        match self {
+            AssociationCode::Standard(code) => code.description(),
+            AssociationCode::Vendor(_) => "vendor-defined association",
+            AssociationCode::Reserved(_) => "reserved association",
+        }
+    }
+}
+
+impl Display for AssociationCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.name() {
+            Some(name) => f.write_str(name),
+            None => write!(f, "0x{:04X}", self.to_u16().unwrap()),
+        }
+    }
+}
+
+impl FromStr for AssociationCode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let s = s.trim();
+        StandardAssociationCode::from_name(s)
+            .map(AssociationCode::Standard)
+            .or_else(|| parse_code(s).and_then(AssociationCode::from_u16))
+            .ok_or_else(|| Error::UnknownCode(s.to_owned()))
+    }
+}
+
 impl FromPrimitive for AssociationCode {
     fn from_i64(_: i64) -> Option<Self> {
         None
@@ -213,7 +385,7 @@ impl FromPrimitive for AssociationCode {
             return Some(AssociationCode::Vendor(n));
         }
 
-        return Some(AssociationCode::Reserved(n));
+        Some(AssociationCode::Reserved(n))
     }
 }
 
@@ -232,7 +404,7 @@ impl ToPrimitive for AssociationCode {
 
 #[repr(u16)]
 #[derive(Debug, Clone, Eq, PartialEq, Copy, FromPrimitive, ToPrimitive, Hash)]
-#[cfg_attr(feature = "serde", derive(Serialize))]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum StandardAccessType {
     ReadWrite = 0x0000,
     ReadOnlyNoDelete,
@@ -240,7 +412,6 @@ pub enum StandardAccessType {
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Copy, Hash)]
-#[cfg_attr(feature = "serde", derive(Serialize))]
 pub enum AccessType {
     Standard(StandardAccessType),
     Reserved(u16),
@@ -258,7 +429,7 @@ impl FromPrimitive for AccessType {
             return Some(AccessType::Standard(ofc));
         }
 
-        return Some(AccessType::Reserved(n));
+        Some(AccessType::Reserved(n))
     }
 }
 
@@ -277,7 +448,7 @@ impl ToPrimitive for AccessType {
 
 #[repr(u16)]
 #[derive(Debug, Clone, Copy, PartialEq, FromPrimitive, ToPrimitive, Ord, PartialOrd, Eq)]
-#[cfg_attr(feature = "serde", derive(Serialize))]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum StandardFilesystemType {
     Undefined = 0x0000,
     GenericFlat,
@@ -286,7 +457,6 @@ pub enum StandardFilesystemType {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-#[cfg_attr(feature = "serde", derive(Serialize))]
 pub enum FilesystemType {
     Standard(StandardFilesystemType),
     Reserved(u16),
@@ -309,7 +479,7 @@ impl FromPrimitive for FilesystemType {
             return Some(FilesystemType::Vendor(n));
         }
 
-        return Some(FilesystemType::Reserved(n));
+        Some(FilesystemType::Reserved(n))
     }
 }
 
@@ -327,7 +497,7 @@ impl ToPrimitive for FilesystemType {
 }
 #[repr(u16)]
 #[derive(Debug, Clone, Copy, PartialEq, FromPrimitive, ToPrimitive, Ord, PartialOrd, Eq, Hash)]
-#[cfg_attr(feature = "serde", derive(Serialize))]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum StandardStorageType {
     Undefined = 0x0000,
     FixedRom,
@@ -337,7 +507,6 @@ pub enum StandardStorageType {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-#[cfg_attr(feature = "serde", derive(Serialize))]
 pub enum StorageType {
     Standard(StandardStorageType),
     Reserved(u16),
@@ -355,7 +524,7 @@ impl FromPrimitive for StorageType {
             return Some(StorageType::Standard(ofc));
         }
 
-        return Some(StorageType::Reserved(n));
+        Some(StorageType::Reserved(n))
     }
 }
 
@@ -372,8 +541,8 @@ impl ToPrimitive for StorageType {
     }
 }
 
+/// With the `serde` feature this serializes as a hex string, e.g. `"0x00010001"`.
 #[derive(Debug, Clone, Copy, PartialEq, FromPrimitive, ToPrimitive, Ord, PartialOrd, Eq, Hash)]
-#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct StorageId(pub(crate) u32);
 
 impl StorageId {
@@ -383,9 +552,9 @@ impl StorageId {
     }
 }
 
-impl Into<u32> for StorageId {
-    fn into(self) -> u32 {
-        self.0
+impl From<StorageId> for u32 {
+    fn from(val: StorageId) -> Self {
+        val.0
     }
 }
 
@@ -396,7 +565,7 @@ impl From<u32> for StorageId {
 }
 
 impl Display for StorageId {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "0x{:08x}", self.0)
     }
 }
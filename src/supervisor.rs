@@ -0,0 +1,466 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::{debug, warn};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    list_devices, Data, Device, DeviceFilter, Error, Session, SessionConfig, Transport,
+    UsbTransport,
+};
+
+/// Finds and opens the device a `Supervisor` keeps connected.
+///
+/// `UsbConnector` finds a USB device by a `DeviceFilter`. Other
+/// implementations can stand in for it, for example in tests.
+pub trait Connector: Send + 'static {
+    type Transport: Transport + 'static;
+
+    /// Opens the device, returning Ok(None) if it isn't attached
+    fn connect(&mut self) -> Result<Option<Device<Self::Transport>>, Error>;
+
+    /// Whether the device last opened by `connect` is still attached
+    fn is_attached(&mut self) -> bool;
+
+    /// Waits until a device may have been attached or detached, or until
+    /// `timeout` has passed
+    fn wait(&mut self, timeout: Duration);
+}
+
+/// Connects to the first USB device matching a `DeviceFilter`.
+///
+/// Where libusb supports hotplug, arrivals and departures of devices with
+/// the filter's vendor and product ID end a `wait` early; elsewhere the bus
+/// is polled.
+pub struct UsbConnector<C: rusb::UsbContext> {
+    context: C,
+    filter: DeviceFilter,
+    // bus number and address of the opened device; a device that comes back
+    // after a brown out is enumerated at a new address
+    opened: Option<(u8, u8)>,
+    hotplug: Option<(rusb::Registration<C>, Arc<AtomicBool>)>,
+}
+
+struct HotplugFlag(Arc<AtomicBool>);
+
+impl<C: rusb::UsbContext> rusb::Hotplug<C> for HotplugFlag {
+    fn device_arrived(&mut self, _device: rusb::Device<C>) {
+        self.0.store(true, Ordering::Release);
+    }
+
+    fn device_left(&mut self, _device: rusb::Device<C>) {
+        self.0.store(true, Ordering::Release);
+    }
+}
+
+impl<C: rusb::UsbContext + 'static> UsbConnector<C> {
+    pub fn new(context: C, filter: DeviceFilter) -> Result<UsbConnector<C>, Error> {
+        let hotplug = if rusb::has_hotplug() {
+            let mut builder = rusb::HotplugBuilder::new();
+            if let Some(vendor_id) = filter.vendor_id {
+                builder.vendor_id(vendor_id);
+            }
+            if let Some(product_id) = filter.product_id {
+                builder.product_id(product_id);
+            }
+            let changed = Arc::new(AtomicBool::new(false));
+            let registration =
+                builder.register(context.clone(), Box::new(HotplugFlag(changed.clone())))?;
+            Some((registration, changed))
+        } else {
+            debug!("hotplug is not supported, polling for devices");
+            None
+        };
+
+        Ok(UsbConnector {
+            context,
+            filter,
+            opened: None,
+            hotplug,
+        })
+    }
+}
+
+impl<C: rusb::UsbContext + 'static> Connector for UsbConnector<C> {
+    type Transport = UsbTransport<C>;
+
+    fn connect(&mut self) -> Result<Option<Device<UsbTransport<C>>>, Error> {
+        let candidate = match list_devices(&self.context)?
+            .into_iter()
+            .find(|candidate| self.filter.matches(&candidate.info))
+        {
+            Some(candidate) => candidate,
+            None => return Ok(None),
+        };
+
+        let device = candidate.open()?;
+        let usb = candidate.device();
+        self.opened = Some((usb.bus_number(), usb.address()));
+        debug!("opened {:?}", candidate.info);
+
+        Ok(Some(device))
+    }
+
+    fn is_attached(&mut self) -> bool {
+        let opened = match self.opened {
+            Some(opened) => opened,
+            None => return false,
+        };
+
+        match self.context.devices() {
+            Ok(devices) => devices
+                .iter()
+                .any(|device| (device.bus_number(), device.address()) == opened),
+            // assume the device is still there if the bus can't be listed
+            Err(e) => {
+                debug!("listing devices failed: {}", e);
+                true
+            }
+        }
+    }
+
+    fn wait(&mut self, timeout: Duration) {
+        let changed = match &self.hotplug {
+            Some((_, changed)) => changed,
+            None => return thread::sleep(timeout),
+        };
+
+        let deadline = Instant::now() + timeout;
+        while !changed.swap(false, Ordering::AcqRel) {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return;
+            }
+            if let Err(e) = self.context.handle_events(Some(remaining)) {
+                debug!("handling hotplug events failed: {}", e);
+                return thread::sleep(remaining);
+            }
+        }
+    }
+}
+
+/// Options for `Supervisor`.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SupervisorConfig {
+    /// Options for the session opened on every connect
+    pub session: SessionConfig,
+
+    /// Device properties, by code, to set after every connect
+    pub properties: Vec<(u16, Data)>,
+
+    /// How often to look for the device while it is disconnected, and to
+    /// check it is still attached while it is connected
+    pub poll_interval: Duration,
+
+    /// Timeout for setting each property
+    pub timeout: Option<Duration>,
+}
+
+impl Default for SupervisorConfig {
+    fn default() -> SupervisorConfig {
+        SupervisorConfig {
+            session: SessionConfig::default(),
+            properties: Vec::new(),
+            poll_interval: Duration::from_secs(1),
+            timeout: Some(Duration::from_secs(5)),
+        }
+    }
+}
+
+/// A change in the connection of a `Supervisor`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ConnectionEvent {
+    /// The device was opened, a session opened on it and the saved
+    /// properties set
+    Connected,
+
+    /// The device was detached or has stopped responding
+    Disconnected,
+
+    /// The device was found but opening it or its session failed; the
+    /// supervisor keeps trying
+    ConnectFailed(String),
+
+    /// Setting a saved property failed while connecting
+    RestoreFailed { code: u16, error: String },
+}
+
+struct Shared<T: Transport> {
+    session: SessionConfig,
+    properties: Mutex<Vec<(u16, Data)>>,
+    timeout: Option<Duration>,
+    device: Mutex<Option<Arc<Device<T>>>>,
+    connected: Condvar,
+    // a call failed in a way that means the device is gone
+    lost: AtomicBool,
+    stop: AtomicBool,
+    subscribers: Mutex<Vec<mpsc::Sender<ConnectionEvent>>>,
+}
+
+impl<T: Transport> Shared<T> {
+    fn device(&self) -> MutexGuard<'_, Option<Arc<Device<T>>>> {
+        self.device.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn emit(&self, event: ConnectionEvent) {
+        debug!("connection event {:?}", event);
+        self.subscribers
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    }
+
+    // Opens the session and sets the saved properties
+    fn restore(&self, device: &Device<T>) -> Result<(), Error> {
+        let session = device.open_session(self.session.clone())?;
+
+        let properties = self
+            .properties
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone();
+        for (code, value) in properties {
+            if let Err(e) = session.set_device_prop_value(code, value, self.timeout) {
+                warn!("restoring property {:#06x} failed: {}", code, e);
+                self.emit(ConnectionEvent::RestoreFailed {
+                    code,
+                    error: e.to_string(),
+                });
+            }
+        }
+
+        session.detach();
+        Ok(())
+    }
+
+    fn connect(&self, device: Device<T>) {
+        if let Err(e) = self.restore(&device) {
+            warn!("opening a session failed: {}", e);
+            let _ = device.transport().release();
+            return self.emit(ConnectionEvent::ConnectFailed(e.to_string()));
+        }
+
+        self.lost.store(false, Ordering::Release);
+        // the event is sent with the lock held so that `subscribe` sees
+        // either the old state or the event
+        let mut current = self.device();
+        *current = Some(Arc::new(device));
+        self.emit(ConnectionEvent::Connected);
+        self.connected.notify_all();
+    }
+
+    fn disconnect(&self, close: bool) {
+        let mut current = self.device();
+        let device = match current.take() {
+            Some(device) => device,
+            None => return,
+        };
+
+        if close {
+            if let Err(e) = Session::new(&device, &self.session).close() {
+                warn!("closing the session failed: {}", e);
+            }
+        }
+        if let Err(e) = device.transport().release() {
+            debug!("releasing the device failed: {}", e);
+        }
+        self.emit(ConnectionEvent::Disconnected);
+    }
+}
+
+fn supervise<K: Connector>(mut connector: K, shared: Arc<Shared<K::Transport>>, poll: Duration) {
+    while !shared.stop.load(Ordering::Acquire) {
+        let connected = shared.device().is_some();
+        if connected {
+            if shared.lost.swap(false, Ordering::AcqRel) || !connector.is_attached() {
+                shared.disconnect(false);
+                continue;
+            }
+        } else {
+            match connector.connect() {
+                Ok(Some(device)) => shared.connect(device),
+                Ok(None) => {}
+                Err(e) => {
+                    warn!("opening the device failed: {}", e);
+                    shared.emit(ConnectionEvent::ConnectFailed(e.to_string()));
+                }
+            }
+        }
+        connector.wait(poll);
+    }
+
+    shared.disconnect(true);
+}
+
+/// Keeps a session open with a device that may be detached and reattached,
+/// e.g. a camera that re-enumerates after a brown out.
+///
+/// A thread looks for the device and, whenever it (re)appears, opens it and
+/// a session on it and sets the saved properties. Calls made while the device
+/// is gone fail with `Usb(NoDevice)`. Changes of the connection are reported
+/// to `subscribe`rs. The session is closed when the supervisor is dropped.
+pub struct Supervisor<T: Transport + 'static = UsbTransport<rusb::Context>> {
+    shared: Arc<Shared<T>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl<C: rusb::UsbContext + 'static> Supervisor<UsbTransport<C>> {
+    /// Supervises the first USB device matching `filter`, see `UsbConnector`.
+    pub fn watch(
+        context: C,
+        filter: DeviceFilter,
+        config: SupervisorConfig,
+    ) -> Result<Supervisor<UsbTransport<C>>, Error> {
+        Ok(Supervisor::start(
+            UsbConnector::new(context, filter)?,
+            config,
+        ))
+    }
+}
+
+impl<T: Transport + 'static> Supervisor<T> {
+    /// Starts supervising the device `connector` opens.
+    pub fn start<K: Connector<Transport = T>>(
+        connector: K,
+        config: SupervisorConfig,
+    ) -> Supervisor<T> {
+        let shared = Arc::new(Shared {
+            session: config.session,
+            properties: Mutex::new(config.properties),
+            timeout: config.timeout,
+            device: Mutex::new(None),
+            connected: Condvar::new(),
+            lost: AtomicBool::new(false),
+            stop: AtomicBool::new(false),
+            subscribers: Mutex::new(Vec::new()),
+        });
+
+        let thread = thread::Builder::new()
+            .name("ptp-supervisor".into())
+            .spawn({
+                let shared = shared.clone();
+                let poll = config.poll_interval;
+                move || supervise(connector, shared, poll)
+            })
+            .expect("failed to spawn the supervisor thread");
+
+        Supervisor {
+            shared,
+            thread: Some(thread),
+        }
+    }
+
+    /// Returns a channel of connection changes. If the device is connected,
+    /// the first event is `Connected`.
+    pub fn subscribe(&self) -> mpsc::Receiver<ConnectionEvent> {
+        let (sender, receiver) = mpsc::channel();
+        let current = self.shared.device();
+        if current.is_some() {
+            let _ = sender.send(ConnectionEvent::Connected);
+        }
+        self.shared
+            .subscribers
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(sender);
+        receiver
+    }
+
+    /// Returns the connected device, if any.
+    pub fn device(&self) -> Option<Arc<Device<T>>> {
+        self.shared.device().clone()
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.shared.device().is_some()
+    }
+
+    /// Waits up to `timeout` for the device to be connected.
+    pub fn wait_connected(&self, timeout: Duration) -> Option<Arc<Device<T>>> {
+        let current = self.shared.device();
+        let (current, _) = self
+            .shared
+            .connected
+            .wait_timeout_while(current, timeout, |device| device.is_none())
+            .unwrap_or_else(|e| e.into_inner());
+        current.clone()
+    }
+
+    /// Runs `f` with the session of the connected device. The session
+    /// belongs to the supervisor and stays open when `f` returns, even if
+    /// `f` closes it.
+    ///
+    /// Fails with `Usb(NoDevice)` while the device is disconnected. An error
+    /// showing the device is gone makes the supervisor reconnect.
+    pub fn with_session<R, F>(&self, f: F) -> Result<R, Error>
+    where
+        F: FnOnce(&Session<'_, T>) -> Result<R, Error>,
+    {
+        let device = self.device().ok_or(rusb::Error::NoDevice)?;
+        let result = f(&Session::attach(&device, &self.shared.session));
+
+        if let Err(e) = &result {
+            let current = self.shared.device();
+            let still_current = current.as_ref().is_some_and(|d| Arc::ptr_eq(d, &device));
+            if still_current && matches!(e.root(), Error::Usb(rusb::Error::NoDevice)) {
+                debug!("the device is gone: {}", e);
+                self.shared.lost.store(true, Ordering::Release);
+            }
+        }
+
+        result
+    }
+
+    /// Sets a device property, and saves it to be set again on every
+    /// reconnect. The property is saved even if the device is disconnected.
+    pub fn set_property(&self, code: u16, value: Data) -> Result<(), Error> {
+        {
+            let mut properties = self
+                .shared
+                .properties
+                .lock()
+                .unwrap_or_else(|e| e.into_inner());
+            match properties.iter_mut().find(|(saved, _)| *saved == code) {
+                Some((_, saved)) => *saved = value.clone(),
+                None => properties.push((code, value.clone())),
+            }
+        }
+
+        let timeout = self.shared.timeout;
+        self.with_session(|session| session.set_device_prop_value(code, value, timeout))
+    }
+
+    /// Returns the saved properties.
+    pub fn properties(&self) -> Vec<(u16, Data)> {
+        self.shared
+            .properties
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Stops supervising, closing the session if the device is connected.
+    pub fn stop(mut self) {
+        self.stop_inner();
+    }
+
+    fn stop_inner(&mut self) {
+        self.shared.stop.store(true, Ordering::Release);
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                warn!("the supervisor thread panicked");
+            }
+        }
+    }
+}
+
+impl<T: Transport + 'static> Drop for Supervisor<T> {
+    fn drop(&mut self) {
+        self.stop_inner();
+    }
+}
@@ -0,0 +1,239 @@
+use log::debug;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::{EndpointDescriptor, Error, InterfaceDescriptor, UsbDescriptors};
+
+/// The endpoints of a PTP interface
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Endpoint {
+    BulkIn,
+    BulkOut,
+    Interrupt,
+}
+
+/// Moves bytes between `Device` and a PTP responder.
+///
+/// `UsbTransport` talks to a real device over USB. Other implementations can
+/// stand in for a device, for example in tests.
+///
+/// Transfers follow USB semantics: a bulk read returns at most `buf.len()`
+/// bytes and ends early at the end of a container, and a container whose
+/// length is a multiple of the packet size is terminated by a zero-length
+/// packet. `Device` writes one as an empty `write_bulk`, and expects one as a
+/// read of 0 bytes after a read that filled its buffer at the end of a
+/// container.
+pub trait Transport: Send + Sync {
+    /// Writes `buf` to the bulk out endpoint, returning the number of bytes
+    /// written
+    fn write_bulk(&self, buf: &[u8], timeout: Duration) -> Result<usize, Error>;
+
+    /// Reads from the bulk in endpoint into `buf`, returning the number of
+    /// bytes read
+    fn read_bulk(&self, buf: &mut [u8], timeout: Duration) -> Result<usize, Error>;
+
+    /// Whether the responder has an interrupt endpoint to send events on.
+    /// Without one, `Device::event` polls for events instead.
+    fn has_interrupt(&self) -> bool {
+        true
+    }
+
+    /// Reads from the interrupt endpoint into `buf`, returning the number of
+    /// bytes read
+    fn read_interrupt(&self, buf: &mut [u8], timeout: Duration) -> Result<usize, Error>;
+
+    /// Returns the largest packet `endpoint` transfers. Defaults to the sizes
+    /// usual for a high speed device.
+    fn max_packet_size(&self, endpoint: Endpoint) -> usize {
+        match endpoint {
+            Endpoint::BulkIn | Endpoint::BulkOut => 512,
+            Endpoint::Interrupt => 64,
+        }
+    }
+
+    /// Makes a class specific request with a data stage from the device to
+    /// the PTP interface, returning the number of bytes read into `buf`
+    fn class_request_in(
+        &self,
+        _request: u8,
+        _buf: &mut [u8],
+        _timeout: Duration,
+    ) -> Result<usize, Error> {
+        Err(Error::NotSupported("class requests"))
+    }
+
+    /// Makes a class specific request with a data stage from the host to the
+    /// PTP interface, or none if `buf` is empty, returning the number of
+    /// bytes written
+    fn class_request_out(
+        &self,
+        _request: u8,
+        _buf: &[u8],
+        _timeout: Duration,
+    ) -> Result<usize, Error> {
+        Err(Error::NotSupported("class requests"))
+    }
+
+    /// Clears a halt condition on `endpoint`
+    fn clear_halt(&self, endpoint: Endpoint) -> Result<(), Error>;
+
+    /// Resets the device
+    fn reset(&self) -> Result<(), Error>;
+
+    /// Releases the device, after which the transport is no longer used
+    fn release(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// A transport over the PTP interface of a USB device
+pub struct UsbTransport<C: rusb::UsbContext> {
+    iface: u8,
+    ep_in: EndpointDescriptor,
+    ep_out: EndpointDescriptor,
+    ep_int: Option<EndpointDescriptor>,
+    handle: Arc<rusb::DeviceHandle<C>>,
+}
+
+impl<C: rusb::UsbContext> UsbTransport<C> {
+    /// Finds and claims the PTP interface of the device, see
+    /// `ConfigDescriptor::find_ptp_interface`.
+    pub fn new(handle: Arc<rusb::DeviceHandle<C>>) -> Result<UsbTransport<C>, Error> {
+        let descriptors = UsbDescriptors::read(&handle)?;
+        let config = handle.active_configuration()?;
+
+        let (interface, found_by) = descriptors
+            .ptp_interface(config)
+            .ok_or(rusb::Error::NotFound)?;
+        debug!("Found interface {} by {:?}", interface.number, found_by);
+
+        UsbTransport::claim(handle, interface)
+    }
+
+    /// Claims the given interface and alternate setting of the active
+    /// configuration, for devices whose PTP interface isn't recognised.
+    pub fn with_interface(
+        handle: Arc<rusb::DeviceHandle<C>>,
+        interface: u8,
+        alt_setting: u8,
+    ) -> Result<UsbTransport<C>, Error> {
+        let descriptors = UsbDescriptors::read(&handle)?;
+        let config = handle.active_configuration()?;
+
+        let interface = descriptors
+            .interface(config, interface, alt_setting)
+            .ok_or(rusb::Error::NotFound)?;
+
+        UsbTransport::claim(handle, interface)
+    }
+
+    fn claim(
+        handle: Arc<rusb::DeviceHandle<C>>,
+        interface: &InterfaceDescriptor,
+    ) -> Result<UsbTransport<C>, Error> {
+        handle.claim_interface(interface.number)?;
+        handle.set_alternate_setting(interface.number, interface.alt_setting)?;
+
+        let find_endpoint =
+            |direction, transfer_type| interface.endpoint(direction, transfer_type).copied();
+
+        Ok(UsbTransport {
+            iface: interface.number,
+            ep_in: find_endpoint(rusb::Direction::In, rusb::TransferType::Bulk)
+                .ok_or(rusb::Error::NotFound)?,
+            ep_out: find_endpoint(rusb::Direction::Out, rusb::TransferType::Bulk)
+                .ok_or(rusb::Error::NotFound)?,
+            ep_int: find_endpoint(rusb::Direction::In, rusb::TransferType::Interrupt),
+            handle,
+        })
+    }
+
+    pub fn handle(&self) -> &Arc<rusb::DeviceHandle<C>> {
+        &self.handle
+    }
+
+    fn endpoint(&self, endpoint: Endpoint) -> Result<&EndpointDescriptor, Error> {
+        match endpoint {
+            Endpoint::BulkIn => Ok(&self.ep_in),
+            Endpoint::BulkOut => Ok(&self.ep_out),
+            Endpoint::Interrupt => self
+                .ep_int
+                .as_ref()
+                .ok_or(Error::NotSupported("an interrupt endpoint")),
+        }
+    }
+}
+
+impl<C: rusb::UsbContext> Transport for UsbTransport<C> {
+    fn write_bulk(&self, buf: &[u8], timeout: Duration) -> Result<usize, Error> {
+        Ok(self.handle.write_bulk(self.ep_out.address, buf, timeout)?)
+    }
+
+    fn read_bulk(&self, buf: &mut [u8], timeout: Duration) -> Result<usize, Error> {
+        Ok(self.handle.read_bulk(self.ep_in.address, buf, timeout)?)
+    }
+
+    fn has_interrupt(&self) -> bool {
+        self.ep_int.is_some()
+    }
+
+    fn read_interrupt(&self, buf: &mut [u8], timeout: Duration) -> Result<usize, Error> {
+        let address = self.endpoint(Endpoint::Interrupt)?.address;
+        Ok(self.handle.read_interrupt(address, buf, timeout)?)
+    }
+
+    fn max_packet_size(&self, endpoint: Endpoint) -> usize {
+        match self.endpoint(endpoint) {
+            Ok(descriptor) => descriptor.packet_size(),
+            Err(_) => 0,
+        }
+    }
+
+    fn class_request_in(
+        &self,
+        request: u8,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> Result<usize, Error> {
+        let request_type = rusb::request_type(
+            rusb::Direction::In,
+            rusb::RequestType::Class,
+            rusb::Recipient::Interface,
+        );
+        Ok(self
+            .handle
+            .read_control(request_type, request, 0, self.iface.into(), buf, timeout)?)
+    }
+
+    fn class_request_out(
+        &self,
+        request: u8,
+        buf: &[u8],
+        timeout: Duration,
+    ) -> Result<usize, Error> {
+        let request_type = rusb::request_type(
+            rusb::Direction::Out,
+            rusb::RequestType::Class,
+            rusb::Recipient::Interface,
+        );
+        Ok(self
+            .handle
+            .write_control(request_type, request, 0, self.iface.into(), buf, timeout)?)
+    }
+
+    fn clear_halt(&self, endpoint: Endpoint) -> Result<(), Error> {
+        Ok(self.handle.clear_halt(self.endpoint(endpoint)?.address)?)
+    }
+
+    fn reset(&self) -> Result<(), Error> {
+        Ok(self.handle.reset()?)
+    }
+
+    fn release(&self) -> Result<(), Error> {
+        Ok(self.handle.release_interface(self.iface)?)
+    }
+}
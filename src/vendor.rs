@@ -0,0 +1,549 @@
+use num_derive::{FromPrimitive, ToPrimitive};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::DeviceInfo;
+
+/// Vendor extension IDs, as reported in `DeviceInfo::vendor_ex_id`.
+#[repr(u32)]
+#[derive(FromPrimitive, ToPrimitive, Debug, Copy, Clone, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum VendorExtensionId {
+    Kodak = 0x0000_0001,
+    EpsonSeiko = 0x0000_0002,
+    Agilent = 0x0000_0003,
+    Polaroid = 0x0000_0004,
+    AgfaGevaert = 0x0000_0005,
+    Microsoft = 0x0000_0006,
+    Equinox = 0x0000_0007,
+    Viewquest = 0x0000_0008,
+    StMicroelectronics = 0x0000_0009,
+    Nikon = 0x0000_000A,
+    Canon = 0x0000_000B,
+    FotoNation = 0x0000_000C,
+    Pentax = 0x0000_000D,
+    Fuji = 0x0000_000E,
+    Sony = 0x0000_0011,
+    Ndd = 0x0000_0012,
+    Samsung = 0x0000_001A,
+    Parrot = 0x0000_001B,
+    Panasonic = 0x0000_001C,
+}
+
+/// A single entry of a `VendorExtensionDesc` string, e.g. `microsoft.com: 1.0`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct VendorExtension {
+    pub name: String,
+    pub version: String,
+}
+
+impl VendorExtension {
+    /// Parses a `VendorExtensionDesc` string such as
+    /// `"microsoft.com: 1.0; android.com: 1.0;"`. Entries without a version
+    /// are returned with an empty `version`.
+    pub fn parse_desc(desc: &str) -> Vec<VendorExtension> {
+        desc.split(';')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                let (name, version) = entry.split_once(':').unwrap_or((entry, ""));
+                VendorExtension {
+                    name: name.trim().to_lowercase(),
+                    version: version.trim().to_owned(),
+                }
+            })
+            .collect()
+    }
+}
+
+type CodeNames = &'static [(u16, &'static str)];
+
+/// Describes the vendor extension spoken by a device: which vendor it is and
+/// the names of the vendor-defined operation, event, property and format codes.
+#[derive(Debug)]
+pub struct VendorProfile {
+    /// Human-readable vendor name
+    pub name: &'static str,
+
+    /// Extension IDs that select this profile
+    pub ids: &'static [VendorExtensionId],
+
+    /// `VendorExtensionDesc` entries that select this profile
+    pub extensions: &'static [&'static str],
+
+    /// Profile whose codes this profile builds upon, e.g. MTP for Android
+    pub parent: Option<&'static VendorProfile>,
+
+    operations: CodeNames,
+    responses: CodeNames,
+    events: CodeNames,
+    properties: CodeNames,
+    formats: CodeNames,
+}
+
+fn lookup(names: CodeNames, code: u16) -> Option<&'static str> {
+    names
+        .iter()
+        .find(|&&(c, _)| c == code)
+        .map(|&(_, name)| name)
+}
+
+fn lookup_code(names: CodeNames, name: &str) -> Option<u16> {
+    names
+        .iter()
+        .find(|&&(_, n)| n.eq_ignore_ascii_case(name))
+        .map(|&(code, _)| code)
+}
+
+impl VendorProfile {
+    /// Selects the profile matching a device's vendor extension ID and
+    /// `VendorExtensionDesc`. A vendor-specific ID always wins; devices that
+    /// report the Microsoft (MTP) ID are refined by their extension string,
+    /// so e.g. Android phones get the Android profile rather than plain MTP.
+    pub fn detect(info: &DeviceInfo) -> Option<&'static VendorProfile> {
+        let by_id = PROFILES
+            .iter()
+            .copied()
+            .find(|p| p.ids.iter().any(|&id| id as u32 == info.vendor_ex_id));
+
+        if let Some(profile) = by_id {
+            if !profile.ids.contains(&VendorExtensionId::Microsoft) {
+                return Some(profile);
+            }
+        }
+
+        let extensions = info.vendor_extensions();
+        PROFILES
+            .iter()
+            .copied()
+            .find(|p| {
+                extensions
+                    .iter()
+                    .any(|ext| p.extensions.contains(&ext.name.as_str()))
+            })
+            .or(by_id)
+    }
+
+    /// Returns the name of a vendor-defined operation code
+    pub fn operation_name(&self, code: u16) -> Option<&'static str> {
+        lookup(self.operations, code).or_else(|| self.parent?.operation_name(code))
+    }
+
+    /// Returns the code of a vendor-defined operation by name, ignoring case
+    pub fn operation_code(&self, name: &str) -> Option<u16> {
+        lookup_code(self.operations, name).or_else(|| self.parent?.operation_code(name))
+    }
+
+    /// Returns the name of a vendor-defined response code
+    pub fn response_name(&self, code: u16) -> Option<&'static str> {
+        lookup(self.responses, code).or_else(|| self.parent?.response_name(code))
+    }
+
+    /// Returns the code of a vendor-defined response by name, ignoring case
+    pub fn response_code(&self, name: &str) -> Option<u16> {
+        lookup_code(self.responses, name).or_else(|| self.parent?.response_code(name))
+    }
+
+    /// Returns the name of a vendor-defined event code
+    pub fn event_name(&self, code: u16) -> Option<&'static str> {
+        lookup(self.events, code).or_else(|| self.parent?.event_name(code))
+    }
+
+    /// Returns the code of a vendor-defined event by name, ignoring case
+    pub fn event_code(&self, name: &str) -> Option<u16> {
+        lookup_code(self.events, name).or_else(|| self.parent?.event_code(name))
+    }
+
+    /// Returns the name of a vendor-defined device or object property code
+    pub fn property_name(&self, code: u16) -> Option<&'static str> {
+        lookup(self.properties, code).or_else(|| self.parent?.property_name(code))
+    }
+
+    /// Returns the name of a vendor-defined object format code
+    pub fn format_name(&self, code: u16) -> Option<&'static str> {
+        lookup(self.formats, code).or_else(|| self.parent?.format_name(code))
+    }
+
+    /// Returns the code of a vendor-defined object format by name, ignoring case
+    pub fn format_code(&self, name: &str) -> Option<u16> {
+        lookup_code(self.formats, name).or_else(|| self.parent?.format_code(name))
+    }
+}
+
+// Ordered from most to least specific: the first profile whose extension
+// string matches wins, so refinements must come before their parents.
+static PROFILES: &[&VendorProfile] = &[
+    &CANON, &NIKON, &SONY, &FUJI, &PANASONIC, &KODAK, &ANDROID, &MTP,
+];
+
+pub static MTP: VendorProfile = VendorProfile {
+    name: "Microsoft MTP",
+    ids: &[VendorExtensionId::Microsoft],
+    extensions: &["microsoft.com", "microsoft.com/wpdna"],
+    parent: None,
+    operations: &[
+        (0x9801, "GetObjectPropsSupported"),
+        (0x9802, "GetObjectPropDesc"),
+        (0x9803, "GetObjectPropValue"),
+        (0x9804, "SetObjectPropValue"),
+        (0x9805, "GetObjectPropList"),
+        (0x9806, "SetObjectPropList"),
+        (0x9807, "GetInterdependentPropDesc"),
+        (0x9808, "SendObjectPropList"),
+        (0x9810, "GetObjectReferences"),
+        (0x9811, "SetObjectReferences"),
+        (0x9812, "UpdateDeviceFirmware"),
+        (0x9820, "Skip"),
+    ],
+    responses: &[
+        (0xA801, "InvalidObjectPropCode"),
+        (0xA802, "InvalidObjectPropFormat"),
+        (0xA803, "InvalidObjectPropValue"),
+        (0xA804, "InvalidObjectReference"),
+        (0xA805, "GroupNotSupported"),
+        (0xA806, "InvalidDataset"),
+        (0xA807, "SpecificationByGroupUnsupported"),
+        (0xA808, "SpecificationByDepthUnsupported"),
+        (0xA809, "ObjectTooLarge"),
+        (0xA80A, "ObjectPropNotSupported"),
+    ],
+    events: &[
+        (0xC801, "ObjectPropChanged"),
+        (0xC802, "ObjectPropDescChanged"),
+        (0xC803, "ObjectReferencesChanged"),
+    ],
+    properties: &[
+        (0xD401, "SynchronizationPartner"),
+        (0xD402, "DeviceFriendlyName"),
+        (0xD403, "VolumeLevel"),
+        (0xD405, "DeviceIcon"),
+        (0xD406, "SessionInitiatorInfo"),
+        (0xD407, "PerceivedDeviceType"),
+        (0xD410, "PlaybackRate"),
+        (0xD411, "PlaybackObject"),
+        (0xD412, "PlaybackContainerIndex"),
+        (0xD413, "PlaybackPosition"),
+        (0xDC01, "StorageID"),
+        (0xDC02, "ObjectFormat"),
+        (0xDC03, "ProtectionStatus"),
+        (0xDC04, "ObjectSize"),
+        (0xDC05, "AssociationType"),
+        (0xDC06, "AssociationDesc"),
+        (0xDC07, "ObjectFileName"),
+        (0xDC08, "DateCreated"),
+        (0xDC09, "DateModified"),
+        (0xDC0A, "Keywords"),
+        (0xDC0B, "ParentObject"),
+        (0xDC0C, "AllowedFolderContents"),
+        (0xDC0D, "Hidden"),
+        (0xDC0E, "SystemObject"),
+        (0xDC41, "PersistentUniqueObjectIdentifier"),
+        (0xDC44, "Name"),
+        (0xDC87, "Width"),
+        (0xDC88, "Height"),
+        (0xDC89, "Duration"),
+    ],
+    formats: &[
+        (0xB802, "Firmware"),
+        (0xB803, "WindowsImageFormat"),
+        (0xB900, "UndefinedAudio"),
+        (0xB901, "WMA"),
+        (0xB902, "OGG"),
+        (0xB903, "AAC"),
+        (0xB904, "Audible"),
+        (0xB906, "FLAC"),
+        (0xB980, "UndefinedVideo"),
+        (0xB981, "WMV"),
+        (0xB982, "MP4Container"),
+        (0xB983, "MP2"),
+        (0xB984, "3GPContainer"),
+        (0xBA01, "AbstractMultimediaAlbum"),
+        (0xBA02, "AbstractImageAlbum"),
+        (0xBA03, "AbstractAudioAlbum"),
+        (0xBA04, "AbstractVideoAlbum"),
+        (0xBA05, "AbstractAudioVideoPlaylist"),
+    ],
+};
+
+pub static ANDROID: VendorProfile = VendorProfile {
+    name: "Android MTP",
+    ids: &[],
+    extensions: &["android.com"],
+    parent: Some(&MTP),
+    operations: &[
+        (0x95C1, "GetPartialObject64"),
+        (0x95C2, "SendPartialObject"),
+        (0x95C3, "TruncateObject"),
+        (0x95C4, "BeginEditObject"),
+        (0x95C5, "EndEditObject"),
+    ],
+    responses: &[],
+    events: &[],
+    properties: &[],
+    formats: &[],
+};
+
+pub static CANON: VendorProfile = VendorProfile {
+    name: "Canon",
+    ids: &[VendorExtensionId::Canon],
+    extensions: &["canon.com"],
+    parent: None,
+    operations: &[
+        (0x9008, "ViewfinderOn"),
+        (0x9009, "ViewfinderOff"),
+        (0x9101, "EOS_GetStorageIDs"),
+        (0x9102, "EOS_GetStorageInfo"),
+        (0x9103, "EOS_GetObjectInfo"),
+        (0x9104, "EOS_GetObject"),
+        (0x9105, "EOS_DeleteObject"),
+        (0x9106, "EOS_FormatStore"),
+        (0x9107, "EOS_GetPartialObject"),
+        (0x9108, "EOS_GetDeviceInfoEx"),
+        (0x9109, "EOS_GetObjectInfoEx"),
+        (0x910A, "EOS_GetThumbEx"),
+        (0x910F, "EOS_RemoteRelease"),
+        (0x9110, "EOS_SetDevicePropValueEx"),
+        (0x9114, "EOS_SetRemoteMode"),
+        (0x9115, "EOS_SetEventMode"),
+        (0x9116, "EOS_GetEvent"),
+        (0x9117, "EOS_TransferComplete"),
+        (0x9118, "EOS_CancelTransfer"),
+        (0x911B, "EOS_SetUILock"),
+        (0x911C, "EOS_ResetUILock"),
+        (0x911D, "EOS_KeepDeviceOn"),
+        (0x9127, "EOS_GetDevicePropValue"),
+        (0x9128, "EOS_RemoteReleaseOn"),
+        (0x9129, "EOS_RemoteReleaseOff"),
+        (0x9151, "EOS_InitiateViewfinder"),
+        (0x9152, "EOS_TerminateViewfinder"),
+        (0x9153, "EOS_GetViewFinderData"),
+        (0x9154, "EOS_DoAf"),
+        (0x9155, "EOS_DriveLens"),
+        (0x9158, "EOS_Zoom"),
+        (0x915B, "EOS_ZoomPosition"),
+        (0x9160, "EOS_AfCancel"),
+    ],
+    responses: &[
+        (0xA001, "UnknownCommand"),
+        (0xA005, "OperationRefused"),
+        (0xA006, "LensCover"),
+        (0xA101, "BatteryLow"),
+        (0xA102, "NotReady"),
+    ],
+    events: &[
+        (0xC101, "RequestGetEvent"),
+        (0xC181, "ObjectAddedEx"),
+        (0xC182, "ObjectRemoved"),
+        (0xC183, "RequestGetObjectInfoEx"),
+        (0xC184, "StorageStatusChanged"),
+        (0xC185, "StorageInfoChanged"),
+        (0xC186, "RequestObjectTransfer"),
+        (0xC187, "ObjectInfoChangedEx"),
+        (0xC188, "ObjectContentChanged"),
+        (0xC189, "PropValueChanged"),
+        (0xC18A, "AvailListChanged"),
+        (0xC18B, "CameraStatusChanged"),
+        (0xC18D, "WillSoonShutdown"),
+        (0xC18E, "ShutdownTimerUpdated"),
+        (0xC18F, "RequestCancelTransfer"),
+        (0xC192, "StoreAdded"),
+        (0xC193, "StoreRemoved"),
+        (0xC194, "BulbExposureTime"),
+        (0xC195, "RecordingTime"),
+    ],
+    properties: &[
+        (0xD101, "Aperture"),
+        (0xD102, "ShutterSpeed"),
+        (0xD103, "ISOSpeed"),
+        (0xD104, "ExpCompensation"),
+        (0xD105, "AutoExposureMode"),
+        (0xD106, "DriveMode"),
+        (0xD107, "MeteringMode"),
+        (0xD108, "FocusMode"),
+        (0xD109, "WhiteBalance"),
+        (0xD10A, "ColorTemperature"),
+        (0xD111, "BatteryPower"),
+        (0xD11B, "CaptureDestination"),
+        (0xD1B0, "EVFOutputDevice"),
+        (0xD1B1, "EVFMode"),
+        (0xD1B3, "EVFWBMode"),
+    ],
+    formats: &[
+        (0xB101, "CRW"),
+        (0xB103, "CR2"),
+        (0xB104, "MOV"),
+        (0xB108, "CR3"),
+    ],
+};
+
+pub static NIKON: VendorProfile = VendorProfile {
+    name: "Nikon",
+    ids: &[VendorExtensionId::Nikon],
+    extensions: &["nikon.com", "nikon.co.jp"],
+    parent: None,
+    operations: &[
+        (0x90C0, "Capture"),
+        (0x90C1, "AfDrive"),
+        (0x90C2, "SetControlMode"),
+        (0x90C3, "DelImageSDRAM"),
+        (0x90C4, "GetLargeThumb"),
+        (0x90C7, "GetEvent"),
+        (0x90C8, "DeviceReady"),
+        (0x90C9, "SetPreWBData"),
+        (0x90CA, "GetVendorPropCodes"),
+        (0x90CB, "AfCaptureSDRAM"),
+        (0x90CC, "GetPictCtrlData"),
+        (0x90CD, "SetPictCtrlData"),
+        (0x9201, "StartLiveView"),
+        (0x9202, "EndLiveView"),
+        (0x9203, "GetLiveViewImg"),
+        (0x9204, "MfDrive"),
+        (0x9205, "ChangeAfArea"),
+        (0x9206, "AfDriveCancel"),
+        (0x9207, "InitiateCaptureRecInMedia"),
+        (0x9209, "GetVendorStorageIDs"),
+        (0x920A, "StartMovieRecInCard"),
+        (0x920B, "EndMovieRec"),
+        (0x920C, "TerminateCapture"),
+    ],
+    responses: &[
+        (0xA001, "HardwareError"),
+        (0xA002, "OutOfFocus"),
+        (0xA003, "ChangeCameraModeFailed"),
+        (0xA004, "InvalidStatus"),
+        (0xA005, "SetPropertyNotSupported"),
+        (0xA006, "WbResetError"),
+        (0xA007, "DustReferenceError"),
+        (0xA008, "ShutterSpeedBulb"),
+        (0xA009, "MirrorUpSequence"),
+        (0xA00A, "CameraModeNotAdjustFNumber"),
+        (0xA00B, "NotLiveView"),
+        (0xA00C, "MfDriveStepEnd"),
+        (0xA00E, "MfDriveStepInsufficiency"),
+        (0xA022, "AdvancedTransferCancel"),
+    ],
+    events: &[
+        (0xC101, "ObjectAddedInSDRAM"),
+        (0xC102, "CaptureCompleteRecInSdram"),
+        (0xC105, "PreviewImageAdded"),
+    ],
+    properties: &[
+        (0xD100, "ExposureTime"),
+        (0xD101, "ACPower"),
+        (0xD102, "WarningStatus"),
+        (0xD103, "MaximumShots"),
+        (0xD104, "AFLockStatus"),
+        (0xD1A0, "LiveViewMode"),
+        (0xD1A1, "LiveViewDriveMode"),
+        (0xD1A2, "LiveViewStatus"),
+        (0xD1A3, "LiveViewImageZoomRatio"),
+        (0xD1A4, "LiveViewProhibitCondition"),
+    ],
+    formats: &[(0xB001, "NEF")],
+};
+
+pub static SONY: VendorProfile = VendorProfile {
+    name: "Sony",
+    ids: &[VendorExtensionId::Sony],
+    extensions: &["sony.net"],
+    parent: None,
+    operations: &[
+        (0x9201, "SDIOConnect"),
+        (0x9202, "SDIOGetExtDeviceInfo"),
+        (0x9203, "GetDevicePropDesc"),
+        (0x9204, "GetDevicePropertyValue"),
+        (0x9205, "SetControlDeviceA"),
+        (0x9206, "GetControlDeviceDesc"),
+        (0x9207, "SetControlDeviceB"),
+        (0x9209, "GetAllDevicePropData"),
+    ],
+    responses: &[],
+    events: &[
+        (0xC201, "ObjectAdded"),
+        (0xC202, "ObjectRemoved"),
+        (0xC203, "PropertyChanged"),
+    ],
+    properties: &[
+        (0xD200, "DPCCompensation"),
+        (0xD201, "DRangeOptimize"),
+        (0xD203, "ImageSize"),
+        (0xD20D, "ShutterSpeed"),
+        (0xD20F, "ColorTemp"),
+        (0xD210, "CCFilter"),
+        (0xD211, "AspectRatio"),
+        (0xD213, "FocusFound"),
+        (0xD215, "ObjectInMemory"),
+        (0xD217, "BatteryLevel"),
+        (0xD21B, "PictureEffect"),
+        (0xD21C, "ABFilter"),
+        (0xD21E, "ISO"),
+        (0xD2C1, "AutoFocus"),
+        (0xD2C2, "Capture"),
+    ],
+    formats: &[(0xB101, "ARW")],
+};
+
+pub static FUJI: VendorProfile = VendorProfile {
+    name: "Fujifilm",
+    ids: &[VendorExtensionId::Fuji],
+    extensions: &["fujifilm.co.jp"],
+    parent: None,
+    operations: &[
+        (0x900C, "InitiateMovieCapture"),
+        (0x900D, "TerminateMovieCapture"),
+        (0x900E, "GetCapturePreview"),
+        (0x9020, "SetFocusPoint"),
+        (0x9022, "ResetFocusPoint"),
+    ],
+    responses: &[],
+    events: &[],
+    properties: &[
+        (0xD017, "ColorTemperature"),
+        (0xD018, "Quality"),
+        (0xD207, "Priority"),
+        (0xD208, "ReleaseMode"),
+        (0xD209, "FocusAreas"),
+        (0xD212, "CurrentState"),
+    ],
+    formats: &[(0xB103, "RAF")],
+};
+
+pub static PANASONIC: VendorProfile = VendorProfile {
+    name: "Panasonic",
+    ids: &[VendorExtensionId::Panasonic],
+    extensions: &["panasonic.co.jp"],
+    parent: None,
+    operations: &[
+        (0x9107, "ListProperty"),
+        (0x9401, "GetProperty"),
+        (0x9402, "SetProperty"),
+        (0x9404, "InitiateCapture"),
+        (0x9405, "CaptureStatus"),
+        (0x9406, "SetCaptureTarget"),
+        (0x9412, "Liveview"),
+        (0x9706, "LiveviewImage"),
+    ],
+    responses: &[],
+    events: &[(0xC108, "ObjectAddedSDRAM")],
+    properties: &[],
+    formats: &[(0xB101, "RW2")],
+};
+
+pub static KODAK: VendorProfile = VendorProfile {
+    name: "Kodak",
+    ids: &[VendorExtensionId::Kodak],
+    extensions: &["kodak.com"],
+    parent: None,
+    operations: &[
+        (0x9003, "GetSerial"),
+        (0x9005, "SetSerial"),
+        (0x9006, "SendFileObjectInfo"),
+        (0x9007, "SendFileObject"),
+    ],
+    responses: &[],
+    events: &[],
+    properties: &[],
+    formats: &[(0xB101, "M3U")],
+};
@@ -0,0 +1,184 @@
+#![cfg(feature = "async")]
+
+mod common;
+
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures::{FutureExt, StreamExt};
+use ptp::{
+    AsyncDevice, CommandCode, Device, EventCode, SessionConfig, StandardCommandCode,
+    StandardEventCode,
+};
+
+use common::{FakeCamera, Reply};
+
+const SLOW: u16 = 0x9003;
+const GET_STORAGE_IDS: u16 = StandardCommandCode::GetStorageIDs as u16;
+
+// A device whose SLOW operation blocks until told to finish
+fn camera() -> (AsyncDevice<FakeCamera>, mpsc::Sender<()>) {
+    let (finish, finished) = mpsc::channel();
+    let finished = Mutex::new(finished);
+    let fake = FakeCamera::new(move |request| match request.code {
+        SLOW => {
+            finished.lock().unwrap().recv().unwrap();
+            Reply::ok()
+        }
+        GET_STORAGE_IDS => Reply::data(vec![1, 0, 0, 0, 1, 0, 1, 0]),
+        _ => Reply::ok(),
+    });
+    (AsyncDevice::new(Device::with_transport(fake)), finish)
+}
+
+fn assert_send_sync<T: Send + Sync>(_: &T) {}
+
+#[tokio::test]
+async fn session_operations() {
+    let (device, _finish) = camera();
+    assert_send_sync(&device);
+
+    let session = device.open_session(SessionConfig::default()).await.unwrap();
+    let ids = session.get_storage_ids(None).await.unwrap();
+    assert_eq!(ids.len(), 1);
+    session.close().await.unwrap();
+
+    let codes: Vec<u16> = device
+        .device()
+        .transport()
+        .log()
+        .iter()
+        .map(|r| r.code)
+        .collect();
+    assert_eq!(
+        codes,
+        [
+            StandardCommandCode::OpenSession as u16,
+            GET_STORAGE_IDS,
+            StandardCommandCode::CloseSession as u16
+        ]
+    );
+}
+
+#[tokio::test]
+async fn dropped_futures_keep_transaction_ids_in_step() {
+    let (device, finish) = camera();
+    let session = device.open_session(SessionConfig::default()).await.unwrap();
+
+    // give up on a transaction while the device is working on it
+    let slow = session.command(CommandCode::Other(SLOW), &[], None, None);
+    let result = tokio::time::timeout(Duration::from_millis(50), slow).await;
+    assert!(result.is_err());
+    finish.send(()).unwrap();
+
+    assert_eq!(session.get_storage_ids(None).await.unwrap().len(), 1);
+    drop(session);
+    device
+        .command(CommandCode::Other(0x9001), &[], None, None)
+        .await
+        .unwrap();
+
+    let fake = device.device().transport();
+    let tids: Vec<u32> = fake.log().iter().map(|r| r.tid).collect();
+    assert_eq!(tids, [0, 1, 2, 3, 0]);
+    assert_eq!(fake.violations(), Vec::<String>::new());
+}
+
+#[tokio::test]
+async fn events_arrive_while_a_transaction_runs() {
+    let (device, finish) = camera();
+    let device = Arc::new(device);
+    let mut events = device.events();
+
+    let slow = tokio::spawn({
+        let device = device.clone();
+        async move {
+            device
+                .command(CommandCode::Other(SLOW), &[], None, None)
+                .await
+        }
+    });
+    let fake = device.device().transport();
+    while fake.log().is_empty() {
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+
+    fake.push_event(StandardEventCode::ObjectAdded as u16, &[0x2a]);
+    fake.push_event(StandardEventCode::ObjectRemoved as u16, &[0x2b]);
+
+    let first = events.next().await.unwrap().unwrap();
+    assert_eq!(
+        first.code,
+        EventCode::Standard(StandardEventCode::ObjectAdded)
+    );
+    assert_eq!(first.params, [0x2a]);
+    let second = events.next().await.unwrap().unwrap();
+    assert_eq!(
+        second.code,
+        EventCode::Standard(StandardEventCode::ObjectRemoved)
+    );
+
+    finish.send(()).unwrap();
+    slow.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn abandoned_event_calls_do_not_take_events() {
+    let (device, _finish) = camera();
+
+    // give up on a few calls that would have waited for a while
+    for _ in 0..4 {
+        let event = device.event(Some(Duration::from_secs(5)));
+        let result = tokio::time::timeout(Duration::from_millis(20), event).await;
+        assert!(result.is_err());
+    }
+    let none = device.event(Some(Duration::from_millis(20))).await;
+    assert!(none.unwrap().is_none());
+
+    let fake = device.device().transport();
+    for param in 1..=4 {
+        fake.push_event(StandardEventCode::ObjectAdded as u16, &[param]);
+    }
+    for param in 1..=4 {
+        let event = device.event(Some(Duration::from_secs(5))).await;
+        assert_eq!(event.unwrap().unwrap().params, [param]);
+    }
+}
+
+#[tokio::test]
+async fn dropped_close_futures_still_close_the_session() {
+    let (device, finish) = camera();
+    let device = Arc::new(device);
+
+    // never polled
+    let session = device.open_session(SessionConfig::default()).await.unwrap();
+    drop(session.close());
+
+    // polled once, while queued behind a slow transaction
+    let session = device.open_session(SessionConfig::default()).await.unwrap();
+    let slow = tokio::spawn({
+        let device = device.clone();
+        async move {
+            device
+                .command(CommandCode::Other(SLOW), &[], None, None)
+                .await
+        }
+    });
+    let fake = device.device().transport();
+    while !fake.log().iter().any(|r| r.code == SLOW) {
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+    assert!(session.close().now_or_never().is_none());
+    finish.send(()).unwrap();
+    slow.await.unwrap().unwrap();
+
+    let session = device.open_session(SessionConfig::default()).await.unwrap();
+    session.close().await.unwrap();
+    let closes = fake
+        .log()
+        .iter()
+        .filter(|r| r.code == StandardCommandCode::CloseSession as u16)
+        .count();
+    assert_eq!(closes, 3);
+}
@@ -0,0 +1,124 @@
+mod common;
+
+use std::time::Duration;
+
+use ptp::{BufferPool, CallOptions, CommandCode, Device, PooledBuffer};
+
+use common::{FakeCamera, Reply, Request, PACKET_SIZE};
+
+const TIMEOUT: Option<Duration> = Some(Duration::from_secs(1));
+// asks for as much data as its parameter says
+const RECEIVE: u16 = 0x9002;
+
+fn payload(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i % 251) as u8).collect()
+}
+
+fn handler(request: &Request) -> Reply {
+    match request.code {
+        RECEIVE => Reply::data(payload(request.params[0] as usize)),
+        _ => Reply::ok(),
+    }
+}
+
+fn device() -> Device<FakeCamera> {
+    Device::with_transport(FakeCamera::new(handler))
+}
+
+fn receive(device: &Device<FakeCamera>, len: usize) -> PooledBuffer {
+    device
+        .command_pooled(
+            CommandCode::Other(RECEIVE),
+            &[len as u32],
+            None,
+            TIMEOUT,
+            &CallOptions::default(),
+        )
+        .unwrap()
+}
+
+#[test]
+fn pooled_payloads_match_what_was_sent() {
+    let device = device();
+    // reused buffers hold what the previous, longer read left in them
+    for &len in &[
+        100_000,
+        0,
+        1,
+        8180,
+        PACKET_SIZE * 16 - 12,
+        PACKET_SIZE * 40,
+        20_000,
+        7,
+    ] {
+        let data = receive(&device, len);
+        assert_eq!(&data[..], &payload(len)[..], "{} bytes", len);
+        assert_eq!(data.into_vec(), payload(len), "{} bytes", len);
+    }
+    assert!(device.transport().violations().is_empty());
+}
+
+#[test]
+fn repeated_reads_reuse_buffers() {
+    let device = device();
+    drop(receive(&device, 50_000));
+    // one for the data, one for the response read while the data is held
+    let warm = device.buffer_pool().stats();
+    assert_eq!(warm.free, 2);
+
+    for _ in 0..10 {
+        let data = receive(&device, 50_000);
+        assert_eq!(data.len(), 50_000);
+    }
+    // the Vec API copies out small payloads, so the buffer stays pooled
+    for _ in 0..10 {
+        let data = device
+            .command(CommandCode::Other(RECEIVE), &[3000], None, TIMEOUT)
+            .unwrap();
+        assert_eq!(data, payload(3000));
+    }
+
+    let stats = device.buffer_pool().stats();
+    assert_eq!(stats.allocations, warm.allocations);
+    assert_eq!(stats.reuses, warm.reuses + 40);
+    assert_eq!(stats.free, 2);
+}
+
+#[test]
+fn buffers_held_at_once_are_distinct() {
+    let device = device();
+    let first = receive(&device, 10_000);
+    let second = receive(&device, 20_000);
+    assert_eq!(&first[..], &payload(10_000)[..]);
+    assert_eq!(&second[..], &payload(20_000)[..]);
+
+    drop(first);
+    drop(second);
+    assert_eq!(device.buffer_pool().stats().free, 3);
+}
+
+#[test]
+fn large_buffers_are_not_retained() {
+    let mut device = device();
+    let pool = BufferPool::new(2, 64 * 1024);
+    device.set_buffer_pool(pool.clone());
+
+    drop(receive(&device, 1000));
+    assert_eq!(pool.stats().free, 2);
+
+    // grown past what the pool keeps, so its storage is handed over
+    let data = receive(&device, 100_000).into_vec();
+    assert_eq!(data, payload(100_000));
+    assert_eq!(pool.stats().free, 1);
+
+    drop(receive(&device, 1000));
+    assert_eq!(pool.stats().free, 2);
+}
+
+#[test]
+fn buffers_outside_a_pool() {
+    let data = PooledBuffer::from(vec![1, 2, 3]);
+    assert_eq!(&*data, &[1, 2, 3]);
+    assert_eq!(data.into_vec(), [1, 2, 3]);
+    assert!(PooledBuffer::empty().is_empty());
+}
@@ -0,0 +1,643 @@
+//! A fake PTP responder for driving `Device` without hardware.
+
+#![allow(dead_code)]
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use ptp::{Endpoint, Error, Transport};
+
+pub const PACKET_SIZE: usize = 512;
+pub const INTERRUPT_PACKET_SIZE: usize = 64;
+
+const HEADER_SIZE: usize = 12;
+const COMMAND: u16 = 1;
+const DATA: u16 = 2;
+const RESPONSE: u16 = 3;
+const EVENT: u16 = 4;
+
+pub const OK: u16 = 0x2001;
+
+pub const CANCEL_REQUEST: u8 = 0x64;
+pub const GET_EXTENDED_EVENT_DATA: u8 = 0x65;
+pub const DEVICE_RESET: u8 = 0x66;
+pub const GET_DEVICE_STATUS: u8 = 0x67;
+const DEVICE_BUSY: u16 = 0x2019;
+const BULK_IN_ADDRESS: u32 = 0x81;
+const CANCEL_CODE: u16 = 0x4001;
+
+/// A transaction as received by the fake device
+#[derive(Debug, Clone, PartialEq)]
+pub struct Request {
+    pub code: u16,
+    pub tid: u32,
+    pub params: Vec<u32>,
+    pub data: Option<Vec<u8>>,
+}
+
+/// A bulk transfer as seen by the fake device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transfer {
+    Out(usize),
+    In { requested: usize, actual: usize },
+}
+
+/// A way for the fake device to break the data-in phase
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// Halts the bulk in endpoint until the host clears it, leaving the
+    /// response queued
+    Stall,
+    /// Sends more than the host's first read asks for, leaving the rest of
+    /// the data and the response queued
+    Babble,
+    /// Answers too late for the host's first read, which times out, leaving
+    /// the data and the response queued
+    Late,
+}
+
+/// What the fake device answers to a request
+#[derive(Debug, Clone)]
+pub struct Reply {
+    pub data: Option<Vec<u8>>,
+    pub code: u16,
+    pub params: Vec<u32>,
+    pub fault: Option<Fault>,
+}
+
+impl Reply {
+    pub fn ok() -> Reply {
+        Reply {
+            data: None,
+            code: OK,
+            params: vec![],
+            fault: None,
+        }
+    }
+
+    pub fn data(data: Vec<u8>) -> Reply {
+        Reply {
+            data: Some(data),
+            ..Reply::ok()
+        }
+    }
+
+    pub fn error(code: u16) -> Reply {
+        Reply {
+            code,
+            ..Reply::ok()
+        }
+    }
+
+    pub fn stall() -> Reply {
+        Reply {
+            fault: Some(Fault::Stall),
+            ..Reply::ok()
+        }
+    }
+
+    pub fn babble(data: Vec<u8>) -> Reply {
+        Reply {
+            fault: Some(Fault::Babble),
+            ..Reply::data(data)
+        }
+    }
+
+    pub fn late(reply: Reply) -> Reply {
+        Reply {
+            fault: Some(Fault::Late),
+            ..reply
+        }
+    }
+}
+
+pub fn container(kind: u16, code: u16, tid: u32, payload: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(HEADER_SIZE + payload.len());
+    buf.extend_from_slice(&((HEADER_SIZE + payload.len()) as u32).to_le_bytes());
+    buf.extend_from_slice(&kind.to_le_bytes());
+    buf.extend_from_slice(&code.to_le_bytes());
+    buf.extend_from_slice(&tid.to_le_bytes());
+    buf.extend_from_slice(payload);
+    buf
+}
+
+fn params_payload(params: &[u32]) -> Vec<u8> {
+    params.iter().flat_map(|p| p.to_le_bytes()).collect()
+}
+
+type Handler = dyn Fn(&Request) -> Reply + Send + Sync;
+
+#[derive(Default)]
+struct State {
+    // command waiting for its data phase or to be answered
+    command: Option<Request>,
+    // data-out container being received
+    data_out: Vec<u8>,
+    // containers for the host to read, and how far into the first one it is
+    replies: VecDeque<Vec<u8>>,
+    offset: usize,
+    zlp: bool,
+    // a data-out container ended on a packet boundary
+    expect_zlp: bool,
+    // the bulk in endpoint is halted, or the next read babbles
+    halted: bool,
+    babble: bool,
+    // the host is recovering and may read until there is nothing left
+    draining: bool,
+    // Get Device Status answers busy this many more times
+    busy_status: usize,
+    class_requests: Vec<u8>,
+    cleared_halts: Vec<Endpoint>,
+    // the timeout of every bulk transfer
+    timeouts: Vec<Duration>,
+    // a request is being handled outside the lock
+    busy: bool,
+    // bulk writes so far, and the one that stops part way through
+    writes: usize,
+    stuck_write: Option<usize>,
+    log: Vec<Request>,
+    transfers: Vec<Transfer>,
+    violations: Vec<String>,
+}
+
+/// A fake device that answers transactions with a handler, following the
+/// USB semantics `Transport` describes. It records every transaction, and
+/// any transfer that doesn't fit the phase the device is in.
+pub struct FakeCamera {
+    handler: Box<Handler>,
+    state: Mutex<State>,
+    events: Mutex<VecDeque<Vec<u8>>>,
+    extended_event_data: Mutex<Vec<u8>>,
+    unplugged: AtomicBool,
+    interrupt: bool,
+    packet_size: usize,
+    latency: Duration,
+}
+
+impl FakeCamera {
+    pub fn new(handler: impl Fn(&Request) -> Reply + Send + Sync + 'static) -> FakeCamera {
+        FakeCamera {
+            handler: Box::new(handler),
+            state: Mutex::default(),
+            events: Mutex::default(),
+            extended_event_data: Mutex::default(),
+            unplugged: AtomicBool::new(false),
+            interrupt: true,
+            packet_size: PACKET_SIZE,
+            latency: Duration::ZERO,
+        }
+    }
+
+    /// Makes every bulk transfer take `latency`, failing with `Timeout`
+    /// if its timeout is shorter.
+    pub fn with_latency(mut self, latency: Duration) -> FakeCamera {
+        self.latency = latency;
+        self
+    }
+
+    /// Sets the packet size of the bulk endpoints, e.g. 64 for a full speed
+    /// device.
+    pub fn with_packet_size(mut self, packet_size: usize) -> FakeCamera {
+        self.packet_size = packet_size;
+        self
+    }
+
+    /// Leaves out the interrupt endpoint, as some cheap devices do.
+    pub fn without_interrupt(mut self) -> FakeCamera {
+        self.interrupt = false;
+        self
+    }
+
+    /// Makes every later transfer fail with `NoDevice`, as if the device
+    /// had been detached.
+    pub fn unplug(&self) {
+        self.unplugged.store(true, Ordering::Release);
+    }
+
+    fn delay(&self, timeout: Duration) -> Result<(), Error> {
+        self.state.lock().unwrap().timeouts.push(timeout);
+        if !timeout.is_zero() && timeout < self.latency {
+            thread::sleep(timeout);
+            return Err(Error::Usb(rusb::Error::Timeout));
+        }
+        thread::sleep(self.latency);
+        Ok(())
+    }
+
+    fn check_attached(&self) -> Result<(), Error> {
+        if self.unplugged.load(Ordering::Acquire) {
+            return Err(Error::Usb(rusb::Error::NoDevice));
+        }
+        Ok(())
+    }
+
+    /// Queues an event for the interrupt endpoint.
+    pub fn push_event(&self, code: u16, params: &[u32]) {
+        let event = container(EVENT, code, 0, &params_payload(params));
+        self.events.lock().unwrap().push_back(event);
+    }
+
+    /// Queues raw data for the interrupt endpoint, such as an event cut
+    /// short.
+    pub fn push_interrupt_data(&self, data: Vec<u8>) {
+        self.events.lock().unwrap().push_back(data);
+    }
+
+    /// Sets the data returned by Get Extended Event Data.
+    pub fn set_extended_event_data(&self, data: Vec<u8>) {
+        *self.extended_event_data.lock().unwrap() = data;
+    }
+
+    /// Returns the transactions received so far.
+    pub fn log(&self) -> Vec<Request> {
+        self.state.lock().unwrap().log.clone()
+    }
+
+    /// Returns the bulk transfers so far, in order.
+    pub fn transfers(&self) -> Vec<Transfer> {
+        self.state.lock().unwrap().transfers.clone()
+    }
+
+    /// Makes the given bulk write, counting from 1, take only its first
+    /// packet and then time out, as a device that stops taking data does.
+    pub fn time_out_write(&self, write: usize) {
+        self.state.lock().unwrap().stuck_write = Some(write);
+    }
+
+    /// Makes Get Device Status answer DeviceBusy the given number of times.
+    pub fn set_busy_status(&self, times: usize) {
+        self.state.lock().unwrap().busy_status = times;
+    }
+
+    /// Returns the class requests received so far.
+    pub fn class_requests(&self) -> Vec<u8> {
+        self.state.lock().unwrap().class_requests.clone()
+    }
+
+    /// Returns the timeouts the bulk transfers so far were given.
+    pub fn timeouts(&self) -> Vec<Duration> {
+        self.state.lock().unwrap().timeouts.clone()
+    }
+
+    /// Returns the endpoints whose halts were cleared so far.
+    pub fn cleared_halts(&self) -> Vec<Endpoint> {
+        self.state.lock().unwrap().cleared_halts.clone()
+    }
+
+    /// Returns descriptions of the transfers that arrived out of order.
+    pub fn violations(&self) -> Vec<String> {
+        self.state.lock().unwrap().violations.clone()
+    }
+
+    fn receive(&self, state: &mut State, buf: &[u8]) {
+        if state.expect_zlp {
+            state.expect_zlp = false;
+            if buf.is_empty() {
+                return;
+            }
+            state
+                .violations
+                .push("data not terminated by a zero-length packet".into());
+        } else if buf.is_empty() {
+            state
+                .violations
+                .push("unexpected zero-length packet".into());
+            return;
+        }
+
+        if !state.data_out.is_empty() {
+            state.data_out.extend_from_slice(buf);
+        } else {
+            if buf.len() < HEADER_SIZE {
+                state
+                    .violations
+                    .push(format!("short write of {} bytes", buf.len()));
+                return;
+            }
+            let kind = u16::from_le_bytes([buf[4], buf[5]]);
+            match kind {
+                COMMAND => {
+                    state.draining = false;
+                    if state.command.is_some() || state.busy || !state.replies.is_empty() {
+                        state
+                            .violations
+                            .push("command written during another transaction".into());
+                    }
+                    let header = parse_header(buf);
+                    state.command = Some(Request {
+                        code: header.1,
+                        tid: header.2,
+                        params: buf[HEADER_SIZE..]
+                            .chunks_exact(4)
+                            .map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+                            .collect(),
+                        data: None,
+                    });
+                    return;
+                }
+                DATA if state.command.is_some() => state.data_out.extend_from_slice(buf),
+                _ => {
+                    state
+                        .violations
+                        .push(format!("unexpected container of type {}", kind));
+                    return;
+                }
+            }
+        }
+
+        let (len, _, tid) = parse_header(&state.data_out);
+        if state.data_out.len() >= len as usize {
+            let command = match state.command.as_mut() {
+                Some(command) => command,
+                None => {
+                    state.violations.push("data without a command".into());
+                    state.data_out.clear();
+                    return;
+                }
+            };
+            if tid != command.tid {
+                let message = format!("data tid {} for command tid {}", tid, command.tid);
+                state.violations.push(message);
+            }
+            if state.data_out.len() > len as usize {
+                let message = format!("{} bytes of a {} byte container", state.data_out.len(), len);
+                state.violations.push(message);
+            }
+            state.expect_zlp = (len as usize).is_multiple_of(self.packet_size);
+            let data = state.data_out.split_off(HEADER_SIZE);
+            state.command.as_mut().unwrap().data = Some(data);
+            state.data_out.clear();
+        }
+    }
+
+    // Fills a bulk-in transfer from the containers waiting to be read
+    fn answer(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        let mut state = self.state.lock().unwrap();
+        if state.expect_zlp {
+            state.expect_zlp = false;
+            state
+                .violations
+                .push("data not terminated by a zero-length packet".into());
+        }
+
+        if state.halted {
+            return Err(Error::Usb(rusb::Error::Pipe));
+        }
+
+        if state.zlp {
+            state.zlp = false;
+            return Ok(0);
+        }
+
+        if state.replies.is_empty() {
+            let request = match state.command.take() {
+                Some(request) if state.data_out.is_empty() => request,
+                _ if state.draining => return Err(Error::Usb(rusb::Error::Timeout)),
+                _ => {
+                    state.violations.push("read with nothing to answer".into());
+                    return Err(Error::Usb(rusb::Error::Timeout));
+                }
+            };
+            state.log.push(request.clone());
+            state.busy = true;
+            drop(state);
+
+            let reply = (self.handler)(&request);
+
+            state = self.state.lock().unwrap();
+            state.busy = false;
+            if let Some(data) = &reply.data {
+                let data = container(DATA, request.code, request.tid, data);
+                state.replies.push_back(data);
+            }
+            let params = params_payload(&reply.params);
+            let response = container(RESPONSE, reply.code, request.tid, &params);
+            state.replies.push_back(response);
+            state.offset = 0;
+
+            match reply.fault {
+                Some(Fault::Stall) => {
+                    state.halted = true;
+                    return Err(Error::Usb(rusb::Error::Pipe));
+                }
+                Some(Fault::Babble) => state.babble = true,
+                Some(Fault::Late) => return Err(Error::Usb(rusb::Error::Timeout)),
+                None => {}
+            }
+        }
+
+        let state = &mut *state;
+        let offset = state.offset;
+        let reply = state.replies.front().unwrap();
+        if state.babble {
+            // what did fit is lost with the transfer
+            state.babble = false;
+            state.offset += buf.len().min(reply.len() - offset);
+            return Err(Error::Usb(rusb::Error::Overflow));
+        }
+        if buf.len() < reply.len() - offset && !buf.len().is_multiple_of(self.packet_size) {
+            // the last packet wouldn't fit
+            state
+                .violations
+                .push(format!("babble into a {} byte read", buf.len()));
+            return Err(Error::Usb(rusb::Error::Overflow));
+        }
+        let n = buf.len().min(reply.len() - offset);
+        buf[..n].copy_from_slice(&reply[offset..offset + n]);
+
+        if offset + n == reply.len() {
+            // a transfer that fills the buffer on a packet boundary is
+            // terminated by a zero-length packet
+            state.zlp = n == buf.len() && reply.len().is_multiple_of(self.packet_size);
+            state.replies.pop_front();
+            state.offset = 0;
+        } else {
+            state.offset += n;
+        }
+
+        Ok(n)
+    }
+}
+
+fn parse_header(buf: &[u8]) -> (u32, u16, u32) {
+    (
+        u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]),
+        u16::from_le_bytes([buf[6], buf[7]]),
+        u32::from_le_bytes([buf[8], buf[9], buf[10], buf[11]]),
+    )
+}
+
+impl Transport for FakeCamera {
+    fn write_bulk(&self, buf: &[u8], timeout: Duration) -> Result<usize, Error> {
+        self.check_attached()?;
+        self.delay(timeout)?;
+        // give other threads a chance to interleave
+        thread::yield_now();
+        let mut state = self.state.lock().unwrap();
+        state.writes += 1;
+        if state.stuck_write == Some(state.writes) {
+            let n = buf.len().min(self.packet_size);
+            state.transfers.push(Transfer::Out(n));
+            self.receive(&mut state, &buf[..n]);
+            return Err(Error::Usb(rusb::Error::Timeout));
+        }
+        state.transfers.push(Transfer::Out(buf.len()));
+        self.receive(&mut state, buf);
+        Ok(buf.len())
+    }
+
+    fn read_bulk(&self, buf: &mut [u8], timeout: Duration) -> Result<usize, Error> {
+        self.check_attached()?;
+        self.delay(timeout)?;
+        thread::yield_now();
+        let n = self.answer(buf)?;
+        self.state.lock().unwrap().transfers.push(Transfer::In {
+            requested: buf.len(),
+            actual: n,
+        });
+        Ok(n)
+    }
+
+    fn has_interrupt(&self) -> bool {
+        self.interrupt
+    }
+
+    fn read_interrupt(&self, buf: &mut [u8], timeout: Duration) -> Result<usize, Error> {
+        if !self.interrupt {
+            return Err(Error::NotSupported("an interrupt endpoint"));
+        }
+        let start = Instant::now();
+        loop {
+            self.check_attached()?;
+            let mut events = self.events.lock().unwrap();
+            if let Some(mut event) = events.pop_front() {
+                let n = buf.len().min(event.len());
+                buf[..n].copy_from_slice(&event[..n]);
+                let rest = event.split_off(n);
+                if !rest.is_empty() {
+                    events.push_front(rest);
+                } else if n == buf.len() && n > 0 && n.is_multiple_of(INTERRUPT_PACKET_SIZE) {
+                    // a transfer that ends on a packet boundary is
+                    // terminated by a zero-length packet
+                    events.push_front(vec![]);
+                }
+                return Ok(n);
+            }
+            drop(events);
+            // a zero timeout waits forever, as it does for USB transfers
+            if !timeout.is_zero() && start.elapsed() >= timeout {
+                return Err(Error::Usb(rusb::Error::Timeout));
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    fn max_packet_size(&self, endpoint: Endpoint) -> usize {
+        match endpoint {
+            Endpoint::Interrupt => INTERRUPT_PACKET_SIZE,
+            _ => self.packet_size,
+        }
+    }
+
+    fn class_request_in(
+        &self,
+        request: u8,
+        buf: &mut [u8],
+        _timeout: Duration,
+    ) -> Result<usize, Error> {
+        self.check_attached()?;
+        let mut state = self.state.lock().unwrap();
+        state.class_requests.push(request);
+        let data = match request {
+            GET_EXTENDED_EVENT_DATA => self.extended_event_data.lock().unwrap().clone(),
+            GET_DEVICE_STATUS => {
+                let code = if state.busy_status > 0 {
+                    state.busy_status -= 1;
+                    DEVICE_BUSY
+                } else {
+                    OK
+                };
+                let halted: &[u32] = if state.halted {
+                    &[BULK_IN_ADDRESS]
+                } else {
+                    &[]
+                };
+                let mut status = ((4 + 4 * halted.len()) as u16).to_le_bytes().to_vec();
+                status.extend(code.to_le_bytes());
+                status.extend(halted.iter().flat_map(|a| a.to_le_bytes()));
+                status
+            }
+            _ => return Err(Error::Usb(rusb::Error::Pipe)),
+        };
+        let n = buf.len().min(data.len());
+        buf[..n].copy_from_slice(&data[..n]);
+        Ok(n)
+    }
+
+    fn class_request_out(
+        &self,
+        request: u8,
+        buf: &[u8],
+        _timeout: Duration,
+    ) -> Result<usize, Error> {
+        self.check_attached()?;
+        let mut state = self.state.lock().unwrap();
+        state.class_requests.push(request);
+        match request {
+            CANCEL_REQUEST => {
+                // back to idle, abandoning the transaction named
+                let tid = match *buf {
+                    [c0, c1, t0, t1, t2, t3] if u16::from_le_bytes([c0, c1]) == CANCEL_CODE => {
+                        u32::from_le_bytes([t0, t1, t2, t3])
+                    }
+                    _ => {
+                        state.violations.push(format!("cancel request {:?}", buf));
+                        return Err(Error::Usb(rusb::Error::Pipe));
+                    }
+                };
+                if state.command.as_ref().is_some_and(|c| c.tid != tid) {
+                    let message = format!("cancel of tid {} during another transaction", tid);
+                    state.violations.push(message);
+                }
+                state.command = None;
+                state.data_out.clear();
+                state.replies.clear();
+                state.offset = 0;
+                state.zlp = false;
+                state.expect_zlp = false;
+                Ok(buf.len())
+            }
+            DEVICE_RESET => {
+                // back to idle, with the session closed
+                state.command = None;
+                state.data_out.clear();
+                state.replies.clear();
+                state.offset = 0;
+                state.zlp = false;
+                state.expect_zlp = false;
+                state.halted = false;
+                state.babble = false;
+                Ok(buf.len())
+            }
+            _ => Err(Error::Usb(rusb::Error::Pipe)),
+        }
+    }
+
+    fn clear_halt(&self, endpoint: Endpoint) -> Result<(), Error> {
+        self.check_attached()?;
+        let mut state = self.state.lock().unwrap();
+        state.cleared_halts.push(endpoint);
+        if endpoint == Endpoint::BulkIn {
+            state.halted = false;
+            state.draining = true;
+        }
+        Ok(())
+    }
+
+    fn reset(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
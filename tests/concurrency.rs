@@ -0,0 +1,175 @@
+mod common;
+
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use ptp::{CallOptions, CommandCode, Device, Priority, SessionConfig, StandardEventCode};
+
+use common::{FakeCamera, Reply, Request};
+
+const ECHO: u16 = 0x9001;
+const SUM: u16 = 0x9002;
+const SLOW: u16 = 0x9003;
+
+// Echoes the first parameter as data, sized so that some replies need
+// several reads, and sums up data sent to it.
+fn echo(request: &Request) -> Reply {
+    match request.code {
+        ECHO => {
+            let p = request.params[0];
+            let len = (p as usize % 4) * 5000 + 1;
+            let mut reply = Reply::data(vec![p as u8; len]);
+            reply.params = vec![p];
+            reply
+        }
+        SUM => {
+            let sum = request.data.iter().flatten().map(|&b| b as u32).sum();
+            Reply {
+                params: vec![sum],
+                ..Reply::ok()
+            }
+        }
+        _ => Reply::ok(),
+    }
+}
+
+#[test]
+fn concurrent_transactions_do_not_interleave() {
+    const THREADS: u32 = 8;
+    const CALLS: u32 = 100;
+
+    let device = Device::with_transport(FakeCamera::new(echo));
+    let session = device.open_session(SessionConfig::default()).unwrap();
+
+    thread::scope(|s| {
+        for t in 0..THREADS {
+            let session = &session;
+            s.spawn(move || {
+                for i in 0..CALLS {
+                    let p = t * CALLS + i;
+                    if i % 3 == 0 {
+                        let data = vec![t as u8; i as usize * 100];
+                        let (_, params) = session
+                            .transaction(CommandCode::Other(SUM), &[], Some(&data), None)
+                            .unwrap();
+                        assert_eq!(params, [t * i * 100]);
+                    } else {
+                        let (data, params) = session
+                            .transaction(CommandCode::Other(ECHO), &[p], None, None)
+                            .unwrap();
+                        assert_eq!(params, [p]);
+                        assert_eq!(data.len(), (p as usize % 4) * 5000 + 1);
+                        assert!(data.iter().all(|&b| b == p as u8));
+                    }
+                }
+            });
+        }
+    });
+    drop(session);
+
+    let fake = device.transport();
+    assert_eq!(fake.violations(), Vec::<String>::new());
+
+    // OpenSession, then transactions numbered in the order they ran, then
+    // CloseSession
+    let tids: Vec<u32> = fake.log().iter().map(|r| r.tid).collect();
+    let expected: Vec<u32> = (0..=THREADS * CALLS + 1).collect();
+    assert_eq!(tids, expected);
+}
+
+// A device whose SLOW operation blocks until told to finish
+fn blocking_camera() -> (FakeCamera, mpsc::Sender<()>) {
+    let (finish, finished) = mpsc::channel();
+    let finished = Mutex::new(finished);
+    let fake = FakeCamera::new(move |request| {
+        if request.code == SLOW {
+            finished.lock().unwrap().recv().unwrap();
+        }
+        Reply::ok()
+    });
+    (fake, finish)
+}
+
+fn wait_until(condition: impl Fn() -> bool) {
+    for _ in 0..1000 {
+        if condition() {
+            return;
+        }
+        thread::sleep(Duration::from_millis(1));
+    }
+    panic!("timed out");
+}
+
+#[test]
+fn high_priority_transactions_go_first() {
+    let (fake, finish) = blocking_camera();
+    let device = Device::with_transport(fake);
+    let order = Arc::new(Mutex::new(vec![]));
+
+    thread::scope(|s| {
+        let device = &device;
+        s.spawn(move || {
+            device
+                .command(CommandCode::Other(SLOW), &[], None, None)
+                .unwrap();
+        });
+        wait_until(|| device.transport().log().len() == 1);
+
+        let spawn = |name: &'static str, priority| {
+            let order = order.clone();
+            s.spawn(move || {
+                let options = CallOptions {
+                    priority,
+                    ..CallOptions::default()
+                };
+                device
+                    .command_with(CommandCode::Other(ECHO), &[0], None, None, &options)
+                    .unwrap();
+                order.lock().unwrap().push(name);
+            });
+        };
+
+        spawn("low", Priority::Low);
+        wait_until(|| device.pending_transactions() == 1);
+        spawn("normal", Priority::Normal);
+        wait_until(|| device.pending_transactions() == 2);
+        spawn("normal 2", Priority::Normal);
+        wait_until(|| device.pending_transactions() == 3);
+        spawn("high", Priority::High);
+        wait_until(|| device.pending_transactions() == 4);
+
+        finish.send(()).unwrap();
+    });
+
+    assert_eq!(
+        *order.lock().unwrap(),
+        ["high", "normal", "normal 2", "low"]
+    );
+    assert_eq!(device.transport().violations(), Vec::<String>::new());
+}
+
+#[test]
+fn events_are_read_during_a_transaction() {
+    let (fake, finish) = blocking_camera();
+    let device = Device::with_transport(fake);
+
+    thread::scope(|s| {
+        let device = &device;
+        s.spawn(move || {
+            device
+                .command(CommandCode::Other(SLOW), &[], None, None)
+                .unwrap();
+        });
+        wait_until(|| device.transport().log().len() == 1);
+
+        device
+            .transport()
+            .push_event(StandardEventCode::ObjectAdded as u16, &[0x2a]);
+        let event = device.event(Some(Duration::from_secs(5))).unwrap();
+        assert!(event.is_some());
+
+        finish.send(()).unwrap();
+    });
+}
@@ -0,0 +1,230 @@
+mod common;
+
+use std::io::Cursor;
+
+use num_traits::FromPrimitive;
+
+use ptp::{
+    AssociationCode, CommandCode, Device, DeviceInfo, Error, ObjectFilesystemInfo,
+    ObjectFormatCode, ObjectHandle, PtpRead, PtpWrite, SessionConfig, StandardCommandCode,
+    StorageId, StreamInfo, VendorExtensionMap,
+};
+
+use common::{FakeCamera, Reply};
+
+const GET_DEVICE_INFO: u16 = StandardCommandCode::GetDeviceInfo as u16;
+const PTP_1_1_OPERATIONS: [StandardCommandCode; 9] = [
+    StandardCommandCode::StartEnumHandles,
+    StandardCommandCode::EnumHandles,
+    StandardCommandCode::StopEnumHandles,
+    StandardCommandCode::GetVendorExtensionMaps,
+    StandardCommandCode::GetVendorDeviceInfo,
+    StandardCommandCode::GetResizedImageObject,
+    StandardCommandCode::GetFilesystemManifest,
+    StandardCommandCode::GetStreamInfo,
+    StandardCommandCode::GetStream,
+];
+
+// An ObjectFilesystemInfo dataset as a device sends it
+fn manifest_entry(handle: u32, size: u64, filename: &str) -> Vec<u8> {
+    let mut buf = vec![];
+    buf.write_ptp_u32(handle).unwrap();
+    buf.write_ptp_u32(0x0001_0001).unwrap();
+    buf.write_ptp_u16(0x3801).unwrap();
+    buf.write_ptp_u16(0).unwrap();
+    buf.write_ptp_u64(size).unwrap();
+    buf.write_ptp_u32(7).unwrap();
+    buf.write_ptp_u16(0).unwrap();
+    buf.write_ptp_u32(0).unwrap();
+    buf.write_ptp_u32(0).unwrap();
+    buf.write_ptp_str(filename).unwrap();
+    buf.write_ptp_str("20261018T151800").unwrap();
+    buf
+}
+
+fn manifest(count: u64, entries: &[&[u8]]) -> Vec<u8> {
+    let mut buf = count.to_le_bytes().to_vec();
+    for entry in entries {
+        buf.extend_from_slice(entry);
+    }
+    buf
+}
+
+#[test]
+fn filesystem_manifest() {
+    let big = 5 << 30;
+    let data = manifest(
+        2,
+        &[
+            &manifest_entry(1, 1234, "IMG_0001.JPG"),
+            &manifest_entry(2, big, "MVI_0002.MOV"),
+        ],
+    );
+
+    let entries = ObjectFilesystemInfo::decode_manifest(&data).unwrap();
+    assert_eq!(entries.len(), 2);
+    let first = &entries[0];
+    assert_eq!(first.object_handle, ObjectHandle::from(1));
+    assert_eq!(first.storage_id, 0x0001_0001);
+    assert_eq!(
+        first.object_format,
+        ObjectFormatCode::from_u16(0x3801).unwrap()
+    );
+    assert_eq!(first.object_compressed_size, 1234);
+    assert_eq!(first.parent_object, 7);
+    assert_eq!(
+        first.association_type,
+        AssociationCode::from_u16(0).unwrap()
+    );
+    assert_eq!(first.filename, "IMG_0001.JPG");
+    assert_eq!(first.modification_date, "20261018T151800");
+
+    // sizes are 64-bit here, but not in ObjectInfo
+    assert_eq!(entries[1].object_compressed_size, big);
+    let info = entries[1].to_object_info();
+    assert_eq!(info.object_compressed_size, u32::MAX);
+    assert_eq!(info.filename, "MVI_0002.MOV");
+    assert_eq!(info.parent_object, 7);
+
+    // the count is a u64, not the u32 of other arrays
+    assert!(ObjectFilesystemInfo::decode_manifest(&manifest(0, &[]))
+        .unwrap()
+        .is_empty());
+    assert!(ObjectFilesystemInfo::decode_manifest(&2u32.to_le_bytes()).is_err());
+}
+
+#[test]
+fn malformed_manifests() {
+    let entry = manifest_entry(1, 1, "A.JPG");
+
+    // fewer entries than counted, more than counted, and a huge count
+    assert!(ObjectFilesystemInfo::decode_manifest(&manifest(2, &[&entry])).is_err());
+    assert!(ObjectFilesystemInfo::decode_manifest(&manifest(1, &[&entry, &entry])).is_err());
+    assert!(ObjectFilesystemInfo::decode_manifest(&manifest(u64::MAX, &[&entry])).is_err());
+
+    let mut truncated = manifest(1, &[&entry]);
+    truncated.pop();
+    assert!(ObjectFilesystemInfo::decode_manifest(&truncated).is_err());
+}
+
+#[test]
+fn stream_info() {
+    let mut data = vec![];
+    data.write_ptp_u64(36).unwrap();
+    data.write_ptp_u64(1_000_000).unwrap();
+    for &field in &[8u32, 65536, 4, 512, 16] {
+        data.write_ptp_u32(field).unwrap();
+    }
+
+    let mut cur = Cursor::new(&data);
+    let info = StreamInfo::decode(&mut cur).unwrap();
+    cur.expect_end().unwrap();
+    assert_eq!(info.dataset_size, 36);
+    assert_eq!(info.time_resolution, 1_000_000);
+    assert_eq!(info.frame_header_size, 8);
+    assert_eq!(info.frame_max_size, 65536);
+    assert_eq!(info.packet_header_size, 4);
+    assert_eq!(info.packet_max_size, 512);
+    assert_eq!(info.packet_alignment, 16);
+
+    assert!(StreamInfo::decode(&mut Cursor::new(&data[..data.len() - 1])).is_err());
+}
+
+#[test]
+fn vendor_extension_maps() {
+    let data = [
+        2, 0, 0, 0, // count
+        0x01, 0x90, 0x02, 0x98, 0x0a, 0x00, 0x00, 0x00, //
+        0x02, 0x90, 0x03, 0x98, 0x06, 0x00, 0x00, 0x00,
+    ];
+
+    let mut cur = Cursor::new(&data[..]);
+    let maps = cur.read_ptp_vec(VendorExtensionMap::decode).unwrap();
+    cur.expect_end().unwrap();
+    let maps: Vec<_> = maps
+        .iter()
+        .map(|m| (m.native_code, m.mapped_code, m.mapped_vendor_ex_id))
+        .collect();
+    assert_eq!(maps, [(0x9001, 0x9802, 10), (0x9002, 0x9803, 6)]);
+}
+
+fn device(version: u16) -> Device<FakeCamera> {
+    let info = DeviceInfo {
+        version,
+        vendor_ex_id: 0,
+        vendor_ex_version: 0,
+        vendor_extension_desc: String::new(),
+        functional_mode: 0,
+        operations_supported: PTP_1_1_OPERATIONS.iter().map(|&op| op as u16).collect(),
+        events_supported: vec![],
+        device_properties_supported: vec![],
+        capture_formats: vec![],
+        image_formats: vec![],
+        manufacturer: "Acme".into(),
+        model: "Camera".into(),
+        device_version: "1.0".into(),
+        serial_number: "1".into(),
+    };
+    let mut data = vec![];
+    info.encode(&mut data).unwrap();
+
+    Device::with_transport(FakeCamera::new(move |request| match request.code {
+        GET_DEVICE_INFO => Reply::data(data.clone()),
+        _ => Reply::ok(),
+    }))
+}
+
+#[test]
+fn ptp_1_1_operations_need_a_ptp_1_1_device() {
+    // a PTP 1.0 device listing the new operation codes, which 1.0 left
+    // unassigned, is not taken at its word
+    let device = device(100);
+    assert_eq!(device.supports(StandardCommandCode::GetStream.into()), None);
+    device.get_device_info(None).unwrap();
+    for &op in &PTP_1_1_OPERATIONS {
+        assert_eq!(device.supports(op.into()), Some(false), "{:?}", op);
+    }
+    assert_eq!(device.supports(CommandCode::Other(0x9001)), Some(false));
+
+    let session = device.open_session(SessionConfig::default()).unwrap();
+    let storage = StorageId::from(0x0001_0001);
+    let errors = vec![
+        session
+            .get_filesystem_manifest(storage, None, None, None)
+            .err(),
+        session.get_stream_info(0, None).err(),
+        session.get_stream(0, None).err(),
+        session.get_vendor_extension_maps(None).err(),
+        session.get_vendor_device_info(1, None).err(),
+        session
+            .get_resized_image_object(ObjectHandle::from(1), 160, 0, None)
+            .err(),
+        session.enum_handles(10, None).err(),
+        session.stop_enum_handles(None).err(),
+    ];
+    for err in errors {
+        assert!(matches!(err, Some(Error::NotSupported(_))), "{:?}", err);
+    }
+
+    // nothing but the session itself reached the device
+    let codes: Vec<_> = device.transport().log().iter().map(|r| r.code).collect();
+    assert_eq!(
+        codes,
+        [GET_DEVICE_INFO, StandardCommandCode::OpenSession as u16]
+    );
+}
+
+#[test]
+fn ptp_1_1_devices_are_asked() {
+    let device = device(110);
+    device.get_device_info(None).unwrap();
+    for &op in &PTP_1_1_OPERATIONS {
+        assert_eq!(device.supports(op.into()), Some(true), "{:?}", op);
+    }
+
+    let session = device.open_session(SessionConfig::default()).unwrap();
+    // the fake answers without data, which is an empty stream
+    assert!(session.get_stream(0, None).unwrap().is_empty());
+    let err = session.get_stream_info(0, None).unwrap_err();
+    assert!(!matches!(err, Error::NotSupported(_)), "{:?}", err);
+}
@@ -0,0 +1,171 @@
+mod common;
+
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use ptp::{
+    CallOptions, CancelToken, CommandCode, Deadline, Device, Error, RetryPolicy, SessionConfig,
+    StandardCommandCode, StandardResponseCode,
+};
+
+use common::{FakeCamera, Reply, Request};
+
+const GET_STORAGE_IDS: u16 = StandardCommandCode::GetStorageIDs as u16;
+const CLOSE_SESSION: u16 = StandardCommandCode::CloseSession as u16;
+const SLOW: u16 = 0x9001;
+const BUSY: u16 = 0x9002;
+const LATENCY: Duration = Duration::from_millis(30);
+
+fn handler(request: &Request) -> Reply {
+    match request.code {
+        GET_STORAGE_IDS => Reply::data(vec![1, 0, 0, 0, 1, 0, 1, 0]),
+        SLOW => {
+            thread::sleep(Duration::from_millis(300));
+            Reply::ok()
+        }
+        BUSY => Reply::error(StandardResponseCode::DeviceBusy as u16),
+        _ => Reply::ok(),
+    }
+}
+
+fn slow_camera() -> Device<FakeCamera> {
+    Device::with_transport(FakeCamera::new(handler).with_latency(LATENCY))
+}
+
+// a data-out and a data-in phase, four transfers in all
+fn exchange(
+    device: &Device<FakeCamera>,
+    timeout: Option<Duration>,
+    options: &CallOptions,
+) -> Result<Vec<u8>, Error> {
+    let code = StandardCommandCode::GetStorageIDs.into();
+    device.command_with(code, &[], Some(&[1, 2, 3]), timeout, options)
+}
+
+#[test]
+fn a_deadline_bounds_the_whole_call() {
+    let device = slow_camera();
+    let timeout = Some(Duration::from_millis(100));
+
+    // each transfer is well within its timeout
+    assert!(exchange(&device, timeout, &CallOptions::default()).is_ok());
+
+    let start = Instant::now();
+    let options = CallOptions::deadline(Deadline::after(Duration::from_millis(80)));
+    let err = exchange(&device, timeout, &options).unwrap_err();
+    assert!(matches!(err.root(), Error::DeadlineExceeded));
+    assert!(start.elapsed() < Duration::from_millis(150));
+
+    // later transfers get what remains of the deadline
+    let timeouts = device.transport().timeouts()[4..].to_vec();
+    assert!(timeouts[0] <= Duration::from_millis(80), "{:?}", timeouts);
+    assert!(timeouts[1] <= Duration::from_millis(50), "{:?}", timeouts);
+    assert!(timeouts[2] < LATENCY, "{:?}", timeouts);
+}
+
+#[test]
+fn transfer_timeouts_are_told_apart() {
+    let device = slow_camera();
+    let options = CallOptions::deadline(Deadline::after(Duration::from_secs(5)));
+    let err = exchange(&device, Some(Duration::from_millis(10)), &options).unwrap_err();
+    assert!(matches!(err.root(), Error::Usb(rusb::Error::Timeout)));
+
+    // without a timeout of its own a transfer waits until the deadline
+    let options = CallOptions::deadline(Deadline::after(Duration::from_millis(50)));
+    let err = exchange(&device, None, &options).unwrap_err();
+    assert!(matches!(err.root(), Error::DeadlineExceeded));
+}
+
+#[test]
+fn nothing_is_sent_after_the_deadline_or_once_cancelled() {
+    let device = Device::with_transport(FakeCamera::new(handler));
+    let expired = CallOptions::deadline(Deadline::at(Instant::now()));
+    let err = exchange(&device, None, &expired).unwrap_err();
+    assert!(matches!(err, Error::DeadlineExceeded));
+
+    let cancel = CancelToken::new();
+    let options = CallOptions {
+        cancel: Some(cancel.clone()),
+        ..CallOptions::default()
+    };
+    assert!(exchange(&device, None, &options).is_ok());
+    cancel.cancel();
+    let err = exchange(&device, None, &options).unwrap_err();
+    assert!(matches!(err, Error::Cancelled));
+
+    assert_eq!(device.transport().log().len(), 1);
+    assert!(device.transport().violations().is_empty());
+}
+
+#[test]
+fn waiting_for_the_device_counts() {
+    let device = Arc::new(Device::with_transport(FakeCamera::new(handler)));
+    let busy = thread::spawn({
+        let device = device.clone();
+        move || device.command(CommandCode::Other(SLOW), &[], None, None)
+    });
+    while device.transport().log().is_empty() {
+        thread::yield_now();
+    }
+
+    let start = Instant::now();
+    let options = CallOptions::deadline(Deadline::after(Duration::from_millis(50)));
+    let err = exchange(&device, None, &options).unwrap_err();
+    assert!(matches!(err, Error::DeadlineExceeded));
+    assert!(start.elapsed() < Duration::from_millis(250));
+    assert_eq!(device.pending_transactions(), 0);
+
+    busy.join().unwrap().unwrap();
+    assert!(exchange(&device, None, &CallOptions::default()).is_ok());
+}
+
+#[test]
+fn retries_stop_when_cancelled() {
+    let cancel = CancelToken::new();
+    let device = Device::with_transport(FakeCamera::new({
+        let cancel = cancel.clone();
+        move |request| {
+            cancel.cancel();
+            handler(request)
+        }
+    }));
+    let options = CallOptions {
+        retry: Some(RetryPolicy {
+            initial_backoff: Duration::from_millis(1),
+            retry_non_idempotent: true,
+            ..RetryPolicy::default()
+        }),
+        cancel: Some(cancel),
+        ..CallOptions::default()
+    };
+
+    let err = device
+        .command_with(CommandCode::Other(BUSY), &[], None, None, &options)
+        .unwrap_err();
+    assert!(matches!(err, Error::Cancelled));
+    assert_eq!(device.transport().log().len(), 1);
+}
+
+#[test]
+fn session_operations_share_a_deadline() {
+    let device = slow_camera();
+    let session = device.open_session(SessionConfig::default()).unwrap();
+
+    let timed = session.with_options(CallOptions::deadline(Deadline::after(
+        Duration::from_millis(135),
+    )));
+    // each call takes three transfers of 30ms, so the second runs out of
+    // time
+    assert_eq!(timed.get_storage_ids(None).unwrap().len(), 1);
+    let err = timed.get_storage_ids(None).unwrap_err();
+    assert!(matches!(err.root(), Error::DeadlineExceeded));
+
+    // the handle leaves the session open
+    drop(timed);
+    assert!(session.options().deadline.is_none());
+    let codes = || -> Vec<u16> { device.transport().log().iter().map(|r| r.code).collect() };
+    assert!(!codes().contains(&CLOSE_SESSION));
+    drop(session);
+    assert_eq!(codes().last(), Some(&CLOSE_SESSION));
+}
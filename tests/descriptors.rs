@@ -0,0 +1,339 @@
+use std::collections::HashMap;
+
+use ptp::{
+    decode_compatible_ids, decode_language_ids, decode_ms_os_string, decode_string,
+    ConfigDescriptor, DeviceDescriptor, DeviceFilter, Error, InterfaceMatch, UsbDescriptors,
+    UsbDeviceInfo,
+};
+
+// Device descriptor of a camera, 04a9:3218, strings 1-3
+const CAMERA_DEVICE: [u8; 18] = [
+    0x12, 0x01, 0x00, 0x02, 0x00, 0x00, 0x00, 0x40, 0xa9, 0x04, 0x18, 0x32, 0x02, 0x00, 0x01, 0x02,
+    0x03, 0x01,
+];
+
+// Its configuration: a still image interface with bulk in, bulk out and
+// interrupt endpoints
+const CAMERA_CONFIG: [u8; 39] = [
+    0x09, 0x02, 0x27, 0x00, 0x01, 0x01, 0x00, 0xc0, 0x01, // configuration
+    0x09, 0x04, 0x00, 0x00, 0x03, 0x06, 0x01, 0x01, 0x00, // interface 0, class 6
+    0x07, 0x05, 0x81, 0x02, 0x00, 0x02, 0x00, // bulk in, 512 bytes
+    0x07, 0x05, 0x02, 0x02, 0x00, 0x02, 0x00, // bulk out, 512 bytes
+    0x07, 0x05, 0x83, 0x03, 0x08, 0x00, 0x09, // interrupt in, 8 bytes
+];
+
+// A mass storage device with a class specific descriptor between its
+// interface and endpoints
+const STORAGE_CONFIG: [u8; 37] = [
+    0x09, 0x02, 0x25, 0x00, 0x01, 0x01, 0x00, 0x80, 0x32, // configuration
+    0x09, 0x04, 0x00, 0x00, 0x02, 0x08, 0x06, 0x50, 0x00, // interface 0, class 8
+    0x05, 0x24, 0x00, 0x10, 0x01, // class specific
+    0x07, 0x05, 0x81, 0x02, 0x00, 0x02, 0x00, // bulk in
+    0x07, 0x05, 0x02, 0x02, 0x00, 0x02, 0x00, // bulk out
+];
+
+const LANGUAGES: [u8; 4] = [0x04, 0x03, 0x09, 0x04];
+
+const MANUFACTURER: [u8; 22] = [
+    0x16, 0x03, b'C', 0, b'a', 0, b'n', 0, b'o', 0, b'n', 0, b' ', 0, b'I', 0, b'n', 0, b'c', 0,
+    b'.', 0,
+];
+
+// A phone: a vendor specific MTP interface named by string 5, followed by an
+// ADB interface with only bulk endpoints
+const PHONE_CONFIG: [u8; 62] = [
+    0x09, 0x02, 0x3e, 0x00, 0x02, 0x01, 0x00, 0x80, 0xfa, // configuration
+    0x09, 0x04, 0x00, 0x00, 0x03, 0xff, 0xff, 0x00, 0x05, // interface 0, class 0xff
+    0x07, 0x05, 0x81, 0x02, 0x00, 0x02, 0x00, // bulk in
+    0x07, 0x05, 0x01, 0x02, 0x00, 0x02, 0x00, // bulk out
+    0x07, 0x05, 0x82, 0x03, 0x1c, 0x00, 0x06, // interrupt in, 28 bytes
+    0x09, 0x04, 0x01, 0x00, 0x02, 0xff, 0x42, 0x01, 0x06, // interface 1, ADB
+    0x07, 0x05, 0x83, 0x02, 0x00, 0x02, 0x00, // bulk in
+    0x07, 0x05, 0x02, 0x02, 0x00, 0x02, 0x00, // bulk out
+];
+
+// "MSFT100", vendor code 0x54
+const MS_OS_STRING: [u8; 18] = [
+    0x12, 0x03, b'M', 0, b'S', 0, b'F', 0, b'T', 0, b'1', 0, b'0', 0, b'0', 0, 0x54, 0x00,
+];
+
+// Extended compatible ID descriptor listing MTP on interface 0
+const COMPATIBLE_IDS: [u8; 40] = [
+    0x28, 0x00, 0x00, 0x00, 0x00, 0x01, 0x04, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, // header, one function
+    0x00, 0x01, b'M', b'T', b'P', 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // interface 0, "MTP"
+];
+
+fn utf16_descriptor(s: &str) -> Vec<u8> {
+    let mut buf = vec![0, 0x03];
+    buf.extend(s.encode_utf16().flat_map(u16::to_le_bytes));
+    buf[0] = buf.len() as u8;
+    buf
+}
+
+#[test]
+fn device_descriptor() {
+    let desc = DeviceDescriptor::decode(&CAMERA_DEVICE).unwrap();
+    assert_eq!(desc.vendor_id, 0x04a9);
+    assert_eq!(desc.product_id, 0x3218);
+    assert_eq!(desc.usb_version, 0x0200);
+    assert_eq!(
+        (
+            desc.manufacturer_index,
+            desc.product_index,
+            desc.serial_number_index
+        ),
+        (1, 2, 3)
+    );
+    assert_eq!(desc.num_configurations, 1);
+
+    assert!(matches!(
+        DeviceDescriptor::decode(&CAMERA_DEVICE[..17]),
+        Err(Error::Malformed(_))
+    ));
+    assert!(matches!(
+        DeviceDescriptor::decode(&CAMERA_CONFIG[..18]),
+        Err(Error::Malformed(_))
+    ));
+}
+
+#[test]
+fn config_descriptor() {
+    let config = ConfigDescriptor::decode(&CAMERA_CONFIG).unwrap();
+    assert_eq!(config.value, 1);
+    assert_eq!(config.interfaces.len(), 1);
+
+    let (interface, found_by) = config.find_ptp_interface(&HashMap::new(), &[]).unwrap();
+    assert_eq!(found_by, InterfaceMatch::StillImageClass);
+    assert_eq!(interface.number, 0);
+    assert_eq!(interface.endpoints.len(), 3);
+
+    let bulk_in = interface
+        .endpoint(rusb::Direction::In, rusb::TransferType::Bulk)
+        .unwrap();
+    assert_eq!((bulk_in.address, bulk_in.max_packet_size), (0x81, 512));
+    let bulk_out = interface
+        .endpoint(rusb::Direction::Out, rusb::TransferType::Bulk)
+        .unwrap();
+    assert_eq!(bulk_out.address, 0x02);
+    let interrupt = interface
+        .endpoint(rusb::Direction::In, rusb::TransferType::Interrupt)
+        .unwrap();
+    assert_eq!((interrupt.address, interrupt.interval), (0x83, 9));
+
+    let storage = ConfigDescriptor::decode(&STORAGE_CONFIG).unwrap();
+    assert_eq!(storage.interfaces[0].endpoints.len(), 2);
+    assert!(storage.find_ptp_interface(&HashMap::new(), &[]).is_none());
+}
+
+#[test]
+fn malformed_config_descriptors() {
+    // truncated
+    assert!(ConfigDescriptor::decode(&CAMERA_CONFIG[..30]).is_err());
+
+    // a descriptor running past the end of the configuration
+    let mut bad = CAMERA_CONFIG;
+    bad[32] = 0x10;
+    assert!(ConfigDescriptor::decode(&bad).is_err());
+
+    // a zero length descriptor
+    let mut bad = CAMERA_CONFIG;
+    bad[18] = 0;
+    assert!(ConfigDescriptor::decode(&bad).is_err());
+
+    // an endpoint before any interface
+    let mut bad = CAMERA_CONFIG[..9].to_vec();
+    bad.extend_from_slice(&CAMERA_CONFIG[18..25]);
+    bad[2] = bad.len() as u8;
+    assert!(ConfigDescriptor::decode(&bad).is_err());
+}
+
+#[test]
+fn string_descriptors() {
+    assert_eq!(decode_language_ids(&LANGUAGES).unwrap(), [0x0409]);
+    assert_eq!(decode_string(&MANUFACTURER).unwrap(), "Canon Inc.");
+    assert_eq!(decode_string(&utf16_descriptor("Ω-1")).unwrap(), "Ω-1");
+    assert!(decode_string(&CAMERA_DEVICE).is_err());
+}
+
+fn camera_descriptors() -> UsbDescriptors {
+    let strings = vec![
+        (1, MANUFACTURER.to_vec()),
+        (2, utf16_descriptor("Canon EOS 5D Mark IV")),
+        (3, utf16_descriptor("0123456789ab")),
+    ]
+    .into_iter()
+    .map(|(index, buf)| (index, decode_string(&buf).unwrap()))
+    .collect();
+
+    UsbDescriptors {
+        device: DeviceDescriptor::decode(&CAMERA_DEVICE).unwrap(),
+        configs: vec![ConfigDescriptor::decode(&CAMERA_CONFIG).unwrap()],
+        strings,
+        compatible_ids: Vec::new(),
+    }
+}
+
+fn camera_info() -> UsbDeviceInfo {
+    UsbDeviceInfo::from_descriptors(&camera_descriptors(), 1, vec![2, 3]).unwrap()
+}
+
+#[test]
+fn device_info_from_descriptors() {
+    let info = camera_info();
+    assert_eq!(info.manufacturer.as_deref(), Some("Canon Inc."));
+    assert_eq!(info.product.as_deref(), Some("Canon EOS 5D Mark IV"));
+    assert_eq!(info.serial_number.as_deref(), Some("0123456789ab"));
+    assert_eq!((info.config, info.interface, info.alt_setting), (1, 0, 0));
+    assert_eq!(info.interface_match, InterfaceMatch::StillImageClass);
+    assert_eq!(info.port_path(), "1-2.3");
+
+    // no strings, and no PTP interface
+    let mut descriptors = camera_descriptors();
+    descriptors.strings.clear();
+    let info = UsbDeviceInfo::from_descriptors(&descriptors, 1, vec![1]).unwrap();
+    assert_eq!(info.serial_number, None);
+
+    descriptors.configs = vec![ConfigDescriptor::decode(&STORAGE_CONFIG).unwrap()];
+    assert!(UsbDeviceInfo::from_descriptors(&descriptors, 1, vec![1]).is_none());
+}
+
+#[test]
+fn ms_os_descriptors() {
+    assert_eq!(decode_ms_os_string(&MS_OS_STRING).unwrap(), 0x54);
+    assert!(decode_ms_os_string(&MANUFACTURER).is_err());
+    assert!(decode_ms_os_string(&MS_OS_STRING[..16]).is_err());
+
+    let ids = decode_compatible_ids(&COMPATIBLE_IDS).unwrap();
+    assert_eq!(ids.len(), 1);
+    assert_eq!(ids[0].first_interface, 0);
+    assert_eq!(ids[0].compatible_id, "MTP");
+    assert_eq!(ids[0].sub_compatible_id, "");
+
+    // truncated, a function missing, and the wrong descriptor index
+    assert!(decode_compatible_ids(&COMPATIBLE_IDS[..10]).is_err());
+    assert!(decode_compatible_ids(&COMPATIBLE_IDS[..30]).is_err());
+    let mut bad = COMPATIBLE_IDS;
+    bad[6] = 0x05;
+    assert!(decode_compatible_ids(&bad).is_err());
+}
+
+#[test]
+fn vendor_specific_mtp_interfaces() {
+    let phone = ConfigDescriptor::decode(&PHONE_CONFIG).unwrap();
+    assert_eq!(phone.interfaces.len(), 2);
+    assert!(phone.interfaces[0].has_ptp_endpoints());
+    assert!(!phone.interfaces[1].has_ptp_endpoints());
+
+    // by the interface string
+    let strings: HashMap<u8, String> = vec![(5, "MTP".to_string()), (6, "ADB Interface".into())]
+        .into_iter()
+        .collect();
+    let (interface, found_by) = phone.find_ptp_interface(&strings, &[]).unwrap();
+    assert_eq!(
+        (interface.number, found_by),
+        (0, InterfaceMatch::InterfaceName)
+    );
+
+    // by the compatible ID
+    let ids = decode_compatible_ids(&COMPATIBLE_IDS).unwrap();
+    let (interface, found_by) = phone.find_ptp_interface(&HashMap::new(), &ids).unwrap();
+    assert_eq!(
+        (interface.number, found_by),
+        (0, InterfaceMatch::CompatibleId)
+    );
+
+    // by the endpoints alone
+    let (interface, found_by) = phone.find_ptp_interface(&HashMap::new(), &[]).unwrap();
+    assert_eq!(
+        (interface.number, found_by),
+        (0, InterfaceMatch::EndpointLayout)
+    );
+
+    // an ADB interface on its own is not mistaken for MTP
+    let mut adb_only = phone.clone();
+    adb_only.interfaces.remove(0);
+    assert!(adb_only.find_ptp_interface(&strings, &ids).is_none());
+
+    // the still image class wins over a vendor specific interface
+    let mut both = phone;
+    both.interfaces
+        .push(ConfigDescriptor::decode(&CAMERA_CONFIG).unwrap().interfaces[0].clone());
+    both.interfaces[2].number = 2;
+    let (interface, found_by) = both.find_ptp_interface(&strings, &ids).unwrap();
+    assert_eq!(
+        (interface.number, found_by),
+        (2, InterfaceMatch::StillImageClass)
+    );
+}
+
+#[test]
+fn explicit_interface() {
+    let mut descriptors = camera_descriptors();
+    descriptors.configs = vec![ConfigDescriptor::decode(&PHONE_CONFIG).unwrap()];
+
+    let adb = descriptors.interface(1, 1, 0).unwrap();
+    assert_eq!(adb.subclass, 0x42);
+    assert!(descriptors.interface(1, 1, 1).is_none());
+    assert!(descriptors.interface(2, 0, 0).is_none());
+
+    let info = UsbDeviceInfo::from_descriptors(&descriptors, 1, vec![4]).unwrap();
+    assert_eq!(info.interface_match, InterfaceMatch::EndpointLayout);
+    assert_eq!(info.interface, 0);
+}
+
+#[test]
+fn filters() {
+    let info = camera_info();
+
+    assert!(DeviceFilter::default().matches(&info));
+    assert!(DeviceFilter {
+        vendor_id: Some(0x04a9),
+        product_id: Some(0x3218),
+        ..DeviceFilter::default()
+    }
+    .matches(&info));
+    assert!(!DeviceFilter {
+        vendor_id: Some(0x04b0),
+        ..DeviceFilter::default()
+    }
+    .matches(&info));
+    assert!(DeviceFilter {
+        bus_number: Some(1),
+        port_numbers: Some(vec![2, 3]),
+        ..DeviceFilter::default()
+    }
+    .matches(&info));
+    assert!(!DeviceFilter {
+        port_numbers: Some(vec![2]),
+        ..DeviceFilter::default()
+    }
+    .matches(&info));
+
+    // manufacturer and product are matched loosely, serial numbers exactly
+    assert!(DeviceFilter {
+        manufacturer: Some("canon".into()),
+        product: Some("5D MARK".into()),
+        ..DeviceFilter::default()
+    }
+    .matches(&info));
+    assert!(DeviceFilter {
+        serial_number: Some("0123456789ab".into()),
+        ..DeviceFilter::default()
+    }
+    .matches(&info));
+    assert!(!DeviceFilter {
+        serial_number: Some("0123".into()),
+        ..DeviceFilter::default()
+    }
+    .matches(&info));
+
+    let mut anonymous = info.clone();
+    anonymous.product = None;
+    assert!(!DeviceFilter {
+        product: Some("EOS".into()),
+        ..DeviceFilter::default()
+    }
+    .matches(&anonymous));
+}
@@ -0,0 +1,472 @@
+use std::io::Cursor;
+
+use num_traits::FromPrimitive;
+use proptest::prelude::*;
+
+use ptp::{
+    AccessType, AssociationCode, Data, DataType, DeviceInfo, Error, FilesystemType, FormData,
+    ObjectFormatCode, ObjectInfo, PropInfo, PtpRead, PtpWrite, StandardObjectFormatCode,
+    StorageInfo, StorageType,
+};
+
+fn read_back(kind: DataType, bytes: &[u8]) -> Data {
+    let mut cur = Cursor::new(bytes);
+    let data = Data::read_type(kind, &mut cur).unwrap();
+    cur.expect_end().unwrap();
+    data
+}
+
+#[test]
+fn golden_strings() {
+    let encode = |s: &str| {
+        let mut out = vec![];
+        out.write_ptp_str(s).unwrap();
+        out
+    };
+
+    assert_eq!(encode(""), vec![0x00]);
+    assert_eq!(encode("A"), vec![0x02, b'A', 0, 0, 0]);
+    assert_eq!(encode("Hi"), vec![0x03, b'H', 0, b'i', 0, 0, 0]);
+    // U+1F4F7 (camera) is a surrogate pair, i.e. two code units
+    assert_eq!(
+        encode("\u{1F4F7}"),
+        vec![0x03, 0x3D, 0xD8, 0xF7, 0xDC, 0, 0]
+    );
+
+    assert_eq!(Data::STR("Hi".into()).encode().unwrap(), encode("Hi"));
+    assert_eq!(Data::STR("".into()).encode().unwrap(), vec![0x00]);
+}
+
+#[test]
+fn string_length_limit() {
+    let longest = "x".repeat(254);
+    let mut out = vec![];
+    out.write_ptp_str(&longest).unwrap();
+    assert_eq!(out[0], 255);
+    assert_eq!(out.len(), 1 + 255 * 2);
+    assert_eq!(Cursor::new(&out).read_ptp_str().unwrap(), longest);
+
+    let too_long = "x".repeat(255);
+    assert!(matches!(
+        vec![].write_ptp_str(&too_long),
+        Err(Error::StringTooLong(256))
+    ));
+    assert!(matches!(
+        Data::STR(too_long).encode(),
+        Err(Error::StringTooLong(256))
+    ));
+}
+
+#[test]
+fn golden_data() {
+    let cases: Vec<(Data, Vec<u8>)> = vec![
+        (Data::INT8(-2), vec![0xFE]),
+        (Data::UINT8(0xAB), vec![0xAB]),
+        (Data::INT16(-2), vec![0xFE, 0xFF]),
+        (Data::UINT16(0x1234), vec![0x34, 0x12]),
+        (Data::INT32(-2), vec![0xFE, 0xFF, 0xFF, 0xFF]),
+        (Data::UINT32(0x1234_5678), vec![0x78, 0x56, 0x34, 0x12]),
+        (
+            Data::INT64(-2),
+            vec![0xFE, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF],
+        ),
+        (
+            Data::UINT64(0x0102_0304_0506_0708),
+            vec![0x08, 0x07, 0x06, 0x05, 0x04, 0x03, 0x02, 0x01],
+        ),
+        (
+            // (high, low): the low half comes first on the wire
+            Data::UINT128((0x1111_1111_1111_1111, 0x2222_2222_2222_2222)),
+            [[0x22; 8], [0x11; 8]].concat(),
+        ),
+        (
+            Data::INT128((0xFFFF_FFFF_FFFF_FFFF, 0xFFFF_FFFF_FFFF_FFFE)),
+            [[0xFE].as_ref(), &[0xFF; 15]].concat(),
+        ),
+        (Data::AUINT8(vec![1, 2]), vec![2, 0, 0, 0, 1, 2]),
+        (Data::AINT8(vec![-1]), vec![1, 0, 0, 0, 0xFF]),
+        (Data::AUINT16(vec![0x0102]), vec![1, 0, 0, 0, 0x02, 0x01]),
+        (Data::AINT16(vec![]), vec![0, 0, 0, 0]),
+        (
+            Data::AUINT32(vec![1, 2]),
+            vec![2, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0],
+        ),
+        (
+            Data::AINT32(vec![-1]),
+            vec![1, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF],
+        ),
+        (
+            Data::AUINT64(vec![1]),
+            vec![1, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0],
+        ),
+        (
+            Data::AINT64(vec![-1]),
+            [vec![1, 0, 0, 0], vec![0xFF; 8]].concat(),
+        ),
+        (
+            Data::AUINT128(vec![(1, 2)]),
+            [
+                vec![1, 0, 0, 0],
+                vec![2, 0, 0, 0, 0, 0, 0, 0],
+                vec![1, 0, 0, 0, 0, 0, 0, 0],
+            ]
+            .concat(),
+        ),
+        (
+            Data::AINT128(vec![(0, 3)]),
+            [vec![1, 0, 0, 0], vec![3, 0, 0, 0, 0, 0, 0, 0], vec![0; 8]].concat(),
+        ),
+        (Data::STR("ok".into()), vec![3, b'o', 0, b'k', 0, 0, 0]),
+    ];
+
+    for (data, bytes) in cases {
+        assert_eq!(data.encode().unwrap(), bytes, "encoding {:?}", data);
+        assert_eq!(read_back(data.data_type(), &bytes), data);
+    }
+}
+
+fn arb_string() -> impl Strategy<Value = String> {
+    "\\PC{0,40}"
+}
+
+fn arb_u128() -> impl Strategy<Value = (u64, u64)> {
+    (any::<u64>(), any::<u64>())
+}
+
+fn arb_data() -> impl Strategy<Value = Data> {
+    prop_oneof![
+        any::<i8>().prop_map(Data::INT8),
+        any::<u8>().prop_map(Data::UINT8),
+        any::<i16>().prop_map(Data::INT16),
+        any::<u16>().prop_map(Data::UINT16),
+        any::<i32>().prop_map(Data::INT32),
+        any::<u32>().prop_map(Data::UINT32),
+        any::<i64>().prop_map(Data::INT64),
+        any::<u64>().prop_map(Data::UINT64),
+        arb_u128().prop_map(Data::INT128),
+        arb_u128().prop_map(Data::UINT128),
+        prop::collection::vec(any::<i8>(), 0..16).prop_map(Data::AINT8),
+        prop::collection::vec(any::<u8>(), 0..16).prop_map(Data::AUINT8),
+        prop::collection::vec(any::<i16>(), 0..16).prop_map(Data::AINT16),
+        prop::collection::vec(any::<u16>(), 0..16).prop_map(Data::AUINT16),
+        prop::collection::vec(any::<i32>(), 0..16).prop_map(Data::AINT32),
+        prop::collection::vec(any::<u32>(), 0..16).prop_map(Data::AUINT32),
+        prop::collection::vec(any::<i64>(), 0..16).prop_map(Data::AINT64),
+        prop::collection::vec(any::<u64>(), 0..16).prop_map(Data::AUINT64),
+        prop::collection::vec(arb_u128(), 0..16).prop_map(Data::AINT128),
+        prop::collection::vec(arb_u128(), 0..16).prop_map(Data::AUINT128),
+        arb_string().prop_map(Data::STR),
+    ]
+}
+
+// Values of the same type as `data`, for building consistent PropInfos.
+fn arb_data_like(data: &Data) -> BoxedStrategy<Data> {
+    let kind = data.data_type();
+    arb_data()
+        .prop_filter("same type", move |d| d.data_type() == kind)
+        .boxed()
+}
+
+fn arb_object_format() -> impl Strategy<Value = ObjectFormatCode> {
+    any::<u16>().prop_filter_map("not an object format", ObjectFormatCode::from_u16)
+}
+
+fn arb_vec_u16() -> impl Strategy<Value = Vec<u16>> {
+    prop::collection::vec(any::<u16>(), 0..16)
+}
+
+fn arb_device_info() -> impl Strategy<Value = DeviceInfo> {
+    (
+        (
+            any::<u16>(),
+            any::<u32>(),
+            any::<u16>(),
+            arb_string(),
+            any::<u16>(),
+        ),
+        (
+            arb_vec_u16(),
+            arb_vec_u16(),
+            arb_vec_u16(),
+            arb_vec_u16(),
+            arb_vec_u16(),
+        ),
+        (arb_string(), arb_string(), arb_string(), arb_string()),
+    )
+        .prop_map(|(a, b, c)| DeviceInfo {
+            version: a.0,
+            vendor_ex_id: a.1,
+            vendor_ex_version: a.2,
+            vendor_extension_desc: a.3,
+            functional_mode: a.4,
+            operations_supported: b.0,
+            events_supported: b.1,
+            device_properties_supported: b.2,
+            capture_formats: b.3,
+            image_formats: b.4,
+            manufacturer: c.0,
+            model: c.1,
+            device_version: c.2,
+            serial_number: c.3,
+        })
+}
+
+fn arb_object_info() -> impl Strategy<Value = ObjectInfo> {
+    (
+        (
+            any::<u32>(),
+            arb_object_format(),
+            any::<u16>(),
+            any::<u32>(),
+            arb_object_format(),
+            any::<u32>(),
+        ),
+        (
+            any::<u32>(),
+            any::<u32>(),
+            any::<u32>(),
+            any::<u32>(),
+            any::<u32>(),
+            any::<u32>(),
+        ),
+        (
+            any::<u16>().prop_filter_map("association", AssociationCode::from_u16),
+            any::<u32>(),
+            any::<u32>(),
+        ),
+        (arb_string(), arb_string(), arb_string(), arb_string()),
+    )
+        .prop_map(|(a, b, c, d)| ObjectInfo {
+            storage_id: a.0,
+            object_format: a.1,
+            protection_status: a.2,
+            object_compressed_size: a.3,
+            thumb_format: a.4,
+            thumb_compressed_size: a.5,
+            thumb_pix_width: b.0,
+            thumb_pix_height: b.1,
+            image_pix_width: b.2,
+            image_pix_height: b.3,
+            image_bit_depth: b.4,
+            parent_object: b.5,
+            association_type: c.0,
+            association_desc: c.1,
+            sequence_number: c.2,
+            filename: d.0,
+            capture_date: d.1,
+            modification_date: d.2,
+            keywords: d.3,
+        })
+}
+
+fn arb_storage_info() -> impl Strategy<Value = StorageInfo> {
+    (
+        // codes the standard reserves are rejected by decode
+        (0u16..=4).prop_filter_map("storage type", StorageType::from_u16),
+        prop_oneof![0u16..=3, 0x8000u16..=0xFFFF]
+            .prop_filter_map("filesystem type", FilesystemType::from_u16),
+        (0u16..=2).prop_filter_map("access type", AccessType::from_u16),
+        any::<u64>(),
+        any::<u64>(),
+        any::<u32>(),
+        arb_string(),
+        arb_string(),
+    )
+        .prop_map(|s| StorageInfo {
+            storage_type: s.0,
+            filesystem_type: s.1,
+            access_capability: s.2,
+            max_capacity: s.3,
+            free_space_in_bytes: s.4,
+            free_space_in_images: s.5,
+            storage_description: s.6,
+            volume_label: s.7,
+        })
+}
+
+fn arb_prop_info() -> impl Strategy<Value = PropInfo> {
+    arb_data().prop_flat_map(|current| {
+        let form = prop_oneof![
+            Just(FormData::None),
+            (
+                arb_data_like(&current),
+                arb_data_like(&current),
+                arb_data_like(&current)
+            )
+                .prop_map(|(min_value, max_value, step)| FormData::Range {
+                    min_value,
+                    max_value,
+                    step
+                }),
+            prop::collection::vec(arb_data_like(&current), 0..8)
+                .prop_map(|array| FormData::Enumeration { array }),
+        ];
+
+        (
+            any::<u16>(),
+            any::<u8>(),
+            any::<u8>(),
+            arb_data_like(&current),
+            Just(current),
+            form,
+        )
+            .prop_map(
+                |(property_code, get_set, is_enable, factory_default, current, form)| PropInfo {
+                    property_code,
+                    data_type: current.data_type(),
+                    get_set,
+                    is_enable,
+                    factory_default,
+                    current,
+                    form,
+                },
+            )
+    })
+}
+
+proptest! {
+    #[test]
+    fn data_round_trip(data in arb_data()) {
+        let bytes = data.encode().unwrap();
+        prop_assert_eq!(read_back(data.data_type(), &bytes), data);
+    }
+
+    #[test]
+    fn string_round_trip(s in "\\PC{0,254}") {
+        let mut bytes = vec![];
+        match bytes.write_ptp_str(&s) {
+            Ok(()) => {
+                let mut cur = Cursor::new(&bytes);
+                prop_assert_eq!(cur.read_ptp_str().unwrap(), s);
+                cur.expect_end().unwrap();
+            }
+            Err(Error::StringTooLong(len)) => {
+                prop_assert!(s.encode_utf16().count() + 1 == len && len > 255)
+            }
+            Err(e) => return Err(TestCaseError::fail(e.to_string())),
+        }
+    }
+
+    #[test]
+    fn device_info_round_trip(info in arb_device_info()) {
+        let mut bytes = vec![];
+        info.encode(&mut bytes).unwrap();
+        prop_assert_eq!(DeviceInfo::decode(&bytes).unwrap(), info);
+    }
+
+    #[test]
+    fn object_info_round_trip(info in arb_object_info()) {
+        let mut bytes = vec![];
+        info.encode(&mut bytes).unwrap();
+        prop_assert_eq!(ObjectInfo::decode(&bytes).unwrap(), info);
+    }
+
+    #[test]
+    fn storage_info_round_trip(info in arb_storage_info()) {
+        let mut bytes = vec![];
+        info.encode(&mut bytes).unwrap();
+        let mut cur = Cursor::new(&bytes);
+        prop_assert_eq!(StorageInfo::decode(&mut cur).unwrap(), info);
+        cur.expect_end().unwrap();
+    }
+
+    #[test]
+    fn prop_info_round_trip(info in arb_prop_info()) {
+        let mut bytes = vec![];
+        info.encode(&mut bytes).unwrap();
+        let mut cur = Cursor::new(&bytes);
+        prop_assert_eq!(PropInfo::decode(&mut cur).unwrap(), info);
+        cur.expect_end().unwrap();
+    }
+}
+
+#[test]
+fn data_type_codes() {
+    let cases = [
+        (DataType::UNDEF, 0x0000, Some(0)),
+        (DataType::INT8, 0x0001, Some(1)),
+        (DataType::UINT16, 0x0004, Some(2)),
+        (DataType::INT32, 0x0005, Some(4)),
+        (DataType::UINT64, 0x0008, Some(8)),
+        (DataType::UINT128, 0x000A, Some(16)),
+        (DataType::AINT8, 0x4001, Some(1)),
+        (DataType::AUINT32, 0x4006, Some(4)),
+        (DataType::AUINT128, 0x400A, Some(16)),
+        (DataType::STR, 0xFFFF, None),
+    ];
+
+    for (kind, code, size) in cases {
+        assert_eq!(kind as u16, code);
+        assert_eq!(DataType::from_u16(code), Some(kind));
+        assert_eq!(kind.size(), size, "size of {:?}", kind);
+        assert_eq!(kind.is_array(), code & 0x4000 != 0 && code != 0xFFFF);
+    }
+    assert_eq!(DataType::AUINT16.element_type(), Some(DataType::UINT16));
+    assert_eq!(DataType::STR.element_type(), None);
+}
+
+#[test]
+fn unknown_data_type_is_an_error() {
+    // a PropInfo for property 0x5001 claiming type 0x000B
+    let bytes = [0x01, 0x50, 0x0B, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+    match PropInfo::decode(&mut Cursor::new(&bytes[..])) {
+        Err(Error::BadDataType(0x000B)) => {}
+        other => panic!("unexpected result {:?}", other),
+    }
+}
+
+#[test]
+fn undefined_data_type_is_an_error() {
+    // a PropInfo for property 0x5001 of type UNDEF, then what would be its
+    // values if it were UINT16
+    let bytes = [
+        0x01, 0x50, 0x00, 0x00, 0x01, 0x01, 0x02, 0x00, 0x03, 0x00, 0x00,
+    ];
+    match PropInfo::decode(&mut Cursor::new(&bytes[..])) {
+        Err(Error::BadDataType(0x0000)) => {}
+        other => panic!("unexpected result {:?}", other),
+    }
+
+    match Data::read_type(DataType::UNDEF, &mut Cursor::new(&[0x02, 0x00][..])) {
+        Err(Error::BadDataType(0x0000)) => {}
+        other => panic!("unexpected result {:?}", other),
+    }
+}
+
+#[test]
+fn typed_accessors() {
+    use std::convert::TryFrom;
+
+    let data = Data::UINT16(0x8001);
+    assert_eq!(data.data_type(), DataType::UINT16);
+    assert_eq!(data.as_u16(), Some(0x8001));
+    assert_eq!(data.as_u32(), None);
+    assert_eq!(data.as_str(), None);
+    assert_eq!(u16::try_from(data.clone()).unwrap(), 0x8001);
+    match u32::try_from(data) {
+        Err(Error::UnexpectedDataType { expected, actual }) => {
+            assert_eq!(expected, DataType::UINT32);
+            assert_eq!(actual, DataType::UINT16);
+        }
+        other => panic!("unexpected result {:?}", other),
+    }
+
+    let data = Data::STR("IMG_0001.JPG".into());
+    assert_eq!(data.as_str(), Some("IMG_0001.JPG"));
+    assert_eq!(String::try_from(data).unwrap(), "IMG_0001.JPG");
+}
+
+#[test]
+fn image_only_format_round_trip() {
+    let info = ObjectInfo {
+        object_format: ObjectFormatCode::ImageOnly,
+        thumb_format: ObjectFormatCode::Standard(StandardObjectFormatCode::ExifJpeg),
+        filename: "IMG_0001.JPG".into(),
+        ..ObjectInfo::default()
+    };
+
+    let mut bytes = vec![];
+    info.encode(&mut bytes).unwrap();
+    assert_eq!(&bytes[4..6], &[0xFF, 0xFF]);
+    assert_eq!(ObjectInfo::decode(&bytes).unwrap(), info);
+}
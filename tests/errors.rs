@@ -0,0 +1,228 @@
+mod common;
+
+use std::error::Error as _;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use ptp::{
+    CommandCode, Device, DeviceInfo, Endpoint, Error, ResponseCode, SessionConfig,
+    StandardCommandCode, StandardResponseCode, TransactionContext, TransactionPhase, Transport,
+};
+
+use common::{FakeCamera, Reply};
+
+fn response(code: StandardResponseCode) -> Error {
+    Error::Response(ResponseCode::Standard(code))
+}
+
+fn in_transaction(source: Error, phase: TransactionPhase) -> Error {
+    Error::Transaction {
+        context: Box::new(TransactionContext {
+            code: StandardCommandCode::GetObject.into(),
+            name: Some("GetObject".into()),
+            params: vec![0x0001_002A],
+            tid: 7,
+            phase,
+        }),
+        source: Box::new(source),
+    }
+}
+
+#[test]
+fn context_is_reported() {
+    let err = in_transaction(
+        response(StandardResponseCode::InvalidObjectHandle),
+        TransactionPhase::Response,
+    );
+
+    assert_eq!(
+        err.to_string(),
+        "GetObject(0x1002a) failed in the response phase of transaction 7: \
+         the ptp device returned an error code: InvalidObjectHandle (the object handle is invalid)"
+    );
+
+    let context = err.context().unwrap();
+    assert_eq!(
+        context.code,
+        CommandCode::Standard(StandardCommandCode::GetObject)
+    );
+    assert_eq!(context.phase, TransactionPhase::Response);
+    assert!(matches!(err.root(), Error::Response(_)));
+    assert!(err.source().is_some());
+    assert_eq!(
+        err.response_code(),
+        Some(ResponseCode::Standard(
+            StandardResponseCode::InvalidObjectHandle
+        ))
+    );
+
+    let usb = in_transaction(Error::Usb(rusb::Error::Pipe), TransactionPhase::DataIn);
+    assert!(usb.to_string().contains("failed in the data-in phase"));
+    assert_eq!(usb.response_code(), None);
+}
+
+#[test]
+fn classification() {
+    let busy = response(StandardResponseCode::DeviceBusy);
+    assert!(busy.is_device_busy());
+    assert!(busy.is_retryable());
+    assert!(!busy.is_session_error());
+
+    let wrapped = in_transaction(busy, TransactionPhase::Response);
+    assert!(wrapped.is_device_busy());
+    assert!(wrapped.is_retryable());
+
+    for code in [
+        StandardResponseCode::SessionNotOpen,
+        StandardResponseCode::SessionAlreadyOpen,
+        StandardResponseCode::InvalidTransactionId,
+    ] {
+        let err = in_transaction(response(code), TransactionPhase::Response);
+        assert!(err.is_session_error(), "{:?}", code);
+        assert!(!err.is_retryable(), "{:?}", code);
+    }
+
+    let invalid = response(StandardResponseCode::InvalidParameter);
+    assert!(!invalid.is_retryable());
+    assert!(!invalid.is_device_busy());
+    assert!(!Error::Response(ResponseCode::Other(0xA102)).is_retryable());
+
+    assert!(Error::Usb(rusb::Error::Timeout).is_retryable());
+    assert!(!Error::Usb(rusb::Error::NoDevice).is_retryable());
+    assert!(!Error::Malformed("short".into()).is_retryable());
+}
+
+// Fails one bulk write of the camera it wraps with a timeout
+struct FailingWrite {
+    camera: FakeCamera,
+    writes: AtomicUsize,
+    fail: usize,
+}
+
+impl Transport for FailingWrite {
+    fn write_bulk(&self, buf: &[u8], timeout: Duration) -> Result<usize, Error> {
+        if self.writes.fetch_add(1, Ordering::AcqRel) == self.fail {
+            return Err(Error::Usb(rusb::Error::Timeout));
+        }
+        self.camera.write_bulk(buf, timeout)
+    }
+
+    fn read_bulk(&self, buf: &mut [u8], timeout: Duration) -> Result<usize, Error> {
+        self.camera.read_bulk(buf, timeout)
+    }
+
+    fn read_interrupt(&self, buf: &mut [u8], timeout: Duration) -> Result<usize, Error> {
+        self.camera.read_interrupt(buf, timeout)
+    }
+
+    fn clear_halt(&self, endpoint: Endpoint) -> Result<(), Error> {
+        self.camera.clear_halt(endpoint)
+    }
+
+    fn reset(&self) -> Result<(), Error> {
+        self.camera.reset()
+    }
+}
+
+// Runs SendObject(0x2A) with data in the first transaction of a session,
+// failing the given bulk write, and returns its error
+fn failed_command(reply: Reply, fail_write: usize, unplug: bool) -> Error {
+    let camera = FakeCamera::new(move |request| match request.code {
+        0x100D => reply.clone(),
+        _ => Reply::ok(),
+    });
+    let device = Device::with_transport(FailingWrite {
+        camera,
+        writes: AtomicUsize::new(0),
+        fail: fail_write,
+    });
+    let session = device.open_session(SessionConfig::default()).unwrap();
+    if unplug {
+        device.transport().camera.unplug();
+    }
+
+    let code = StandardCommandCode::SendObject.into();
+    let err = session
+        .command(code, &[0x2A], Some(&[1, 2, 3]), None)
+        .unwrap_err();
+    drop(session);
+    err
+}
+
+fn assert_context(err: &Error, phase: TransactionPhase) {
+    let context = err.context().expect("no transaction context");
+    assert_eq!(
+        context.code,
+        CommandCode::Standard(StandardCommandCode::SendObject)
+    );
+    assert_eq!(context.params, [0x2A]);
+    assert_eq!(context.tid, 1);
+    assert_eq!(context.phase, phase, "{}", err);
+}
+
+#[test]
+fn commands_report_the_failed_phase() {
+    // writes are the OpenSession command, then the SendObject command and
+    // its data
+    let never = usize::MAX;
+
+    let err = failed_command(Reply::ok(), never, true);
+    assert_context(&err, TransactionPhase::Command);
+    assert!(matches!(err.root(), Error::Usb(rusb::Error::NoDevice)));
+
+    let err = failed_command(Reply::ok(), 2, false);
+    assert_context(&err, TransactionPhase::DataOut);
+    assert!(matches!(err.root(), Error::Usb(rusb::Error::Timeout)));
+
+    let err = failed_command(Reply::stall(), never, false);
+    assert_context(&err, TransactionPhase::DataIn);
+    assert!(err.is_stall());
+
+    let invalid = StandardResponseCode::InvalidObjectHandle;
+    let err = failed_command(Reply::error(invalid as u16), never, false);
+    assert_context(&err, TransactionPhase::Response);
+    assert_eq!(err.response_code(), Some(invalid.into()));
+    assert!(err
+        .to_string()
+        .starts_with("SendObject(0x2a) failed in the response phase of transaction 1: "));
+}
+
+#[test]
+fn context_names_vendor_operations() {
+    let info = DeviceInfo {
+        version: 100,
+        vendor_ex_id: 6,
+        vendor_ex_version: 100,
+        vendor_extension_desc: "microsoft.com: 1.0;".into(),
+        functional_mode: 0,
+        operations_supported: vec![],
+        events_supported: vec![],
+        device_properties_supported: vec![],
+        capture_formats: vec![],
+        image_formats: vec![],
+        manufacturer: "Acme".into(),
+        model: "Player".into(),
+        device_version: "1.0".into(),
+        serial_number: "1".into(),
+    };
+    let mut info_data = vec![];
+    info.encode(&mut info_data).unwrap();
+    let device = Device::with_transport(FakeCamera::new(move |request| match request.code {
+        0x1001 => Reply::data(info_data.clone()),
+        _ => Reply::error(StandardResponseCode::OperationNotSupported as u16),
+    }));
+
+    // unknown until the vendor is
+    let get_prop_list = CommandCode::Other(0x9805);
+    let err = device.command(get_prop_list, &[1], None, None).unwrap_err();
+    assert_eq!(err.context().unwrap().name, None);
+    assert!(err.to_string().starts_with("0x9805(0x1) failed"));
+
+    device.get_device_info(None).unwrap();
+    let err = device.command(get_prop_list, &[1], None, None).unwrap_err();
+    assert_eq!(
+        err.context().unwrap().name.as_deref(),
+        Some("GetObjectPropList")
+    );
+    assert!(err.to_string().starts_with("GetObjectPropList(0x1) failed"));
+}
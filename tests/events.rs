@@ -0,0 +1,115 @@
+mod common;
+
+use std::time::Duration;
+
+use ptp::{Device, EventCode, ExtendedEventData, StandardEventCode};
+
+use common::{container, FakeCamera, Reply, INTERRUPT_PACKET_SIZE};
+
+const TIMEOUT: Option<Duration> = Some(Duration::from_millis(100));
+// Canon's property change list, a vendor event
+const PROPERTY_CHANGES: u16 = 0xc189;
+
+fn camera() -> Device<FakeCamera> {
+    Device::with_transport(FakeCamera::new(|_| Reply::ok()))
+}
+
+fn extended_data(code: u16, tid: u32, params: &[&[u8]]) -> Vec<u8> {
+    let mut buf = vec![];
+    buf.extend(code.to_le_bytes());
+    buf.extend(tid.to_le_bytes());
+    buf.extend((params.len() as u16).to_le_bytes());
+    for param in params {
+        buf.extend((param.len() as u16).to_le_bytes());
+        buf.extend(*param);
+    }
+    buf
+}
+
+#[test]
+fn events_span_several_packets() {
+    let device = camera();
+    let params: Vec<u32> = (1..=40).collect();
+    device.transport().push_event(PROPERTY_CHANGES, &params);
+    device
+        .transport()
+        .push_event(StandardEventCode::ObjectAdded as u16, &[7]);
+
+    let event = device.event(TIMEOUT).unwrap().unwrap();
+    assert_eq!(event.code, EventCode::Vendor(PROPERTY_CHANGES));
+    assert_eq!(event.params, params);
+    assert!(event.extended.is_none());
+
+    // nothing of the long event is left over
+    let event = device.event(TIMEOUT).unwrap().unwrap();
+    assert_eq!(
+        event.code,
+        EventCode::Standard(StandardEventCode::ObjectAdded)
+    );
+    assert_eq!(event.params, [7]);
+    assert!(device.event(TIMEOUT).unwrap().is_none());
+}
+
+#[test]
+fn zero_length_packets_are_skipped() {
+    let device = camera();
+    // 12 + 29 * 4 bytes, two whole packets
+    let params: Vec<u32> = (0..29).collect();
+    assert_eq!(12 + params.len() * 4, 2 * INTERRUPT_PACKET_SIZE);
+    device.transport().push_event(PROPERTY_CHANGES, &params);
+    device
+        .transport()
+        .push_event(StandardEventCode::StoreFull as u16, &[1]);
+
+    assert_eq!(device.event(TIMEOUT).unwrap().unwrap().params, params);
+    let event = device.event(TIMEOUT).unwrap().unwrap();
+    assert_eq!(
+        event.code,
+        EventCode::Standard(StandardEventCode::StoreFull)
+    );
+}
+
+#[test]
+fn events_cut_short_are_fetched_whole() {
+    let device = camera();
+    let fake = device.transport();
+
+    // the container announces 300 bytes of payload, but the device sends
+    // only the first three parameters
+    let mut cut = container(
+        4,
+        PROPERTY_CHANGES,
+        0,
+        &[1, 0, 0, 0, 2, 0, 0, 0, 3, 0, 0, 0],
+    );
+    cut[..4].copy_from_slice(&312u32.to_le_bytes());
+    fake.push_interrupt_data(cut);
+
+    let list = vec![0xab; 300];
+    fake.set_extended_event_data(extended_data(PROPERTY_CHANGES, 0, &[&[1, 0], &list]));
+
+    let event = device.event(TIMEOUT).unwrap().unwrap();
+    assert_eq!(event.code, EventCode::Vendor(PROPERTY_CHANGES));
+    assert_eq!(event.params, [1, 2, 3]);
+    let extended = event.extended.unwrap();
+    assert_eq!(extended.code, EventCode::Vendor(PROPERTY_CHANGES));
+    assert_eq!(extended.params, [vec![1, 0], list]);
+
+    assert!(device.event(TIMEOUT).unwrap().is_none());
+}
+
+#[test]
+fn extended_event_data_decoding() {
+    let buf = extended_data(0x4002, 9, &[&[1, 2, 3, 4], &[], &[5]]);
+    let data = ExtendedEventData::decode(&buf).unwrap();
+    assert_eq!(
+        data.code,
+        EventCode::Standard(StandardEventCode::ObjectAdded)
+    );
+    assert_eq!(data.tid, 9);
+    assert_eq!(data.params, [vec![1, 2, 3, 4], vec![], vec![5]]);
+
+    // a parameter running past the end, and a missing one
+    assert!(ExtendedEventData::decode(&buf[..buf.len() - 1]).is_err());
+    assert!(ExtendedEventData::decode(&buf[..17]).is_err());
+}
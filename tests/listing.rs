@@ -0,0 +1,280 @@
+mod common;
+
+use num_traits::{FromPrimitive, ToPrimitive};
+
+use ptp::{
+    object_prop, AssociationCode, Data, Device, DeviceInfo, ObjectFormatCode, ObjectHandle,
+    ObjectInfo, PtpWrite, SessionConfig, StandardCommandCode, StandardResponseCode, StorageId,
+    MTP_GET_OBJECT_PROP_LIST,
+};
+
+use common::{FakeCamera, Reply, Request};
+
+const GET_DEVICE_INFO: u16 = StandardCommandCode::GetDeviceInfo as u16;
+const GET_OBJECT_HANDLES: u16 = StandardCommandCode::GetObjectHandles as u16;
+const GET_OBJECT_INFO: u16 = StandardCommandCode::GetObjectInfo as u16;
+const GET_FILESYSTEM_MANIFEST: u16 = StandardCommandCode::GetFilesystemManifest as u16;
+const CARD: u32 = 0x0001_0001;
+const INTERNAL: u32 = 0x0002_0001;
+
+// A DCIM folder with two images on the card, and one image in internal
+// memory. Only fields that every listing method can fill are set.
+fn objects() -> Vec<(ObjectHandle, ObjectInfo)> {
+    let object = |storage_id, format, size, parent, filename: &str| ObjectInfo {
+        storage_id,
+        object_format: ObjectFormatCode::from_u16(format).unwrap(),
+        object_compressed_size: size,
+        parent_object: parent,
+        filename: filename.into(),
+        modification_date: "20261018T120000".into(),
+        ..ObjectInfo::default()
+    };
+
+    vec![
+        (
+            ObjectHandle::from(1),
+            ObjectInfo {
+                association_type: AssociationCode::from_u16(0x0001).unwrap(),
+                ..object(CARD, 0x3001, 0, 0, "DCIM")
+            },
+        ),
+        (
+            ObjectHandle::from(2),
+            object(CARD, 0x3801, 4_000_000, 1, "IMG_0001.JPG"),
+        ),
+        (
+            ObjectHandle::from(3),
+            ObjectInfo {
+                protection_status: 1,
+                ..object(CARD, 0x3801, 5_000_000, 1, "IMG_0002.JPG")
+            },
+        ),
+        (
+            ObjectHandle::from(4),
+            object(INTERNAL, 0x3801, 3_000_000, 0, "IMG_0003.JPG"),
+        ),
+    ]
+}
+
+fn in_storage(storage_id: u32) -> impl Iterator<Item = (ObjectHandle, ObjectInfo)> {
+    objects()
+        .into_iter()
+        .filter(move |(_, info)| info.storage_id == storage_id)
+}
+
+fn manifest(storage_id: u32) -> Vec<u8> {
+    let objects: Vec<_> = in_storage(storage_id).collect();
+    let mut buf = vec![];
+    buf.write_ptp_u64(objects.len() as u64).unwrap();
+    for (handle, info) in objects {
+        buf.write_ptp_u32(handle.into()).unwrap();
+        buf.write_ptp_u32(info.storage_id).unwrap();
+        buf.write_ptp_u16(info.object_format.to_u16().unwrap())
+            .unwrap();
+        buf.write_ptp_u16(info.protection_status).unwrap();
+        buf.write_ptp_u64(info.object_compressed_size.into())
+            .unwrap();
+        buf.write_ptp_u32(info.parent_object).unwrap();
+        buf.write_ptp_u16(info.association_type.to_u16().unwrap())
+            .unwrap();
+        buf.write_ptp_u32(info.association_desc).unwrap();
+        buf.write_ptp_u32(info.sequence_number).unwrap();
+        buf.write_ptp_str(&info.filename).unwrap();
+        buf.write_ptp_str(&info.modification_date).unwrap();
+    }
+    buf
+}
+
+// The list for all objects of all storages, as the wildcard asks for
+fn prop_list() -> Vec<u8> {
+    let mut entries = vec![];
+    for (handle, info) in objects() {
+        let format = info.object_format.to_u16().unwrap();
+        let association = info.association_type.to_u16().unwrap();
+        let props = [
+            (object_prop::STORAGE_ID, Data::UINT32(info.storage_id)),
+            (object_prop::OBJECT_FORMAT, Data::UINT16(format)),
+            (
+                object_prop::PROTECTION_STATUS,
+                Data::UINT16(info.protection_status),
+            ),
+            (
+                object_prop::OBJECT_SIZE,
+                Data::UINT64(info.object_compressed_size.into()),
+            ),
+            (object_prop::ASSOCIATION_TYPE, Data::UINT16(association)),
+            (
+                object_prop::ASSOCIATION_DESC,
+                Data::UINT32(info.association_desc),
+            ),
+            (object_prop::OBJECT_FILE_NAME, Data::STR(info.filename)),
+            (
+                object_prop::DATE_MODIFIED,
+                Data::STR(info.modification_date),
+            ),
+            (object_prop::PARENT_OBJECT, Data::UINT32(info.parent_object)),
+        ];
+        entries.extend(
+            props
+                .iter()
+                .map(|(code, value)| (handle, *code, value.clone())),
+        );
+    }
+
+    let mut buf = vec![];
+    buf.write_ptp_u32(entries.len() as u32).unwrap();
+    for (handle, code, value) in entries {
+        buf.write_ptp_u32(handle.into()).unwrap();
+        buf.write_ptp_u16(code).unwrap();
+        buf.write_ptp_u16(value.data_type() as u16).unwrap();
+        buf.extend(value.encode().unwrap());
+    }
+    buf
+}
+
+fn handles(storage_id: u32) -> Vec<u8> {
+    let handles: Vec<u32> = in_storage(storage_id).map(|(h, _)| h.into()).collect();
+    let mut buf = vec![];
+    buf.write_ptp_u32(handles.len() as u32).unwrap();
+    for handle in handles {
+        buf.write_ptp_u32(handle).unwrap();
+    }
+    buf
+}
+
+fn object_info(handle: u32) -> Reply {
+    match objects().into_iter().find(|(h, _)| u32::from(*h) == handle) {
+        Some((_, info)) => {
+            let mut buf = vec![];
+            info.encode(&mut buf).unwrap();
+            Reply::data(buf)
+        }
+        None => Reply::error(StandardResponseCode::InvalidObjectHandle as u16),
+    }
+}
+
+// A camera offering `operations`, failing those in `broken`
+fn camera(version: u16, operations: &[u16], broken: &'static [u16]) -> Device<FakeCamera> {
+    let info = DeviceInfo {
+        version,
+        vendor_ex_id: 6,
+        vendor_ex_version: 100,
+        vendor_extension_desc: "microsoft.com: 1.0;".into(),
+        functional_mode: 0,
+        operations_supported: operations.to_vec(),
+        events_supported: vec![],
+        device_properties_supported: vec![],
+        capture_formats: vec![],
+        image_formats: vec![],
+        manufacturer: "Acme".into(),
+        model: "Camera".into(),
+        device_version: "1.0".into(),
+        serial_number: "1".into(),
+    };
+    let mut info_data = vec![];
+    info.encode(&mut info_data).unwrap();
+
+    let device = Device::with_transport(FakeCamera::new(move |request: &Request| {
+        if broken.contains(&request.code) {
+            return Reply::error(StandardResponseCode::GeneralError as u16);
+        }
+        match request.code {
+            GET_DEVICE_INFO => Reply::data(info_data.clone()),
+            GET_FILESYSTEM_MANIFEST => Reply::data(manifest(request.params[0])),
+            MTP_GET_OBJECT_PROP_LIST => Reply::data(prop_list()),
+            GET_OBJECT_HANDLES => Reply::data(handles(request.params[0])),
+            GET_OBJECT_INFO => object_info(request.params[0]),
+            _ => Reply::ok(),
+        }
+    }));
+    device.get_device_info(None).unwrap();
+    device
+}
+
+// Lists both storages, returning what was listed and the operations used
+fn list(device: &Device<FakeCamera>) -> (Vec<Vec<(ObjectHandle, ObjectInfo)>>, Vec<u16>) {
+    let session = device.open_session(SessionConfig::default()).unwrap();
+    let lists = [CARD, INTERNAL]
+        .iter()
+        .map(|&id| session.list_storage(StorageId::from(id), None).unwrap())
+        .collect();
+    drop(session);
+
+    let mut codes: Vec<_> = device.transport().log().iter().map(|r| r.code).collect();
+    codes.retain(|&code| {
+        [
+            GET_FILESYSTEM_MANIFEST,
+            MTP_GET_OBJECT_PROP_LIST,
+            GET_OBJECT_HANDLES,
+            GET_OBJECT_INFO,
+        ]
+        .contains(&code)
+    });
+    (lists, codes)
+}
+
+fn expected() -> Vec<Vec<(ObjectHandle, ObjectInfo)>> {
+    vec![in_storage(CARD).collect(), in_storage(INTERNAL).collect()]
+}
+
+#[test]
+fn listing_with_the_filesystem_manifest() {
+    let device = camera(
+        110,
+        &[GET_FILESYSTEM_MANIFEST, MTP_GET_OBJECT_PROP_LIST],
+        &[],
+    );
+    let (lists, codes) = list(&device);
+    assert_eq!(lists, expected());
+    assert_eq!(codes, [GET_FILESYSTEM_MANIFEST; 2]);
+}
+
+#[test]
+fn listing_with_object_property_lists() {
+    // a PTP 1.0 device can't have a manifest, whatever it claims
+    let device = camera(
+        100,
+        &[GET_FILESYSTEM_MANIFEST, MTP_GET_OBJECT_PROP_LIST],
+        &[],
+    );
+    let (lists, codes) = list(&device);
+    assert_eq!(lists, expected());
+    assert_eq!(codes, [MTP_GET_OBJECT_PROP_LIST; 2]);
+}
+
+#[test]
+fn listing_object_by_object() {
+    let device = camera(100, &[], &[]);
+    let (lists, codes) = list(&device);
+    assert_eq!(lists, expected());
+    assert_eq!(
+        codes,
+        [
+            GET_OBJECT_HANDLES,
+            GET_OBJECT_INFO,
+            GET_OBJECT_INFO,
+            GET_OBJECT_INFO,
+            GET_OBJECT_HANDLES,
+            GET_OBJECT_INFO
+        ]
+    );
+}
+
+#[test]
+fn failing_fast_paths_fall_through() {
+    let device = camera(
+        110,
+        &[GET_FILESYSTEM_MANIFEST, MTP_GET_OBJECT_PROP_LIST],
+        &[GET_FILESYSTEM_MANIFEST, MTP_GET_OBJECT_PROP_LIST],
+    );
+    let (lists, codes) = list(&device);
+    assert_eq!(lists, expected());
+    assert_eq!(
+        codes[..3],
+        [
+            GET_FILESYSTEM_MANIFEST,
+            MTP_GET_OBJECT_PROP_LIST,
+            GET_OBJECT_HANDLES
+        ]
+    );
+}
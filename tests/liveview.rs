@@ -0,0 +1,183 @@
+use std::time::Duration;
+
+use ptp::{
+    CanonLiveView, Error, FocusArea, LiveView, LiveViewFrame, LiveViewFrames, NikonLiveView,
+    SonyLiveView, UsbTransport,
+};
+
+type Canon = CanonLiveView<'static, UsbTransport<rusb::Context>>;
+type Nikon = NikonLiveView<'static, UsbTransport<rusb::Context>>;
+type Sony = SonyLiveView<'static, UsbTransport<rusb::Context>>;
+
+// the start and end of a baseline JPEG, enough to tell where it lies
+const JPEG: [u8; 12] = [
+    0xFF, 0xD8, 0xFF, 0xE0, 0x00, 0x10, b'J', b'F', b'I', b'F', 0xFF, 0xD9,
+];
+
+fn canon_block(kind: u32, data: &[u8]) -> Vec<u8> {
+    let mut block = ((data.len() + 8) as u32).to_le_bytes().to_vec();
+    block.extend(kind.to_le_bytes());
+    block.extend(data);
+    block
+}
+
+fn bins(channel: u32) -> Vec<u8> {
+    (0..256u32)
+        .flat_map(|bin| (channel * 1000 + bin).to_le_bytes())
+        .collect()
+}
+
+#[test]
+fn canon_frames() {
+    // an EOS viewfinder payload: zoom info, histogram, then the image
+    let mut data = canon_block(5, &[1, 0, 0, 0]);
+    let histogram: Vec<u8> = (0..4).flat_map(bins).collect();
+    data.extend(canon_block(3, &histogram));
+    data.extend(canon_block(1, &JPEG));
+
+    let frame = Canon::parse_frame(&data).unwrap().unwrap();
+    assert_eq!(frame.jpeg, JPEG);
+    assert_eq!(frame.extra, [(5, vec![1, 0, 0, 0])]);
+    let histogram = frame.histogram.unwrap();
+    assert_eq!(histogram.luminance.len(), 256);
+    assert_eq!(histogram.luminance[255], 255);
+    let [red, green, blue] = histogram.rgb.unwrap();
+    assert_eq!((red[0], green[1], blue[2]), (1000, 2001, 3002));
+
+    // luminance only
+    let mut data = canon_block(3, &bins(0));
+    data.extend(canon_block(1, &JPEG));
+    let histogram = Canon::parse_frame(&data).unwrap().unwrap().histogram;
+    assert!(histogram.unwrap().rgb.is_none());
+}
+
+#[test]
+fn malformed_canon_frames() {
+    // no image yet
+    assert!(Canon::parse_frame(&canon_block(5, &[0; 4]))
+        .unwrap()
+        .is_none());
+    assert!(Canon::parse_frame(&[]).unwrap().is_none());
+
+    // a block running past the payload, and one shorter than its header
+    let mut data = canon_block(1, &JPEG);
+    data.truncate(data.len() - 1);
+    assert!(matches!(
+        Canon::parse_frame(&data),
+        Err(Error::Malformed(_))
+    ));
+    assert!(Canon::parse_frame(&[4, 0, 0, 0, 1, 0, 0, 0]).is_err());
+
+    // a histogram of neither one nor four channels
+    let mut data = canon_block(3, &[0; 100]);
+    data.extend(canon_block(1, &JPEG));
+    assert!(matches!(
+        Canon::parse_frame(&data),
+        Err(Error::Malformed(_))
+    ));
+}
+
+#[test]
+fn nikon_frames() {
+    // GetLiveViewImg: a 384 byte header of big-endian fields, then the image
+    let mut header = vec![0u8; 384];
+    let fields: [u16; 12] = [
+        640, 424, 6000, 4000, 6000, 4000, 3000, 2000, 300, 200, 1000, 800,
+    ];
+    for (i, field) in fields.iter().enumerate() {
+        header[i * 2..i * 2 + 2].copy_from_slice(&field.to_be_bytes());
+    }
+    let mut data = header;
+    data.extend(JPEG);
+
+    let frame = Nikon::parse_frame(&data).unwrap().unwrap();
+    assert_eq!(frame.jpeg, JPEG);
+    assert_eq!(
+        frame.focus_area,
+        Some(FocusArea {
+            x: 850,
+            y: 700,
+            width: 300,
+            height: 200,
+        })
+    );
+    assert!(frame.histogram.is_none());
+
+    // models with a short header have no focus area, and a payload without
+    // an image has no frame
+    let mut data = vec![0; 8];
+    data.extend(JPEG);
+    assert!(Nikon::parse_frame(&data)
+        .unwrap()
+        .unwrap()
+        .focus_area
+        .is_none());
+    assert!(Nikon::parse_frame(&[0; 384]).unwrap().is_none());
+}
+
+#[test]
+fn sony_frames() {
+    // the live view object: offset and size of the image, padding, image
+    let mut data = vec![];
+    data.extend(16u32.to_le_bytes());
+    data.extend((JPEG.len() as u32).to_le_bytes());
+    data.extend([0; 8]);
+    data.extend(JPEG);
+    data.extend([0; 4]);
+
+    let frame = Sony::parse_frame(&data).unwrap().unwrap();
+    assert_eq!(frame.jpeg, JPEG);
+    assert!(frame.focus_area.is_none());
+
+    // not ready yet
+    assert!(Sony::parse_frame(&[]).unwrap().is_none());
+    assert!(Sony::parse_frame(&[8, 0, 0, 0, 0, 0, 0, 0])
+        .unwrap()
+        .is_none());
+
+    // an image past the end, and one whose end overflows
+    data[4..8].copy_from_slice(&100u32.to_le_bytes());
+    assert!(matches!(Sony::parse_frame(&data), Err(Error::Malformed(_))));
+    data[0..8].copy_from_slice(&[0xff; 8]);
+    assert!(Sony::parse_frame(&data).is_err());
+}
+
+struct StaticView;
+
+impl LiveView for StaticView {
+    fn start(&mut self, _timeout: Option<Duration>) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn next_frame(&mut self, _timeout: Option<Duration>) -> Result<Option<LiveViewFrame>, Error> {
+        Canon::parse_frame(&canon_block(1, &JPEG))
+    }
+
+    fn stop(&mut self, _timeout: Option<Duration>) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+#[test]
+fn frame_rates() {
+    for &fps in &[
+        0.0,
+        -1.0,
+        f64::NAN,
+        f64::INFINITY,
+        f64::MIN_POSITIVE,
+        1e-300,
+    ] {
+        let err = LiveViewFrames::new(StaticView, fps, None).err();
+        assert!(
+            matches!(err, Some(Error::InvalidFrameRate(_))),
+            "{}: {:?}",
+            fps,
+            err
+        );
+    }
+
+    let frames = StaticView.frames(1000.0, None).unwrap();
+    let frames: Vec<_> = frames.take(3).collect::<Result<_, _>>().unwrap();
+    assert!(frames.iter().all(|frame| frame.jpeg == JPEG));
+}
@@ -0,0 +1,181 @@
+//! Malformed input from the device is an error, never a panic.
+
+use std::collections::VecDeque;
+use std::io::Cursor;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use num_traits::FromPrimitive;
+
+use ptp::{
+    ContainerInfo, ContainerType, Device, Endpoint, Error, FilesystemType, ResponseCode,
+    StandardCommandCode, StorageInfo, StorageType, Transport,
+};
+
+// Answers bulk reads from a script of transfers, whatever was sent
+struct Script {
+    reads: Mutex<VecDeque<Vec<u8>>>,
+}
+
+impl Script {
+    fn device(reads: &[&[u8]]) -> Device<Script> {
+        Device::with_transport(Script {
+            reads: Mutex::new(reads.iter().map(|r| r.to_vec()).collect()),
+        })
+    }
+
+    fn remaining(&self) -> usize {
+        self.reads.lock().unwrap().len()
+    }
+}
+
+impl Transport for Script {
+    fn write_bulk(&self, buf: &[u8], _timeout: Duration) -> Result<usize, Error> {
+        Ok(buf.len())
+    }
+
+    fn read_bulk(&self, buf: &mut [u8], _timeout: Duration) -> Result<usize, Error> {
+        let read = self.reads.lock().unwrap().pop_front();
+        let read = read.ok_or(Error::Usb(rusb::Error::Timeout))?;
+        if read.len() > buf.len() {
+            return Err(Error::Usb(rusb::Error::Overflow));
+        }
+        buf[..read.len()].copy_from_slice(&read);
+        Ok(read.len())
+    }
+
+    fn read_interrupt(&self, _buf: &mut [u8], _timeout: Duration) -> Result<usize, Error> {
+        Err(Error::Usb(rusb::Error::Timeout))
+    }
+
+    fn clear_halt(&self, _endpoint: Endpoint) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn reset(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+fn header(len: u32, kind: u16, code: u16, tid: u32) -> Vec<u8> {
+    let mut buf = len.to_le_bytes().to_vec();
+    buf.extend(kind.to_le_bytes());
+    buf.extend(code.to_le_bytes());
+    buf.extend(tid.to_le_bytes());
+    buf
+}
+
+fn get_storage_ids(device: &Device<Script>) -> Result<Vec<u8>, Error> {
+    device.command(StandardCommandCode::GetStorageIDs.into(), &[], None, None)
+}
+
+#[test]
+fn container_lengths_below_the_header() {
+    for len in 0..12 {
+        let err = ContainerInfo::parse(&header(len, 3, 0x2001, 0)[..]).unwrap_err();
+        assert!(matches!(err, Error::Malformed(_)), "{}: {:?}", len, err);
+    }
+
+    let info = ContainerInfo::parse(&header(12, 3, 0x2001, 7)[..]).unwrap();
+    assert_eq!(info.payload_len, 0);
+    assert_eq!(info.kind, ContainerType::Response);
+    assert_eq!(info.tid, 7);
+
+    // an unknown container type, and a header cut short
+    assert!(matches!(
+        ContainerInfo::parse(&header(12, 9, 0x2001, 0)[..]),
+        Err(Error::Malformed(_))
+    ));
+    assert!(ContainerInfo::parse(&header(12, 3, 0x2001, 0)[..11]).is_err());
+
+    // the same from a device
+    let device = Script::device(&[&header(4, 3, 0x2001, 0)]);
+    let err = get_storage_ids(&device).unwrap_err();
+    assert!(matches!(err.root(), Error::Malformed(_)), "{:?}", err);
+}
+
+#[test]
+fn short_first_reads() {
+    for len in [0, 1, 11] {
+        let device = Script::device(&[&header(12, 3, 0x2001, 0)[..len]]);
+        let err = get_storage_ids(&device).unwrap_err();
+        assert!(
+            matches!(err.root(), Error::Malformed(_)),
+            "{}: {:?}",
+            len,
+            err
+        );
+    }
+}
+
+#[test]
+fn oversized_payloads_are_refused_before_allocating() {
+    // a data container claiming almost 4 GiB
+    let mut device = Script::device(&[&header(u32::MAX, 2, 0x1004, 0)]);
+    device.set_max_payload_size(1 << 20);
+    match get_storage_ids(&device).unwrap_err().root() {
+        Error::PayloadTooLarge { len, max } => {
+            assert_eq!(*len, u32::MAX as usize - 12);
+            assert_eq!(*max, 1 << 20);
+        }
+        err => panic!("unexpected error {:?}", err),
+    }
+    // only the header was read, into a buffer of the usual size
+    assert_eq!(device.transport().remaining(), 0);
+    let stats = device.buffer_pool().stats();
+    assert_eq!(stats.allocations, 1);
+
+    // the limit itself is fine
+    let mut data = header(112, 2, 0x1004, 0);
+    data.extend([0; 100]);
+    let mut device = Script::device(&[&data, &header(12, 3, 0x2001, 0)]);
+    device.set_max_payload_size(100);
+    assert_eq!(get_storage_ids(&device).unwrap().len(), 100);
+}
+
+fn storage_info(storage_type: u16, filesystem_type: u16, access: u16) -> Vec<u8> {
+    let mut buf = vec![];
+    for code in [storage_type, filesystem_type, access] {
+        buf.extend(code.to_le_bytes());
+    }
+    buf.extend([0; 8 + 8 + 4]);
+    buf.extend([0, 0]);
+    buf
+}
+
+#[test]
+fn unknown_storage_codes() {
+    let decode = |buf: Vec<u8>| StorageInfo::decode(&mut Cursor::new(buf));
+
+    let info = decode(storage_info(0x0004, 0x8001, 0x0002)).unwrap();
+    assert_eq!(info.storage_type, StorageType::from_u16(0x0004).unwrap());
+    assert_eq!(info.filesystem_type, FilesystemType::Vendor(0x8001));
+    for bad in [
+        storage_info(0x0005, 0x0002, 0x0000),
+        storage_info(0x0003, 0x0004, 0x0000),
+        storage_info(0x0003, 0x0002, 0x0003),
+    ] {
+        let err = decode(bad).unwrap_err();
+        assert!(matches!(err, Error::Malformed(_)), "{:?}", err);
+    }
+
+    let mut truncated = storage_info(0x0003, 0x0002, 0x0000);
+    truncated.pop();
+    assert!(decode(truncated).is_err());
+}
+
+#[test]
+fn unknown_response_codes() {
+    assert_eq!(
+        ResponseCode::from_u16(0x2fff),
+        Some(ResponseCode::Other(0x2fff))
+    );
+    assert_eq!(
+        ResponseCode::from_u16(0xa001),
+        Some(ResponseCode::Other(0xa001))
+    );
+
+    let device = Script::device(&[&header(12, 3, 0xa001, 0)]);
+    let err = get_storage_ids(&device).unwrap_err();
+    assert_eq!(err.response_code(), Some(ResponseCode::Other(0xa001)));
+}
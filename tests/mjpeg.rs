@@ -0,0 +1,212 @@
+#![cfg(feature = "mjpeg")]
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use ptp::{Error, LiveView, LiveViewFrame, MjpegServer};
+
+struct FakeLiveView {
+    polls: Arc<AtomicU32>,
+    // polls that fail, and how
+    failures: Vec<(u32, rusb::Error)>,
+}
+
+impl LiveView for FakeLiveView {
+    fn start(&mut self, _timeout: Option<Duration>) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn next_frame(&mut self, _timeout: Option<Duration>) -> Result<Option<LiveViewFrame>, Error> {
+        let n = self.polls.fetch_add(1, Ordering::SeqCst);
+        if let Some(&(_, e)) = self.failures.iter().find(|&&(poll, _)| poll == n) {
+            return Err(Error::Usb(e));
+        }
+        // every other poll has no frame ready, like a real camera catching up
+        if n % 2 == 1 {
+            return Ok(None);
+        }
+
+        let mut jpeg = vec![0xFF, 0xD8, 0xFF, 0xE0];
+        jpeg.extend_from_slice(&n.to_le_bytes());
+        jpeg.extend_from_slice(&[0xFF, 0xD9]);
+        Ok(Some(LiveViewFrame {
+            jpeg,
+            focus_area: None,
+            histogram: None,
+            extra: vec![],
+        }))
+    }
+
+    fn stop(&mut self, _timeout: Option<Duration>) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+struct Viewer {
+    reader: BufReader<TcpStream>,
+}
+
+impl Viewer {
+    fn connect(addr: SocketAddr) -> Viewer {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        write!(stream, "GET /live HTTP/1.1\r\nHost: camera\r\n\r\n").unwrap();
+
+        let mut viewer = Viewer {
+            reader: BufReader::new(stream),
+        };
+        let head = viewer.read_head();
+        assert_eq!(head[0], "HTTP/1.1 200 OK");
+        assert!(head
+            .iter()
+            .any(|h| h == "Content-Type: multipart/x-mixed-replace; boundary=ptpframe"));
+        viewer
+    }
+
+    fn read_head(&mut self) -> Vec<String> {
+        let mut lines = vec![];
+        loop {
+            let mut line = String::new();
+            self.reader.read_line(&mut line).unwrap();
+            let line = line.trim_end().to_owned();
+            if line.is_empty() {
+                return lines;
+            }
+            lines.push(line);
+        }
+    }
+
+    fn read_frame(&mut self) -> Vec<u8> {
+        let head = self.read_head();
+        assert_eq!(head[0], "--ptpframe");
+        assert_eq!(head[1], "Content-Type: image/jpeg");
+        let len: usize = head[2]
+            .strip_prefix("Content-Length: ")
+            .unwrap()
+            .parse()
+            .unwrap();
+
+        let mut jpeg = vec![0; len + 2];
+        self.reader.read_exact(&mut jpeg).unwrap();
+        assert_eq!(&jpeg[len..], b"\r\n");
+        jpeg.truncate(len);
+        jpeg
+    }
+}
+
+fn frame_number(jpeg: &[u8]) -> u32 {
+    assert_eq!(&jpeg[..4], &[0xFF, 0xD8, 0xFF, 0xE0]);
+    u32::from_le_bytes([jpeg[4], jpeg[5], jpeg[6], jpeg[7]])
+}
+
+#[test]
+fn serves_frames_to_multiple_viewers() {
+    let server = MjpegServer::bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    let handle = server.handle();
+
+    let polls = Arc::new(AtomicU32::new(0));
+    let runner = {
+        let polls = polls.clone();
+        thread::spawn(move || {
+            let mut view = FakeLiveView {
+                polls,
+                failures: vec![],
+            };
+            server.run(&mut view, 100.0, None)
+        })
+    };
+
+    // nobody is watching yet, so the camera must not be polled
+    thread::sleep(Duration::from_millis(200));
+    assert_eq!(polls.load(Ordering::SeqCst), 0);
+
+    let mut first = Viewer::connect(addr);
+    let mut second = Viewer::connect(addr);
+
+    for viewer in [&mut first, &mut second] {
+        let mut last = None;
+        for _ in 0..5 {
+            let n = frame_number(&viewer.read_frame());
+            assert_eq!(n % 2, 0);
+            assert!(last.is_none_or(|last| n > last));
+            last = Some(n);
+        }
+    }
+    assert_eq!(handle.viewers(), 2);
+
+    handle.stop();
+    runner.join().unwrap().unwrap();
+}
+
+#[test]
+fn idle_clients_do_not_block_shutdown() {
+    let server = MjpegServer::bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    let handle = server.handle();
+
+    let polls = Arc::new(AtomicU32::new(0));
+    let runner = {
+        let polls = polls.clone();
+        thread::spawn(move || {
+            let mut view = FakeLiveView {
+                polls,
+                failures: vec![],
+            };
+            server.run(&mut view, 100.0, None)
+        })
+    };
+
+    // one client never sends a request, the other never reads its frames
+    let _silent = TcpStream::connect(addr).unwrap();
+    let mut stalled = TcpStream::connect(addr).unwrap();
+    write!(stalled, "GET / HTTP/1.1\r\n\r\n").unwrap();
+
+    thread::sleep(Duration::from_millis(500));
+    assert_eq!(handle.viewers(), 1);
+    assert!(polls.load(Ordering::SeqCst) > 0);
+
+    let stopped = Instant::now();
+    handle.stop();
+    runner.join().unwrap().unwrap();
+    assert!(stopped.elapsed() < Duration::from_secs(1));
+}
+
+#[test]
+fn only_fatal_errors_stop_the_server() {
+    let server = MjpegServer::bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+
+    let polls = Arc::new(AtomicU32::new(0));
+    let runner = {
+        let polls = polls.clone();
+        thread::spawn(move || {
+            let mut view = FakeLiveView {
+                polls,
+                failures: vec![
+                    (0, rusb::Error::Timeout),
+                    (2, rusb::Error::Pipe),
+                    (8, rusb::Error::NoDevice),
+                ],
+            };
+            server.run(&mut view, 100.0, None)
+        })
+    };
+
+    let mut viewer = Viewer::connect(addr);
+    assert!(frame_number(&viewer.read_frame()) >= 4);
+
+    let result = runner.join().unwrap();
+    assert!(
+        matches!(result, Err(Error::Usb(rusb::Error::NoDevice))),
+        "{:?}",
+        result
+    );
+    assert_eq!(polls.load(Ordering::SeqCst), 9);
+}
@@ -0,0 +1,176 @@
+use num_traits::FromPrimitive;
+
+use ptp::{
+    AssociationCode, CommandCode, Error, EventCode, ObjectFormatCode, ResponseCode,
+    StandardAssociationCode, StandardCommandCode, StandardEventCode, StandardObjectFormatCode,
+    StandardResponseCode, CANON, MTP, NIKON,
+};
+
+#[test]
+fn display_uses_names_or_hex() {
+    let code = CommandCode::Standard(StandardCommandCode::GetObject);
+    assert_eq!(code.to_string(), "GetObject");
+    assert_eq!(code.description(), "download an object");
+    assert_eq!(CommandCode::Other(0x9101).to_string(), "0x9101");
+
+    assert_eq!(
+        ResponseCode::Standard(StandardResponseCode::DeviceBusy).to_string(),
+        "DeviceBusy"
+    );
+    assert_eq!(EventCode::Vendor(0xC101).to_string(), "0xC101");
+    assert_eq!(ObjectFormatCode::ImageOnly.to_string(), "ImageOnly");
+    assert_eq!(
+        AssociationCode::Standard(StandardAssociationCode::GenericFolder).to_string(),
+        "GenericFolder"
+    );
+    assert_eq!(AssociationCode::Vendor(0x8001).to_string(), "0x8001");
+}
+
+#[test]
+fn vendor_names() {
+    let get_prop_list = CommandCode::Other(0x9805);
+    assert_eq!(get_prop_list.name(), None);
+    assert_eq!(get_prop_list.name_for(None), None);
+    assert_eq!(
+        get_prop_list.name_for(Some(&MTP)),
+        Some("GetObjectPropList")
+    );
+
+    let not_ready = ResponseCode::Other(0xA102);
+    assert_eq!(not_ready.name_for(Some(&CANON)), Some("NotReady"));
+    assert_eq!(not_ready.name_for(Some(&NIKON)), None);
+    assert_eq!(
+        ResponseCode::Other(0xA00B).name_for(Some(&NIKON)),
+        Some("NotLiveView")
+    );
+
+    assert_eq!(
+        EventCode::Vendor(0xC801).name_for(Some(&MTP)),
+        Some("ObjectPropChanged")
+    );
+    assert_eq!(
+        ObjectFormatCode::Vendor(0xB001).name_for(Some(&NIKON)),
+        Some("NEF")
+    );
+    assert_eq!(
+        ObjectFormatCode::Standard(StandardObjectFormatCode::Png).name_for(Some(&NIKON)),
+        Some("Png")
+    );
+}
+
+#[test]
+fn vendor_descriptions() {
+    let get_prop_list = CommandCode::Other(0x9805);
+    assert_eq!(get_prop_list.description(), "vendor-defined operation");
+    assert_eq!(
+        get_prop_list.description_for(Some(&MTP)),
+        "Microsoft MTP operation"
+    );
+    assert_eq!(
+        get_prop_list.description_for(Some(&NIKON)),
+        "vendor-defined operation"
+    );
+    assert_eq!(
+        CommandCode::Standard(StandardCommandCode::GetObject).description_for(Some(&MTP)),
+        "download an object"
+    );
+
+    assert_eq!(
+        ResponseCode::Other(0xA102).description_for(Some(&CANON)),
+        "Canon response"
+    );
+    assert_eq!(
+        EventCode::Vendor(0xC801).description_for(None),
+        "vendor-defined event"
+    );
+    assert_eq!(
+        EventCode::Vendor(0xC801).description_for(Some(&MTP)),
+        "Microsoft MTP event"
+    );
+    assert_eq!(
+        ObjectFormatCode::Vendor(0xB001).description_for(Some(&NIKON)),
+        "Nikon format"
+    );
+}
+
+#[test]
+fn parse_names_and_numbers() {
+    assert_eq!(
+        "GetObject".parse::<CommandCode>().unwrap(),
+        CommandCode::Standard(StandardCommandCode::GetObject)
+    );
+    assert_eq!(
+        "getobject".parse::<CommandCode>().unwrap(),
+        CommandCode::Standard(StandardCommandCode::GetObject)
+    );
+    assert_eq!(
+        "0x1009".parse::<CommandCode>().unwrap(),
+        CommandCode::Standard(StandardCommandCode::GetObject)
+    );
+    assert_eq!(
+        "0x9153".parse::<CommandCode>().unwrap(),
+        CommandCode::Other(0x9153)
+    );
+    assert_eq!(
+        CommandCode::parse_for("GetObjectPropList", Some(&MTP)).unwrap(),
+        CommandCode::Other(0x9805)
+    );
+    assert_eq!(
+        ResponseCode::parse_for("NotReady", Some(&CANON)).unwrap(),
+        ResponseCode::Other(0xA102)
+    );
+    assert_eq!(
+        "ObjectAdded".parse::<EventCode>().unwrap(),
+        EventCode::Standard(StandardEventCode::ObjectAdded)
+    );
+    assert_eq!(
+        " ExifJpeg ".parse::<ObjectFormatCode>().unwrap(),
+        ObjectFormatCode::Standard(StandardObjectFormatCode::ExifJpeg)
+    );
+    assert_eq!(
+        "imageonly".parse::<ObjectFormatCode>().unwrap(),
+        ObjectFormatCode::ImageOnly
+    );
+    assert_eq!(
+        ObjectFormatCode::parse_for("NEF", Some(&NIKON)).unwrap(),
+        ObjectFormatCode::Vendor(0xB001)
+    );
+    assert_eq!(
+        "Album".parse::<AssociationCode>().unwrap(),
+        AssociationCode::Standard(StandardAssociationCode::Album)
+    );
+
+    for bad in ["GetObjectPropList", "0x", "0x10000", "NoSuchCode", ""] {
+        match bad.parse::<CommandCode>() {
+            Err(Error::UnknownCode(s)) => assert_eq!(s, bad),
+            other => panic!("parsing {:?} gave {:?}", bad, other),
+        }
+    }
+    // not in the event code ranges
+    assert!("0x1001".parse::<EventCode>().is_err());
+}
+
+#[test]
+fn display_round_trips_through_from_str() {
+    for n in 0x1000..=0x1025 {
+        let code = CommandCode::from_u16(n).unwrap();
+        assert_eq!(code.to_string().parse::<CommandCode>().unwrap(), code);
+    }
+    for n in 0x2000..=0x2020 {
+        let code = ResponseCode::from_u16(n).unwrap();
+        assert_eq!(code.to_string().parse::<ResponseCode>().unwrap(), code);
+    }
+    for n in 0x4000..=0x400C {
+        let code = EventCode::from_u16(n).unwrap();
+        assert_eq!(code.to_string().parse::<EventCode>().unwrap(), code);
+    }
+}
+
+#[test]
+fn response_errors_are_readable() {
+    let err = Error::Response(ResponseCode::Standard(StandardResponseCode::DeviceBusy));
+    assert_eq!(
+        err.to_string(),
+        "the ptp device returned an error code: DeviceBusy (the device is busy)"
+    );
+}
@@ -0,0 +1,250 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use ptp::{
+    decode_canon_events, decode_nikon_events, Device, DeviceInfo, Error, EventCode, EventPolling,
+    SessionConfig, StandardCommandCode, StandardEventCode, VendorExtensionId, CANON_EOS_GET_EVENT,
+    MTP_GET_OBJECT_PROP_LIST, MTP_OBJECT_PROP_CHANGED, NIKON_GET_EVENT,
+};
+
+use common::{FakeCamera, Reply};
+
+const GET_DEVICE_INFO: u16 = StandardCommandCode::GetDeviceInfo as u16;
+const GET_OBJECT_HANDLES: u16 = StandardCommandCode::GetObjectHandles as u16;
+const OBJECT_FILE_NAME: u16 = 0xdc07;
+const PROTECTION_STATUS: u16 = 0xdc03;
+
+fn device_info(vendor: VendorExtensionId, operations: &[u16]) -> DeviceInfo {
+    DeviceInfo {
+        version: 100,
+        vendor_ex_id: vendor as u32,
+        vendor_ex_version: 100,
+        vendor_extension_desc: String::new(),
+        functional_mode: 0,
+        operations_supported: operations.to_vec(),
+        events_supported: vec![],
+        device_properties_supported: vec![],
+        capture_formats: vec![],
+        image_formats: vec![],
+        manufacturer: "Acme".into(),
+        model: "Bulk Only".into(),
+        device_version: "1.0".into(),
+        serial_number: "1".into(),
+    }
+}
+
+fn encoded(info: &DeviceInfo) -> Vec<u8> {
+    let mut buf = vec![];
+    info.encode(&mut buf).unwrap();
+    buf
+}
+
+fn u32s(values: &[u32]) -> Vec<u8> {
+    let mut buf = (values.len() as u32).to_le_bytes().to_vec();
+    buf.extend(values.iter().flat_map(|v| v.to_le_bytes()));
+    buf
+}
+
+fn code(event: StandardEventCode) -> EventCode {
+    EventCode::Standard(event)
+}
+
+#[test]
+fn canon_events() {
+    let mut buf = vec![];
+    // ObjectAddedEx with two parameters
+    buf.extend([16, 0, 0, 0, 0x81, 0xc1, 0, 0, 1, 0, 0, 0x90, 2, 0, 0, 0]);
+    // a record whose code isn't an event code
+    buf.extend([12, 0, 0, 0, 0, 0, 1, 0, 0xff, 0xff, 0xff, 0xff]);
+    // CaptureComplete, standard
+    buf.extend([8, 0, 0, 0, 0x0b, 0x40, 0, 0]);
+    // the end, and padding after it
+    buf.extend([8, 0, 0, 0, 0, 0, 0, 0, 0xaa, 0xbb]);
+
+    let events = decode_canon_events(&buf).unwrap();
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0].code, EventCode::Vendor(0xc181));
+    assert_eq!(events[0].params, [0x9000_0001, 2]);
+    assert_eq!(events[1].code, code(StandardEventCode::CaptureComplete));
+    assert!(events[1].params.is_empty());
+
+    // no records at all
+    assert!(decode_canon_events(&[]).unwrap().is_empty());
+    // a record running past the end, and one shorter than its header
+    assert!(decode_canon_events(&buf[..12]).is_err());
+    assert!(decode_canon_events(&[4, 0, 0, 0, 0, 0, 0, 0]).is_err());
+}
+
+#[test]
+fn nikon_events() {
+    let buf = [
+        2, 0, // two events
+        0x02, 0x40, 0x2a, 0, 0, 0, // ObjectAdded 42
+        0x06, 0x40, 0x01, 0, 0, 0, // DevicePropChanged 1
+    ];
+    let events = decode_nikon_events(&buf).unwrap();
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0].code, code(StandardEventCode::ObjectAdded));
+    assert_eq!(events[0].params, [42]);
+    assert_eq!(events[1].code, code(StandardEventCode::DevicePropChanged));
+
+    assert!(decode_nikon_events(&buf[..8]).is_err());
+    let mut extra = buf.to_vec();
+    extra.push(0);
+    assert!(decode_nikon_events(&extra).is_err());
+}
+
+#[test]
+fn strategies_follow_the_vendor() {
+    use VendorExtensionId::{Canon, Microsoft, Nikon};
+
+    let detect =
+        |vendor, operations: &[u16]| EventPolling::detect(&device_info(vendor, operations));
+    assert_eq!(
+        detect(Canon, &[CANON_EOS_GET_EVENT]),
+        EventPolling::CanonGetEvent
+    );
+    assert_eq!(
+        detect(Nikon, &[NIKON_GET_EVENT]),
+        EventPolling::NikonGetEvent
+    );
+    assert_eq!(
+        detect(Microsoft, &[MTP_GET_OBJECT_PROP_LIST]),
+        EventPolling::ObjectPropList
+    );
+    assert_eq!(detect(Microsoft, &[]), EventPolling::ObjectHandles);
+
+    // the vendor's GetEvent is only used on that vendor's devices
+    assert_eq!(
+        detect(Nikon, &[CANON_EOS_GET_EVENT]),
+        EventPolling::ObjectHandles
+    );
+}
+
+#[test]
+fn object_handles_are_compared() {
+    let handles = Arc::new(Mutex::new(vec![1, 2]));
+    let info = encoded(&device_info(VendorExtensionId::Kodak, &[]));
+    let fake = FakeCamera::new({
+        let handles = handles.clone();
+        move |request| match request.code {
+            GET_DEVICE_INFO => Reply::data(info.clone()),
+            GET_OBJECT_HANDLES => Reply::data(u32s(&handles.lock().unwrap())),
+            _ => Reply::ok(),
+        }
+    })
+    .without_interrupt();
+    let mut device = Device::with_transport(fake);
+    device.set_event_poll_interval(Duration::from_millis(1));
+
+    // without a session there is nothing to poll
+    assert!(device
+        .event(Some(Duration::from_millis(10)))
+        .unwrap()
+        .is_none());
+    assert!(device.transport().log().is_empty());
+
+    let _session = device.open_session(SessionConfig::default()).unwrap();
+    let timeout = Some(Duration::from_millis(20));
+    assert!(device.event(timeout).unwrap().is_none());
+
+    *handles.lock().unwrap() = vec![2, 3];
+    let added = device.event(timeout).unwrap().unwrap();
+    assert_eq!(added.code, code(StandardEventCode::ObjectAdded));
+    assert_eq!(added.params, [3]);
+    let removed = device.event(timeout).unwrap().unwrap();
+    assert_eq!(removed.code, code(StandardEventCode::ObjectRemoved));
+    assert_eq!(removed.params, [1]);
+    assert!(device.event(timeout).unwrap().is_none());
+}
+
+// An ObjectPropList of (handle, property, UINT16 value) elements
+fn prop_list(entries: &[(u32, u16, u16)]) -> Vec<u8> {
+    let mut buf = (entries.len() as u32).to_le_bytes().to_vec();
+    for &(handle, prop, value) in entries {
+        buf.extend(handle.to_le_bytes());
+        buf.extend(prop.to_le_bytes());
+        buf.extend(4u16.to_le_bytes());
+        buf.extend(value.to_le_bytes());
+    }
+    buf
+}
+
+#[test]
+fn object_properties_are_compared() {
+    let props = Arc::new(Mutex::new(vec![
+        (1, OBJECT_FILE_NAME, 0),
+        (1, PROTECTION_STATUS, 0),
+    ]));
+    let info = encoded(&device_info(
+        VendorExtensionId::Microsoft,
+        &[MTP_GET_OBJECT_PROP_LIST],
+    ));
+    let fake = FakeCamera::new({
+        let props = props.clone();
+        move |request| match request.code {
+            GET_DEVICE_INFO => Reply::data(info.clone()),
+            MTP_GET_OBJECT_PROP_LIST => Reply::data(prop_list(&props.lock().unwrap())),
+            _ => Reply::ok(),
+        }
+    })
+    .without_interrupt();
+    let mut device = Device::with_transport(fake);
+    device.set_event_poll_interval(Duration::from_millis(1));
+    let _session = device.open_session(SessionConfig::default()).unwrap();
+
+    let timeout = Some(Duration::from_millis(20));
+    assert!(device.event(timeout).unwrap().is_none());
+
+    *props.lock().unwrap() = vec![
+        (1, OBJECT_FILE_NAME, 0),
+        (1, PROTECTION_STATUS, 1),
+        (2, OBJECT_FILE_NAME, 0),
+    ];
+    let events: Vec<_> = (0..2)
+        .map(|_| device.event(timeout).unwrap().unwrap())
+        .collect();
+    assert_eq!(events[0].code, code(StandardEventCode::ObjectAdded));
+    assert_eq!(events[0].params, [2]);
+    assert_eq!(events[1].code, EventCode::Vendor(MTP_OBJECT_PROP_CHANGED));
+    assert_eq!(events[1].params, [1, PROTECTION_STATUS as u32]);
+}
+
+#[test]
+fn vendor_get_event() {
+    let fake = FakeCamera::new(|request| match request.code {
+        NIKON_GET_EVENT => Reply::data(vec![1, 0, 0x0b, 0x40, 0, 0, 0, 0]),
+        _ => Reply::ok(),
+    })
+    .without_interrupt();
+    let mut device = Device::with_transport(fake);
+    device.set_event_polling(EventPolling::NikonGetEvent);
+    let _session = device.open_session(SessionConfig::default()).unwrap();
+
+    let event = device.event(None).unwrap().unwrap();
+    assert_eq!(event.code, code(StandardEventCode::CaptureComplete));
+
+    // the strategy was given, so DeviceInfo wasn't needed
+    let codes: Vec<u16> = device.transport().log().iter().map(|r| r.code).collect();
+    assert_eq!(
+        codes,
+        [StandardCommandCode::OpenSession as u16, NIKON_GET_EVENT]
+    );
+}
+
+#[test]
+fn polling_errors_are_returned() {
+    let fake = FakeCamera::new(|request| match request.code {
+        CANON_EOS_GET_EVENT => Reply::error(0x2005),
+        _ => Reply::ok(),
+    })
+    .without_interrupt();
+    let mut device = Device::with_transport(fake);
+    device.set_event_polling(EventPolling::CanonGetEvent);
+    let _session = device.open_session(SessionConfig::default()).unwrap();
+
+    let err = device.event(None).unwrap_err();
+    assert!(matches!(err.root(), Error::Response(_)));
+}
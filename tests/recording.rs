@@ -0,0 +1,202 @@
+mod common;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use ptp::{
+    CommandCode, ContainerType, Device, Endpoint, Error, EventCode, RecordEntry, Recording,
+    RecordingTransport, ReplayTransport, SessionConfig, StandardCommandCode, Transport,
+    RECORDING_VERSION,
+};
+
+use common::{FakeCamera, Reply, Request, PACKET_SIZE};
+
+const TIMEOUT: Option<Duration> = Some(Duration::from_millis(100));
+const GET_STORAGE_IDS: u16 = StandardCommandCode::GetStorageIDs as u16;
+const STORAGE_IDS: [u8; 8] = [1, 0, 0, 0, 1, 0, 1, 0];
+// sends data, or asks for as much data as its parameter says
+const SEND: u16 = 0x9001;
+const RECEIVE: u16 = 0x9002;
+const PROPERTY_CHANGES: u16 = 0xc189;
+
+fn payload(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i % 251) as u8).collect()
+}
+
+// Stalls the first GetStorageIDs and babbles on the second
+fn camera() -> FakeCamera {
+    let calls = Arc::new(AtomicUsize::new(0));
+    FakeCamera::new(move |request: &Request| match request.code {
+        GET_STORAGE_IDS => match calls.fetch_add(1, Ordering::AcqRel) {
+            0 => Reply::stall(),
+            1 => Reply::babble(vec![0; 20000]),
+            _ => Reply::data(STORAGE_IDS.to_vec()),
+        },
+        RECEIVE => Reply::data(payload(request.params[0] as usize)),
+        _ => Reply::ok(),
+    })
+}
+
+// A session with a bit of everything, returning what the host saw
+fn session<T: Transport>(device: &Device<T>) -> Result<Vec<String>, Error> {
+    let mut seen = vec![];
+    let session = device.open_session(SessionConfig::default())?;
+    for _ in 0..3 {
+        seen.push(format!("{:?}", session.get_storage_ids(TIMEOUT)));
+    }
+    for &len in &[0, 100, PACKET_SIZE * 4 - 12, 100_000] {
+        let data = session.command(CommandCode::Other(RECEIVE), &[len as u32], None, TIMEOUT)?;
+        seen.push(format!("{} {}", data.len(), data == payload(len)));
+    }
+    session.command(CommandCode::Other(SEND), &[], Some(&payload(5000)), TIMEOUT)?;
+    seen.push(format!("{:?}", device.event(TIMEOUT)?));
+    Ok(seen)
+}
+
+fn record() -> (Recording, Vec<String>) {
+    let fake = camera();
+    fake.push_event(PROPERTY_CHANGES, &(1..=40).collect::<Vec<_>>());
+    let device = Device::with_transport(RecordingTransport::new(fake));
+    let seen = session(&device).unwrap();
+    assert!(device.transport().inner().violations().is_empty());
+    (device.transport().recording(), seen)
+}
+
+fn containers(recording: &Recording) -> Vec<(Endpoint, ContainerType, u16, u32)> {
+    recording
+        .entries
+        .iter()
+        .filter_map(|entry| match entry {
+            RecordEntry::Container(c) => Some((c.endpoint, c.kind, c.code, c.tid)),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn containers_are_recorded_whole() {
+    let (recording, seen) = record();
+    assert!(seen[0].contains("Pipe"), "{:?}", seen);
+    assert!(seen[1].contains("Overflow"), "{:?}", seen);
+    assert!(seen[2].starts_with("Ok"), "{:?}", seen);
+
+    let containers = containers(&recording);
+    let open = StandardCommandCode::OpenSession as u16;
+    assert_eq!(
+        containers[..2],
+        [
+            (Endpoint::BulkOut, ContainerType::Command, open, 0),
+            (Endpoint::BulkIn, ContainerType::Response, 0x2001, 0),
+        ]
+    );
+    assert!(containers.contains(&(
+        Endpoint::Interrupt,
+        ContainerType::Event,
+        PROPERTY_CHANGES,
+        0
+    )));
+
+    // the large download is one container, however many transfers it took
+    let download = recording.entries.iter().find_map(|entry| match entry {
+        RecordEntry::Container(c) if c.payload.len() == 100_000 => Some(c),
+        _ => None,
+    });
+    assert!(download.unwrap().is_complete());
+    assert_eq!(download.unwrap().payload, payload(100_000));
+
+    // the stall, and the babble followed by what was drained of it
+    let faults: Vec<_> = recording
+        .entries
+        .iter()
+        .filter_map(|entry| match entry {
+            RecordEntry::Fault { error, .. } => Some(*error),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(faults[..2], [rusb::Error::Pipe, rusb::Error::Timeout]);
+    assert_eq!(faults[2], rusb::Error::Overflow);
+    let drained = recording.entries.iter().find_map(|entry| match entry {
+        RecordEntry::Fragment { bytes, .. } => Some(bytes),
+        _ => None,
+    });
+    assert!(drained.unwrap().iter().all(|&b| b == 0));
+
+    let elapsed: Vec<_> = recording.entries.iter().map(|e| e.elapsed()).collect();
+    assert!(elapsed.windows(2).all(|w| w[0] <= w[1]));
+}
+
+#[test]
+fn recordings_replay_from_a_file() {
+    let (recording, seen) = record();
+    let path = std::env::temp_dir().join(format!("ptp-recording-{}.ptprec", std::process::id()));
+    recording.save(&path).unwrap();
+    let loaded = Recording::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(loaded, recording);
+
+    let device = Device::with_transport(ReplayTransport::new(loaded));
+    assert_eq!(session(&device).unwrap(), seen);
+    assert_eq!(device.transport().remaining(), 0);
+}
+
+#[test]
+fn diverging_requests_are_reported() {
+    let (recording, _) = record();
+    let device = Device::with_transport(ReplayTransport::new(recording));
+    let session = device.open_session(SessionConfig::default()).unwrap();
+
+    // the recording asked for the storage IDs here
+    let err = session
+        .command(CommandCode::Other(RECEIVE), &[4], None, TIMEOUT)
+        .unwrap_err();
+    match err.root() {
+        Error::ReplayMismatch {
+            index,
+            expected,
+            actual,
+        } => {
+            assert_eq!(*index, 2);
+            assert!(expected.contains("0x1004"), "{}", expected);
+            assert!(actual.contains("0x9002"), "{}", actual);
+        }
+        err => panic!("unexpected error {:?}", err),
+    }
+}
+
+#[test]
+fn file_format_is_versioned() {
+    let (recording, _) = record();
+    let mut file = vec![];
+    recording.write_to(&mut file).unwrap();
+    assert_eq!(&file[..6], b"PTPREC");
+    assert_eq!(file[6..8], RECORDING_VERSION.to_le_bytes());
+    assert_eq!(Recording::read_from(&file[..]).unwrap(), recording);
+
+    let mut newer = file.clone();
+    newer[6..8].copy_from_slice(&(RECORDING_VERSION + 1).to_le_bytes());
+    let err = Recording::read_from(&newer[..]).unwrap_err();
+    assert!(matches!(err, Error::Malformed(_)), "{:?}", err);
+
+    assert!(Recording::read_from(&b"PTPMTP\x01\x00"[..]).is_err());
+    // an entry cut short
+    assert!(Recording::read_from(&file[..file.len() - 1]).is_err());
+}
+
+#[test]
+fn replayed_events() {
+    let (recording, _) = record();
+    let events = recording
+        .entries
+        .iter()
+        .filter(|entry| entry.endpoint() == Endpoint::Interrupt)
+        .count();
+    assert_eq!(events, 1);
+
+    // events come from their own pipe, whenever the host asks for them
+    let device = Device::with_transport(ReplayTransport::new(recording));
+    let event = device.event(TIMEOUT).unwrap().unwrap();
+    assert_eq!(event.code, EventCode::Vendor(PROPERTY_CHANGES));
+    assert_eq!(event.params, (1..=40).collect::<Vec<_>>());
+    assert!(device.event(TIMEOUT).unwrap().is_none());
+}
@@ -0,0 +1,143 @@
+mod common;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use ptp::{
+    Device, DeviceStatus, Endpoint, Error, RecoveryPolicy, SessionConfig, StandardCommandCode,
+    StandardResponseCode,
+};
+
+use common::{FakeCamera, Reply, DEVICE_RESET, GET_DEVICE_STATUS};
+
+const OPEN_SESSION: u16 = StandardCommandCode::OpenSession as u16;
+const CLOSE_SESSION: u16 = StandardCommandCode::CloseSession as u16;
+const GET_STORAGE_IDS: u16 = StandardCommandCode::GetStorageIDs as u16;
+const STORAGE_IDS: [u8; 8] = [1, 0, 0, 0, 1, 0, 1, 0];
+
+// Answers GetStorageIDs with `first` the first time, and properly after
+fn camera(first: Reply) -> FakeCamera {
+    let calls = Arc::new(AtomicUsize::new(0));
+    FakeCamera::new(move |request| match request.code {
+        GET_STORAGE_IDS if calls.fetch_add(1, Ordering::AcqRel) == 0 => first.clone(),
+        GET_STORAGE_IDS => Reply::data(STORAGE_IDS.to_vec()),
+        _ => Reply::ok(),
+    })
+}
+
+// without a session
+fn storage_ids(device: &Device<FakeCamera>) -> Result<Vec<u8>, Error> {
+    device.command(StandardCommandCode::GetStorageIDs.into(), &[], None, None)
+}
+
+fn calls(device: &Device<FakeCamera>) -> Vec<(u16, u32)> {
+    device
+        .transport()
+        .log()
+        .iter()
+        .map(|r| (r.code, r.tid))
+        .collect()
+}
+
+#[test]
+fn stalls_are_recovered_within_the_command() {
+    let device = Device::with_transport(camera(Reply::stall()));
+    let session = device.open_session(SessionConfig::default()).unwrap();
+
+    let err = session.get_storage_ids(None).unwrap_err();
+    assert!(err.is_stall());
+    assert!(matches!(err.root(), Error::Usb(rusb::Error::Pipe)));
+
+    // the stale response was discarded, so the next call gets its own
+    assert_eq!(session.get_storage_ids(None).unwrap().len(), 1);
+    drop(session);
+    assert_eq!(
+        calls(&device),
+        [
+            (OPEN_SESSION, 0),
+            (GET_STORAGE_IDS, 1),
+            (GET_STORAGE_IDS, 2),
+            (CLOSE_SESSION, 3)
+        ]
+    );
+
+    let fake = device.transport();
+    assert_eq!(fake.cleared_halts(), [Endpoint::BulkIn, Endpoint::BulkOut]);
+    assert_eq!(fake.class_requests(), [GET_DEVICE_STATUS]);
+    assert!(fake.violations().is_empty());
+}
+
+#[test]
+fn babble_discards_the_rest_of_the_container() {
+    let device = Device::with_transport(camera(Reply::babble(vec![0; 20000])));
+
+    let err = storage_ids(&device).unwrap_err();
+    assert!(matches!(err.root(), Error::Usb(rusb::Error::Overflow)));
+    assert_eq!(storage_ids(&device).unwrap(), STORAGE_IDS);
+    assert!(device.transport().violations().is_empty());
+}
+
+#[test]
+fn manual_recovery() {
+    let mut device = Device::with_transport(camera(Reply::stall()));
+    device.set_recovery_policy(RecoveryPolicy::manual());
+
+    assert!(storage_ids(&device).unwrap_err().is_stall());
+    // still halted
+    assert!(storage_ids(&device).unwrap_err().is_stall());
+    assert!(device.transport().cleared_halts().is_empty());
+
+    // the device takes a while to get ready
+    device.transport().set_busy_status(3);
+    device.recover().unwrap();
+    assert_eq!(device.transport().class_requests(), [GET_DEVICE_STATUS; 4]);
+    assert_eq!(storage_ids(&device).unwrap(), STORAGE_IDS);
+}
+
+#[test]
+fn device_reset_closes_the_session() {
+    let mut device = Device::with_transport(camera(Reply::stall()));
+    device.set_recovery_policy(RecoveryPolicy {
+        device_reset: true,
+        ..RecoveryPolicy::default()
+    });
+
+    let session = device.open_session(SessionConfig::default()).unwrap();
+    assert!(session.get_storage_ids(None).unwrap_err().is_stall());
+    assert_eq!(
+        device.transport().class_requests(),
+        [DEVICE_RESET, GET_DEVICE_STATUS]
+    );
+
+    // the handle knows its session is gone, and a new one can be opened
+    drop(session);
+    let session = device.open_session(SessionConfig::default()).unwrap();
+    assert_eq!(session.get_storage_ids(None).unwrap().len(), 1);
+    drop(session);
+    assert_eq!(
+        calls(&device),
+        [
+            (OPEN_SESSION, 0),
+            (GET_STORAGE_IDS, 1),
+            (OPEN_SESSION, 0),
+            (GET_STORAGE_IDS, 1),
+            (CLOSE_SESSION, 2)
+        ]
+    );
+}
+
+#[test]
+fn device_status_decoding() {
+    let status = DeviceStatus::decode(&[8, 0, 0x19, 0x20, 0x81, 0, 0, 0]).unwrap();
+    assert_eq!(status.code, StandardResponseCode::DeviceBusy.into());
+    assert!(status.is_busy());
+    assert_eq!(status.params, [0x81]);
+
+    let status = DeviceStatus::decode(&[4, 0, 0x01, 0x20, 0xff]).unwrap();
+    assert!(status.is_ok());
+    assert!(status.params.is_empty());
+
+    // longer than what arrived, and a parameter cut short
+    assert!(DeviceStatus::decode(&[8, 0, 0x01, 0x20]).is_err());
+    assert!(DeviceStatus::decode(&[6, 0, 0x01, 0x20, 0, 0]).is_err());
+}
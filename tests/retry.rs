@@ -0,0 +1,149 @@
+mod common;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use ptp::{
+    is_idempotent, CommandCode, Device, Error, ResponseCode, RetryPolicy, SessionConfig,
+    StandardCommandCode, StandardResponseCode,
+};
+
+use common::{FakeCamera, Reply, CANCEL_REQUEST};
+
+const GET_STORAGE_IDS: u16 = StandardCommandCode::GetStorageIDs as u16;
+const SET_DEVICE_PROP_VALUE: u16 = StandardCommandCode::SetDevicePropValue as u16;
+const STORAGE_IDS: [u8; 8] = [1, 0, 0, 0, 1, 0, 1, 0];
+
+#[test]
+fn backoff_grows_up_to_the_maximum() {
+    let policy = RetryPolicy {
+        initial_backoff: Duration::from_millis(100),
+        backoff_multiplier: 2.0,
+        max_backoff: Duration::from_millis(500),
+        ..RetryPolicy::default()
+    };
+
+    let delays: Vec<_> = (1..=5).map(|retry| policy.backoff(retry)).collect();
+    assert_eq!(
+        delays,
+        [100, 200, 400, 500, 500]
+            .map(Duration::from_millis)
+            .to_vec()
+    );
+    assert_eq!(policy.backoff(u32::MAX), Duration::from_millis(500));
+}
+
+#[test]
+fn transient_errors() {
+    let policy = RetryPolicy::default();
+    let busy = Error::Response(StandardResponseCode::DeviceBusy.into());
+    assert!(policy.is_transient(&busy));
+    assert!(policy.is_transient(&Error::Usb(rusb::Error::Pipe)));
+    assert!(!policy.is_transient(&Error::Usb(rusb::Error::NoDevice)));
+    assert!(!policy.is_transient(&Error::Response(
+        StandardResponseCode::InvalidObjectHandle.into()
+    )));
+
+    let canon_busy = Error::Response(ResponseCode::Other(0xA102));
+    assert!(!policy.is_transient(&canon_busy));
+    let policy = RetryPolicy {
+        transient_responses: vec![ResponseCode::Other(0xA102)],
+        ..RetryPolicy::default()
+    };
+    assert!(policy.is_transient(&canon_busy));
+    assert!(!policy.is_transient(&busy));
+}
+
+#[test]
+fn non_idempotent_operations_need_opting_in() {
+    let get_object: CommandCode = StandardCommandCode::GetObject.into();
+    let send_object: CommandCode = StandardCommandCode::SendObject.into();
+    let delete_object: CommandCode = StandardCommandCode::DeleteObject.into();
+
+    assert!(is_idempotent(get_object));
+    assert!(!is_idempotent(send_object));
+    assert!(!is_idempotent(delete_object));
+    assert!(!is_idempotent(StandardCommandCode::InitiateCapture.into()));
+    assert!(is_idempotent(CommandCode::Other(0x9805)));
+    assert!(!is_idempotent(CommandCode::Other(0x9153)));
+
+    let policy = RetryPolicy::default();
+    assert!(policy.allows(get_object));
+    assert!(!policy.allows(send_object));
+    assert!(!policy.allows(delete_object));
+
+    let policy = RetryPolicy {
+        retry_non_idempotent: true,
+        ..RetryPolicy::default()
+    };
+    assert!(policy.allows(send_object));
+
+    assert!(!RetryPolicy::none().allows(get_object));
+}
+
+#[test]
+fn late_answers_are_discarded_before_retrying() {
+    // the first GetStorageIDs is answered after the host's read timed out
+    let calls = AtomicUsize::new(0);
+    let fake = FakeCamera::new(move |request| match request.code {
+        GET_STORAGE_IDS if calls.fetch_add(1, Ordering::AcqRel) == 0 => {
+            Reply::late(Reply::data(STORAGE_IDS.to_vec()))
+        }
+        GET_STORAGE_IDS => Reply::data(STORAGE_IDS.to_vec()),
+        _ => Reply::ok(),
+    });
+    let mut device = Device::with_transport(fake);
+    device.set_retry_policy(RetryPolicy {
+        initial_backoff: Duration::from_millis(1),
+        ..RetryPolicy::default()
+    });
+
+    let session = device.open_session(SessionConfig::default()).unwrap();
+    let ids = session.get_storage_ids(None).unwrap();
+    assert_eq!(ids.len(), 1);
+    // and the transaction after it is in step too
+    assert_eq!(session.get_storage_ids(None).unwrap().len(), 1);
+    drop(session);
+
+    let fake = device.transport();
+    assert!(fake.violations().is_empty(), "{:?}", fake.violations());
+    let calls: Vec<_> = fake.log().iter().map(|r| (r.code, r.tid)).collect();
+    assert_eq!(
+        calls[1..4],
+        [
+            (GET_STORAGE_IDS, 1),
+            (GET_STORAGE_IDS, 2),
+            (GET_STORAGE_IDS, 3)
+        ]
+    );
+}
+
+#[test]
+fn data_out_timeouts_are_cancelled_before_retrying() {
+    let fake = FakeCamera::new(|_| Reply::ok());
+    // writes 1 and 2 are the commands of OpenSession and SetDevicePropValue
+    fake.time_out_write(3);
+    let mut device = Device::with_transport(fake);
+    device.set_retry_policy(RetryPolicy {
+        initial_backoff: Duration::from_millis(1),
+        ..RetryPolicy::default()
+    });
+
+    let session = device.open_session(SessionConfig::default()).unwrap();
+    let value = vec![0xab; 1000];
+    let code = CommandCode::Standard(StandardCommandCode::SetDevicePropValue);
+    session
+        .command(code, &[0x5001], Some(&value), None)
+        .unwrap();
+    drop(session);
+
+    let fake = device.transport();
+    assert!(fake.violations().is_empty(), "{:?}", fake.violations());
+    assert!(fake.class_requests().contains(&CANCEL_REQUEST));
+    let calls: Vec<_> = fake
+        .log()
+        .iter()
+        .map(|r| (r.code, r.tid, r.data.as_ref().map(Vec::len)))
+        .collect();
+    assert_eq!(calls[1], (SET_DEVICE_PROP_VALUE, 2, Some(1000)));
+}
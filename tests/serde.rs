@@ -0,0 +1,396 @@
+#![cfg(feature = "serde")]
+
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use ptp::{
+    AccessType, AssociationCode, CommandCode, ConnectionEvent, ContainerInfo, ContainerType, Data,
+    DataType, DeviceInfo, Endpoint, Event, EventCode, FilesystemType, FocusArea, FormData,
+    Histogram, LiveViewFrame, ObjectFilesystemInfo, ObjectFormatCode, ObjectHandle, ObjectInfo,
+    ObjectPropListEntry, ObjectTree, PoolStats, PropInfo, RecordEntry, RecordedContainer,
+    Recording, RecoveryPolicy, ResponseCode, RetryPolicy, SessionConfig, StandardAccessType,
+    StandardAssociationCode, StandardCommandCode, StandardEventCode, StandardFilesystemType,
+    StandardObjectFormatCode, StandardResponseCode, StandardStorageType, StorageId, StorageInfo,
+    StorageType, StreamInfo, SupervisorConfig, TransactionContext, TransactionPhase,
+    VendorExtension, VendorExtensionId, VendorExtensionMap,
+};
+
+// Serializes `value`, reads it back and checks the result serializes to the
+// same JSON. Returns that JSON.
+fn round_trip<T: Serialize + DeserializeOwned>(value: &T) -> Value {
+    let json = serde_json::to_value(value).unwrap();
+    let back: T = serde_json::from_value(json.clone()).unwrap();
+    assert_eq!(serde_json::to_value(&back).unwrap(), json);
+    json
+}
+
+fn round_trip_eq<T: Serialize + DeserializeOwned + PartialEq + std::fmt::Debug>(value: T) -> Value {
+    let json = serde_json::to_value(&value).unwrap();
+    let back: T = serde_json::from_value(json.clone()).unwrap();
+    assert_eq!(back, value);
+    json
+}
+
+#[test]
+fn code_enums() {
+    assert_eq!(
+        round_trip_eq(EventCode::Standard(StandardEventCode::ObjectAdded)),
+        json!({"code": "0x4002", "name": "ObjectAdded"})
+    );
+    assert_eq!(
+        round_trip_eq(EventCode::Vendor(0xC101)),
+        json!({"code": "0xC101"})
+    );
+    assert_eq!(
+        round_trip_eq(EventCode::Reserved(0x4FFF)),
+        json!({"code": "0x4FFF"})
+    );
+    assert_eq!(
+        round_trip_eq(CommandCode::Standard(StandardCommandCode::GetObject)),
+        json!({"code": "0x1009", "name": "GetObject"})
+    );
+    assert_eq!(
+        round_trip_eq(CommandCode::Other(0x9153)),
+        json!({"code": "0x9153"})
+    );
+    assert_eq!(
+        round_trip_eq(ResponseCode::Standard(StandardResponseCode::DeviceBusy)),
+        json!({"code": "0x2019", "name": "DeviceBusy"})
+    );
+    assert_eq!(
+        round_trip_eq(ResponseCode::Other(0xA102)),
+        json!({"code": "0xA102"})
+    );
+    assert_eq!(
+        round_trip_eq(ObjectFormatCode::Standard(
+            StandardObjectFormatCode::ExifJpeg
+        )),
+        json!({"code": "0x3801", "name": "ExifJpeg"})
+    );
+    assert_eq!(
+        round_trip_eq(ObjectFormatCode::ImageOnly),
+        json!({"code": "0xFFFF", "name": "ImageOnly"})
+    );
+    assert_eq!(
+        round_trip_eq(ObjectFormatCode::Vendor(0xB101)),
+        json!({"code": "0xB101"})
+    );
+    assert_eq!(
+        round_trip_eq(AssociationCode::Standard(
+            StandardAssociationCode::GenericFolder
+        )),
+        json!({"code": "0x0001", "name": "GenericFolder"})
+    );
+    round_trip_eq(AssociationCode::Vendor(0x8001));
+    round_trip_eq(AccessType::Standard(StandardAccessType::ReadOnly));
+    round_trip_eq(AccessType::Reserved(0x0010));
+    round_trip_eq(FilesystemType::Standard(StandardFilesystemType::DCF));
+    round_trip_eq(FilesystemType::Vendor(0x8002));
+    round_trip_eq(StorageType::Standard(StandardStorageType::RemovableRam));
+    round_trip_eq(StorageType::Reserved(0x0100));
+}
+
+#[test]
+fn code_enums_accept_integers_and_ignore_names() {
+    let code: EventCode = serde_json::from_value(json!({"code": 0x4002})).unwrap();
+    assert_eq!(code, EventCode::Standard(StandardEventCode::ObjectAdded));
+
+    let code: CommandCode =
+        serde_json::from_value(json!({"code": "0x1009", "name": "SomethingElse"})).unwrap();
+    assert_eq!(code, CommandCode::Standard(StandardCommandCode::GetObject));
+
+    // not a standard, vendor or reserved event code
+    assert!(serde_json::from_value::<EventCode>(json!({"code": "0x1001"})).is_err());
+    assert!(serde_json::from_value::<EventCode>(json!({"code": "0x14002"})).is_err());
+    assert!(serde_json::from_value::<EventCode>(json!({"code": "ObjectAdded"})).is_err());
+}
+
+#[test]
+fn handles_and_storage_ids() {
+    assert_eq!(
+        round_trip_eq(ObjectHandle::from(0x0001_002A)),
+        json!("0x0001002A")
+    );
+    assert_eq!(round_trip_eq(ObjectHandle::ROOT), json!("0xFFFFFFFF"));
+    assert_eq!(
+        round_trip_eq(StorageId::from(0x0001_0001)),
+        json!("0x00010001")
+    );
+    assert_eq!(round_trip_eq(StorageId::all()), json!("0xFFFFFFFF"));
+
+    let handle: ObjectHandle = serde_json::from_value(json!(42)).unwrap();
+    assert_eq!(handle, ObjectHandle::from(42));
+}
+
+#[test]
+fn data_is_tagged_with_its_type() {
+    let values = vec![
+        Data::UNDEF,
+        Data::INT8(-1),
+        Data::UINT8(0xFF),
+        Data::INT16(-300),
+        Data::UINT16(0x8001),
+        Data::INT32(-70000),
+        Data::UINT32(0xDEAD_BEEF),
+        Data::INT64(i64::MIN),
+        Data::UINT64(u64::MAX),
+        Data::INT128((1, 2)),
+        Data::UINT128((u64::MAX, 0)),
+        Data::AINT8(vec![-1, 1]),
+        Data::AUINT8(vec![1, 2, 3]),
+        Data::AINT16(vec![-2]),
+        Data::AUINT16(vec![100, 200]),
+        Data::AINT32(vec![]),
+        Data::AUINT32(vec![0xFFFF_FFFF]),
+        Data::AINT64(vec![-5, 5]),
+        Data::AUINT64(vec![7]),
+        Data::AINT128(vec![(0, 3)]),
+        Data::AUINT128(vec![(1, 1), (2, 2)]),
+        Data::STR("IMG_0001.JPG".into()),
+    ];
+
+    for data in values {
+        let json = round_trip_eq(data.clone());
+        let tag: DataType = serde_json::from_value(json["type"].clone()).unwrap();
+        assert_eq!(tag, data.data_type(), "{}", json);
+    }
+
+    assert_eq!(
+        serde_json::to_value(Data::UINT16(0x8001)).unwrap(),
+        json!({"type": "UINT16", "value": 0x8001})
+    );
+    assert_eq!(
+        serde_json::to_value(Data::UNDEF).unwrap(),
+        json!({"type": "UNDEF"})
+    );
+}
+
+fn sample_device_info() -> DeviceInfo {
+    DeviceInfo {
+        version: 110,
+        vendor_ex_id: 0x0000_000B,
+        vendor_ex_version: 100,
+        vendor_extension_desc: "microsoft.com: 1.0;".into(),
+        functional_mode: 0,
+        operations_supported: vec![0x1001, 0x1002, 0x9153],
+        events_supported: vec![0x4002, 0xC101],
+        device_properties_supported: vec![0x5001],
+        capture_formats: vec![0x3801],
+        image_formats: vec![0x3801, 0xB103],
+        manufacturer: "Canon Inc.".into(),
+        model: "Canon EOS R5".into(),
+        device_version: "1-1.8.1".into(),
+        serial_number: "0123456789".into(),
+    }
+}
+
+fn sample_object_info() -> ObjectInfo {
+    ObjectInfo {
+        storage_id: 0x0001_0001,
+        object_format: ObjectFormatCode::Standard(StandardObjectFormatCode::ExifJpeg),
+        object_compressed_size: 1_234_567,
+        thumb_format: ObjectFormatCode::Standard(StandardObjectFormatCode::Jfif),
+        parent_object: 0x10,
+        association_type: AssociationCode::Standard(StandardAssociationCode::Undefined),
+        filename: "IMG_0001.JPG".into(),
+        capture_date: "20261018T101500".into(),
+        ..ObjectInfo::default()
+    }
+}
+
+#[test]
+fn datasets() {
+    round_trip_eq(sample_device_info());
+    let json = round_trip_eq(sample_object_info());
+    assert_eq!(
+        json["object_format"],
+        json!({"code": "0x3801", "name": "ExifJpeg"})
+    );
+
+    round_trip_eq(StorageInfo {
+        storage_type: StorageType::Standard(StandardStorageType::RemovableRam),
+        filesystem_type: FilesystemType::Standard(StandardFilesystemType::DCF),
+        access_capability: AccessType::Standard(StandardAccessType::ReadWrite),
+        max_capacity: 64 << 30,
+        free_space_in_bytes: 12 << 30,
+        free_space_in_images: 900,
+        storage_description: "SD".into(),
+        volume_label: "EOS_DIGITAL".into(),
+    });
+
+    round_trip(&ObjectFilesystemInfo {
+        object_handle: ObjectHandle::from(7),
+        storage_id: 0x0001_0001,
+        object_format: ObjectFormatCode::Standard(StandardObjectFormatCode::Association),
+        protection_status: 0,
+        object_compressed_size: 0,
+        parent_object: 0,
+        association_type: AssociationCode::Standard(StandardAssociationCode::GenericFolder),
+        association_desc: 0,
+        sequence_number: 0,
+        filename: "DCIM".into(),
+        modification_date: "20261018T101500".into(),
+    });
+    round_trip(&StreamInfo {
+        dataset_size: 36,
+        time_resolution: 1_000_000,
+        frame_header_size: 8,
+        frame_max_size: 1 << 20,
+        packet_header_size: 4,
+        packet_max_size: 512,
+        packet_alignment: 4,
+    });
+    round_trip(&VendorExtensionMap {
+        native_code: 0x9153,
+        mapped_code: 0x9001,
+        mapped_vendor_ex_id: 6,
+    });
+    round_trip(&ContainerInfo {
+        payload_len: 100,
+        kind: ContainerType::Data,
+        code: 0x1009,
+        tid: 3,
+    });
+}
+
+#[test]
+fn prop_infos() {
+    let range = PropInfo {
+        property_code: 0x5007,
+        data_type: DataType::UINT16,
+        get_set: 1,
+        is_enable: 1,
+        factory_default: Data::UINT16(280),
+        current: Data::UINT16(400),
+        form: FormData::Range {
+            min_value: Data::UINT16(140),
+            max_value: Data::UINT16(2200),
+            step: Data::UINT16(10),
+        },
+    };
+    let json = round_trip_eq(range);
+    assert_eq!(json["data_type"], json!("UINT16"));
+
+    round_trip_eq(PropInfo {
+        property_code: 0xD1B0,
+        data_type: DataType::STR,
+        get_set: 0,
+        is_enable: 1,
+        factory_default: Data::STR(String::new()),
+        current: Data::STR("TFT".into()),
+        form: FormData::Enumeration {
+            array: vec![Data::STR("TFT".into()), Data::STR("PC".into())],
+        },
+    });
+}
+
+#[test]
+fn events_and_other_types() {
+    let event = Event::new(0x4002, &[0x2A, 0, 1, 0]).unwrap();
+    let json = round_trip(&event);
+    assert_eq!(
+        json,
+        json!({"code": {"code": "0x4002", "name": "ObjectAdded"}, "params": [0x0001_002A]})
+    );
+
+    round_trip(&ObjectPropListEntry {
+        object_handle: ObjectHandle::from(0x2A),
+        property_code: 0xDC07,
+        value: Data::STR("IMG_0001.JPG".into()),
+    });
+    round_trip(&LiveViewFrame {
+        jpeg: vec![0xFF, 0xD8, 0xFF, 0xD9],
+        focus_area: Some(FocusArea {
+            x: 10,
+            y: 20,
+            width: 100,
+            height: 50,
+        }),
+        histogram: Some(Histogram {
+            luminance: vec![0; 256],
+            rgb: None,
+        }),
+        extra: vec![(5, vec![1, 2, 3])],
+    });
+    round_trip_eq(VendorExtension {
+        name: "microsoft.com".into(),
+        version: "1.0".into(),
+    });
+    round_trip_eq(VendorExtensionId::Canon);
+    round_trip_eq(DataType::AUINT32);
+}
+
+#[test]
+fn configs() {
+    let mut json = round_trip_eq(RetryPolicy::default());
+    assert_eq!(json["transient_usb_errors"][0], "Timeout");
+    json["transient_usb_errors"] = json!(["Sometimes"]);
+    assert!(serde_json::from_value::<RetryPolicy>(json).is_err());
+
+    round_trip_eq(RecoveryPolicy::default());
+    round_trip(&SessionConfig::default());
+    let mut config = SupervisorConfig::default();
+    config.properties.push((0x5001, Data::UINT8(2)));
+    round_trip(&config);
+}
+
+#[test]
+fn diagnostics() {
+    let json = round_trip_eq(TransactionContext {
+        code: CommandCode::Other(0x9805),
+        name: Some("GetObjectPropList".into()),
+        params: vec![0x2A],
+        tid: 7,
+        phase: TransactionPhase::DataIn,
+    });
+    assert_eq!(json["phase"], "DataIn");
+
+    round_trip_eq(ConnectionEvent::RestoreFailed {
+        code: 0x5001,
+        error: "the device is busy".into(),
+    });
+    round_trip_eq(PoolStats {
+        allocations: 2,
+        reuses: 5,
+        free: 1,
+    });
+
+    let container = RecordedContainer {
+        endpoint: Endpoint::BulkIn,
+        kind: ContainerType::Response,
+        code: 0x2001,
+        tid: 7,
+        length: 12,
+        payload: vec![],
+        elapsed: Duration::from_millis(3),
+    };
+    let json = round_trip_eq(Recording {
+        bulk_in_packet_size: 512,
+        bulk_out_packet_size: 512,
+        interrupt_packet_size: 64,
+        has_interrupt: true,
+        entries: vec![
+            RecordEntry::Container(container),
+            RecordEntry::Fault {
+                endpoint: Endpoint::BulkOut,
+                error: rusb::Error::Pipe,
+                elapsed: Duration::from_millis(4),
+            },
+        ],
+    });
+    assert_eq!(json["entries"][1]["Fault"]["error"], "Pipe");
+
+    let info = sample_object_info();
+    round_trip(&ObjectTree {
+        handle: ObjectHandle::from(1),
+        info: info.clone(),
+        children: Some(vec![ObjectTree {
+            handle: ObjectHandle::from(2),
+            info,
+            children: None,
+        }]),
+    });
+}
@@ -0,0 +1,107 @@
+mod common;
+
+use std::num::NonZeroU32;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use ptp::{Device, SessionConfig, StandardCommandCode, StandardResponseCode};
+
+use common::{FakeCamera, Reply, Request};
+
+const OPEN_SESSION: u16 = StandardCommandCode::OpenSession as u16;
+const CLOSE_SESSION: u16 = StandardCommandCode::CloseSession as u16;
+const GET_STORAGE_IDS: u16 = StandardCommandCode::GetStorageIDs as u16;
+
+fn storage_ids(request: &Request) -> Reply {
+    match request.code {
+        GET_STORAGE_IDS => Reply::data(vec![1, 0, 0, 0, 1, 0, 1, 0]),
+        _ => Reply::ok(),
+    }
+}
+
+fn calls(fake: &FakeCamera) -> Vec<(u16, u32)> {
+    fake.log().iter().map(|r| (r.code, r.tid)).collect()
+}
+
+#[test]
+fn session_numbers_transactions_and_closes_on_drop() {
+    let device = Device::with_transport(FakeCamera::new(storage_ids));
+
+    let session = device.open_session(SessionConfig::default()).unwrap();
+    assert_eq!(session.get_storage_ids(None).unwrap().len(), 1);
+    assert_eq!(session.get_storage_ids(None).unwrap().len(), 1);
+
+    // one session at a time
+    let err = device.open_session(SessionConfig::default()).err().unwrap();
+    assert_eq!(
+        err.response_code(),
+        Some(StandardResponseCode::SessionAlreadyOpen.into())
+    );
+
+    drop(session);
+    assert_eq!(
+        calls(device.transport()),
+        [
+            (OPEN_SESSION, 0),
+            (GET_STORAGE_IDS, 1),
+            (GET_STORAGE_IDS, 2),
+            (CLOSE_SESSION, 3)
+        ]
+    );
+
+    // a new session starts over
+    let session = device.open_session(SessionConfig::default()).unwrap();
+    session.close().unwrap();
+    assert_eq!(
+        calls(device.transport())[4..],
+        [(OPEN_SESSION, 0), (CLOSE_SESSION, 1)]
+    );
+}
+
+#[test]
+fn session_left_open_on_the_device_is_replaced() {
+    // the device still has a session from an earlier process
+    let open = AtomicBool::new(true);
+    let device = Device::with_transport(FakeCamera::new(move |request| match request.code {
+        OPEN_SESSION if open.swap(true, Ordering::SeqCst) => {
+            Reply::error(StandardResponseCode::SessionAlreadyOpen as u16)
+        }
+        CLOSE_SESSION => {
+            open.store(false, Ordering::SeqCst);
+            Reply::ok()
+        }
+        _ => Reply::ok(),
+    }));
+
+    let err = device
+        .open_session(SessionConfig {
+            close_existing: false,
+            ..SessionConfig::default()
+        })
+        .err()
+        .unwrap();
+    assert_eq!(
+        err.response_code(),
+        Some(StandardResponseCode::SessionAlreadyOpen.into())
+    );
+
+    let session = device
+        .open_session(SessionConfig {
+            session_id: NonZeroU32::new(2).unwrap(),
+            ..SessionConfig::default()
+        })
+        .unwrap();
+    assert_eq!(session.id().get(), 2);
+    drop(session);
+
+    assert_eq!(
+        calls(device.transport()),
+        [
+            (OPEN_SESSION, 0),
+            (OPEN_SESSION, 0),
+            (CLOSE_SESSION, 1),
+            (OPEN_SESSION, 0),
+            (CLOSE_SESSION, 1)
+        ]
+    );
+    assert_eq!(device.transport().log()[3].params, [2]);
+}
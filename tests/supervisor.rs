@@ -0,0 +1,181 @@
+mod common;
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Duration;
+
+use ptp::{
+    ConnectionEvent, Connector, Data, Device, Error, StandardCommandCode, Supervisor,
+    SupervisorConfig,
+};
+
+use common::{FakeCamera, Reply};
+
+const OPEN_SESSION: u16 = StandardCommandCode::OpenSession as u16;
+const CLOSE_SESSION: u16 = StandardCommandCode::CloseSession as u16;
+const SET_PROP: u16 = StandardCommandCode::SetDevicePropValue as u16;
+const GET_STORAGE_IDS: u16 = StandardCommandCode::GetStorageIDs as u16;
+
+const WHITE_BALANCE: u16 = 0x5005;
+const ISO: u16 = 0x500f;
+const DEVICE_PROP_NOT_SUPPORTED: u16 = 0x200a;
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+fn handler(request: &common::Request) -> Reply {
+    match request.code {
+        GET_STORAGE_IDS => Reply::data(vec![1, 0, 0, 0, 1, 0, 1, 0]),
+        SET_PROP if request.params == [DEVICE_PROP_NOT_SUPPORTED as u32] => {
+            Reply::error(DEVICE_PROP_NOT_SUPPORTED)
+        }
+        _ => Reply::ok(),
+    }
+}
+
+// Opens a new fake camera whenever the device is attached
+#[derive(Clone, Default)]
+struct FakeBus {
+    attached: Arc<AtomicBool>,
+    opened: Arc<AtomicUsize>,
+}
+
+impl Connector for FakeBus {
+    type Transport = FakeCamera;
+
+    fn connect(&mut self) -> Result<Option<Device<FakeCamera>>, Error> {
+        if !self.attached.load(Ordering::Acquire) {
+            return Ok(None);
+        }
+        self.opened.fetch_add(1, Ordering::AcqRel);
+        Ok(Some(Device::with_transport(FakeCamera::new(handler))))
+    }
+
+    fn is_attached(&mut self) -> bool {
+        self.attached.load(Ordering::Acquire)
+    }
+
+    fn wait(&mut self, timeout: Duration) {
+        thread::sleep(timeout);
+    }
+}
+
+fn supervise(bus: &FakeBus, properties: Vec<(u16, Data)>) -> Supervisor<FakeCamera> {
+    let config = SupervisorConfig {
+        properties,
+        poll_interval: Duration::from_millis(5),
+        ..SupervisorConfig::default()
+    };
+    Supervisor::start(bus.clone(), config)
+}
+
+fn next(events: &mpsc::Receiver<ConnectionEvent>) -> ConnectionEvent {
+    events.recv_timeout(TIMEOUT).unwrap()
+}
+
+fn requests(device: &Device<FakeCamera>) -> Vec<(u16, u32, Vec<u32>)> {
+    device
+        .transport()
+        .log()
+        .into_iter()
+        .map(|r| (r.code, r.tid, r.params))
+        .collect()
+}
+
+#[test]
+fn reconnects_and_restores_properties() {
+    let bus = FakeBus::default();
+    let supervisor = supervise(&bus, vec![(WHITE_BALANCE, Data::UINT16(2))]);
+    let events = supervisor.subscribe();
+    assert!(supervisor.device().is_none());
+    assert!(matches!(
+        supervisor.with_session(|session| session.get_storage_ids(None)),
+        Err(Error::Usb(rusb::Error::NoDevice))
+    ));
+
+    bus.attached.store(true, Ordering::Release);
+    assert_eq!(next(&events), ConnectionEvent::Connected);
+    supervisor.set_property(ISO, Data::UINT16(400)).unwrap();
+    let first = supervisor.device().unwrap();
+
+    // the camera browns out
+    bus.attached.store(false, Ordering::Release);
+    first.transport().unplug();
+    assert_eq!(next(&events), ConnectionEvent::Disconnected);
+    assert!(!supervisor.is_connected());
+
+    bus.attached.store(true, Ordering::Release);
+    assert_eq!(next(&events), ConnectionEvent::Connected);
+    let ids = supervisor
+        .with_session(|session| session.get_storage_ids(None))
+        .unwrap();
+    assert_eq!(ids.len(), 1);
+
+    let second = supervisor.device().unwrap();
+    assert!(!Arc::ptr_eq(&first, &second));
+    supervisor.stop();
+
+    let restored = vec![
+        (OPEN_SESSION, 0, vec![1]),
+        (SET_PROP, 1, vec![WHITE_BALANCE as u32]),
+        (SET_PROP, 2, vec![ISO as u32]),
+        (GET_STORAGE_IDS, 3, vec![]),
+        (CLOSE_SESSION, 4, vec![]),
+    ];
+    assert_eq!(requests(&second), restored);
+    let values: Vec<_> = second
+        .transport()
+        .log()
+        .into_iter()
+        .filter_map(|r| r.data)
+        .collect();
+    assert_eq!(values, [vec![2, 0], vec![0x90, 0x01]]);
+    assert_eq!(bus.opened.load(Ordering::Acquire), 2);
+}
+
+#[test]
+fn a_failed_call_triggers_a_reconnect() {
+    let bus = FakeBus::default();
+    bus.attached.store(true, Ordering::Release);
+    let supervisor = supervise(&bus, vec![]);
+    let first = supervisor.wait_connected(TIMEOUT).unwrap();
+    let events = supervisor.subscribe();
+    assert_eq!(next(&events), ConnectionEvent::Connected);
+
+    // the device re-enumerated before the bus was checked again
+    first.transport().unplug();
+    let result = supervisor.with_session(|session| session.get_storage_ids(None));
+    assert!(matches!(
+        result.unwrap_err().root(),
+        Error::Usb(rusb::Error::NoDevice)
+    ));
+
+    assert_eq!(next(&events), ConnectionEvent::Disconnected);
+    assert_eq!(next(&events), ConnectionEvent::Connected);
+    let second = supervisor.device().unwrap();
+    assert_eq!(requests(&second), [(OPEN_SESSION, 0, vec![1])]);
+}
+
+#[test]
+fn failed_restores_are_reported() {
+    let bus = FakeBus::default();
+    bus.attached.store(true, Ordering::Release);
+    let supervisor = supervise(
+        &bus,
+        vec![
+            (DEVICE_PROP_NOT_SUPPORTED, Data::UINT8(1)),
+            (ISO, Data::UINT16(100)),
+        ],
+    );
+    let events = supervisor.subscribe();
+
+    match next(&events) {
+        ConnectionEvent::RestoreFailed { code, .. } => assert_eq!(code, DEVICE_PROP_NOT_SUPPORTED),
+        other => panic!("unexpected event {:?}", other),
+    }
+    assert_eq!(next(&events), ConnectionEvent::Connected);
+
+    // the other properties are still set
+    let device = supervisor.device().unwrap();
+    assert_eq!(requests(&device).len(), 3);
+    assert_eq!(supervisor.properties().len(), 2);
+}
@@ -0,0 +1,142 @@
+mod common;
+
+use std::time::Duration;
+
+use ptp::{CommandCode, Device};
+
+use common::{FakeCamera, Reply, Request, Transfer, PACKET_SIZE};
+
+const HEADER_SIZE: usize = 12;
+const TIMEOUT: Option<Duration> = Some(Duration::from_secs(1));
+// sends data, or asks for as much data as its parameter says
+const SEND: u16 = 0x9001;
+const RECEIVE: u16 = 0x9002;
+
+fn payload(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i % 251) as u8).collect()
+}
+
+fn handler(request: &Request) -> Reply {
+    match request.code {
+        RECEIVE => Reply::data(payload(request.params[0] as usize)),
+        _ => Reply::ok(),
+    }
+}
+
+fn device(packet_size: usize) -> Device<FakeCamera> {
+    Device::with_transport(FakeCamera::new(handler).with_packet_size(packet_size))
+}
+
+fn send(device: &Device<FakeCamera>, len: usize) -> Vec<Transfer> {
+    let before = device.transport().transfers().len();
+    let data = payload(len);
+    device
+        .command(CommandCode::Other(SEND), &[], Some(&data), TIMEOUT)
+        .unwrap();
+    assert_eq!(device.transport().log().last().unwrap().data, Some(data));
+    device.transport().transfers()[before..].to_vec()
+}
+
+fn receive(device: &Device<FakeCamera>, len: usize) -> Vec<Transfer> {
+    let before = device.transport().transfers().len();
+    let data = device
+        .command(CommandCode::Other(RECEIVE), &[len as u32], None, TIMEOUT)
+        .unwrap();
+    assert_eq!(data, payload(len));
+    device.transport().transfers()[before..].to_vec()
+}
+
+fn writes(transfers: &[Transfer]) -> Vec<usize> {
+    transfers
+        .iter()
+        .filter_map(|t| match *t {
+            Transfer::Out(n) => Some(n),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn writes_on_packet_boundaries_end_with_a_zero_length_packet() {
+    for &packet_size in &[64, PACKET_SIZE, 1024] {
+        let device = device(packet_size);
+        for &len in &[
+            packet_size - 1,
+            packet_size,
+            packet_size + 1,
+            2 * packet_size,
+            1024 * 1024,
+            1024 * 1024 + packet_size,
+        ] {
+            let writes = writes(&send(&device, len - HEADER_SIZE));
+            // the command container, then the data
+            assert_eq!(writes.iter().sum::<usize>(), HEADER_SIZE + len);
+            let zlp = len.is_multiple_of(packet_size);
+            assert_eq!(*writes.last().unwrap() == 0, zlp, "{} byte container", len);
+            assert!(!writes[..writes.len() - 1].contains(&0));
+        }
+        assert!(device.transport().violations().is_empty());
+    }
+}
+
+#[test]
+fn commands_are_not_followed_by_a_zero_length_packet() {
+    let device = device(PACKET_SIZE);
+    receive(&device, 0);
+    assert_eq!(writes(&device.transport().transfers()), [16]);
+}
+
+#[test]
+fn reads_follow_the_container_length() {
+    let device = device(PACKET_SIZE);
+    let read = |requested, actual| Transfer::In { requested, actual };
+    let response = read(8192, 12);
+
+    // fits the first transfer, ending on a packet boundary or not
+    assert_eq!(receive(&device, 500)[1..], [read(8192, 512), response]);
+    assert_eq!(receive(&device, 499)[1..], [read(8192, 511), response]);
+
+    // fills the first transfer exactly, so a zero-length packet follows
+    assert_eq!(
+        receive(&device, 8192 - HEADER_SIZE)[1..],
+        [read(8192, 8192), read(PACKET_SIZE, 0), response]
+    );
+
+    // the rest is asked for in whole packets
+    assert_eq!(
+        receive(&device, 8692 - HEADER_SIZE)[1..],
+        [read(8192, 8192), read(512, 500), response]
+    );
+    assert_eq!(
+        receive(&device, 20000 - HEADER_SIZE)[1..],
+        [read(8192, 8192), read(12288, 11808), response]
+    );
+    assert_eq!(
+        receive(&device, 9216 - HEADER_SIZE)[1..],
+        [
+            read(8192, 8192),
+            read(1024, 1024),
+            read(PACKET_SIZE, 0),
+            response
+        ]
+    );
+
+    assert!(device.transport().violations().is_empty());
+}
+
+#[test]
+fn reads_on_packet_boundaries_do_not_hang() {
+    for &packet_size in &[64, PACKET_SIZE, 1024] {
+        let device = device(packet_size);
+        for n in 1..=20 {
+            let len = n * packet_size;
+            receive(&device, len - HEADER_SIZE);
+            receive(&device, len + 1 - HEADER_SIZE);
+            receive(&device, len - 1 - HEADER_SIZE);
+        }
+        for &len in &[1024 * 1024, 1024 * 1024 + 8192, 3 * 1024 * 1024 + 100] {
+            receive(&device, len - HEADER_SIZE);
+        }
+        assert!(device.transport().violations().is_empty());
+    }
+}
@@ -0,0 +1,121 @@
+mod common;
+
+use ptp::{
+    Device, DeviceInfo, StandardCommandCode, VendorExtension, VendorExtensionId, VendorProfile,
+    ANDROID, CANON, FUJI, KODAK, MTP, NIKON, PANASONIC, SONY,
+};
+
+use common::{FakeCamera, Reply};
+
+const GET_DEVICE_INFO: u16 = StandardCommandCode::GetDeviceInfo as u16;
+
+fn device_info(vendor_ex_id: u32, desc: &str) -> DeviceInfo {
+    DeviceInfo {
+        version: 100,
+        vendor_ex_id,
+        vendor_ex_version: 100,
+        vendor_extension_desc: desc.into(),
+        functional_mode: 0,
+        operations_supported: vec![],
+        events_supported: vec![],
+        device_properties_supported: vec![],
+        capture_formats: vec![],
+        image_formats: vec![],
+        manufacturer: "Acme".into(),
+        model: "Camera".into(),
+        device_version: "1.0".into(),
+        serial_number: "1".into(),
+    }
+}
+
+fn extension(name: &str, version: &str) -> VendorExtension {
+    VendorExtension {
+        name: name.into(),
+        version: version.into(),
+    }
+}
+
+fn detect(vendor_ex_id: u32, desc: &str) -> Option<&'static str> {
+    VendorProfile::detect(&device_info(vendor_ex_id, desc)).map(|p| p.name)
+}
+
+#[test]
+fn extension_descriptions() {
+    assert_eq!(
+        VendorExtension::parse_desc("microsoft.com: 1.0; android.com: 1.0;"),
+        [
+            extension("microsoft.com", "1.0"),
+            extension("android.com", "1.0")
+        ]
+    );
+
+    // no version, stray whitespace and separators, and upper case names
+    assert_eq!(
+        VendorExtension::parse_desc(" Canon.com ;; microsoft.com/WpdNa: 1.0 "),
+        [
+            extension("canon.com", ""),
+            extension("microsoft.com/wpdna", "1.0")
+        ]
+    );
+    assert!(VendorExtension::parse_desc("").is_empty());
+    assert!(VendorExtension::parse_desc(" ; ").is_empty());
+}
+
+#[test]
+fn profiles_by_extension_id() {
+    use VendorExtensionId::*;
+
+    let profiles = [
+        (Canon, &CANON),
+        (Nikon, &NIKON),
+        (Sony, &SONY),
+        (Fuji, &FUJI),
+        (Panasonic, &PANASONIC),
+        (Kodak, &KODAK),
+        (Microsoft, &MTP),
+    ];
+    for &(id, profile) in &profiles {
+        assert_eq!(detect(id as u32, ""), Some(profile.name), "{:?}", id);
+    }
+    assert_eq!(detect(Polaroid as u32, ""), None);
+    assert_eq!(detect(0xffff_ffff, ""), None);
+}
+
+#[test]
+fn profiles_by_extension_description() {
+    let microsoft = VendorExtensionId::Microsoft as u32;
+    let android = "microsoft.com: 1.0; android.com: 1.0;";
+
+    // MTP devices are told apart by their extensions
+    assert_eq!(detect(microsoft, android), Some(ANDROID.name));
+    assert_eq!(detect(microsoft, "microsoft.com: 1.0;"), Some(MTP.name));
+    // a camera reporting the MTP ID with its own extension
+    assert_eq!(detect(microsoft, "nikon.com: 1.0;"), Some(NIKON.name));
+    // a vendor ID wins over the description
+    assert_eq!(
+        detect(VendorExtensionId::Canon as u32, android),
+        Some(CANON.name)
+    );
+    // an unknown ID with a known extension
+    assert_eq!(detect(0, "sony.net: 1.0"), Some(SONY.name));
+}
+
+#[test]
+fn device_info_selects_the_profile() {
+    let info = device_info(
+        VendorExtensionId::Microsoft as u32,
+        "microsoft.com: 1.0; android.com: 1.0;",
+    );
+    let mut data = vec![];
+    info.encode(&mut data).unwrap();
+    let device = Device::with_transport(FakeCamera::new(move |request| match request.code {
+        GET_DEVICE_INFO => Reply::data(data.clone()),
+        _ => Reply::ok(),
+    }));
+
+    assert!(device.vendor().is_none());
+    let decoded = device.get_device_info(None).unwrap();
+    assert_eq!(decoded.vendor_extensions().len(), 2);
+    assert_eq!(device.vendor().map(|p| p.name), Some(ANDROID.name));
+    assert_eq!(ANDROID.parent.map(|p| p.name), Some(MTP.name));
+}